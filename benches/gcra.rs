@@ -0,0 +1,127 @@
+//! Throughput of the GCRA hot path, alone and behind route matching.
+//!
+//! Run with: cargo bench --features bench --bench gcra
+
+use criterion::{Criterion, Throughput, criterion_group, criterion_main};
+use http::Method;
+use route_ratelimit::bench::{Bucket, check};
+use route_ratelimit::{Algorithm, RateLimit, RateLimitMiddleware};
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+const THREADS: u64 = 8;
+
+/// One request per microsecond, so a clock advancing by a microsecond per
+/// request is never limited.
+fn limit() -> RateLimit {
+    RateLimit::new(1_000_000, Duration::from_secs(1))
+}
+
+fn bench_try_acquire(c: &mut Criterion) {
+    let mut group = c.benchmark_group("gcra");
+    group.throughput(Throughput::Elements(1));
+
+    let bucket = Bucket::new(Algorithm::Gcra, limit());
+    let mut now = 0;
+    group.bench_function("try_acquire", |b| {
+        b.iter(|| {
+            now += 1_000;
+            black_box(bucket.try_acquire(now))
+        });
+    });
+
+    // Threads race to update one TAT, whether or not they are admitted
+    group.throughput(Throughput::Elements(THREADS));
+    group.bench_function("try_acquire_contended", |b| {
+        b.iter_custom(|iters| {
+            let bucket = Bucket::new(Algorithm::Gcra, limit());
+            let start = Instant::now();
+            std::thread::scope(|scope| {
+                for _ in 0..THREADS {
+                    scope.spawn(|| {
+                        for i in 0..iters {
+                            black_box(bucket.try_acquire(i * 1_000));
+                        }
+                    });
+                }
+            });
+            start.elapsed()
+        });
+    });
+    group.finish();
+}
+
+/// Thirty routes across three hosts, each limited per burst and sustained.
+fn middleware() -> RateLimitMiddleware {
+    const PATHS: [&str; 9] = [
+        "/order",
+        "/orders",
+        "/cancel-all",
+        "/book",
+        "/price",
+        "/midpoint",
+        "/trades",
+        "/positions",
+        "/markets",
+    ];
+    ["clob.example.com", "data.example.com", "gamma.example.com"]
+        .into_iter()
+        .fold(RateLimitMiddleware::builder(), |builder, host| {
+            builder.host(host, |host| {
+                PATHS.into_iter().fold(
+                    host.route(|r| r.limit(u32::MAX, Duration::from_secs(10))),
+                    |host, path| {
+                        host.route(|r| {
+                            r.path(path)
+                                .limit(u32::MAX, Duration::from_secs(10))
+                                .limit(u32::MAX, Duration::from_secs(600))
+                        })
+                    },
+                )
+            })
+        })
+        .build()
+}
+
+fn bench_check(c: &mut Criterion) {
+    let middleware = middleware();
+    let mut group = c.benchmark_group("middleware");
+    group.throughput(Throughput::Elements(1));
+    group.bench_function("check_30_routes", |b| {
+        b.iter(|| {
+            black_box(check(
+                &middleware,
+                black_box("gamma.example.com"),
+                &Method::POST,
+                black_box("/markets"),
+            ))
+        });
+    });
+
+    // Threads checking the same route share its buckets
+    group.throughput(Throughput::Elements(THREADS));
+    group.bench_function("check_30_routes_contended", |b| {
+        b.iter_custom(|iters| {
+            let start = Instant::now();
+            std::thread::scope(|scope| {
+                for _ in 0..THREADS {
+                    scope.spawn(|| {
+                        for _ in 0..iters {
+                            black_box(check(
+                                &middleware,
+                                "gamma.example.com",
+                                &Method::POST,
+                                "/markets",
+                            ));
+                        }
+                    });
+                }
+            });
+            start.elapsed()
+        });
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_try_acquire, bench_check);
+criterion_main!(benches);
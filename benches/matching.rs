@@ -0,0 +1,74 @@
+//! Cost of matching a request against a large route table, with and without
+//! the match cache.
+//!
+//! Run with: cargo bench --bench matching
+
+use criterion::{Criterion, criterion_group, criterion_main};
+use http::Method;
+use route_ratelimit::{RateLimitBuilder, RateLimitMiddleware};
+use std::hint::black_box;
+use std::time::Duration;
+
+const PATHS: [&str; 12] = [
+    "/order",
+    "/orders",
+    "/cancel-all",
+    "/book",
+    "/books",
+    "/price",
+    "/prices",
+    "/midpoint",
+    "/midpoints",
+    "/trades",
+    "/positions",
+    "/markets",
+];
+
+/// Roughly the shape of the Polymarket example: a few hosts, each with a
+/// general limit and a route per endpoint.
+fn routes(builder: RateLimitBuilder) -> RateLimitMiddleware {
+    ["clob.example.com", "data.example.com", "gamma.example.com"]
+        .into_iter()
+        .fold(builder, |builder, host| {
+            builder.host(host, |host| {
+                PATHS.into_iter().fold(
+                    host.route(|r| r.limit(u32::MAX, Duration::from_secs(10))),
+                    |host, path| {
+                        host.route(|r| {
+                            r.method(Method::POST)
+                                .path(path)
+                                .limit(u32::MAX, Duration::from_secs(10))
+                                .limit(u32::MAX, Duration::from_secs(600))
+                        })
+                    },
+                )
+            })
+        })
+        .build()
+}
+
+fn bench_matching(c: &mut Criterion) {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_time()
+        .build()
+        .unwrap();
+    let acquire = |middleware: &RateLimitMiddleware| {
+        runtime
+            .block_on(middleware.acquire(
+                black_box("gamma.example.com"),
+                &Method::POST,
+                black_box("/markets"),
+            ))
+            .unwrap();
+    };
+
+    let mut group = c.benchmark_group("acquire");
+    let cached = routes(RateLimitMiddleware::builder());
+    group.bench_function("cached", |b| b.iter(|| acquire(&cached)));
+    let uncached = routes(RateLimitMiddleware::builder().match_cache_capacity(0));
+    group.bench_function("uncached", |b| b.iter(|| acquire(&uncached)));
+    group.finish();
+}
+
+criterion_group!(benches, bench_matching);
+criterion_main!(benches);
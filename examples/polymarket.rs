@@ -145,6 +145,23 @@ async fn main() {
     // Create the middleware
     let middleware = polymarket_middleware();
 
+    // List the active configuration
+    for route in &middleware.routes() {
+        let limits: Vec<_> = route
+            .limits()
+            .iter()
+            .map(|limit| format!("{}/{:?}", limit.requests, limit.window))
+            .collect();
+        println!(
+            "{:?} {:?} {:?}: {}",
+            route.hosts(),
+            route.methods(),
+            route.path_prefix(),
+            limits.join(", ")
+        );
+    }
+    println!();
+
     // Create a rate-limited client
     let client = ClientBuilder::new(reqwest::Client::new())
         .with(middleware)
@@ -219,4 +236,18 @@ mod tests {
         // Just verify it builds without panicking
         let _middleware = polymarket_middleware();
     }
+
+    #[test]
+    fn test_route_count() {
+        let middleware = polymarket_middleware();
+        let routes = middleware.routes();
+        assert_eq!(routes.len(), 34);
+
+        let clob = routes
+            .iter()
+            .filter(|route| route.hosts().contains("clob.polymarket.com"))
+            .count();
+        assert_eq!(clob, 23);
+        assert!(routes.iter().all(|route| !route.limits().is_empty()));
+    }
 }
@@ -0,0 +1,70 @@
+//! Load rate limit configuration from a TOML file.
+//!
+//! This example reads `examples/ratelimits.toml` (or the path given as the first
+//! argument) and builds the middleware from it.
+//!
+//! Run with: cargo run --example toml_config --features serde
+
+use reqwest_middleware::ClientBuilder;
+use route_ratelimit::{RateLimitBuilder, RateLimitConfig};
+use std::path::PathBuf;
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let path = std::env::args().nth(1).map_or_else(
+        || PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("examples/ratelimits.toml"),
+        PathBuf::from,
+    );
+
+    let config: RateLimitConfig = toml::from_str(&std::fs::read_to_string(&path)?)?;
+    println!(
+        "Loaded {} routes from {}",
+        config.routes.len(),
+        path.display()
+    );
+
+    for route in &config.routes {
+        let hosts: Vec<&str> = route.hosts.iter().map(String::as_str).collect();
+        let methods: Vec<&str> = route.methods.iter().map(|m| m.as_str()).collect();
+        let method = if methods.is_empty() {
+            "*".to_string()
+        } else {
+            methods.join(",")
+        };
+        let path = if route.path_prefix.is_empty() {
+            "/"
+        } else {
+            &route.path_prefix
+        };
+        for limit in &route.limits {
+            println!(
+                "  {} {method} {path}: {} requests per {:?}",
+                hosts.join(","),
+                limit.requests,
+                limit.window
+            );
+        }
+    }
+
+    let middleware = RateLimitBuilder::from_config(config).build();
+
+    let _client = ClientBuilder::new(reqwest::Client::new())
+        .with(middleware)
+        .build();
+
+    println!("Client configured with rate limiting middleware");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bundled_config_loads() {
+        let config: RateLimitConfig = toml::from_str(include_str!("ratelimits.toml")).unwrap();
+        assert_eq!(config.routes.len(), 4);
+
+        let _middleware = RateLimitBuilder::from_config(config).build();
+    }
+}
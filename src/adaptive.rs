@@ -0,0 +1,122 @@
+//! Adaptive rate limiting that backs off when the server throttles.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::types::{Adaptive, RateLimit};
+
+/// Factor the rate is multiplied by on every `429 Too Many Requests` response.
+const DECREASE_FACTOR: f64 = 0.5;
+
+/// Number of successful responses over which the rate recovers from its
+/// minimum to its maximum.
+const RECOVERY_STEPS: f64 = 20.0;
+
+/// Per-route adaptive rate, adjusted AIMD-style.
+///
+/// The rate is a multiple of the route's configured limits. It starts at the
+/// maximum, is halved on every `429` response down to the minimum, and grows
+/// back by a twentieth of the range on every successful response. Other
+/// responses leave it unchanged.
+#[derive(Debug)]
+pub(crate) struct AdaptiveRate {
+    /// The current rate, as the bits of an `f64`.
+    rate: AtomicU64,
+    min: f64,
+    max: f64,
+}
+
+impl AdaptiveRate {
+    /// Create a rate starting at `adaptive.max_rate`.
+    pub fn new(adaptive: Adaptive) -> Self {
+        Self {
+            rate: AtomicU64::new(adaptive.max_rate.to_bits()),
+            min: adaptive.min_rate,
+            max: adaptive.max_rate,
+        }
+    }
+
+    /// The current rate, as a multiple of the configured limits.
+    pub fn rate(&self) -> f64 {
+        f64::from_bits(self.rate.load(Ordering::Relaxed))
+    }
+
+    /// Back off after the server throttled a request.
+    pub fn record_throttled(&self) {
+        self.update(|rate| (rate * DECREASE_FACTOR).max(self.min));
+    }
+
+    /// Recover after a successful response.
+    pub fn record_success(&self) {
+        let step = (self.max - self.min) / RECOVERY_STEPS;
+        self.update(|rate| (rate + step).min(self.max));
+    }
+
+    /// `limit` at the current rate: the same number of requests over a
+    /// proportionally longer window.
+    pub fn scale(&self, limit: &RateLimit) -> RateLimit {
+        let mut scaled = limit.clone();
+        scaled.window = limit.window.div_f64(self.rate());
+        scaled
+    }
+
+    fn update(&self, f: impl Fn(f64) -> f64) {
+        // The closure always returns `Some`, so the update cannot fail
+        let _ = self
+            .rate
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |bits| {
+                Some(f(f64::from_bits(bits)).to_bits())
+            });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn rate() -> AdaptiveRate {
+        AdaptiveRate::new(Adaptive {
+            min_rate: 0.25,
+            max_rate: 1.0,
+        })
+    }
+
+    #[test]
+    fn test_backs_off_multiplicatively_down_to_min() {
+        let rate = rate();
+        assert_eq!(rate.rate(), 1.0);
+
+        rate.record_throttled();
+        assert_eq!(rate.rate(), 0.5);
+        rate.record_throttled();
+        assert_eq!(rate.rate(), 0.25);
+        rate.record_throttled();
+        assert_eq!(rate.rate(), 0.25);
+    }
+
+    #[test]
+    fn test_recovers_additively_up_to_max() {
+        let rate = rate();
+        rate.record_throttled();
+        rate.record_throttled();
+
+        rate.record_success();
+        assert!((rate.rate() - 0.2875).abs() < 1e-9);
+        for _ in 0..100 {
+            rate.record_success();
+        }
+        assert_eq!(rate.rate(), 1.0);
+    }
+
+    #[test]
+    fn test_scale_stretches_window() {
+        let rate = rate();
+        let limit = RateLimit::new(10, Duration::from_secs(1));
+        assert_eq!(rate.scale(&limit), limit);
+
+        rate.record_throttled();
+        let scaled = rate.scale(&limit);
+        assert_eq!(scaled.requests, 10);
+        assert_eq!(scaled.window, Duration::from_secs(2));
+    }
+}
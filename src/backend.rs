@@ -0,0 +1,357 @@
+//! Pluggable storage for GCRA state.
+//!
+//! By default, every middleware keeps its rate limit state in its own memory,
+//! so limits are only enforced within one process. Implementing
+//! [`StateBackend`] on top of an external store, such as Redis, lets several
+//! processes share the limits of their GCRA routes.
+
+use dashmap::DashMap;
+use std::fmt::Debug;
+use std::time::Duration;
+
+use crate::gcra::GcraState;
+use crate::route_table::RouteTable;
+use crate::snapshot::scope_identity;
+use crate::types::{RateLimit, RouteKey};
+
+/// Storage for the theoretical arrival time (TAT) of each GCRA bucket.
+///
+/// Set with
+/// [`RateLimitBuilder::with_state_backend`](crate::RateLimitBuilder::with_state_backend).
+/// Implementations must update a bucket atomically, e.g. with a
+/// compare-and-swap or a server-side script, since requests from every
+/// thread, and with a shared store every process, race to update it.
+pub trait StateBackend: Debug + Send + Sync {
+    /// Try to admit a request to the bucket `key` at `now`.
+    ///
+    /// Admitting the request moves the bucket's TAT to `emission` after the
+    /// later of `now` and its current TAT. The request is admitted if that
+    /// new TAT is at most `limit` after `now`; otherwise the TAT is left
+    /// unchanged and the time until the request could be admitted is
+    /// returned. `emission` already accounts for the request's weight and is
+    /// at most `limit`.
+    ///
+    /// `now` is read from the middleware's [`Clock`](crate::Clock), measured
+    /// from its origin. The default clock starts when the middleware is
+    /// built, so backends shared between processes need a common time base,
+    /// such as a clock based on system time or the store's own clock.
+    ///
+    /// Keys identify a route by its filters, a limit by its position in the
+    /// route, and a per-key bucket by its key, so they are the same for the
+    /// same configuration in every process.
+    ///
+    /// # Errors
+    ///
+    /// Returns the time to wait before the request could be admitted.
+    fn try_acquire(
+        &self,
+        key: &str,
+        now: Duration,
+        emission: Duration,
+        limit: Duration,
+    ) -> Result<(), Duration>;
+
+    /// Give back `emission` of the bucket `key`, taken at or before `now` by
+    /// a request that is then not sent after all.
+    ///
+    /// Called by
+    /// [`try_acquire_n`](crate::RateLimitMiddleware::try_acquire_n) and
+    /// [`acquire_at`](crate::RateLimitMiddleware::acquire_at) for limits they
+    /// reserved before another limit turned out to be short, with the same
+    /// `emission` and `limit` as the admission. The TAT should not move below
+    /// `now`, so that a refund never creates more than a full burst.
+    ///
+    /// The default does nothing, leaving the quota taken.
+    fn refund(&self, key: &str, now: Duration, emission: Duration, limit: Duration) {
+        let _ = (key, now, emission, limit);
+    }
+}
+
+/// A [`StateBackend`] keeping each bucket in process memory, like the
+/// middleware does without a backend.
+#[derive(Debug, Default)]
+pub struct InMemoryBackend {
+    state: DashMap<String, GcraState>,
+}
+
+impl InMemoryBackend {
+    /// Create a backend without any buckets.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of buckets created so far.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.state.len()
+    }
+
+    /// Returns `true` if no bucket was created yet.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.state.is_empty()
+    }
+}
+
+impl StateBackend for InMemoryBackend {
+    fn try_acquire(
+        &self,
+        key: &str,
+        now: Duration,
+        emission: Duration,
+        limit: Duration,
+    ) -> Result<(), Duration> {
+        let tick_nanos = tick_nanos(limit);
+        let ticks = |duration: Duration| (duration.as_nanos() / u128::from(tick_nanos)) as u64;
+
+        let state = match self.state.get(key) {
+            Some(state) => state,
+            None => self
+                .state
+                .entry(key.to_owned())
+                .or_insert_with(GcraState::new)
+                .downgrade(),
+        };
+        state
+            .try_acquire_weighted(ticks(now), ticks(emission), ticks(limit), 1)
+            .map_err(|wait| wait * tick_nanos)
+    }
+
+    fn refund(&self, key: &str, now: Duration, emission: Duration, limit: Duration) {
+        let tick_nanos = tick_nanos(limit);
+        let ticks = |duration: Duration| (duration.as_nanos() / u128::from(tick_nanos)) as u64;
+        if let Some(state) = self.state.get(key) {
+            state.refund(ticks(now), ticks(emission), ticks(limit), 1);
+        }
+    }
+}
+
+/// The length of the ticks to keep the buckets of `limit` in: nanoseconds,
+/// or microseconds where nanoseconds would overflow, like the middleware's
+/// own state.
+fn tick_nanos(limit: Duration) -> u32 {
+    if limit.as_nanos() <= u128::from(u64::MAX / 2) {
+        1
+    } else {
+        1_000
+    }
+}
+
+/// Try to admit a request of `weight` to the bucket of `limit` at `key`
+/// through `backend`.
+pub(crate) fn try_acquire(
+    backend: &dyn StateBackend,
+    table: &RouteTable,
+    key: &RouteKey,
+    now_nanos: u64,
+    limit: &RateLimit,
+    weight: u32,
+) -> Result<(), Duration> {
+    let limit_window = limit.burst_window();
+    let emission = limit.emission_of(u64::from(weight)).min(limit_window);
+    backend.try_acquire(
+        &bucket_key(table, key),
+        Duration::from_nanos(now_nanos),
+        emission,
+        limit_window,
+    )
+}
+
+/// Give back what [`try_acquire`] took for a request of `weight` from the
+/// bucket of `limit` at `key` through `backend`.
+pub(crate) fn refund(
+    backend: &dyn StateBackend,
+    table: &RouteTable,
+    key: &RouteKey,
+    now_nanos: u64,
+    limit: &RateLimit,
+    weight: u32,
+) {
+    let limit_window = limit.burst_window();
+    let emission = limit.emission_of(u64::from(weight)).min(limit_window);
+    backend.refund(
+        &bucket_key(table, key),
+        Duration::from_nanos(now_nanos),
+        emission,
+        limit_window,
+    );
+}
+
+/// The key of a bucket in a [`StateBackend`]: the identity of the routes it
+/// belongs to, the index of its limit, and its per-key bucket, if any.
+fn bucket_key(table: &RouteTable, key: &RouteKey) -> String {
+    let scope = scope_identity(table, &key.scope).unwrap_or_default();
+    match &key.key {
+        Some(bucket) => format!("{scope} #{} [{bucket}]", key.limit_index),
+        None => format!("{scope} #{}", key.limit_index),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestClock;
+    use crate::{Algorithm, RateLimitBuilder, RateLimitMiddleware, ThrottleBehavior};
+    use http::Method;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn test_in_memory_backend_admits_burst_then_waits() {
+        let backend = InMemoryBackend::new();
+        let emission = Duration::from_millis(100);
+        let limit = Duration::from_secs(1);
+
+        for _ in 0..10 {
+            assert!(
+                backend
+                    .try_acquire("a", Duration::ZERO, emission, limit)
+                    .is_ok()
+            );
+        }
+        assert_eq!(
+            backend.try_acquire("a", Duration::ZERO, emission, limit),
+            Err(emission)
+        );
+        // Buckets are independent
+        assert!(
+            backend
+                .try_acquire("b", Duration::ZERO, emission, limit)
+                .is_ok()
+        );
+        assert_eq!(backend.len(), 2);
+
+        assert!(backend.try_acquire("a", emission, emission, limit).is_ok());
+    }
+
+    fn routes(builder: RateLimitBuilder) -> RateLimitBuilder {
+        builder.jitter(0.0).host("api.example.com", |host| {
+            host.route(|r| {
+                r.limit(20, Duration::from_secs(10))
+                    .on_limit(ThrottleBehavior::Error)
+            })
+            .route(|r| {
+                r.path("/order")
+                    .weight(3)
+                    .burst_limit(10, Duration::from_secs(1), 6)
+                    .on_limit(ThrottleBehavior::Error)
+            })
+        })
+    }
+
+    #[tokio::test]
+    async fn test_in_memory_backend_matches_local_state() {
+        let local_clock = Arc::new(TestClock::new());
+        let local = routes(RateLimitMiddleware::builder())
+            .with_clock(local_clock.clone())
+            .build();
+        let shared_clock = Arc::new(TestClock::new());
+        let backend = Arc::new(InMemoryBackend::new());
+        let shared = routes(RateLimitMiddleware::builder())
+            .with_clock(shared_clock.clone())
+            .with_state_backend(backend.clone())
+            .build();
+
+        for step in 0..60_u64 {
+            let path = if step % 3 == 0 { "/book" } else { "/order" };
+            let advance = Duration::from_millis(step * 37 % 250);
+            local_clock.advance(advance);
+            shared_clock.advance(advance);
+
+            let retry_after = |result: Result<(), crate::RateLimitError>| {
+                result.map_err(|err| match err {
+                    crate::RateLimitError::RateLimited {
+                        limit_index,
+                        retry_after,
+                        ..
+                    } => (limit_index, retry_after),
+                    err => panic!("unexpected error: {err}"),
+                })
+            };
+            assert_eq!(
+                retry_after(local.acquire("api.example.com", &Method::POST, path).await),
+                retry_after(shared.acquire("api.example.com", &Method::POST, path).await),
+                "step {step}"
+            );
+        }
+        assert_eq!(shared.state_count(), 0);
+        assert_eq!(backend.len(), 2);
+    }
+
+    /// Records the keys it is asked about.
+    #[derive(Debug, Default)]
+    struct RecordingBackend {
+        inner: InMemoryBackend,
+        keys: Mutex<Vec<String>>,
+    }
+
+    impl StateBackend for RecordingBackend {
+        fn try_acquire(
+            &self,
+            key: &str,
+            now: Duration,
+            emission: Duration,
+            limit: Duration,
+        ) -> Result<(), Duration> {
+            self.keys.lock().unwrap().push(key.to_owned());
+            self.inner.try_acquire(key, now, emission, limit)
+        }
+    }
+
+    #[test]
+    fn test_try_acquire_n_refunds_backend_limits() {
+        let backend = Arc::new(InMemoryBackend::new());
+        let middleware = RateLimitMiddleware::builder()
+            .with_clock(Arc::new(TestClock::new()))
+            .with_state_backend(backend.clone())
+            .route(|r| {
+                r.limit(4, Duration::from_secs(1))
+                    .limit(3, Duration::from_secs(10))
+            })
+            .build();
+        let try_acquire_n = |n| middleware.try_acquire_n("api.example.com", &Method::GET, "/", n);
+
+        assert!(try_acquire_n(2).is_ok());
+        // The first limit has room for two more, the second only for one
+        assert!(try_acquire_n(2).is_err());
+        // So the first gave its two back
+        assert!(try_acquire_n(1).is_ok());
+        assert_eq!(backend.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_custom_backend_receives_gcra_buckets() {
+        let backend = Arc::new(RecordingBackend::default());
+        let middleware = RateLimitMiddleware::builder()
+            .with_state_backend(backend.clone())
+            .host("api.example.com", |host| {
+                host.route(|r| {
+                    r.path("/book")
+                        .key_by_header("x-user")
+                        .limit(10, Duration::from_secs(1))
+                })
+                .route(|r| {
+                    r.path("/order")
+                        .algorithm(Algorithm::TokenBucket)
+                        .limit(10, Duration::from_secs(1))
+                })
+            })
+            .build();
+
+        middleware
+            .acquire("api.example.com", &Method::GET, "/book")
+            .await
+            .unwrap();
+        middleware
+            .acquire("api.example.com", &Method::POST, "/order")
+            .await
+            .unwrap();
+
+        // Only the GCRA route goes through the backend, by route identity
+        let keys = backend.keys.lock().unwrap().clone();
+        assert_eq!(keys.len(), 1);
+        assert!(keys[0].contains("/book"), "{keys:?}");
+        assert!(keys[0].ends_with(" #0"), "{keys:?}");
+        assert_eq!(middleware.state_count(), 1);
+    }
+}
@@ -0,0 +1,64 @@
+//! Internals exposed to the benchmarks in `benches/`.
+//!
+//! Enabled by the `bench` feature. Not part of the public API and exempt from
+//! semver guarantees.
+
+use http::{HeaderMap, Method};
+use std::future::Future;
+use std::pin::pin;
+use std::task::{Context, Poll, Waker};
+
+use crate::limiter::LimiterState;
+use crate::middleware::RateLimitMiddleware;
+use crate::request::RequestParts;
+use crate::types::{Algorithm, Priority, RateLimit};
+
+/// The state of a single bucket.
+#[derive(Debug)]
+pub struct Bucket {
+    state: LimiterState,
+    limit: RateLimit,
+}
+
+impl Bucket {
+    /// Create a bucket enforcing `limit` with `algorithm`, with the full burst
+    /// available.
+    #[must_use]
+    pub fn new(algorithm: Algorithm, limit: RateLimit) -> Self {
+        Self {
+            state: LimiterState::new(algorithm),
+            limit,
+        }
+    }
+
+    /// Try to admit a unit-weight request at `now_nanos`.
+    pub fn try_acquire(&self, now_nanos: u64) -> bool {
+        self.state
+            .try_acquire_weighted(now_nanos, &self.limit, 1)
+            .is_ok()
+    }
+}
+
+/// Run the middleware's route matching and limit checks for a request to
+/// `host`, `method`, and `path`, without sending anything.
+///
+/// Returns `true` if the request was admitted without waiting.
+pub fn check(middleware: &RateLimitMiddleware, host: &str, method: &Method, path: &str) -> bool {
+    let headers = HeaderMap::new();
+    let parts = RequestParts {
+        scheme: Some("https"),
+        host: Some(host),
+        method,
+        path,
+        query: None,
+        headers: &headers,
+        request: None,
+    };
+    let table = middleware.table.load();
+    let check = pin!(middleware.check_and_apply_limits(&table, &parts, None, Priority::default()));
+    // A request that is admitted right away completes on the first poll
+    matches!(
+        check.poll(&mut Context::from_waker(Waker::noop())),
+        Poll::Ready(Ok(_))
+    )
+}
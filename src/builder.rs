@@ -1,17 +1,51 @@
 //! Builder API for configuring the rate limiting middleware.
 
-use dashmap::DashMap;
-use http::Method;
+use arc_swap::ArcSwap;
+use http::{HeaderName, Method, StatusCode};
+use reqwest::Request;
+use std::collections::{BTreeMap, BTreeSet};
 use std::sync::Arc;
-use std::time::{Duration, Instant};
+use std::sync::atomic::AtomicBool;
+use std::time::Duration;
 
-use crate::middleware::RateLimitMiddleware;
-use crate::types::{RateLimit, Route, ThrottleBehavior};
+use crate::backend::StateBackend;
+use crate::clock::{Clock, MonotonicClock};
+use crate::error::BuildError;
+use crate::events::{ThrottleCallback, ThrottleEvent};
+use crate::jitter::{JitterSource, RandomJitter};
+use crate::match_cache::DEFAULT_MATCH_CACHE_CAPACITY;
+use crate::middleware::{DEFAULT_CLEANUP_STALE_AFTER, DEFAULT_JITTER, RateLimitMiddleware};
+use crate::pattern::PathPattern;
+use crate::request::unbracket;
+use crate::route_table::{self, RouteTable};
+use crate::types::{
+    Adaptive, Algorithm, Cooldown, KeyFn, LimitUnit, MatchMode, RateLimit, Route, StatusPredicate,
+    ThrottleBehavior, UnmatchedBehavior,
+};
+use crate::warning::{self, ConfigWarning};
 
 /// Builder for configuring a [`RateLimitMiddleware`].
 #[derive(Debug, Default, Clone)]
 pub struct RateLimitBuilder {
     pub(crate) routes: Vec<Route>,
+    pools: BTreeMap<String, RateLimit>,
+    clock: Option<Arc<dyn Clock>>,
+    respect_retry_after: bool,
+    respect_ratelimit_headers: bool,
+    auto_cleanup: Option<Duration>,
+    on_throttle: Option<ThrottleCallback>,
+    jitter: Option<f64>,
+    jitter_source: Option<Arc<dyn JitterSource>>,
+    state_backend: Option<Arc<dyn StateBackend>>,
+    bypass_methods: Vec<Method>,
+    strict: bool,
+    deny_warnings: bool,
+    match_mode: MatchMode,
+    unmatched: UnmatchedBehavior,
+    max_total_delay: Option<Duration>,
+    match_cache_capacity: Option<usize>,
+    state_capacity: usize,
+    cleanup_stale_after: Option<u32>,
 }
 
 impl RateLimitBuilder {
@@ -23,9 +57,10 @@ impl RateLimitBuilder {
 
     /// Add a route using a closure-based configuration.
     ///
-    /// # Panics
-    ///
-    /// Panics if no limits are configured via `.limit()`.
+    /// The route is checked when the middleware is built: if it is invalid,
+    /// e.g. if no limits are configured via `.limit()`,
+    /// [`build`](Self::build) panics and [`try_build`](Self::try_build)
+    /// returns an error.
     ///
     /// # Example
     ///
@@ -45,7 +80,7 @@ impl RateLimitBuilder {
     {
         let builder = RouteBuilder::new();
         let configured = configure(builder);
-        self.routes.push(configured.into_route());
+        self.routes.push(configured.into_unchecked_route());
         self
     }
 
@@ -86,267 +121,3026 @@ impl RateLimitBuilder {
     where
         F: FnOnce(HostBuilder) -> HostBuilder,
     {
-        let host_str = host.into();
-        let host_builder = HostBuilder::new(host_str);
+        let host_builder = HostBuilder::new(unbracket(&host.into()).to_owned());
         let configured = configure(host_builder);
         self.routes.extend(configured.routes);
         self
     }
 
-    /// Add a pre-configured route.
-    #[must_use]
-    pub fn add_route(mut self, route: Route) -> Self {
-        self.routes.push(route);
-        self
-    }
-
-    /// Build the middleware.
-    ///
-    /// # Warnings
+    /// Create a builder from a deserialized configuration.
     ///
-    /// If the `tracing` feature is enabled, this method will emit a warning
-    /// when catch-all routes (routes with no host, method, or path filters)
-    /// are followed by more specific routes. This pattern may cause unexpected
-    /// behavior since all matching routes' limits are applied.
+    /// The routes are checked when the middleware is built, with the same
+    /// checks the route builders apply: every route needs at least one limit,
+    /// limits need non-zero requests and windows, and a route cannot set both
+    /// a path prefix and a path pattern. Build configurations from untrusted
+    /// sources with [`try_build`](Self::try_build) to get these as errors.
+    #[cfg(feature = "serde")]
     #[must_use]
-    pub fn build(self) -> RateLimitMiddleware {
-        #[cfg(feature = "tracing")]
-        self.warn_catch_all_route_order();
-
-        RateLimitMiddleware {
-            routes: Arc::new(self.routes),
-            state: Arc::new(DashMap::new()),
-            start_instant: Instant::now(),
-        }
-    }
-
-    /// Emit a warning if catch-all routes precede more specific routes.
-    #[cfg(feature = "tracing")]
-    fn warn_catch_all_route_order(&self) {
-        // Collect indices of all catch-all routes
-        let catch_all_indices: Vec<usize> = self
-            .routes
-            .iter()
-            .enumerate()
-            .filter(|(_, route)| route.is_catch_all())
-            .map(|(i, _)| i)
-            .collect();
-
-        // For each catch-all, warn about specific routes that follow it
-        for &catch_all_index in &catch_all_indices {
-            // Find the first specific route after this catch-all
-            if let Some((specific_index, _)) = self
-                .routes
-                .iter()
-                .enumerate()
-                .skip(catch_all_index + 1)
-                .find(|(_, route)| !route.is_catch_all())
-            {
-                tracing::warn!(
-                    catch_all_route_index = catch_all_index,
-                    specific_route_index = specific_index,
-                    "Catch-all route (index {}) precedes more specific route (index {}). \
-                     All matching routes' limits are applied, so the catch-all will affect \
-                     requests intended for the specific route. Consider reordering routes \
-                     or using host-scoped builders.",
-                    catch_all_index,
-                    specific_index
-                );
-            }
+    pub fn from_config(config: crate::config::RateLimitConfig) -> Self {
+        Self {
+            pools: config.pools,
+            ..Self::from_routes(config.routes)
         }
     }
-}
-
-/// Builder for configuring routes within a specific host scope.
-///
-/// Created by [`RateLimitBuilder::host`]. All routes created within this builder
-/// will automatically have the host set.
-#[derive(Debug, Clone)]
-pub struct HostBuilder {
-    host: String,
-    routes: Vec<Route>,
-}
 
-impl HostBuilder {
-    fn new(host: String) -> Self {
+    /// Create a builder from already-constructed routes, checked when the
+    /// middleware is built.
+    #[must_use]
+    pub(crate) fn from_routes(routes: Vec<Route>) -> Self {
         Self {
-            host,
-            routes: Vec::new(),
+            routes,
+            ..Self::default()
         }
     }
 
-    /// Add a route within this host using a closure-based configuration.
+    /// Define a quota pool named `name`, allowing `requests` per `window`
+    /// across every route that [draws from it](RouteBuilder::draws_from).
     ///
-    /// The host is automatically set for each route.
+    /// Pools model provider-wide quotas shared by otherwise unrelated routes,
+    /// where each route may cost a different amount: a request to a route
+    /// drawing from a pool is only admitted if both the route's own limits and
+    /// the pool have room. Pools use [`Algorithm::Gcra`] and are checked after
+    /// the route's own limits; a request matching several routes that draw
+    /// from the same pool is counted once, at the cost of the first. Defining
+    /// a pool again replaces its limit.
     ///
-    /// # Panics
+    /// The limit is checked when the middleware is built, like route limits.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use route_ratelimit::RateLimitMiddleware;
+    /// use std::time::Duration;
     ///
-    /// Panics if no limits are configured via `.limit()`.
+    /// // Searches cost 5 units of the provider's quota, lookups 1
+    /// let middleware = RateLimitMiddleware::builder()
+    ///     .pool("provider", 1000, Duration::from_secs(60))
+    ///     .route(|r| {
+    ///         r.path("/search")
+    ///             .draws_from("provider", 5)
+    ///             .limit(10, Duration::from_secs(1))
+    ///     })
+    ///     .route(|r| {
+    ///         r.path("/lookup")
+    ///             .draws_from("provider", 1)
+    ///             .limit(50, Duration::from_secs(1))
+    ///     })
+    ///     .build();
+    /// ```
     #[must_use]
-    pub fn route<F>(mut self, configure: F) -> Self
-    where
-        F: FnOnce(HostRouteBuilder) -> HostRouteBuilder,
-    {
-        let builder = HostRouteBuilder::new();
-        let configured = configure(builder);
-        assert!(
-            !configured.limits.is_empty(),
-            "route must have at least one limit configured via .limit()"
-        );
-        let route = Route {
-            host: Some(self.host.clone()),
-            method: configured.method,
-            path_prefix: configured.path_prefix,
-            limits: configured.limits,
-            on_limit: configured.on_limit,
-        };
-        self.routes.push(route);
+    pub fn pool(mut self, name: impl Into<String>, requests: u32, window: Duration) -> Self {
+        self.pools
+            .insert(name.into(), RateLimit::unchecked(requests, window, None));
         self
     }
-}
 
-/// Builder for configuring a single route within a host scope.
-///
-/// Created by [`HostBuilder::route`] closure. Configure the route and the
-/// closure will automatically add it to the host.
-#[derive(Debug, Default, Clone)]
-pub struct HostRouteBuilder {
-    method: Option<Method>,
-    path_prefix: String,
-    limits: Vec<RateLimit>,
-    on_limit: ThrottleBehavior,
-}
-
-impl HostRouteBuilder {
-    fn new() -> Self {
-        Self::default()
+    /// Use a custom [`Clock`] as the time source.
+    ///
+    /// Defaults to a [`MonotonicClock`] created when [`build`](Self::build) is
+    /// called. This is mainly useful in tests, together with
+    /// [`TestClock`](crate::testing::TestClock).
+    #[must_use]
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = Some(clock);
+        self
     }
 
-    /// Set the HTTP method to match.
+    /// Synchronize with server-side rate limiting via `Retry-After` headers.
+    ///
+    /// When enabled, a `429 Too Many Requests` or `503 Service Unavailable`
+    /// response carrying a `Retry-After` header pushes every limit that the
+    /// request matched forward, so that subsequent requests are held back until
+    /// the server-advised time. Both the delay-seconds and HTTP-date forms of the
+    /// header are supported. Responses to requests that matched no route are
+    /// ignored.
+    ///
+    /// Disabled by default.
     #[must_use]
-    pub fn method(mut self, method: Method) -> Self {
-        self.method = Some(method);
+    pub fn respect_retry_after(mut self, enabled: bool) -> Self {
+        self.respect_retry_after = enabled;
         self
     }
 
-    /// Set the path prefix to match (e.g., "/order").
+    /// Stop early when the server advertises its quota is used up via
+    /// `X-RateLimit-Remaining` and `X-RateLimit-Reset` headers.
+    ///
+    /// When enabled, a response of any status with `X-RateLimit-Remaining: 0`
+    /// pushes every limit that the request matched forward to the time given
+    /// by `X-RateLimit-Reset`, instead of waiting to be told by a `429`. The
+    /// reset may be a number of seconds, possibly fractional, or a Unix
+    /// timestamp in seconds. Missing or malformed headers are ignored, as are
+    /// responses to requests that matched no route.
+    ///
+    /// Disabled by default.
     #[must_use]
-    pub fn path(mut self, path_prefix: impl Into<String>) -> Self {
-        self.path_prefix = path_prefix.into();
+    pub fn respect_ratelimit_headers(mut self, enabled: bool) -> Self {
+        self.respect_ratelimit_headers = enabled;
         self
     }
 
-    /// Add a rate limit.
+    /// Keep the state of GCRA limits in a [`StateBackend`], such as an
+    /// external store shared by several processes.
+    ///
+    /// Every request to a route using [`Algorithm::Gcra`] is admitted through
+    /// the backend; other algorithms keep their state in the middleware.
+    /// Features that read or adjust the state after the fact only see the
+    /// middleware's own state, so they do not apply to backend buckets:
+    /// refunds by [`count_if`](RouteBuilder::count_if), deferral by
+    /// [`respect_retry_after`](Self::respect_retry_after) and
+    /// [`respect_ratelimit_headers`](Self::respect_ratelimit_headers),
+    /// introspection such as
+    /// [`quota_remaining`](RateLimitMiddleware::quota_remaining), and state
+    /// snapshots and cleanup.
+    ///
+    /// By default, all state is kept in the middleware.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use route_ratelimit::{InMemoryBackend, RateLimitMiddleware};
+    /// use std::sync::Arc;
+    /// use std::time::Duration;
+    ///
+    /// let middleware = RateLimitMiddleware::builder()
+    ///     .with_state_backend(Arc::new(InMemoryBackend::new()))
+    ///     .route(|r| r.limit(100, Duration::from_secs(10)))
+    ///     .build();
+    /// ```
     #[must_use]
-    pub fn limit(mut self, requests: u32, window: Duration) -> Self {
-        self.limits.push(RateLimit::new(requests, window));
+    pub fn with_state_backend(mut self, backend: Arc<dyn StateBackend>) -> Self {
+        self.state_backend = Some(backend);
         self
     }
 
-    /// Set the behavior when rate limit is exceeded.
+    /// Automatically remove stale state entries every `interval`.
+    ///
+    /// On [`build`](Self::build), a background task is spawned that calls
+    /// [`RateLimitMiddleware::cleanup`] at this cadence. The task stops once the
+    /// last clone of the middleware has been dropped.
+    ///
+    /// **Requires a Tokio runtime**: `build()` panics if called outside of one
+    /// when this option is set.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `interval` is zero.
     #[must_use]
-    pub fn on_limit(mut self, behavior: ThrottleBehavior) -> Self {
-        self.on_limit = behavior;
+    pub fn auto_cleanup(mut self, interval: Duration) -> Self {
+        assert!(
+            !interval.is_zero(),
+            "cleanup interval must be greater than 0"
+        );
+        self.auto_cleanup = Some(interval);
         self
     }
-}
-
-/// Builder for configuring a single route (without host scope).
-///
-/// Created by [`RateLimitBuilder::route`] closure. Configure the route and
-/// the closure will automatically add it to the middleware.
-#[derive(Debug, Default, Clone)]
-pub struct RouteBuilder {
-    host: Option<String>,
-    method: Option<Method>,
-    path_prefix: String,
-    limits: Vec<RateLimit>,
-    on_limit: ThrottleBehavior,
-}
 
-impl RouteBuilder {
-    fn new() -> Self {
-        Self::default()
+    /// Set how many windows after fully recovering a state entry becomes stale.
+    ///
+    /// [`RateLimitMiddleware::cleanup`] removes entries that fully recovered
+    /// their burst capacity more than `multiplier` times their limit's window
+    /// ago. A removed entry starts over with a full burst, so a larger
+    /// multiplier keeps idle per-key buckets around longer at the cost of
+    /// memory.
+    ///
+    /// Defaults to `2`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `multiplier` is zero.
+    #[must_use]
+    pub fn cleanup_stale_after(mut self, multiplier: u32) -> Self {
+        assert!(
+            multiplier >= 1,
+            "cleanup staleness multiplier must be at least 1"
+        );
+        self.cleanup_stale_after = Some(multiplier);
+        self
     }
 
-    fn into_route(self) -> Route {
+    /// Set the maximum random jitter added to delays, as a fraction of the wait.
+    ///
+    /// Delayed requests sleep for their required wait plus a random extra of up
+    /// to `fraction` of that wait, so that requests released together do not
+    /// all retry at the same instant. The `retry_after` reported by rejected
+    /// requests is jittered the same way, so that callers retrying on it
+    /// spread out too. `0.0` disables jitter, making delays and reported
+    /// waits exactly as long as the limit requires.
+    ///
+    /// Defaults to `0.5`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `fraction` is not within `0.0..=1.0`.
+    #[must_use]
+    pub fn jitter(mut self, fraction: f64) -> Self {
         assert!(
-            !self.limits.is_empty(),
-            "route must have at least one limit configured via .limit()"
+            (0.0..=1.0).contains(&fraction),
+            "jitter fraction must be between 0.0 and 1.0"
         );
-        Route {
-            host: self.host,
-            method: self.method,
-            path_prefix: self.path_prefix,
-            limits: self.limits,
-            on_limit: self.on_limit,
-        }
+        self.jitter = Some(fraction);
+        self
     }
 
-    /// Set the host to match (e.g., "api.example.com").
+    /// Use a custom [`JitterSource`] for the random part of delays.
     ///
-    /// Note: Consider using [`RateLimitBuilder::host`] instead if you're
-    /// configuring multiple routes for the same host.
+    /// Defaults to [`RandomJitter`]. This is mainly useful in tests, together
+    /// with [`FixedJitter`](crate::testing::FixedJitter), to make delays exact
+    /// while keeping jitter enabled.
     #[must_use]
-    pub fn host(mut self, host: impl Into<String>) -> Self {
-        self.host = Some(host.into());
+    pub fn with_jitter_source(mut self, source: Arc<dyn JitterSource>) -> Self {
+        self.jitter_source = Some(source);
         self
     }
 
-    /// Set the HTTP method to match.
+    /// Cap the total time a single request may be delayed by rate limits.
+    ///
+    /// A request matching several limits can be delayed once for each of them.
+    /// Once the delays a request has slept through plus the next required wait
+    /// would exceed `budget`, it fails with [`RateLimitError::RateLimited`]
+    /// instead, whatever the route's [`ThrottleBehavior`]. Time spent queued
+    /// behind other delayed requests counts towards the budget too.
+    ///
+    /// Defaults to no limit. A request with its own timeout, set with
+    /// [`reqwest::RequestBuilder::timeout`], is never delayed longer than that
+    /// timeout either, so that it is left for the request itself.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `budget` is zero.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use route_ratelimit::RateLimitMiddleware;
+    /// use std::time::Duration;
+    ///
+    /// let middleware = RateLimitMiddleware::builder()
+    ///     .max_total_delay(Duration::from_secs(2))
+    ///     .route(|r| {
+    ///         r.limit(10, Duration::from_secs(1))
+    ///             .limit(100, Duration::from_secs(60))
+    ///     })
+    ///     .build();
+    /// ```
+    ///
+    /// [`RateLimitError::RateLimited`]: crate::RateLimitError::RateLimited
     #[must_use]
-    pub fn method(mut self, method: Method) -> Self {
-        self.method = Some(method);
+    pub fn max_total_delay(mut self, budget: Duration) -> Self {
+        assert!(!budget.is_zero(), "max_total_delay must be greater than 0");
+        self.max_total_delay = Some(budget);
         self
     }
 
-    /// Set the path prefix to match (e.g., "/order").
+    /// Set how many distinct requests the middleware remembers the matching
+    /// routes of.
+    ///
+    /// Requests with the same scheme, host, method, path, and query match the
+    /// same routes, so repeating one skips comparing it against every route.
+    /// Once the cache is full, each new request evicts one entry that has not
+    /// been hit recently. `0` disables the cache, which avoids its memory use
+    /// when requests rarely repeat, such as paths that embed ids.
+    ///
+    /// Defaults to `1024`.
     #[must_use]
-    pub fn path(mut self, path_prefix: impl Into<String>) -> Self {
-        self.path_prefix = path_prefix.into();
+    pub fn match_cache_capacity(mut self, capacity: usize) -> Self {
+        self.match_cache_capacity = Some(capacity);
         self
     }
 
-    /// Add a rate limit.
+    /// Size the rate limit state for `capacity` buckets up front.
+    ///
+    /// Each route limit has a bucket, and routes keyed by a header have one per
+    /// distinct value, so services expecting many keys can avoid the map
+    /// growing repeatedly as they first appear. This is only a hint: the state
+    /// still grows beyond it as needed. The same capacity is reserved again
+    /// whenever the routes are [updated](RateLimitMiddleware::update_routes).
     #[must_use]
-    pub fn limit(mut self, requests: u32, window: Duration) -> Self {
-        self.limits.push(RateLimit::new(requests, window));
+    pub fn state_capacity(mut self, capacity: usize) -> Self {
+        self.state_capacity = capacity;
         self
     }
 
-    /// Set the behavior when rate limit is exceeded.
+    /// Exempt requests with any of `methods` from rate limiting entirely.
+    ///
+    /// Bypassed requests skip every route, including concurrency limits, and
+    /// consume no quota. Useful for CORS preflight `OPTIONS` requests, which
+    /// would otherwise count against method-less limits meant for real calls.
+    ///
+    /// Defaults to no methods. Calling this again replaces the previous set.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use http::Method;
+    /// use route_ratelimit::RateLimitMiddleware;
+    /// use std::time::Duration;
+    ///
+    /// let middleware = RateLimitMiddleware::builder()
+    ///     .bypass_methods(&[Method::OPTIONS])
+    ///     .route(|r| r.limit(100, Duration::from_secs(10)))
+    ///     .build();
+    /// ```
     #[must_use]
-    pub fn on_limit(mut self, behavior: ThrottleBehavior) -> Self {
-        self.on_limit = behavior;
+    pub fn bypass_methods(mut self, methods: &[Method]) -> Self {
+        self.bypass_methods = methods.to_vec();
         self
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    /// Require routes that match every request to be declared with
+    /// [`RouteBuilder::catch_all`].
+    ///
+    /// A route whose filters were forgotten silently limits all traffic. In
+    /// strict mode, [`build`](Self::build) panics on such a route unless it was
+    /// explicitly marked as a catch-all, and [`try_build`](Self::try_build)
+    /// returns [`BuildError::UndeclaredCatchAll`].
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use route_ratelimit::RateLimitMiddleware;
+    /// use std::time::Duration;
+    ///
+    /// let middleware = RateLimitMiddleware::builder()
+    ///     .strict()
+    ///     .route(|r| r.path("/order").limit(10, Duration::from_secs(1)))
+    ///     .route(|r| r.catch_all().limit(100, Duration::from_secs(10)))
+    ///     .build();
+    /// ```
+    #[must_use]
+    pub fn strict(mut self) -> Self {
+        self.strict = true;
+        self
+    }
 
-    #[test]
-    fn test_builder_api() {
-        let middleware = RateLimitMiddleware::builder()
-            .route(|r| {
-                r.host("api.example.com")
-                    .method(Method::POST)
-                    .path("/order")
-                    .limit(100, Duration::from_secs(10))
-                    .limit(1000, Duration::from_secs(60))
-                    .on_limit(ThrottleBehavior::Delay)
-            })
-            .route(|r| {
-                r.path("/data")
-                    .limit(50, Duration::from_secs(10))
+    /// Treat the warnings of [`validate`](Self::validate) as errors.
+    ///
+    /// [`try_build`](Self::try_build) then returns
+    /// [`BuildError::Warnings`] for a configuration with any, and
+    /// [`build`](Self::build) panics.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use route_ratelimit::{BuildError, RateLimitMiddleware};
+    /// use std::time::Duration;
+    ///
+    /// let result = RateLimitMiddleware::builder()
+    ///     .deny_warnings()
+    ///     .route(|r| r.path("/book").limit(100, Duration::from_secs(10)))
+    ///     .route(|r| r.path("/book").limit(50, Duration::from_secs(1)))
+    ///     .try_build();
+    /// assert!(matches!(result, Err(BuildError::Warnings(_))));
+    /// ```
+    #[must_use]
+    pub fn deny_warnings(mut self) -> Self {
+        self.deny_warnings = true;
+        self
+    }
+
+    /// Set which of the routes matching a request apply to it.
+    ///
+    /// By default, every matching route applies, so a request to `/book`
+    /// counts against both a `/book` route and a catch-all route. With
+    /// [`MatchMode::MostSpecific`], only the most specific matching route
+    /// applies; see its documentation for how routes are ranked. With
+    /// [`MatchMode::FirstMatch`], only the first matching route applies.
+    ///
+    /// Defaults to [`MatchMode::All`].
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use route_ratelimit::{MatchMode, RateLimitMiddleware};
+    /// use std::time::Duration;
+    ///
+    /// // Requests to `/book` only count against the `/book` limit
+    /// let middleware = RateLimitMiddleware::builder()
+    ///     .match_mode(MatchMode::MostSpecific)
+    ///     .host("api.example.com", |host| {
+    ///         host.route(|r| r.limit(100, Duration::from_secs(10)))
+    ///             .route(|r| r.path("/book").limit(500, Duration::from_secs(10)))
+    ///     })
+    ///     .build();
+    /// ```
+    #[must_use]
+    pub fn match_mode(mut self, mode: MatchMode) -> Self {
+        self.match_mode = mode;
+        self
+    }
+
+    /// Set what happens to requests that match no route.
+    ///
+    /// By default they are sent without any limit. With
+    /// [`UnmatchedBehavior::Deny`], they fail with
+    /// [`RateLimitError::Unmatched`](crate::RateLimitError::Unmatched)
+    /// instead, so that every endpoint a client calls must be configured.
+    /// Methods exempted with [`bypass_methods`](Self::bypass_methods) and a
+    /// [disabled](RateLimitMiddleware::set_enabled) middleware still let
+    /// every request through.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use route_ratelimit::{RateLimitMiddleware, UnmatchedBehavior};
+    /// use std::time::Duration;
+    ///
+    /// let middleware = RateLimitMiddleware::builder()
+    ///     .unmatched(UnmatchedBehavior::Deny)
+    ///     .host("api.example.com", |host| {
+    ///         host.route(|r| r.path("/book").limit(100, Duration::from_secs(10)))
+    ///     })
+    ///     .build();
+    ///
+    /// let req = reqwest::Client::new()
+    ///     .get("https://api.example.com/orders")
+    ///     .build()
+    ///     .unwrap();
+    /// assert!(!middleware.would_allow(&req));
+    /// ```
+    #[must_use]
+    pub fn unmatched(mut self, behavior: UnmatchedBehavior) -> Self {
+        self.unmatched = behavior;
+        self
+    }
+
+    /// Register a callback invoked whenever a request is throttled.
+    ///
+    /// The callback receives a [`ThrottleEvent`] describing the request, the
+    /// limit that was exceeded, the required wait, and whether the request was
+    /// delayed or rejected. A delayed request may trigger the callback again if
+    /// it is still limited after waiting.
+    ///
+    /// The callback runs inline on the request path, so it should be cheap.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use route_ratelimit::RateLimitMiddleware;
+    /// use std::sync::Arc;
+    /// use std::sync::atomic::{AtomicUsize, Ordering};
+    /// use std::time::Duration;
+    ///
+    /// let throttled = Arc::new(AtomicUsize::new(0));
+    /// let counter = throttled.clone();
+    ///
+    /// let middleware = RateLimitMiddleware::builder()
+    ///     .on_throttle(move |event| {
+    ///         counter.fetch_add(1, Ordering::Relaxed);
+    ///         eprintln!("throttled {} for {:?}", event.path, event.wait);
+    ///     })
+    ///     .route(|r| r.limit(100, Duration::from_secs(10)))
+    ///     .build();
+    /// ```
+    #[must_use]
+    pub fn on_throttle<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(ThrottleEvent) + Send + Sync + 'static,
+    {
+        self.on_throttle = Some(ThrottleCallback(Arc::new(callback)));
+        self
+    }
+
+    /// Add a pre-configured route.
+    #[must_use]
+    pub fn add_route(mut self, route: Route) -> Self {
+        self.routes.push(route);
+        self
+    }
+
+    /// Append the routes of `other` after this builder's routes, in order.
+    ///
+    /// Lets separate modules each build the routes of one service and an
+    /// application combine them. Only the routes are taken; every other
+    /// setting of `other` is discarded.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use route_ratelimit::{RateLimitBuilder, RateLimitMiddleware};
+    /// use std::time::Duration;
+    ///
+    /// fn clob_limits() -> RateLimitBuilder {
+    ///     RateLimitMiddleware::builder().host("clob.polymarket.com", |host| {
+    ///         host.route(|r| r.limit(9000, Duration::from_secs(10)))
+    ///     })
+    /// }
+    ///
+    /// let middleware = RateLimitMiddleware::builder()
+    ///     .jitter(0.1)
+    ///     .merge(clob_limits())
+    ///     .build();
+    /// ```
+    #[must_use]
+    pub fn merge(mut self, other: RateLimitBuilder) -> Self {
+        self.routes.extend(other.routes);
+        self
+    }
+
+    /// Check the configured routes for likely mistakes.
+    ///
+    /// Reports an empty configuration, routes matching exactly the same
+    /// requests as an earlier route, and limits that can never throttle a
+    /// request because a more general route has a limit at least as strict,
+    /// counting by route weight. Routes are only compared when one clearly
+    /// covers the other, so not every redundant limit is found. Under
+    /// [`MatchMode::MostSpecific`] and [`MatchMode::FirstMatch`], general
+    /// routes do not apply alongside specific ones, so no limit is reported
+    /// as shadowed.
+    ///
+    /// This is never called by [`build`](Self::build).
+    ///
+    /// # Errors
+    ///
+    /// Returns every [`ConfigWarning`] found.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use route_ratelimit::{ConfigWarning, RateLimitMiddleware};
+    /// use std::time::Duration;
+    ///
+    /// let builder = RateLimitMiddleware::builder().host("api.example.com", |host| {
+    ///     host.route(|r| r.limit(100, Duration::from_secs(10)))
+    ///         .route(|r| r.path("/book").limit(500, Duration::from_secs(10)))
+    /// });
+    ///
+    /// let warnings = builder.validate().unwrap_err();
+    /// assert!(matches!(
+    ///     warnings[0],
+    ///     ConfigWarning::ShadowedLimit { route: 1, .. }
+    /// ));
+    /// ```
+    pub fn validate(&self) -> Result<(), Vec<ConfigWarning>> {
+        let warnings = warning::check(&self.routes, self.match_mode);
+        if warnings.is_empty() {
+            Ok(())
+        } else {
+            Err(warnings)
+        }
+    }
+
+    /// Return the configured routes, discarding all other settings.
+    ///
+    /// Useful for building the new routes passed to
+    /// [`RateLimitMiddleware::update_routes`].
+    #[must_use]
+    pub fn into_routes(self) -> Vec<Route> {
+        self.routes
+    }
+
+    /// Build the middleware.
+    ///
+    /// # Warnings
+    ///
+    /// If the `tracing` feature is enabled, this method will emit a warning
+    /// when catch-all routes (routes with no host, method, or path filters)
+    /// are followed by more specific routes. This pattern may cause unexpected
+    /// behavior since all matching routes' limits are applied. Under
+    /// [`MatchMode::FirstMatch`], the warning points out that the specific
+    /// routes never apply, and under [`MatchMode::MostSpecific`] there is
+    /// none.
+    ///
+    /// # Panics
+    ///
+    /// Panics if [`auto_cleanup`](Self::auto_cleanup) is set and this is called
+    /// outside of a Tokio runtime, or if the configuration is invalid; see
+    /// [`try_build`](Self::try_build).
+    #[must_use]
+    pub fn build(self) -> RateLimitMiddleware {
+        self.try_build().unwrap_or_else(|err| panic!("{err}"))
+    }
+
+    /// Build the middleware, or return what is wrong with the configuration.
+    ///
+    /// Unlike [`build`](Self::build), this does not panic on configurations
+    /// read from users, such as those passed to
+    /// [`from_config`](Self::from_config). Route builder methods that take a
+    /// single setting, such as [`RouteBuilder::weight`], still panic on an
+    /// invalid argument right away.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a route has no limits or an invalid limit, if its
+    /// settings are otherwise invalid, if routes in a shared group disagree,
+    /// if the builder is [`strict`](Self::strict) and a route without filters
+    /// was not declared a catch-all, or if the builder
+    /// [denies warnings](Self::deny_warnings) and the configuration has any.
+    ///
+    /// # Panics
+    ///
+    /// Panics if [`auto_cleanup`](Self::auto_cleanup) is set and this is called
+    /// outside of a Tokio runtime.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use route_ratelimit::{BuildError, InvalidLimit, RateLimitMiddleware};
+    /// use std::time::Duration;
+    ///
+    /// let result = RateLimitMiddleware::builder()
+    ///     .route(|r| r.path("/book").limit(0, Duration::from_secs(10)))
+    ///     .try_build();
+    /// assert!(matches!(
+    ///     result,
+    ///     Err(BuildError::InvalidLimit {
+    ///         route: 0,
+    ///         limit: 0,
+    ///         reason: InvalidLimit::ZeroRequests,
+    ///     })
+    /// ));
+    /// ```
+    pub fn try_build(self) -> Result<RateLimitMiddleware, BuildError> {
+        for (index, route) in self.routes.iter().enumerate() {
+            route.check().map_err(|err| err.at(index))?;
+        }
+        if self.strict {
+            self.check_catch_all_declared()?;
+        }
+        route_table::shared_groups(&self.routes)?;
+        route_table::check_pools(&self.routes, &self.pools)?;
+        if self.deny_warnings {
+            self.validate().map_err(BuildError::Warnings)?;
+        }
+        #[cfg(feature = "tracing")]
+        if self.match_mode != MatchMode::MostSpecific {
+            self.warn_catch_all_route_order();
+        }
+
+        let match_cache_capacity = self
+            .match_cache_capacity
+            .unwrap_or(DEFAULT_MATCH_CACHE_CAPACITY);
+        let middleware = RateLimitMiddleware {
+            table: Arc::new(ArcSwap::from_pointee(RouteTable::new(
+                self.routes,
+                self.pools,
+                self.match_mode,
+                match_cache_capacity,
+                self.state_capacity,
+            ))),
+            clock: self
+                .clock
+                .unwrap_or_else(|| Arc::new(MonotonicClock::new())),
+            respect_retry_after: self.respect_retry_after,
+            respect_ratelimit_headers: self.respect_ratelimit_headers,
+            on_throttle: self.on_throttle,
+            jitter: self.jitter.unwrap_or(DEFAULT_JITTER),
+            jitter_source: self.jitter_source.unwrap_or_else(|| Arc::new(RandomJitter)),
+            state_backend: self.state_backend,
+            bypass_methods: self.bypass_methods.into(),
+            max_total_delay: self.max_total_delay,
+            match_mode: self.match_mode,
+            unmatched: self.unmatched,
+            match_cache_capacity,
+            state_capacity: self.state_capacity,
+            cleanup_stale_after: self
+                .cleanup_stale_after
+                .unwrap_or(DEFAULT_CLEANUP_STALE_AFTER),
+            enabled: Arc::new(AtomicBool::new(true)),
+            shutdown: Arc::default(),
+        };
+
+        if let Some(interval) = self.auto_cleanup {
+            middleware.spawn_auto_cleanup(interval);
+        }
+
+        Ok(middleware)
+    }
+
+    /// Fail if a route matches every request without being declared a
+    /// catch-all.
+    fn check_catch_all_declared(&self) -> Result<(), BuildError> {
+        match self
+            .routes
+            .iter()
+            .position(|route| !route.catch_all && route.is_catch_all())
+        {
+            Some(route) => Err(BuildError::UndeclaredCatchAll { route }),
+            None => Ok(()),
+        }
+    }
+
+    /// Emit a warning if catch-all routes precede more specific routes.
+    #[cfg(feature = "tracing")]
+    fn warn_catch_all_route_order(&self) {
+        // Collect indices of all catch-all routes
+        let catch_all_indices: Vec<usize> = self
+            .routes
+            .iter()
+            .enumerate()
+            .filter(|(_, route)| route.is_catch_all())
+            .map(|(i, _)| i)
+            .collect();
+
+        // For each catch-all, warn about specific routes that follow it
+        for &catch_all_index in &catch_all_indices {
+            // Find the first specific route after this catch-all. Under
+            // `FirstMatch`, a higher priority lets a route apply anyway
+            let catch_all_priority = self.routes[catch_all_index].priority;
+            if let Some((specific_index, _)) = self
+                .routes
+                .iter()
+                .enumerate()
+                .skip(catch_all_index + 1)
+                .find(|(_, route)| {
+                    !route.is_catch_all()
+                        && (self.match_mode != MatchMode::FirstMatch
+                            || route.priority <= catch_all_priority)
+                })
+            {
+                if self.match_mode == MatchMode::FirstMatch {
+                    tracing::warn!(
+                        catch_all_route_index = catch_all_index,
+                        specific_route_index = specific_index,
+                        "Catch-all route (index {}) precedes more specific route (index {}). \
+                         Only the first matching route applies, so the specific route never \
+                         does. Move the catch-all after it.",
+                        catch_all_index,
+                        specific_index
+                    );
+                    continue;
+                }
+                tracing::warn!(
+                    catch_all_route_index = catch_all_index,
+                    specific_route_index = specific_index,
+                    "Catch-all route (index {}) precedes more specific route (index {}). \
+                     All matching routes' limits are applied, so the catch-all will affect \
+                     requests intended for the specific route. Consider reordering routes \
+                     or using host-scoped builders.",
+                    catch_all_index,
+                    specific_index
+                );
+            }
+        }
+    }
+}
+
+/// Builder for configuring routes within a specific host scope.
+///
+/// Created by [`RateLimitBuilder::host`]. All routes created within this builder
+/// will automatically have the host set.
+#[derive(Debug, Clone)]
+pub struct HostBuilder {
+    host: String,
+    default_behavior: ThrottleBehavior,
+    routes: Vec<Route>,
+}
+
+impl HostBuilder {
+    fn new(host: String) -> Self {
+        Self {
+            host,
+            default_behavior: ThrottleBehavior::default(),
+            routes: Vec::new(),
+        }
+    }
+
+    /// Set the behavior when rate limit is exceeded for routes added after
+    /// this call, unless a route sets its own with
+    /// [`on_limit`](HostRouteBuilder::on_limit).
+    #[must_use]
+    pub fn default_behavior(mut self, behavior: ThrottleBehavior) -> Self {
+        self.default_behavior = behavior;
+        self
+    }
+
+    /// Add a route within this host using a closure-based configuration.
+    ///
+    /// The host is automatically set for each route. The route is checked
+    /// when the middleware is built, like those of
+    /// [`RateLimitBuilder::route`].
+    #[must_use]
+    pub fn route<F>(mut self, configure: F) -> Self
+    where
+        F: FnOnce(HostRouteBuilder) -> HostRouteBuilder,
+    {
+        let builder = HostRouteBuilder::new();
+        let configured = configure(builder);
+        let route = Route {
+            hosts: BTreeSet::from([self.host.clone()]),
+            host_suffixes: BTreeSet::new(),
+            scheme: configured.scheme,
+            methods: route_methods(configured.methods, configured.include_head),
+            path_prefix: configured.path_prefix,
+            path_pattern: configured.path_pattern,
+            excluded_paths: configured.excluded_paths,
+            case_insensitive_path: configured.case_insensitive_path,
+            exact_path: configured.exact_path,
+            template: configured.template,
+            query: configured.query,
+            headers: configured.headers,
+            limits: configured.limits,
+            on_limit: configured.on_limit.unwrap_or(self.default_behavior),
+            key_header: configured.key_header,
+            key_fn: configured.key_fn,
+            key_capture: configured.key_capture,
+            weight: configured.weight.unwrap_or(1),
+            shared_group: configured.shared_group,
+            algorithm: configured.algorithm,
+            max_concurrent: configured.max_concurrent,
+            max_pending: configured.max_pending,
+            cooldown: configured.cooldown,
+            adaptive: configured.adaptive,
+            catch_all: false,
+            hedge_after: configured.hedge_after,
+            count_if: configured.count_if,
+            default_response_bytes: configured.default_response_bytes,
+            single_flight: configured.single_flight,
+            ramp_up: configured.ramp_up,
+            priority: configured.priority,
+            pools: configured.pools,
+        };
+        self.routes.push(route);
+        self
+    }
+}
+
+/// The methods a route matches, adding `HEAD` wherever `GET` is matched if
+/// `include_head` is set.
+fn route_methods(mut methods: Vec<Method>, include_head: bool) -> Vec<Method> {
+    if include_head && methods.contains(&Method::GET) && !methods.contains(&Method::HEAD) {
+        methods.push(Method::HEAD);
+    }
+    methods
+}
+
+/// Builder for configuring a single route within a host scope.
+///
+/// Created by [`HostBuilder::route`] closure. Configure the route and the
+/// closure will automatically add it to the host.
+#[derive(Debug, Default, Clone)]
+pub struct HostRouteBuilder {
+    scheme: Option<String>,
+    methods: Vec<Method>,
+    include_head: bool,
+    path_prefix: String,
+    path_pattern: Option<PathPattern>,
+    excluded_paths: Vec<String>,
+    case_insensitive_path: bool,
+    exact_path: bool,
+    template: Option<String>,
+    query: Vec<(String, String)>,
+    headers: Vec<(HeaderName, Option<String>)>,
+    limits: Vec<RateLimit>,
+    on_limit: Option<ThrottleBehavior>,
+    key_header: Option<HeaderName>,
+    key_fn: Option<KeyFn>,
+    key_capture: Option<String>,
+    weight: Option<u32>,
+    shared_group: Option<String>,
+    algorithm: Algorithm,
+    max_concurrent: Option<usize>,
+    max_pending: Option<usize>,
+    cooldown: Option<Cooldown>,
+    adaptive: Option<Adaptive>,
+    hedge_after: Option<Duration>,
+    count_if: Option<StatusPredicate>,
+    default_response_bytes: u64,
+    single_flight: bool,
+    ramp_up: Option<Duration>,
+    priority: i32,
+    pools: Vec<(String, u32)>,
+}
+
+impl HostRouteBuilder {
+    fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the URL scheme to match (e.g., "https").
+    ///
+    /// Requests with any other scheme do not match the route. Compared
+    /// case-insensitively.
+    #[must_use]
+    pub fn scheme(mut self, scheme: impl Into<String>) -> Self {
+        self.scheme = Some(scheme.into().to_ascii_lowercase());
+        self
+    }
+
+    /// Add an HTTP method to match.
+    ///
+    /// A route with several methods matches a request with any of them, and
+    /// all of them share the route's limits.
+    #[must_use]
+    pub fn method(mut self, method: Method) -> Self {
+        if !self.methods.contains(&method) {
+            self.methods.push(method);
+        }
+        self
+    }
+
+    /// Add several HTTP methods to match.
+    #[must_use]
+    pub fn methods(self, methods: impl IntoIterator<Item = Method>) -> Self {
+        methods.into_iter().fold(self, Self::method)
+    }
+
+    /// Also match `HEAD` requests if the route matches `GET`.
+    ///
+    /// `HEAD` and `GET` requests then share the route's limits. Has no effect
+    /// on routes that do not match `GET`, including routes matching any method.
+    #[must_use]
+    pub fn include_head(mut self) -> Self {
+        self.include_head = true;
+        self
+    }
+
+    /// Set the path prefix to match (e.g., "/order").
+    ///
+    /// The prefix matches whole segments, so `/order` matches `/order` and
+    /// `/order/123` but not `/orders`. Use [`path_exact`](Self::path_exact)
+    /// to leave out the sub-resources.
+    #[must_use]
+    pub fn path(mut self, path_prefix: impl Into<String>) -> Self {
+        self.path_prefix = path_prefix.into();
+        self.exact_path = false;
+        self
+    }
+
+    /// Set the only path to match (e.g., "/order"), with or without a
+    /// trailing slash.
+    ///
+    /// Unlike [`path`](Self::path), `/order` then matches neither
+    /// `/order/123` nor any other sub-resource. Cannot be combined with
+    /// [`path_pattern`](Self::path_pattern).
+    #[must_use]
+    pub fn path_exact(mut self, path: impl Into<String>) -> Self {
+        self.path_prefix = path.into();
+        self.exact_path = true;
+        self
+    }
+
+    /// Set a glob pattern the whole path must match (e.g., "/markets/*/book").
+    ///
+    /// `*` matches exactly one segment and `**` matches zero or more segments.
+    /// See [`PathPattern`] for details. Cannot be combined with [`path`](Self::path).
+    ///
+    /// # Panics
+    ///
+    /// Panics if `pattern` does not start with `/`.
+    #[must_use]
+    pub fn path_pattern(mut self, pattern: &str) -> Self {
+        self.path_pattern = Some(PathPattern::new(pattern));
+        self
+    }
+
+    /// Set a glob pattern the whole path must match, and give each distinct
+    /// value of its `{capture}` segment its own bucket.
+    ///
+    /// Combines [`path_pattern`](Self::path_pattern) with per-key limiting:
+    /// with `"/markets/{id}/book"` and `"id"`, `/markets/A/book` and
+    /// `/markets/B/book` are limited independently, like requests keyed by
+    /// [`key_by_header`](Self::key_by_header). Unlike
+    /// [`key_fn`](Self::key_fn), this also applies to requests through the
+    /// tower layer.
+    ///
+    /// ```rust
+    /// # use route_ratelimit::RateLimitMiddleware;
+    /// # use std::time::Duration;
+    /// let middleware = RateLimitMiddleware::builder()
+    ///     .route(|r| {
+    ///         r.path_pattern_key("/markets/{id}/book", "id")
+    ///             .limit(10, Duration::from_secs(1))
+    ///     })
+    ///     .build();
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if `pattern` does not start with `/`. The route panics when
+    /// added if `pattern` has no `{capture}` segment, or if it is also keyed
+    /// by a header or a key function.
+    #[must_use]
+    pub fn path_pattern_key(mut self, pattern: &str, capture: &str) -> Self {
+        self.path_pattern = Some(PathPattern::new(pattern));
+        self.key_capture = Some(capture.to_owned());
+        self
+    }
+
+    /// Exclude requests under a path prefix from the route (e.g., "/api/health").
+    ///
+    /// Uses the same segment boundaries as [`path`](Self::path), so excluding
+    /// `/api/health` also excludes `/api/health/live` but not `/api/healthz`.
+    /// Can be called several times to exclude several prefixes.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `path_prefix` is empty.
+    #[must_use]
+    pub fn exclude_path(mut self, path_prefix: impl Into<String>) -> Self {
+        let path_prefix = path_prefix.into();
+        assert!(!path_prefix.is_empty(), "excluded path must not be empty");
+        self.excluded_paths.push(path_prefix);
+        self
+    }
+
+    /// Compare paths ignoring ASCII case, so that `/order` also matches
+    /// `/Order/123`.
+    ///
+    /// Applies to the path prefix, path pattern, and excluded paths, keeping
+    /// their segment boundaries. Paths are compared case-sensitively by
+    /// default.
+    #[must_use]
+    pub fn case_insensitive_path(mut self) -> Self {
+        self.case_insensitive_path = true;
+        self
+    }
+
+    /// Describe the route's paths with a stable label, such as
+    /// `/order/{id}`, wherever the route is reported: in
+    /// [`RateLimitError`](crate::RateLimitError)s and
+    /// [`stats`](RateLimitMiddleware::stats). Defaults to the path prefix or
+    /// pattern.
+    ///
+    /// Only a label: requests are still matched by the path prefix or
+    /// pattern.
+    ///
+    /// ```rust
+    /// # use route_ratelimit::RateLimitMiddleware;
+    /// # use std::time::Duration;
+    /// let middleware = RateLimitMiddleware::builder()
+    ///     .route(|r| {
+    ///         r.path("/order")
+    ///             .template("/order/{id}")
+    ///             .limit(10, Duration::from_secs(1))
+    ///     })
+    ///     .build();
+    /// ```
+    #[must_use]
+    pub fn template(mut self, template: impl Into<String>) -> Self {
+        self.template = Some(template.into());
+        self
+    }
+
+    /// Require a query parameter with the given value (e.g., `type=deep`).
+    ///
+    /// Can be called several times; every pair must be present for the route
+    /// to match. Values are compared after percent-decoding.
+    #[must_use]
+    pub fn query(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.query.push((key.into(), value.into()));
+        self
+    }
+
+    /// Add a rate limit.
+    #[must_use]
+    pub fn limit(mut self, requests: u32, window: Duration) -> Self {
+        self.limits
+            .push(RateLimit::unchecked(requests, window, None));
+        self
+    }
+
+    /// Add a limit of `requests` per second.
+    ///
+    /// Shorthand for `limit(requests, Duration::from_secs(1))`.
+    #[must_use]
+    pub fn limit_per_second(mut self, requests: u32) -> Self {
+        self.limits
+            .push(RateLimit::unchecked(requests, Duration::from_secs(1), None));
+        self
+    }
+
+    /// Add a limit of `requests` per minute.
+    #[must_use]
+    pub fn limit_per_minute(mut self, requests: u32) -> Self {
+        self.limits.push(RateLimit::unchecked(
+            requests,
+            Duration::from_secs(60),
+            None,
+        ));
+        self
+    }
+
+    /// Add a limit of `requests` per hour.
+    #[must_use]
+    pub fn limit_per_hour(mut self, requests: u32) -> Self {
+        self.limits.push(RateLimit::unchecked(
+            requests,
+            Duration::from_secs(60 * 60),
+            None,
+        ));
+        self
+    }
+
+    /// Add a rate limit with its own behavior when exceeded, overriding
+    /// [`on_limit`](Self::on_limit) for this limit only.
+    ///
+    /// For example, a burst limit can delay requests to smooth them out while
+    /// a daily budget fails them immediately.
+    #[must_use]
+    pub fn limit_with(
+        mut self,
+        requests: u32,
+        window: Duration,
+        behavior: ThrottleBehavior,
+    ) -> Self {
+        let mut limit = RateLimit::unchecked(requests, window, None);
+        limit.on_limit = Some(behavior);
+        self.limits.push(limit);
+        self
+    }
+
+    /// Add a rate limit that each key gets separately, within the route's
+    /// other limits.
+    ///
+    /// With [`key_by_header`](Self::key_by_header), a route's limits normally
+    /// apply to each key separately. Once it has a per-key limit, its other
+    /// limits are shared by all keys instead, so a request must pass both a
+    /// shared budget and its own key's limit. This keeps a single noisy key
+    /// from starving the others.
+    ///
+    /// ```rust
+    /// # use route_ratelimit::RateLimitMiddleware;
+    /// # use std::time::Duration;
+    /// let middleware = RateLimitMiddleware::builder()
+    ///     .route(|r| {
+    ///         r.key_by_header("X-User-Id")
+    ///             .limit(1000, Duration::from_secs(10))
+    ///             .per_key_limit(100, Duration::from_secs(10))
+    ///     })
+    ///     .build();
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Building the route panics if it has no key.
+    #[must_use]
+    pub fn per_key_limit(mut self, requests: u32, window: Duration) -> Self {
+        let mut limit = RateLimit::unchecked(requests, window, None);
+        limit.per_key = true;
+        self.limits.push(limit);
+        self
+    }
+
+    /// Add a rate limit with a burst capacity separate from its request count.
+    ///
+    /// Sustains `requests` per `window` while allowing up to `burst` requests
+    /// at once. See [`RateLimit::with_burst`].
+    #[must_use]
+    pub fn burst_limit(mut self, requests: u32, window: Duration, burst: u32) -> Self {
+        self.limits
+            .push(RateLimit::unchecked(requests, window, Some(burst)));
+        self
+    }
+
+    /// Add a limit holding up to `capacity` requests at once and refilling at
+    /// `refill_per_sec` requests per second. See [`RateLimit::token_bucket`].
+    #[must_use]
+    pub fn token_bucket_limit(mut self, capacity: u32, refill_per_sec: u32) -> Self {
+        self.limits.push(RateLimit::unchecked(
+            refill_per_sec,
+            Duration::from_secs(1),
+            Some(capacity),
+        ));
+        self
+    }
+
+    /// Add a limit of `bytes` of response bodies per `window`.
+    ///
+    /// Requests are admitted while any bytes are left, and the size of each
+    /// response body is charged once it arrives, so a large response can
+    /// exhaust the budget and delay the requests after it. The size is read
+    /// from the `Content-Length` header; responses without one are charged
+    /// [`default_response_bytes`](Self::default_response_bytes). A charge
+    /// never takes more than the whole budget.
+    ///
+    /// ```rust
+    /// # use route_ratelimit::RateLimitMiddleware;
+    /// # use std::time::Duration;
+    /// let middleware = RateLimitMiddleware::builder()
+    ///     .route(|r| {
+    ///         r.path("/download")
+    ///             .limit(10, Duration::from_secs(1))
+    ///             .bandwidth_limit(50_000_000, Duration::from_secs(60))
+    ///     })
+    ///     .build();
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Building the route panics if it uses the sliding window log
+    /// algorithm.
+    #[must_use]
+    pub fn bandwidth_limit(mut self, bytes: u32, window: Duration) -> Self {
+        let mut limit = RateLimit::unchecked(bytes, window, None);
+        limit.unit = LimitUnit::Bytes;
+        self.limits.push(limit);
+        self
+    }
+
+    /// Set the size charged to bandwidth limits for responses without a
+    /// `Content-Length` header, such as chunked ones. Defaults to 0.
+    #[must_use]
+    pub fn default_response_bytes(mut self, bytes: u64) -> Self {
+        self.default_response_bytes = bytes;
+        self
+    }
+
+    /// Set the behavior when rate limit is exceeded.
+    ///
+    /// Overrides the host's [`default_behavior`](HostBuilder::default_behavior).
+    #[must_use]
+    pub fn on_limit(mut self, behavior: ThrottleBehavior) -> Self {
+        self.on_limit = Some(behavior);
+        self
+    }
+
+    /// Require a request header with the given value (e.g., `X-Internal: true`).
+    ///
+    /// Can be called several times; every header must match. Values are
+    /// compared exactly.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `name` is not a valid header name.
+    #[must_use]
+    pub fn header(mut self, name: &str, value: impl Into<String>) -> Self {
+        let name = HeaderName::from_bytes(name.as_bytes()).expect("invalid header name");
+        self.headers.push((name, Some(value.into())));
+        self
+    }
+
+    /// Require a request header to be present, with any value.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `name` is not a valid header name.
+    #[must_use]
+    pub fn header_present(mut self, name: &str) -> Self {
+        let name = HeaderName::from_bytes(name.as_bytes()).expect("invalid header name");
+        self.headers.push((name, None));
+        self
+    }
+
+    /// Give each distinct value of a request header its own bucket.
+    ///
+    /// For example, keying by `X-User-Id` limits every user independently.
+    /// Requests without the header share a single default bucket.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `header` is not a valid header name.
+    #[must_use]
+    pub fn key_by_header(mut self, header: &str) -> Self {
+        let name = HeaderName::from_bytes(header.as_bytes()).expect("invalid header name");
+        self.key_header = Some(name);
+        self
+    }
+
+    /// Give each distinct key computed from a request its own bucket.
+    ///
+    /// Covers keys that [`key_by_header`](Self::key_by_header) cannot, such
+    /// as a combination of the host and a path segment. Requests for which
+    /// `key_fn` returns `None` share a single default bucket. The function is
+    /// only called for requests sent through the reqwest middleware; others,
+    /// such as those through the tower layer, use the default bucket.
+    ///
+    /// The function sees the whole request, including the authority of its
+    /// URL, so limits can also be kept per endpoint instance, e.g. with
+    /// [`keys::by_authority`](crate::keys::by_authority) for one bucket per
+    /// `host:port`.
+    ///
+    /// ```rust
+    /// # use route_ratelimit::RateLimitMiddleware;
+    /// # use std::time::Duration;
+    /// // A bucket per tenant, taken from paths like /tenants/{id}/...
+    /// let middleware = RateLimitMiddleware::builder()
+    ///     .route(|r| {
+    ///         r.path("/tenants")
+    ///             .key_fn(|req| req.url().path_segments()?.nth(1).map(str::to_owned))
+    ///             .limit(10, Duration::from_secs(1))
+    ///     })
+    ///     .build();
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Building the route panics if it is also keyed by a header.
+    #[must_use]
+    pub fn key_fn<F>(mut self, key_fn: F) -> Self
+    where
+        F: Fn(&Request) -> Option<String> + Send + Sync + 'static,
+    {
+        self.key_fn = Some(KeyFn::new(key_fn));
+        self
+    }
+
+    /// Set how many tokens each matching request consumes (default 1).
+    ///
+    /// Individual requests can override this with a
+    /// [`RequestWeight`](crate::RequestWeight) extension.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `weight` is 0.
+    #[must_use]
+    pub fn weight(mut self, weight: u32) -> Self {
+        assert!(weight > 0, "weight must be greater than 0");
+        self.weight = Some(weight);
+        self
+    }
+
+    /// Share this route's limit state with every other route in the same group.
+    ///
+    /// Requests to any route in the group draw from one set of buckets, for
+    /// example to enforce a backend-wide cap across several hosts or paths.
+    /// All routes in a group must be configured with identical limits and
+    /// algorithm; [`RateLimitBuilder::build`] panics otherwise. A request
+    /// matching several routes of the same group is only counted once.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use route_ratelimit::RateLimitMiddleware;
+    /// use std::time::Duration;
+    ///
+    /// let middleware = RateLimitMiddleware::builder()
+    ///     .route(|r| {
+    ///         r.host("clob.polymarket.com")
+    ///             .shared_group("clob")
+    ///             .limit(9000, Duration::from_secs(10))
+    ///     })
+    ///     .route(|r| {
+    ///         r.host("clob-staging.polymarket.com")
+    ///             .shared_group("clob")
+    ///             .limit(9000, Duration::from_secs(10))
+    ///     })
+    ///     .build();
+    /// ```
+    #[must_use]
+    pub fn shared_group(mut self, name: &str) -> Self {
+        self.shared_group = Some(name.to_string());
+        self
+    }
+
+    /// Select the algorithm that enforces this route's limits (default
+    /// [`Algorithm::Gcra`]).
+    #[must_use]
+    pub fn algorithm(mut self, algorithm: Algorithm) -> Self {
+        self.algorithm = algorithm;
+        self
+    }
+
+    /// Cap the number of matching requests in flight at once.
+    ///
+    /// A request takes a slot before it is sent and releases it once the
+    /// response has been received. When every slot is taken, the route's
+    /// [`on_limit`](Self::on_limit) behavior applies: `Delay` waits for a free
+    /// slot, `DelayWithTimeout` waits at most the configured duration, `Error`
+    /// fails immediately with [`RateLimitError::ConcurrencyLimited`], and
+    /// `Respond429` answers immediately with a `429` response.
+    ///
+    /// [`RateLimitError::ConcurrencyLimited`]: crate::RateLimitError::ConcurrencyLimited
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max` is 0.
+    #[must_use]
+    pub fn max_concurrent(mut self, max: usize) -> Self {
+        assert!(max > 0, "max_concurrent must be greater than 0");
+        self.max_concurrent = Some(max);
+        self
+    }
+
+    /// Cap the number of matching requests delayed at once.
+    ///
+    /// A request counts as pending while it sleeps on one of the route's
+    /// limits or waits for its turn behind other delayed requests. Once `max`
+    /// requests are pending, further requests that would be delayed fail
+    /// right away with [`RateLimitError::RateLimited`] instead of piling up,
+    /// so callers get backpressure rather than unbounded latency. See
+    /// [`RateLimitMiddleware::pending`](crate::RateLimitMiddleware::pending).
+    ///
+    /// [`RateLimitError::RateLimited`]: crate::RateLimitError::RateLimited
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max` is 0.
+    #[must_use]
+    pub fn max_pending(mut self, max: usize) -> Self {
+        assert!(max > 0, "max_pending must be greater than 0");
+        self.max_pending = Some(max);
+        self
+    }
+
+    /// Only count requests whose response status satisfies `predicate`.
+    ///
+    /// Requests are admitted as usual, but once the response arrives, the
+    /// quota they took is given back if `predicate` returns `false` for its
+    /// status, e.g. for cached `304 Not Modified` responses the server does not
+    /// count. Requests that fail without a response keep counting. A refund
+    /// never frees more than a full burst.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use http::StatusCode;
+    /// use route_ratelimit::RateLimitMiddleware;
+    /// use std::time::Duration;
+    ///
+    /// let middleware = RateLimitMiddleware::builder()
+    ///     .route(|r| {
+    ///         r.limit(100, Duration::from_secs(10))
+    ///             .count_if(|status| status != StatusCode::NOT_MODIFIED)
+    ///     })
+    ///     .build();
+    /// ```
+    #[must_use]
+    pub fn count_if<F>(mut self, predicate: F) -> Self
+    where
+        F: Fn(StatusCode) -> bool + Send + Sync + 'static,
+    {
+        self.count_if = Some(StatusPredicate::new(predicate));
+        self
+    }
+
+    /// Coalesce identical requests in flight at the same time.
+    ///
+    /// While a `GET` or `HEAD` request matching this route is in flight, later
+    /// requests with the same method, URL, and credentials (and bucket key,
+    /// for routes keyed by a header) do not hit the server or take any quota.
+    /// They wait for the first request's response instead and each receive a
+    /// copy of it. The `Authorization`, `Proxy-Authorization`, and `Cookie`
+    /// headers count as credentials; other headers are ignored, so only use
+    /// this where they do not change the response.
+    ///
+    /// The first response's body is read into memory to be shared. If the first
+    /// request fails, waiting duplicates are sent on their own.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use route_ratelimit::RateLimitMiddleware;
+    /// use std::time::Duration;
+    ///
+    /// let middleware = RateLimitMiddleware::builder()
+    ///     .route(|r| {
+    ///         r.path("/book")
+    ///             .limit(100, Duration::from_secs(10))
+    ///             .single_flight()
+    ///     })
+    ///     .build();
+    /// ```
+    #[must_use]
+    pub fn single_flight(mut self) -> Self {
+        self.single_flight = true;
+        self
+    }
+
+    /// Warm up newly created buckets over `duration` instead of starting them
+    /// full.
+    ///
+    /// A new bucket starts with the part of its burst that would have refilled
+    /// in the time left after `duration`, and is full once `duration` has
+    /// passed, so a freshly started process or a new per-key bucket cannot
+    /// spend a whole burst at once. Durations longer than the time a limit
+    /// takes to refill its burst start that limit's buckets empty.
+    ///
+    /// Only applies to the middleware's own state: buckets kept in a
+    /// [`StateBackend`](crate::StateBackend) start full.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `duration` is zero, or when the route is built if it uses
+    /// [`Algorithm::SlidingWindowLog`].
+    #[must_use]
+    pub fn ramp_up(mut self, duration: Duration) -> Self {
+        assert!(!duration.is_zero(), "ramp_up must be greater than 0");
+        self.ramp_up = Some(duration);
+        self
+    }
+
+    /// Rank the route among the routes matching a request (default 0).
+    ///
+    /// Under [`MatchMode::FirstMatch`], the matching route with the highest
+    /// priority applies, whatever the order routes are configured in; under
+    /// [`MatchMode::MostSpecific`], priority decides between equally specific
+    /// routes. Under [`MatchMode::All`], limits are checked highest priority
+    /// first. Routes of equal priority keep their configured order.
+    #[must_use]
+    pub fn priority(mut self, priority: i32) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// Also count each request to this route against the quota pool named
+    /// `pool`, defined with [`RateLimitBuilder::pool`], as `cost` requests.
+    ///
+    /// The cost is independent of the route's [`weight`](Self::weight). A
+    /// rejected or delayed request follows the route's
+    /// [`on_limit`](Self::on_limit) behavior, whether its own limits or the
+    /// pool is exhausted.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `cost` is 0.
+    #[must_use]
+    pub fn draws_from(mut self, pool: impl Into<String>, cost: u32) -> Self {
+        assert!(cost > 0, "pool cost must be greater than 0");
+        self.pools.push((pool.into(), cost));
+        self
+    }
+
+    /// Hedge slow requests: if a request has not completed after `delay`, send
+    /// a duplicate and return whichever response arrives first.
+    ///
+    /// The duplicate must pass rate limiting like any other request, consuming
+    /// quota and following the route's [`on_limit`](Self::on_limit) behavior.
+    /// If it is rejected, or is still delayed when the original completes, no
+    /// duplicate is sent. The slower request is cancelled.
+    ///
+    /// Only requests whose body can be cloned are hedged, so streaming bodies
+    /// are sent once. If a request matches several hedging routes, the shortest
+    /// delay applies. Hedging is only performed by the reqwest middleware, not
+    /// by [`RateLimitService`](crate::RateLimitService).
+    ///
+    /// Only hedge idempotent requests: both copies may reach the server.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `delay` is zero.
+    #[must_use]
+    pub fn hedge_after(mut self, delay: Duration) -> Self {
+        assert!(!delay.is_zero(), "hedge_after must be greater than 0");
+        self.hedge_after = Some(delay);
+        self
+    }
+
+    /// Open a circuit breaker after `failures` consecutive throttle events.
+    ///
+    /// While the circuit is open, requests matching the route fail immediately
+    /// with [`RateLimitError::CircuitOpen`] for the `cooldown` duration, rather
+    /// than being delayed, and `Respond429` routes answer with a `429`
+    /// response. Afterwards the circuit is half-open: a request admitted
+    /// without being throttled closes it, while a single throttle event opens
+    /// it again. The throttle event that opens the circuit fails its request
+    /// as well.
+    ///
+    /// [`RateLimitError::CircuitOpen`]: crate::RateLimitError::CircuitOpen
+    ///
+    /// # Panics
+    ///
+    /// Panics if `failures` is 0 or `cooldown` is zero.
+    #[must_use]
+    pub fn cooldown_after(mut self, failures: u32, cooldown: Duration) -> Self {
+        assert!(failures > 0, "cooldown failures must be greater than 0");
+        assert!(
+            !cooldown.is_zero(),
+            "cooldown duration must be greater than 0"
+        );
+        self.cooldown = Some(Cooldown {
+            failures,
+            duration: cooldown,
+        });
+        self
+    }
+
+    /// Adapt the route's rate to the server's feedback, between `min_rate`
+    /// and `max_rate` times its configured limits.
+    ///
+    /// The route starts at `max_rate`. Each `429 Too Many Requests` response
+    /// halves its rate, down to `min_rate`, and each successful response adds
+    /// back a twentieth of the range, so it recovers to `max_rate` after twenty
+    /// successes in a row. Other responses leave the rate unchanged. A rate of
+    /// `0.5` allows the configured number of requests per twice the window.
+    ///
+    /// # Panics
+    ///
+    /// Panics unless `0 < min_rate <= max_rate <= 1`.
+    #[must_use]
+    pub fn adaptive(mut self, min_rate: f64, max_rate: f64) -> Self {
+        let adaptive = Adaptive { min_rate, max_rate };
+        adaptive.validate();
+        self.adaptive = Some(adaptive);
+        self
+    }
+}
+
+/// Builder for configuring a single route (without host scope).
+///
+/// Created by [`RateLimitBuilder::route`] closure. Configure the route and
+/// the closure will automatically add it to the middleware. Can also be
+/// created on its own with [`Route::builder`] and finished with
+/// [`into_route`](Self::into_route).
+#[derive(Debug, Default, Clone)]
+pub struct RouteBuilder {
+    hosts: BTreeSet<String>,
+    host_suffixes: BTreeSet<String>,
+    scheme: Option<String>,
+    methods: Vec<Method>,
+    include_head: bool,
+    path_prefix: String,
+    path_pattern: Option<PathPattern>,
+    excluded_paths: Vec<String>,
+    case_insensitive_path: bool,
+    exact_path: bool,
+    template: Option<String>,
+    query: Vec<(String, String)>,
+    headers: Vec<(HeaderName, Option<String>)>,
+    limits: Vec<RateLimit>,
+    on_limit: ThrottleBehavior,
+    key_header: Option<HeaderName>,
+    key_fn: Option<KeyFn>,
+    key_capture: Option<String>,
+    weight: Option<u32>,
+    shared_group: Option<String>,
+    algorithm: Algorithm,
+    max_concurrent: Option<usize>,
+    max_pending: Option<usize>,
+    cooldown: Option<Cooldown>,
+    adaptive: Option<Adaptive>,
+    catch_all: bool,
+    hedge_after: Option<Duration>,
+    count_if: Option<StatusPredicate>,
+    default_response_bytes: u64,
+    single_flight: bool,
+    ramp_up: Option<Duration>,
+    priority: i32,
+    pools: Vec<(String, u32)>,
+}
+
+impl RouteBuilder {
+    fn new() -> Self {
+        Self::default()
+    }
+
+    /// Finish the route.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the route is invalid, e.g. if no limits are configured via
+    /// `.limit()`.
+    #[must_use]
+    pub fn into_route(self) -> Route {
+        let route = self.into_unchecked_route();
+        route.validate();
+        route
+    }
+
+    /// Finish the route without checking it, for
+    /// [`RateLimitBuilder::try_build`] to report any errors.
+    fn into_unchecked_route(self) -> Route {
+        Route {
+            hosts: self.hosts,
+            host_suffixes: self.host_suffixes,
+            scheme: self.scheme,
+            methods: route_methods(self.methods, self.include_head),
+            path_prefix: self.path_prefix,
+            path_pattern: self.path_pattern,
+            excluded_paths: self.excluded_paths,
+            case_insensitive_path: self.case_insensitive_path,
+            exact_path: self.exact_path,
+            template: self.template,
+            query: self.query,
+            headers: self.headers,
+            limits: self.limits,
+            on_limit: self.on_limit,
+            key_header: self.key_header,
+            key_fn: self.key_fn,
+            key_capture: self.key_capture,
+            weight: self.weight.unwrap_or(1),
+            shared_group: self.shared_group,
+            algorithm: self.algorithm,
+            max_concurrent: self.max_concurrent,
+            max_pending: self.max_pending,
+            cooldown: self.cooldown,
+            adaptive: self.adaptive,
+            catch_all: self.catch_all,
+            hedge_after: self.hedge_after,
+            count_if: self.count_if,
+            default_response_bytes: self.default_response_bytes,
+            single_flight: self.single_flight,
+            ramp_up: self.ramp_up,
+            priority: self.priority,
+            pools: self.pools,
+        }
+    }
+
+    /// Set the host to match (e.g., "api.example.com"), replacing any hosts set
+    /// before. Use [`hosts`](Self::hosts) to match several hosts.
+    ///
+    /// IPv6 addresses match with or without brackets (`"::1"` or `"[::1]"`).
+    ///
+    /// Note: Consider using [`RateLimitBuilder::host`] instead if you're
+    /// configuring multiple routes for the same host.
+    #[must_use]
+    pub fn host(mut self, host: impl Into<String>) -> Self {
+        self.hosts = BTreeSet::from([unbracket(&host.into()).to_owned()]);
+        self
+    }
+
+    /// Add a domain suffix to match, e.g. `".api.example.com"` to match every
+    /// tenant subdomain such as `tenant1.api.example.com`.
+    ///
+    /// Suffixes only match at a label boundary: `.api.example.com` (or
+    /// `api.example.com`) matches `tenant1.api.example.com` but neither
+    /// `notapi.example.com` nor `api.example.com` itself, which can be added
+    /// with [`host`](Self::host). All matching hosts share the route's limits.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `suffix` is empty or only a dot.
+    #[must_use]
+    pub fn host_suffix(mut self, suffix: impl Into<String>) -> Self {
+        let suffix = suffix.into();
+        assert!(
+            !suffix.trim_start_matches('.').is_empty(),
+            "host suffix must not be empty"
+        );
+        self.host_suffixes.insert(suffix);
+        self
+    }
+
+    /// Add several equivalent hosts to match, keeping those already set.
+    ///
+    /// A request matches if its host is any of the configured hosts, and all of
+    /// them share the route's limits.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use route_ratelimit::RateLimitMiddleware;
+    /// use std::time::Duration;
+    ///
+    /// let middleware = RateLimitMiddleware::builder()
+    ///     .route(|r| {
+    ///         r.hosts(["api.example.com", "api-eu.example.com"])
+    ///             .limit(100, Duration::from_secs(10))
+    ///     })
+    ///     .build();
+    /// ```
+    #[must_use]
+    pub fn hosts<I>(mut self, hosts: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<String>,
+    {
+        self.hosts.extend(
+            hosts
+                .into_iter()
+                .map(|host| unbracket(&host.into()).to_owned()),
+        );
+        self
+    }
+
+    /// Set the URL scheme to match (e.g., "https").
+    ///
+    /// Requests with any other scheme do not match the route. Compared
+    /// case-insensitively.
+    #[must_use]
+    pub fn scheme(mut self, scheme: impl Into<String>) -> Self {
+        self.scheme = Some(scheme.into().to_ascii_lowercase());
+        self
+    }
+
+    /// Add an HTTP method to match.
+    ///
+    /// A route with several methods matches a request with any of them, and
+    /// all of them share the route's limits.
+    #[must_use]
+    pub fn method(mut self, method: Method) -> Self {
+        if !self.methods.contains(&method) {
+            self.methods.push(method);
+        }
+        self
+    }
+
+    /// Add several HTTP methods to match.
+    #[must_use]
+    pub fn methods(self, methods: impl IntoIterator<Item = Method>) -> Self {
+        methods.into_iter().fold(self, Self::method)
+    }
+
+    /// Also match `HEAD` requests if the route matches `GET`.
+    ///
+    /// `HEAD` and `GET` requests then share the route's limits. Has no effect
+    /// on routes that do not match `GET`, including routes matching any method.
+    #[must_use]
+    pub fn include_head(mut self) -> Self {
+        self.include_head = true;
+        self
+    }
+
+    /// Set the path prefix to match (e.g., "/order").
+    ///
+    /// The prefix matches whole segments, so `/order` matches `/order` and
+    /// `/order/123` but not `/orders`. Use [`path_exact`](Self::path_exact)
+    /// to leave out the sub-resources.
+    #[must_use]
+    pub fn path(mut self, path_prefix: impl Into<String>) -> Self {
+        self.path_prefix = path_prefix.into();
+        self.exact_path = false;
+        self
+    }
+
+    /// Set the only path to match (e.g., "/order"), with or without a
+    /// trailing slash.
+    ///
+    /// Unlike [`path`](Self::path), `/order` then matches neither
+    /// `/order/123` nor any other sub-resource. Cannot be combined with
+    /// [`path_pattern`](Self::path_pattern).
+    #[must_use]
+    pub fn path_exact(mut self, path: impl Into<String>) -> Self {
+        self.path_prefix = path.into();
+        self.exact_path = true;
+        self
+    }
+
+    /// Set a glob pattern the whole path must match (e.g., "/markets/*/book").
+    ///
+    /// `*` matches exactly one segment and `**` matches zero or more segments.
+    /// See [`PathPattern`] for details. Cannot be combined with [`path`](Self::path).
+    ///
+    /// # Panics
+    ///
+    /// Panics if `pattern` does not start with `/`.
+    #[must_use]
+    pub fn path_pattern(mut self, pattern: &str) -> Self {
+        self.path_pattern = Some(PathPattern::new(pattern));
+        self
+    }
+
+    /// Set a glob pattern the whole path must match, and give each distinct
+    /// value of its `{capture}` segment its own bucket.
+    ///
+    /// Combines [`path_pattern`](Self::path_pattern) with per-key limiting:
+    /// with `"/markets/{id}/book"` and `"id"`, `/markets/A/book` and
+    /// `/markets/B/book` are limited independently, like requests keyed by
+    /// [`key_by_header`](Self::key_by_header). Unlike
+    /// [`key_fn`](Self::key_fn), this also applies to requests through the
+    /// tower layer.
+    ///
+    /// ```rust
+    /// # use route_ratelimit::RateLimitMiddleware;
+    /// # use std::time::Duration;
+    /// let middleware = RateLimitMiddleware::builder()
+    ///     .route(|r| {
+    ///         r.path_pattern_key("/markets/{id}/book", "id")
+    ///             .limit(10, Duration::from_secs(1))
+    ///     })
+    ///     .build();
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if `pattern` does not start with `/`. The route panics when
+    /// added if `pattern` has no `{capture}` segment, or if it is also keyed
+    /// by a header or a key function.
+    #[must_use]
+    pub fn path_pattern_key(mut self, pattern: &str, capture: &str) -> Self {
+        self.path_pattern = Some(PathPattern::new(pattern));
+        self.key_capture = Some(capture.to_owned());
+        self
+    }
+
+    /// Exclude requests under a path prefix from the route (e.g., "/api/health").
+    ///
+    /// Uses the same segment boundaries as [`path`](Self::path), so excluding
+    /// `/api/health` also excludes `/api/health/live` but not `/api/healthz`.
+    /// Can be called several times to exclude several prefixes.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `path_prefix` is empty.
+    #[must_use]
+    pub fn exclude_path(mut self, path_prefix: impl Into<String>) -> Self {
+        let path_prefix = path_prefix.into();
+        assert!(!path_prefix.is_empty(), "excluded path must not be empty");
+        self.excluded_paths.push(path_prefix);
+        self
+    }
+
+    /// Compare paths ignoring ASCII case, so that `/order` also matches
+    /// `/Order/123`.
+    ///
+    /// Applies to the path prefix, path pattern, and excluded paths, keeping
+    /// their segment boundaries. Paths are compared case-sensitively by
+    /// default.
+    #[must_use]
+    pub fn case_insensitive_path(mut self) -> Self {
+        self.case_insensitive_path = true;
+        self
+    }
+
+    /// Describe the route's paths with a stable label, such as
+    /// `/order/{id}`, wherever the route is reported: in
+    /// [`RateLimitError`](crate::RateLimitError)s and
+    /// [`stats`](RateLimitMiddleware::stats). Defaults to the path prefix or
+    /// pattern.
+    ///
+    /// Only a label: requests are still matched by the path prefix or
+    /// pattern.
+    ///
+    /// ```rust
+    /// # use route_ratelimit::RateLimitMiddleware;
+    /// # use std::time::Duration;
+    /// let middleware = RateLimitMiddleware::builder()
+    ///     .route(|r| {
+    ///         r.path("/order")
+    ///             .template("/order/{id}")
+    ///             .limit(10, Duration::from_secs(1))
+    ///     })
+    ///     .build();
+    /// ```
+    #[must_use]
+    pub fn template(mut self, template: impl Into<String>) -> Self {
+        self.template = Some(template.into());
+        self
+    }
+
+    /// Require a query parameter with the given value (e.g., `type=deep`).
+    ///
+    /// Can be called several times; every pair must be present for the route
+    /// to match. Values are compared after percent-decoding.
+    #[must_use]
+    pub fn query(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.query.push((key.into(), value.into()));
+        self
+    }
+
+    /// Add a rate limit.
+    #[must_use]
+    pub fn limit(mut self, requests: u32, window: Duration) -> Self {
+        self.limits
+            .push(RateLimit::unchecked(requests, window, None));
+        self
+    }
+
+    /// Add a limit of `requests` per second.
+    ///
+    /// Shorthand for `limit(requests, Duration::from_secs(1))`.
+    #[must_use]
+    pub fn limit_per_second(mut self, requests: u32) -> Self {
+        self.limits
+            .push(RateLimit::unchecked(requests, Duration::from_secs(1), None));
+        self
+    }
+
+    /// Add a limit of `requests` per minute.
+    #[must_use]
+    pub fn limit_per_minute(mut self, requests: u32) -> Self {
+        self.limits.push(RateLimit::unchecked(
+            requests,
+            Duration::from_secs(60),
+            None,
+        ));
+        self
+    }
+
+    /// Add a limit of `requests` per hour.
+    #[must_use]
+    pub fn limit_per_hour(mut self, requests: u32) -> Self {
+        self.limits.push(RateLimit::unchecked(
+            requests,
+            Duration::from_secs(60 * 60),
+            None,
+        ));
+        self
+    }
+
+    /// Add a rate limit with its own behavior when exceeded, overriding
+    /// [`on_limit`](Self::on_limit) for this limit only.
+    ///
+    /// For example, a burst limit can delay requests to smooth them out while
+    /// a daily budget fails them immediately.
+    #[must_use]
+    pub fn limit_with(
+        mut self,
+        requests: u32,
+        window: Duration,
+        behavior: ThrottleBehavior,
+    ) -> Self {
+        let mut limit = RateLimit::unchecked(requests, window, None);
+        limit.on_limit = Some(behavior);
+        self.limits.push(limit);
+        self
+    }
+
+    /// Add a rate limit that each key gets separately, within the route's
+    /// other limits.
+    ///
+    /// With [`key_by_header`](Self::key_by_header), a route's limits normally
+    /// apply to each key separately. Once it has a per-key limit, its other
+    /// limits are shared by all keys instead, so a request must pass both a
+    /// shared budget and its own key's limit. This keeps a single noisy key
+    /// from starving the others.
+    ///
+    /// ```rust
+    /// # use route_ratelimit::RateLimitMiddleware;
+    /// # use std::time::Duration;
+    /// let middleware = RateLimitMiddleware::builder()
+    ///     .route(|r| {
+    ///         r.key_by_header("X-User-Id")
+    ///             .limit(1000, Duration::from_secs(10))
+    ///             .per_key_limit(100, Duration::from_secs(10))
+    ///     })
+    ///     .build();
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Building the route panics if it has no key.
+    #[must_use]
+    pub fn per_key_limit(mut self, requests: u32, window: Duration) -> Self {
+        let mut limit = RateLimit::unchecked(requests, window, None);
+        limit.per_key = true;
+        self.limits.push(limit);
+        self
+    }
+
+    /// Add a rate limit with a burst capacity separate from its request count.
+    ///
+    /// Sustains `requests` per `window` while allowing up to `burst` requests
+    /// at once. See [`RateLimit::with_burst`].
+    #[must_use]
+    pub fn burst_limit(mut self, requests: u32, window: Duration, burst: u32) -> Self {
+        self.limits
+            .push(RateLimit::unchecked(requests, window, Some(burst)));
+        self
+    }
+
+    /// Add a limit holding up to `capacity` requests at once and refilling at
+    /// `refill_per_sec` requests per second. See [`RateLimit::token_bucket`].
+    #[must_use]
+    pub fn token_bucket_limit(mut self, capacity: u32, refill_per_sec: u32) -> Self {
+        self.limits.push(RateLimit::unchecked(
+            refill_per_sec,
+            Duration::from_secs(1),
+            Some(capacity),
+        ));
+        self
+    }
+
+    /// Add a limit of `bytes` of response bodies per `window`.
+    ///
+    /// Requests are admitted while any bytes are left, and the size of each
+    /// response body is charged once it arrives, so a large response can
+    /// exhaust the budget and delay the requests after it. The size is read
+    /// from the `Content-Length` header; responses without one are charged
+    /// [`default_response_bytes`](Self::default_response_bytes). A charge
+    /// never takes more than the whole budget.
+    ///
+    /// ```rust
+    /// # use route_ratelimit::RateLimitMiddleware;
+    /// # use std::time::Duration;
+    /// let middleware = RateLimitMiddleware::builder()
+    ///     .route(|r| {
+    ///         r.path("/download")
+    ///             .limit(10, Duration::from_secs(1))
+    ///             .bandwidth_limit(50_000_000, Duration::from_secs(60))
+    ///     })
+    ///     .build();
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Building the route panics if it uses the sliding window log
+    /// algorithm.
+    #[must_use]
+    pub fn bandwidth_limit(mut self, bytes: u32, window: Duration) -> Self {
+        let mut limit = RateLimit::unchecked(bytes, window, None);
+        limit.unit = LimitUnit::Bytes;
+        self.limits.push(limit);
+        self
+    }
+
+    /// Set the size charged to bandwidth limits for responses without a
+    /// `Content-Length` header, such as chunked ones. Defaults to 0.
+    #[must_use]
+    pub fn default_response_bytes(mut self, bytes: u64) -> Self {
+        self.default_response_bytes = bytes;
+        self
+    }
+
+    /// Set the behavior when rate limit is exceeded.
+    #[must_use]
+    pub fn on_limit(mut self, behavior: ThrottleBehavior) -> Self {
+        self.on_limit = behavior;
+        self
+    }
+
+    /// Require a request header with the given value (e.g., `X-Internal: true`).
+    ///
+    /// Can be called several times; every header must match. Values are
+    /// compared exactly.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `name` is not a valid header name.
+    #[must_use]
+    pub fn header(mut self, name: &str, value: impl Into<String>) -> Self {
+        let name = HeaderName::from_bytes(name.as_bytes()).expect("invalid header name");
+        self.headers.push((name, Some(value.into())));
+        self
+    }
+
+    /// Require a request header to be present, with any value.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `name` is not a valid header name.
+    #[must_use]
+    pub fn header_present(mut self, name: &str) -> Self {
+        let name = HeaderName::from_bytes(name.as_bytes()).expect("invalid header name");
+        self.headers.push((name, None));
+        self
+    }
+
+    /// Give each distinct value of a request header its own bucket.
+    ///
+    /// For example, keying by `X-User-Id` limits every user independently.
+    /// Requests without the header share a single default bucket.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `header` is not a valid header name.
+    #[must_use]
+    pub fn key_by_header(mut self, header: &str) -> Self {
+        let name = HeaderName::from_bytes(header.as_bytes()).expect("invalid header name");
+        self.key_header = Some(name);
+        self
+    }
+
+    /// Give each distinct key computed from a request its own bucket.
+    ///
+    /// Covers keys that [`key_by_header`](Self::key_by_header) cannot, such
+    /// as a combination of the host and a path segment. Requests for which
+    /// `key_fn` returns `None` share a single default bucket. The function is
+    /// only called for requests sent through the reqwest middleware; others,
+    /// such as those through the tower layer, use the default bucket.
+    ///
+    /// The function sees the whole request, including the authority of its
+    /// URL, so limits can also be kept per endpoint instance, e.g. with
+    /// [`keys::by_authority`](crate::keys::by_authority) for one bucket per
+    /// `host:port`.
+    ///
+    /// ```rust
+    /// # use route_ratelimit::RateLimitMiddleware;
+    /// # use std::time::Duration;
+    /// // A bucket per tenant, taken from paths like /tenants/{id}/...
+    /// let middleware = RateLimitMiddleware::builder()
+    ///     .route(|r| {
+    ///         r.path("/tenants")
+    ///             .key_fn(|req| req.url().path_segments()?.nth(1).map(str::to_owned))
+    ///             .limit(10, Duration::from_secs(1))
+    ///     })
+    ///     .build();
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Building the route panics if it is also keyed by a header.
+    #[must_use]
+    pub fn key_fn<F>(mut self, key_fn: F) -> Self
+    where
+        F: Fn(&Request) -> Option<String> + Send + Sync + 'static,
+    {
+        self.key_fn = Some(KeyFn::new(key_fn));
+        self
+    }
+
+    /// Set how many tokens each matching request consumes (default 1).
+    ///
+    /// Individual requests can override this with a
+    /// [`RequestWeight`](crate::RequestWeight) extension.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `weight` is 0.
+    #[must_use]
+    pub fn weight(mut self, weight: u32) -> Self {
+        assert!(weight > 0, "weight must be greater than 0");
+        self.weight = Some(weight);
+        self
+    }
+
+    /// Share this route's limit state with every other route in the same group.
+    ///
+    /// Requests to any route in the group draw from one set of buckets, for
+    /// example to enforce a backend-wide cap across several hosts or paths.
+    /// All routes in a group must be configured with identical limits and
+    /// algorithm; [`RateLimitBuilder::build`] panics otherwise. A request
+    /// matching several routes of the same group is only counted once.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use route_ratelimit::RateLimitMiddleware;
+    /// use std::time::Duration;
+    ///
+    /// let middleware = RateLimitMiddleware::builder()
+    ///     .route(|r| {
+    ///         r.host("clob.polymarket.com")
+    ///             .shared_group("clob")
+    ///             .limit(9000, Duration::from_secs(10))
+    ///     })
+    ///     .route(|r| {
+    ///         r.host("clob-staging.polymarket.com")
+    ///             .shared_group("clob")
+    ///             .limit(9000, Duration::from_secs(10))
+    ///     })
+    ///     .build();
+    /// ```
+    #[must_use]
+    pub fn shared_group(mut self, name: &str) -> Self {
+        self.shared_group = Some(name.to_string());
+        self
+    }
+
+    /// Select the algorithm that enforces this route's limits (default
+    /// [`Algorithm::Gcra`]).
+    #[must_use]
+    pub fn algorithm(mut self, algorithm: Algorithm) -> Self {
+        self.algorithm = algorithm;
+        self
+    }
+
+    /// Cap the number of matching requests in flight at once.
+    ///
+    /// A request takes a slot before it is sent and releases it once the
+    /// response has been received. When every slot is taken, the route's
+    /// [`on_limit`](Self::on_limit) behavior applies: `Delay` waits for a free
+    /// slot, `DelayWithTimeout` waits at most the configured duration, `Error`
+    /// fails immediately with [`RateLimitError::ConcurrencyLimited`], and
+    /// `Respond429` answers immediately with a `429` response.
+    ///
+    /// [`RateLimitError::ConcurrencyLimited`]: crate::RateLimitError::ConcurrencyLimited
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max` is 0.
+    #[must_use]
+    pub fn max_concurrent(mut self, max: usize) -> Self {
+        assert!(max > 0, "max_concurrent must be greater than 0");
+        self.max_concurrent = Some(max);
+        self
+    }
+
+    /// Cap the number of matching requests delayed at once.
+    ///
+    /// A request counts as pending while it sleeps on one of the route's
+    /// limits or waits for its turn behind other delayed requests. Once `max`
+    /// requests are pending, further requests that would be delayed fail
+    /// right away with [`RateLimitError::RateLimited`] instead of piling up,
+    /// so callers get backpressure rather than unbounded latency. See
+    /// [`RateLimitMiddleware::pending`](crate::RateLimitMiddleware::pending).
+    ///
+    /// [`RateLimitError::RateLimited`]: crate::RateLimitError::RateLimited
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max` is 0.
+    #[must_use]
+    pub fn max_pending(mut self, max: usize) -> Self {
+        assert!(max > 0, "max_pending must be greater than 0");
+        self.max_pending = Some(max);
+        self
+    }
+
+    /// Only count requests whose response status satisfies `predicate`.
+    ///
+    /// Requests are admitted as usual, but once the response arrives, the
+    /// quota they took is given back if `predicate` returns `false` for its
+    /// status, e.g. for cached `304 Not Modified` responses the server does not
+    /// count. Requests that fail without a response keep counting. A refund
+    /// never frees more than a full burst.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use http::StatusCode;
+    /// use route_ratelimit::RateLimitMiddleware;
+    /// use std::time::Duration;
+    ///
+    /// let middleware = RateLimitMiddleware::builder()
+    ///     .route(|r| {
+    ///         r.limit(100, Duration::from_secs(10))
+    ///             .count_if(|status| status != StatusCode::NOT_MODIFIED)
+    ///     })
+    ///     .build();
+    /// ```
+    #[must_use]
+    pub fn count_if<F>(mut self, predicate: F) -> Self
+    where
+        F: Fn(StatusCode) -> bool + Send + Sync + 'static,
+    {
+        self.count_if = Some(StatusPredicate::new(predicate));
+        self
+    }
+
+    /// Coalesce identical requests in flight at the same time.
+    ///
+    /// While a `GET` or `HEAD` request matching this route is in flight, later
+    /// requests with the same method, URL, and credentials (and bucket key,
+    /// for routes keyed by a header) do not hit the server or take any quota.
+    /// They wait for the first request's response instead and each receive a
+    /// copy of it. The `Authorization`, `Proxy-Authorization`, and `Cookie`
+    /// headers count as credentials; other headers are ignored, so only use
+    /// this where they do not change the response.
+    ///
+    /// The first response's body is read into memory to be shared. If the first
+    /// request fails, waiting duplicates are sent on their own.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use route_ratelimit::RateLimitMiddleware;
+    /// use std::time::Duration;
+    ///
+    /// let middleware = RateLimitMiddleware::builder()
+    ///     .route(|r| {
+    ///         r.path("/book")
+    ///             .limit(100, Duration::from_secs(10))
+    ///             .single_flight()
+    ///     })
+    ///     .build();
+    /// ```
+    #[must_use]
+    pub fn single_flight(mut self) -> Self {
+        self.single_flight = true;
+        self
+    }
+
+    /// Warm up newly created buckets over `duration` instead of starting them
+    /// full.
+    ///
+    /// A new bucket starts with the part of its burst that would have refilled
+    /// in the time left after `duration`, and is full once `duration` has
+    /// passed, so a freshly started process or a new per-key bucket cannot
+    /// spend a whole burst at once. Durations longer than the time a limit
+    /// takes to refill its burst start that limit's buckets empty.
+    ///
+    /// Only applies to the middleware's own state: buckets kept in a
+    /// [`StateBackend`](crate::StateBackend) start full.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `duration` is zero, or when the route is built if it uses
+    /// [`Algorithm::SlidingWindowLog`].
+    #[must_use]
+    pub fn ramp_up(mut self, duration: Duration) -> Self {
+        assert!(!duration.is_zero(), "ramp_up must be greater than 0");
+        self.ramp_up = Some(duration);
+        self
+    }
+
+    /// Rank the route among the routes matching a request (default 0).
+    ///
+    /// Under [`MatchMode::FirstMatch`], the matching route with the highest
+    /// priority applies, whatever the order routes are configured in; under
+    /// [`MatchMode::MostSpecific`], priority decides between equally specific
+    /// routes. Under [`MatchMode::All`], limits are checked highest priority
+    /// first. Routes of equal priority keep their configured order.
+    #[must_use]
+    pub fn priority(mut self, priority: i32) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// Also count each request to this route against the quota pool named
+    /// `pool`, defined with [`RateLimitBuilder::pool`], as `cost` requests.
+    ///
+    /// The cost is independent of the route's [`weight`](Self::weight). A
+    /// rejected or delayed request follows the route's
+    /// [`on_limit`](Self::on_limit) behavior, whether its own limits or the
+    /// pool is exhausted.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `cost` is 0.
+    #[must_use]
+    pub fn draws_from(mut self, pool: impl Into<String>, cost: u32) -> Self {
+        assert!(cost > 0, "pool cost must be greater than 0");
+        self.pools.push((pool.into(), cost));
+        self
+    }
+
+    /// Hedge slow requests: if a request has not completed after `delay`, send
+    /// a duplicate and return whichever response arrives first.
+    ///
+    /// The duplicate must pass rate limiting like any other request, consuming
+    /// quota and following the route's [`on_limit`](Self::on_limit) behavior.
+    /// If it is rejected, or is still delayed when the original completes, no
+    /// duplicate is sent. The slower request is cancelled.
+    ///
+    /// Only requests whose body can be cloned are hedged, so streaming bodies
+    /// are sent once. If a request matches several hedging routes, the shortest
+    /// delay applies. Hedging is only performed by the reqwest middleware, not
+    /// by [`RateLimitService`](crate::RateLimitService).
+    ///
+    /// Only hedge idempotent requests: both copies may reach the server.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `delay` is zero.
+    #[must_use]
+    pub fn hedge_after(mut self, delay: Duration) -> Self {
+        assert!(!delay.is_zero(), "hedge_after must be greater than 0");
+        self.hedge_after = Some(delay);
+        self
+    }
+
+    /// Open a circuit breaker after `failures` consecutive throttle events.
+    ///
+    /// While the circuit is open, requests matching the route fail immediately
+    /// with [`RateLimitError::CircuitOpen`] for the `cooldown` duration, rather
+    /// than being delayed, and `Respond429` routes answer with a `429`
+    /// response. Afterwards the circuit is half-open: a request admitted
+    /// without being throttled closes it, while a single throttle event opens
+    /// it again. The throttle event that opens the circuit fails its request
+    /// as well.
+    ///
+    /// [`RateLimitError::CircuitOpen`]: crate::RateLimitError::CircuitOpen
+    ///
+    /// # Panics
+    ///
+    /// Panics if `failures` is 0 or `cooldown` is zero.
+    #[must_use]
+    pub fn cooldown_after(mut self, failures: u32, cooldown: Duration) -> Self {
+        assert!(failures > 0, "cooldown failures must be greater than 0");
+        assert!(
+            !cooldown.is_zero(),
+            "cooldown duration must be greater than 0"
+        );
+        self.cooldown = Some(Cooldown {
+            failures,
+            duration: cooldown,
+        });
+        self
+    }
+
+    /// Adapt the route's rate to the server's feedback, between `min_rate`
+    /// and `max_rate` times its configured limits.
+    ///
+    /// The route starts at `max_rate`. Each `429 Too Many Requests` response
+    /// halves its rate, down to `min_rate`, and each successful response adds
+    /// back a twentieth of the range, so it recovers to `max_rate` after twenty
+    /// successes in a row. Other responses leave the rate unchanged. A rate of
+    /// `0.5` allows the configured number of requests per twice the window.
+    ///
+    /// # Panics
+    ///
+    /// Panics unless `0 < min_rate <= max_rate <= 1`.
+    #[must_use]
+    pub fn adaptive(mut self, min_rate: f64, max_rate: f64) -> Self {
+        let adaptive = Adaptive { min_rate, max_rate };
+        adaptive.validate();
+        self.adaptive = Some(adaptive);
+        self
+    }
+
+    /// Declare that this route intentionally matches every request.
+    ///
+    /// A route without host, scheme, method, path, or query filters applies its
+    /// limits to all traffic. Such routes must call this when the builder is
+    /// [`strict`](RateLimitBuilder::strict); otherwise it has no effect.
+    #[must_use]
+    pub fn catch_all(mut self) -> Self {
+        self.catch_all = true;
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::InvalidLimit;
+
+    #[test]
+    fn test_builder_api() {
+        let middleware = RateLimitMiddleware::builder()
+            .route(|r| {
+                r.host("api.example.com")
+                    .method(Method::POST)
+                    .path("/order")
+                    .limit(100, Duration::from_secs(10))
+                    .limit(1000, Duration::from_secs(60))
+                    .on_limit(ThrottleBehavior::Delay)
+            })
+            .route(|r| {
+                r.path("/data")
+                    .limit(50, Duration::from_secs(10))
                     .on_limit(ThrottleBehavior::Error)
             })
             .build();
 
-        assert_eq!(middleware.routes.len(), 2);
-        assert_eq!(middleware.routes[0].limits.len(), 2);
-        assert_eq!(middleware.routes[1].limits.len(), 1);
+        assert_eq!(middleware.table.load().routes.len(), 2);
+        assert_eq!(middleware.table.load().routes[0].limits.len(), 2);
+        assert_eq!(middleware.table.load().routes[1].limits.len(), 1);
+        assert_eq!(middleware.table.load().routes[0].weight, 1);
+        assert!(middleware.table.load().routes[0].key_header.is_none());
+    }
+
+    #[test]
+    fn test_key_by_header() {
+        let middleware = RateLimitMiddleware::builder()
+            .route(|r| {
+                r.key_by_header("X-User-Id")
+                    .limit(10, Duration::from_secs(1))
+            })
+            .host("api.example.com", |host| {
+                host.route(|r| {
+                    r.key_by_header("x-api-key")
+                        .limit(10, Duration::from_secs(1))
+                })
+            })
+            .build();
+
+        // Header names are case-insensitive
+        assert_eq!(
+            middleware.table.load().routes[0]
+                .key_header
+                .as_ref()
+                .map(HeaderName::as_str),
+            Some("x-user-id")
+        );
+        assert_eq!(
+            middleware.table.load().routes[1]
+                .key_header
+                .as_ref()
+                .map(HeaderName::as_str),
+            Some("x-api-key")
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid header name")]
+    fn test_invalid_key_header_panics() {
+        let _builder = RouteBuilder::new().key_by_header("bad header");
+    }
+
+    #[test]
+    fn test_multiple_hosts() {
+        let middleware = RateLimitMiddleware::builder()
+            .route(|r| {
+                r.host("api-us.example.com")
+                    .hosts(["api.example.com", "api-eu.example.com"])
+                    .limit(100, Duration::from_secs(10))
+            })
+            .route(|r| {
+                r.hosts(["api.example.com", "api-eu.example.com"])
+                    .host("api-us.example.com")
+                    .limit(100, Duration::from_secs(10))
+            })
+            .build();
+
+        assert_eq!(middleware.table.load().routes[0].hosts.len(), 3);
+        assert!(
+            middleware.table.load().routes[0]
+                .hosts
+                .contains("api-eu.example.com")
+        );
+        assert!(
+            middleware.table.load().routes[0]
+                .hosts
+                .contains("api-us.example.com")
+        );
+
+        // `host` replaces the hosts set before it
+        assert_eq!(
+            middleware.table.load().routes[1].hosts,
+            BTreeSet::from(["api-us.example.com".to_string()])
+        );
+    }
+
+    #[test]
+    fn test_host_suffix() {
+        let middleware = RateLimitMiddleware::builder()
+            .route(|r| {
+                r.host_suffix(".api.example.com")
+                    .host("api.example.com")
+                    .limit(100, Duration::from_secs(10))
+            })
+            .build();
+
+        let table = middleware.table.load();
+        assert!(table.routes[0].host_suffixes.contains(".api.example.com"));
+        assert!(!table.routes[0].is_catch_all());
+        assert_eq!(
+            table.routes[0].host_description(),
+            "api.example.com,*.api.example.com"
+        );
+    }
+
+    #[test]
+    fn test_methods() {
+        let routes = RateLimitMiddleware::builder()
+            .route(|r| {
+                r.methods([Method::PUT, Method::PATCH])
+                    .method(Method::PUT)
+                    .limit(100, Duration::from_secs(10))
+            })
+            .route(|r| {
+                r.include_head()
+                    .method(Method::GET)
+                    .limit(100, Duration::from_secs(10))
+            })
+            .host("api.example.com", |host| {
+                host.route(|r| r.include_head().limit(100, Duration::from_secs(10)))
+                    .route(|r| {
+                        r.method(Method::POST)
+                            .include_head()
+                            .limit(100, Duration::from_secs(10))
+                    })
+            })
+            .into_routes();
+
+        assert_eq!(routes[0].methods, [Method::PUT, Method::PATCH]);
+        assert_eq!(routes[1].methods, [Method::GET, Method::HEAD]);
+        // Without GET, including HEAD must not narrow the route to HEAD
+        assert!(routes[2].methods.is_empty());
+        assert_eq!(routes[3].methods, [Method::POST]);
+    }
+
+    #[test]
+    #[should_panic(expected = "host suffix must not be empty")]
+    fn test_empty_host_suffix_panics() {
+        let _builder = RouteBuilder::new().host_suffix(".");
+    }
+
+    #[test]
+    fn test_route_weight() {
+        let middleware = RateLimitMiddleware::builder()
+            .route(|r| {
+                r.path("/orders")
+                    .weight(5)
+                    .limit(100, Duration::from_secs(10))
+            })
+            .host("api.example.com", |host| {
+                host.route(|r| r.path("/batch").weight(3).limit(10, Duration::from_secs(1)))
+            })
+            .build();
+
+        assert_eq!(middleware.table.load().routes[0].weight, 5);
+        assert_eq!(middleware.table.load().routes[1].weight, 3);
+    }
+
+    #[test]
+    #[should_panic(expected = "weight must be greater than 0")]
+    fn test_zero_weight_panics() {
+        let _builder = RouteBuilder::new().weight(0);
+    }
+
+    #[test]
+    fn test_jitter() {
+        assert_eq!(RateLimitMiddleware::builder().build().jitter, 0.5);
+        assert_eq!(
+            RateLimitMiddleware::builder().jitter(0.0).build().jitter,
+            0.0
+        );
+        assert_eq!(
+            RateLimitMiddleware::builder().jitter(1.0).build().jitter,
+            1.0
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "jitter fraction must be between 0.0 and 1.0")]
+    fn test_invalid_jitter_panics() {
+        let _builder = RateLimitBuilder::new().jitter(1.5);
+    }
+
+    #[test]
+    #[should_panic(expected = "jitter fraction must be between 0.0 and 1.0")]
+    fn test_nan_jitter_panics() {
+        let _builder = RateLimitBuilder::new().jitter(f64::NAN);
+    }
+
+    #[test]
+    fn test_route_scheme() {
+        let middleware = RateLimitMiddleware::builder()
+            .route(|r| r.scheme("HTTP").limit(10, Duration::from_secs(1)))
+            .host("api.example.com", |host| {
+                host.route(|r| r.limit(100, Duration::from_secs(1)))
+            })
+            .build();
+
+        assert_eq!(
+            middleware.table.load().routes[0].scheme.as_deref(),
+            Some("http")
+        );
+        assert_eq!(middleware.table.load().routes[1].scheme, None);
+    }
+
+    #[test]
+    fn test_route_query() {
+        let middleware = RateLimitMiddleware::builder()
+            .route(|r| {
+                r.path("/search")
+                    .query("type", "deep")
+                    .query("lang", "en")
+                    .limit(10, Duration::from_secs(1))
+            })
+            .host("api.example.com", |host| {
+                host.route(|r| {
+                    r.query("type", "shallow")
+                        .limit(100, Duration::from_secs(1))
+                })
+            })
+            .build();
+
+        assert_eq!(
+            middleware.table.load().routes[0].query,
+            vec![
+                ("type".to_string(), "deep".to_string()),
+                ("lang".to_string(), "en".to_string()),
+            ]
+        );
+        assert_eq!(
+            middleware.table.load().routes[1].query,
+            vec![("type".to_string(), "shallow".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_route_shared_group() {
+        let middleware = RateLimitMiddleware::builder()
+            .route(|r| {
+                r.path("/a")
+                    .shared_group("backend")
+                    .limit(3, Duration::from_secs(10))
+            })
+            .host("api.example.com", |host| {
+                host.route(|r| r.shared_group("backend").limit(3, Duration::from_secs(10)))
+            })
+            .build();
+
+        let table = middleware.table.load();
+        assert_eq!(table.routes[0].shared_group.as_deref(), Some("backend"));
+        assert_eq!(table.routes[1].shared_group.as_deref(), Some("backend"));
+        assert_eq!(table.groups["backend"], 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "must have identical limits and algorithm")]
+    fn test_shared_group_with_different_limits_panics() {
+        let _middleware = RateLimitMiddleware::builder()
+            .route(|r| r.shared_group("backend").limit(3, Duration::from_secs(10)))
+            .route(|r| r.shared_group("backend").limit(5, Duration::from_secs(10)))
+            .build();
+    }
+
+    #[test]
+    fn test_route_algorithm() {
+        let middleware = RateLimitMiddleware::builder()
+            .route(|r| {
+                r.algorithm(Algorithm::TokenBucket)
+                    .limit(100, Duration::from_secs(10))
+            })
+            .host("api.example.com", |host| {
+                host.route(|r| r.limit(10, Duration::from_secs(1)))
+            })
+            .build();
+
+        assert_eq!(
+            middleware.table.load().routes[0].algorithm,
+            Algorithm::TokenBucket
+        );
+        assert_eq!(middleware.table.load().routes[1].algorithm, Algorithm::Gcra);
+    }
+
+    #[test]
+    fn test_route_max_concurrent() {
+        let middleware = RateLimitMiddleware::builder()
+            .route(|r| {
+                r.path("/orders")
+                    .max_concurrent(4)
+                    .limit(100, Duration::from_secs(10))
+            })
+            .host("api.example.com", |host| {
+                host.route(|r| r.limit(10, Duration::from_secs(1)))
+            })
+            .build();
+
+        assert_eq!(middleware.table.load().routes[0].max_concurrent, Some(4));
+        assert_eq!(middleware.table.load().routes[1].max_concurrent, None);
+        assert!(middleware.table.load().semaphores[0].is_some());
+        assert!(middleware.table.load().semaphores[1].is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "max_concurrent must be greater than 0")]
+    fn test_zero_max_concurrent_panics() {
+        let _builder = RouteBuilder::new().max_concurrent(0);
+    }
+
+    #[test]
+    #[should_panic(expected = "max_pending must be greater than 0")]
+    fn test_zero_max_pending_panics() {
+        let _builder = RouteBuilder::new().max_pending(0);
+    }
+
+    #[test]
+    fn test_limit_per_unit() {
+        let middleware = RateLimitMiddleware::builder()
+            .route(|r| r.limit_per_second(10).limit_per_minute(300))
+            .host("api.example.com", |host| {
+                host.route(|r| r.limit_per_hour(1000))
+            })
+            .build();
+
+        let table = middleware.table.load();
+        assert_eq!(
+            table.routes[0].limits,
+            [RateLimit::per_second(10), RateLimit::per_minute(300)]
+        );
+        assert_eq!(table.routes[1].limits[0].window, Duration::from_secs(3600));
+    }
+
+    #[test]
+    fn test_burst_limit() {
+        let middleware = RateLimitMiddleware::builder()
+            .route(|r| r.burst_limit(10, Duration::from_secs(1), 50))
+            .host("api.example.com", |host| {
+                host.route(|r| r.burst_limit(5, Duration::from_secs(1), 20))
+            })
+            .build();
+
+        let table = middleware.table.load();
+        assert_eq!(
+            table.routes[0].limits[0],
+            RateLimit::with_burst(10, Duration::from_secs(1), 50)
+        );
+        assert_eq!(table.routes[1].limits[0].burst, Some(20));
+    }
+
+    #[test]
+    #[should_panic(expected = "sliding window log algorithm does not support a separate burst")]
+    fn test_sliding_window_burst_panics() {
+        let _middleware = RateLimitMiddleware::builder()
+            .route(|r| {
+                r.algorithm(Algorithm::SlidingWindowLog)
+                    .burst_limit(10, Duration::from_secs(1), 50)
+            })
+            .build();
+    }
+
+    #[test]
+    #[should_panic(expected = "sliding window log algorithm does not support bandwidth limits")]
+    fn test_sliding_window_bandwidth_limit_panics() {
+        let _middleware = RateLimitMiddleware::builder()
+            .route(|r| {
+                r.algorithm(Algorithm::SlidingWindowLog)
+                    .bandwidth_limit(1_000_000, Duration::from_secs(1))
+            })
+            .build();
+    }
+
+    #[test]
+    #[should_panic(expected = "sliding window log algorithm does not support ramp_up")]
+    fn test_sliding_window_ramp_up_panics() {
+        let _middleware = RateLimitMiddleware::builder()
+            .route(|r| {
+                r.algorithm(Algorithm::SlidingWindowLog)
+                    .limit(10, Duration::from_secs(1))
+                    .ramp_up(Duration::from_secs(1))
+            })
+            .build();
+    }
+
+    #[test]
+    fn test_strict_accepts_declared_catch_all() {
+        let middleware = RateLimitMiddleware::builder()
+            .strict()
+            .host("api.example.com", |host| {
+                host.route(|r| r.limit(10, Duration::from_secs(1)))
+            })
+            .route(|r| r.path("/order").limit(10, Duration::from_secs(1)))
+            .route(|r| r.catch_all().limit(100, Duration::from_secs(10)))
+            .build();
+
+        let table = middleware.table.load();
+        assert!(!table.routes[1].catch_all);
+        assert!(table.routes[2].catch_all);
+    }
+
+    #[test]
+    #[should_panic(expected = "route 1 matches every request")]
+    fn test_strict_rejects_undeclared_catch_all() {
+        let _middleware = RateLimitMiddleware::builder()
+            .strict()
+            .route(|r| r.path("/order").limit(10, Duration::from_secs(1)))
+            .route(|r| r.limit(100, Duration::from_secs(10)))
+            .build();
+    }
+
+    #[test]
+    fn test_lenient_allows_undeclared_catch_all() {
+        let middleware = RateLimitMiddleware::builder()
+            .route(|r| r.limit(100, Duration::from_secs(10)))
+            .build();
+
+        let table = middleware.table.load();
+        assert!(table.routes[0].is_catch_all());
+        assert!(!table.routes[0].catch_all);
+    }
+
+    #[test]
+    fn test_route_cooldown_after() {
+        let middleware = RateLimitMiddleware::builder()
+            .route(|r| {
+                r.cooldown_after(5, Duration::from_secs(30))
+                    .limit(10, Duration::from_secs(1))
+            })
+            .host("api.example.com", |host| {
+                host.route(|r| r.limit(10, Duration::from_secs(1)))
+            })
+            .build();
+
+        let table = middleware.table.load();
+        assert_eq!(
+            table.routes[0].cooldown,
+            Some(Cooldown {
+                failures: 5,
+                duration: Duration::from_secs(30),
+            })
+        );
+        assert_eq!(table.routes[1].cooldown, None);
+        assert!(table.breakers[0].is_some());
+        assert!(table.breakers[1].is_none());
+    }
+
+    #[test]
+    fn test_state_capacity() {
+        let middleware = RateLimitMiddleware::builder()
+            .state_capacity(100_000)
+            .route(|r| {
+                r.key_by_header("X-User-Id")
+                    .limit(1, Duration::from_secs(1))
+            })
+            .build();
+
+        assert!(middleware.table.load().state.capacity() >= 100_000);
+
+        let req = reqwest::Client::new()
+            .get("https://api.example.com/")
+            .header("X-User-Id", "1")
+            .build()
+            .unwrap();
+        assert!(middleware.would_allow(&req));
+    }
+
+    #[test]
+    #[should_panic(expected = "adaptive rates must satisfy 0 < min_rate <= max_rate <= 1")]
+    fn test_inverted_adaptive_rates_panics() {
+        let _builder = RouteBuilder::new().adaptive(1.0, 0.5);
+    }
+
+    #[test]
+    #[should_panic(expected = "key_by_header and key_fn are mutually exclusive")]
+    fn test_key_header_and_key_fn_panics() {
+        let _middleware = RateLimitBuilder::new()
+            .route(|r| {
+                r.key_by_header("X-User-Id")
+                    .key_fn(|req| Some(req.url().path().to_owned()))
+                    .limit(10, Duration::from_secs(1))
+            })
+            .build();
+    }
+
+    #[test]
+    #[should_panic(expected = "key capture {market} is not in the route's path pattern")]
+    fn test_path_pattern_key_without_capture_panics() {
+        let _middleware = RateLimitBuilder::new()
+            .route(|r| {
+                r.path_pattern_key("/markets/{id}/book", "market")
+                    .limit(10, Duration::from_secs(1))
+            })
+            .build();
+    }
+
+    #[test]
+    #[should_panic(expected = "per-key limits require a key")]
+    fn test_per_key_limit_without_key_panics() {
+        let _middleware = RateLimitBuilder::new()
+            .route(|r| {
+                r.limit(1000, Duration::from_secs(10))
+                    .per_key_limit(100, Duration::from_secs(10))
+            })
+            .build();
+    }
+
+    #[test]
+    #[should_panic(expected = "hedge_after must be greater than 0")]
+    fn test_zero_hedge_after_panics() {
+        let _builder = RouteBuilder::new().hedge_after(Duration::ZERO);
+    }
+
+    #[test]
+    #[should_panic(expected = "cleanup staleness multiplier must be at least 1")]
+    fn test_zero_cleanup_stale_after_panics() {
+        let _builder = RateLimitMiddleware::builder().cleanup_stale_after(0);
+    }
+
+    #[test]
+    #[should_panic(expected = "max_total_delay must be greater than 0")]
+    fn test_zero_max_total_delay_panics() {
+        let _builder = RateLimitMiddleware::builder().max_total_delay(Duration::ZERO);
+    }
+
+    #[test]
+    #[should_panic(expected = "cooldown failures must be greater than 0")]
+    fn test_zero_cooldown_failures_panics() {
+        let _builder = RouteBuilder::new().cooldown_after(0, Duration::from_secs(1));
     }
 
     #[test]
@@ -371,28 +3165,87 @@ mod tests {
             .build();
 
         // 4 routes for CLOB + 2 routes for Data API = 6 routes
-        assert_eq!(middleware.routes.len(), 6);
+        assert_eq!(middleware.table.load().routes.len(), 6);
 
         // Check that all CLOB routes have the correct host
         for i in 0..4 {
-            assert_eq!(
-                middleware.routes[i].host.as_deref(),
-                Some("clob.polymarket.com")
+            assert!(
+                middleware.table.load().routes[i]
+                    .hosts
+                    .contains("clob.polymarket.com")
             );
         }
 
         // Check that all Data API routes have the correct host
         for i in 4..6 {
-            assert_eq!(
-                middleware.routes[i].host.as_deref(),
-                Some("data-api.polymarket.com")
+            assert!(
+                middleware.table.load().routes[i]
+                    .hosts
+                    .contains("data-api.polymarket.com")
             );
         }
 
         // Check the trading endpoint has burst + sustained limits
-        assert_eq!(middleware.routes[3].path_prefix, "/order");
-        assert_eq!(middleware.routes[3].method, Some(Method::POST));
-        assert_eq!(middleware.routes[3].limits.len(), 2);
+        assert_eq!(middleware.table.load().routes[3].path_prefix, "/order");
+        assert_eq!(middleware.table.load().routes[3].methods, [Method::POST]);
+        assert_eq!(middleware.table.load().routes[3].limits.len(), 2);
+    }
+
+    #[test]
+    fn test_merge_appends_routes_in_order() {
+        let clob = RateLimitMiddleware::builder().host("clob.polymarket.com", |host| {
+            host.route(|r| r.limit(9000, Duration::from_secs(10)))
+                .route(|r| r.path("/book").limit(1500, Duration::from_secs(10)))
+        });
+        let data =
+            RateLimitMiddleware::builder()
+                .jitter(0.0)
+                .host("data-api.polymarket.com", |host| {
+                    host.route(|r| r.limit(1000, Duration::from_secs(10)))
+                        .route(|r| r.path("/trades").limit(200, Duration::from_secs(10)))
+                });
+
+        let middleware = clob.merge(data).build();
+
+        let table = middleware.table.load();
+        assert_eq!(table.routes.len(), 4);
+        assert!(
+            table.routes[..2]
+                .iter()
+                .all(|route| route.hosts.contains("clob.polymarket.com"))
+        );
+        assert!(
+            table.routes[2..]
+                .iter()
+                .all(|route| route.hosts.contains("data-api.polymarket.com"))
+        );
+        assert_eq!(table.routes[1].path_prefix, "/book");
+        assert_eq!(table.routes[3].path_prefix, "/trades");
+        // Settings other than routes are not taken from the merged builder
+        assert_eq!(middleware.jitter, DEFAULT_JITTER);
+    }
+
+    #[test]
+    fn test_host_default_behavior() {
+        let middleware = RateLimitMiddleware::builder()
+            .host("api.example.com", |host| {
+                host.route(|r| r.limit(100, Duration::from_secs(10)))
+                    .default_behavior(ThrottleBehavior::Error)
+                    .route(|r| r.path("/book").limit(10, Duration::from_secs(10)))
+                    .route(|r| {
+                        r.path("/order")
+                            .limit(10, Duration::from_secs(10))
+                            .on_limit(ThrottleBehavior::Delay)
+                    })
+            })
+            .build();
+
+        let table = middleware.table.load();
+        // Routes added before the default keep the type default
+        assert_eq!(table.routes[0].on_limit, ThrottleBehavior::Delay);
+        assert_eq!(table.routes[1].on_limit, ThrottleBehavior::Error);
+        // An explicit behavior wins, even if it equals the type default
+        assert_eq!(table.routes[2].on_limit, ThrottleBehavior::Delay);
     }
 
     #[test]
@@ -407,11 +3260,11 @@ mod tests {
             })
             .build();
 
-        assert_eq!(middleware.routes.len(), 2);
-        assert!(middleware.routes[0].host.is_none()); // Global route
+        assert_eq!(middleware.table.load().routes.len(), 2);
+        assert!(middleware.table.load().routes[0].hosts.is_empty()); // Global route
         assert_eq!(
-            middleware.routes[1].host.as_deref(),
-            Some("api.example.com")
+            middleware.table.load().routes[1].hosts,
+            BTreeSet::from(["api.example.com".to_string()])
         );
     }
 
@@ -426,7 +3279,59 @@ mod tests {
             })
             .build();
 
-        assert_eq!(middleware.routes.len(), 3);
+        assert_eq!(middleware.table.load().routes.len(), 3);
+    }
+
+    #[test]
+    fn test_path_pattern_builder() {
+        let middleware = RateLimitMiddleware::builder()
+            .route(|r| {
+                r.path_pattern("/markets/*/book")
+                    .limit(10, Duration::from_secs(1))
+            })
+            .host("api.example.com", |host| {
+                host.route(|r| {
+                    r.path_pattern("/users/**")
+                        .limit(10, Duration::from_secs(1))
+                })
+            })
+            .build();
+
+        assert_eq!(
+            middleware.table.load().routes[0]
+                .path_pattern
+                .as_ref()
+                .map(PathPattern::as_str),
+            Some("/markets/*/book")
+        );
+        assert!(middleware.table.load().routes[0].path_prefix.is_empty());
+        assert!(middleware.table.load().routes[1].path_pattern.is_some());
+    }
+
+    #[test]
+    #[should_panic(expected = "path and path_pattern are mutually exclusive")]
+    fn test_path_and_pattern_panics() {
+        let _middleware = RateLimitMiddleware::builder()
+            .route(|r| {
+                r.path("/markets")
+                    .path_pattern("/markets/*/book")
+                    .limit(10, Duration::from_secs(1))
+            })
+            .build();
+    }
+
+    #[test]
+    #[should_panic(expected = "path and path_pattern are mutually exclusive")]
+    fn test_host_path_and_pattern_panics() {
+        let _middleware = RateLimitMiddleware::builder()
+            .host("api.example.com", |host| {
+                host.route(|r| {
+                    r.path("/markets")
+                        .path_pattern("/markets/*/book")
+                        .limit(10, Duration::from_secs(1))
+                })
+            })
+            .build();
     }
 
     #[test]
@@ -444,4 +3349,133 @@ mod tests {
             .host("api.example.com", |host| host.route(|r| r.path("/test")))
             .build();
     }
+
+    #[test]
+    fn test_try_build_reports_route_without_limit() {
+        let result = RateLimitMiddleware::builder()
+            .route(|r| r.path("/book").limit(10, Duration::from_secs(1)))
+            .host("api.example.com", |host| host.route(|r| r.path("/test")))
+            .try_build();
+        assert_eq!(result.unwrap_err(), BuildError::NoLimits { route: 1 });
+    }
+
+    #[test]
+    fn test_try_build_reports_invalid_limit() {
+        let result = RateLimitMiddleware::builder()
+            .route(|r| {
+                r.limit(10, Duration::from_secs(1))
+                    .burst_limit(10, Duration::ZERO, 5)
+            })
+            .try_build();
+        assert_eq!(
+            result.unwrap_err(),
+            BuildError::InvalidLimit {
+                route: 0,
+                limit: 1,
+                reason: InvalidLimit::ZeroWindow,
+            }
+        );
+
+        // Less than a nanosecond per request would round the interval to zero
+        let result = RateLimitMiddleware::builder()
+            .route(|r| r.limit(2_000, Duration::from_micros(1)))
+            .try_build();
+        assert_eq!(
+            result.unwrap_err(),
+            BuildError::InvalidLimit {
+                route: 0,
+                limit: 0,
+                reason: InvalidLimit::WindowTooShort,
+            }
+        );
+    }
+
+    #[test]
+    fn test_try_build_reports_invalid_route() {
+        let err = RateLimitMiddleware::builder()
+            .route(|r| {
+                r.algorithm(Algorithm::SlidingWindowLog)
+                    .burst_limit(10, Duration::from_secs(1), 20)
+            })
+            .try_build()
+            .unwrap_err();
+        assert!(matches!(err, BuildError::InvalidRoute { route: 0, .. }));
+        assert_eq!(
+            err.to_string(),
+            "the sliding window log algorithm does not support a separate burst (route 0)"
+        );
+    }
+
+    #[test]
+    fn test_try_build_reports_undeclared_catch_all() {
+        let result = RateLimitMiddleware::builder()
+            .strict()
+            .route(|r| r.path("/order").limit(10, Duration::from_secs(1)))
+            .route(|r| r.limit(100, Duration::from_secs(10)))
+            .try_build();
+        assert_eq!(
+            result.unwrap_err(),
+            BuildError::UndeclaredCatchAll { route: 1 }
+        );
+    }
+
+    #[test]
+    fn test_try_build_reports_shared_group_mismatch() {
+        let result = RateLimitMiddleware::builder()
+            .route(|r| r.shared_group("backend").limit(3, Duration::from_secs(10)))
+            .route(|r| r.shared_group("backend").limit(5, Duration::from_secs(10)))
+            .try_build();
+        assert_eq!(
+            result.unwrap_err(),
+            BuildError::SharedGroupMismatch {
+                group: "backend".to_owned()
+            }
+        );
+    }
+
+    #[test]
+    fn test_try_build_reports_unknown_pool() {
+        let result = RateLimitMiddleware::builder()
+            .pool("provider", 100, Duration::from_secs(60))
+            .route(|r| {
+                r.draws_from("provider", 1)
+                    .limit(10, Duration::from_secs(1))
+            })
+            .route(|r| r.draws_from("other", 1).limit(10, Duration::from_secs(1)))
+            .try_build();
+        assert_eq!(
+            result.unwrap_err(),
+            BuildError::UnknownPool {
+                route: 1,
+                pool: "other".to_owned()
+            }
+        );
+
+        let result = RateLimitMiddleware::builder()
+            .pool("provider", 0, Duration::from_secs(60))
+            .try_build();
+        assert_eq!(
+            result.unwrap_err(),
+            BuildError::InvalidPool {
+                pool: "provider".to_owned(),
+                reason: InvalidLimit::ZeroRequests,
+            }
+        );
+    }
+
+    #[test]
+    fn test_try_build_denies_warnings_on_request() {
+        let builder = RateLimitMiddleware::builder()
+            .route(|r| r.path("/book").limit(100, Duration::from_secs(10)))
+            .route(|r| r.path("/book").limit(50, Duration::from_secs(1)));
+        assert!(builder.clone().try_build().is_ok());
+
+        assert_eq!(
+            builder.deny_warnings().try_build().unwrap_err(),
+            BuildError::Warnings(vec![ConfigWarning::DuplicateRoute {
+                first: 0,
+                duplicate: 1
+            }])
+        );
+    }
 }
@@ -0,0 +1,131 @@
+//! Circuit breaker that fails requests fast after repeated throttling.
+
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::time::Duration;
+
+use crate::types::Cooldown;
+
+/// Per-route circuit breaker state.
+///
+/// Counts consecutive throttle events and opens the circuit once they reach
+/// the configured number. While open, requests are rejected without waiting for
+/// the rate limiter. Once the cooldown ends the circuit is half-open: a request
+/// admitted without throttling closes it, but a single further throttle event
+/// opens it again.
+#[derive(Debug)]
+pub(crate) struct CircuitBreaker {
+    /// Consecutive throttle events since the last unthrottled request.
+    failures: AtomicU32,
+    /// Time until which the circuit is open, in nanoseconds since the start
+    /// instant. Zero if it has never opened.
+    open_until_nanos: AtomicU64,
+    threshold: u32,
+    cooldown_nanos: u64,
+}
+
+impl CircuitBreaker {
+    /// Create a closed circuit breaker.
+    pub fn new(cooldown: Cooldown) -> Self {
+        Self {
+            failures: AtomicU32::new(0),
+            open_until_nanos: AtomicU64::new(0),
+            threshold: cooldown.failures,
+            cooldown_nanos: cooldown.duration.as_nanos().min(u64::MAX as u128) as u64,
+        }
+    }
+
+    /// Returns Ok(()) if the circuit lets requests through at `now_nanos`, or
+    /// Err(remaining_cooldown) if it is open.
+    pub fn check(&self, now_nanos: u64) -> Result<(), Duration> {
+        let open_until = self.open_until_nanos.load(Ordering::Acquire);
+        if open_until > now_nanos {
+            return Err(Duration::from_nanos(open_until - now_nanos));
+        }
+        Ok(())
+    }
+
+    /// Count a throttle event at `now_nanos`.
+    ///
+    /// Returns `true` if this event opened the circuit.
+    pub fn record_throttle(&self, now_nanos: u64) -> bool {
+        let failures = self
+            .failures
+            .fetch_add(1, Ordering::AcqRel)
+            .saturating_add(1);
+        if failures < self.threshold {
+            return false;
+        }
+
+        // Half-open once the cooldown ends: one more throttle event reopens it
+        self.failures
+            .store(self.threshold.saturating_sub(1), Ordering::Release);
+        self.open_until_nanos.fetch_max(
+            now_nanos.saturating_add(self.cooldown_nanos),
+            Ordering::AcqRel,
+        );
+        true
+    }
+
+    /// Reset the count after a request was admitted without throttling.
+    pub fn record_success(&self) {
+        self.failures.store(0, Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECOND: u64 = 1_000_000_000;
+
+    fn breaker() -> CircuitBreaker {
+        CircuitBreaker::new(Cooldown {
+            failures: 3,
+            duration: Duration::from_secs(5),
+        })
+    }
+
+    #[test]
+    fn test_opens_after_consecutive_throttles() {
+        let breaker = breaker();
+
+        assert!(!breaker.record_throttle(0));
+        assert!(!breaker.record_throttle(0));
+        assert!(breaker.check(0).is_ok());
+        assert!(breaker.record_throttle(SECOND));
+
+        assert_eq!(breaker.check(SECOND), Err(Duration::from_secs(5)));
+        assert_eq!(breaker.check(5 * SECOND), Err(Duration::from_secs(1)));
+        assert!(breaker.check(6 * SECOND).is_ok());
+    }
+
+    #[test]
+    fn test_success_resets_count() {
+        let breaker = breaker();
+
+        breaker.record_throttle(0);
+        breaker.record_throttle(0);
+        breaker.record_success();
+        assert!(!breaker.record_throttle(0));
+        assert!(!breaker.record_throttle(0));
+        assert!(breaker.check(0).is_ok());
+    }
+
+    #[test]
+    fn test_half_open_after_cooldown() {
+        let breaker = breaker();
+        for _ in 0..3 {
+            breaker.record_throttle(0);
+        }
+
+        // A single throttle event after the cooldown reopens the circuit
+        assert!(breaker.check(5 * SECOND).is_ok());
+        assert!(breaker.record_throttle(5 * SECOND));
+        assert!(breaker.check(6 * SECOND).is_err());
+
+        // A success after the cooldown closes it
+        breaker.record_success();
+        assert!(!breaker.record_throttle(10 * SECOND));
+        assert!(breaker.check(10 * SECOND).is_ok());
+    }
+}
@@ -0,0 +1,50 @@
+//! Time sources for rate limiting.
+//!
+//! The middleware measures time as nanoseconds elapsed since an arbitrary,
+//! fixed origin. Abstracting this behind the [`Clock`] trait allows tests to
+//! control time precisely instead of relying on real sleeps.
+
+use std::fmt::Debug;
+use std::time::Instant;
+
+/// A monotonic time source used by the middleware.
+///
+/// Implementations must be monotonic: successive calls must never return a
+/// smaller value than a previous call.
+pub trait Clock: Debug + Send + Sync {
+    /// Returns the number of nanoseconds elapsed since the clock's origin.
+    fn now_nanos(&self) -> u64;
+}
+
+/// The default [`Clock`], backed by [`Instant`].
+///
+/// The origin is the moment the clock was created.
+#[derive(Debug, Clone, Copy)]
+pub struct MonotonicClock {
+    start: Instant,
+}
+
+impl MonotonicClock {
+    /// Create a new clock whose origin is the current instant.
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Default for MonotonicClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for MonotonicClock {
+    #[inline]
+    fn now_nanos(&self) -> u64 {
+        // Use saturating conversion to prevent overflow on very long-running processes
+        // (would require running for ~585 years to overflow)
+        self.start.elapsed().as_nanos().min(u64::MAX as u128) as u64
+    }
+}
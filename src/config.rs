@@ -0,0 +1,317 @@
+//! Serde support for loading rate limit configuration from files.
+//!
+//! Enabled by the `serde` feature. Durations are represented as integer
+//! milliseconds, HTTP methods and header names as strings, and path patterns
+//! by their glob source.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::types::{RateLimit, Route};
+
+/// A serializable rate limit configuration.
+///
+/// Load it with any serde format and pass it to
+/// [`RateLimitBuilder::from_config`](crate::RateLimitBuilder::from_config).
+///
+/// # Example
+///
+/// ```rust
+/// use route_ratelimit::{RateLimitBuilder, RateLimitConfig};
+///
+/// let config: RateLimitConfig = serde_json::from_str(r#"{
+///     "routes": [
+///         {
+///             "hosts": ["api.example.com"],
+///             "methods": ["POST"],
+///             "path_prefix": "/order",
+///             "limits": [{ "requests": 100, "window": 10000 }],
+///             "on_limit": "error"
+///         }
+///     ]
+/// }"#).unwrap();
+///
+/// let middleware = RateLimitBuilder::from_config(config).build();
+/// ```
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RateLimitConfig {
+    /// The routes to configure, in matching order.
+    #[serde(default)]
+    pub routes: Vec<Route>,
+    /// Quota pools routes can draw from, by name. See
+    /// [`RateLimitBuilder::pool`](crate::RateLimitBuilder::pool).
+    #[serde(default)]
+    pub pools: BTreeMap<String, RateLimit>,
+}
+
+/// Serialize a [`Duration`](std::time::Duration) as integer milliseconds.
+pub(crate) mod duration_millis {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use std::time::Duration;
+
+    pub fn serialize<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+        let millis = duration.as_millis().min(u64::MAX as u128) as u64;
+        millis.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        u64::deserialize(deserializer).map(Duration::from_millis)
+    }
+}
+
+/// Serialize an optional [`Duration`](std::time::Duration) as integer
+/// milliseconds.
+pub(crate) mod option_duration_millis {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::time::Duration;
+
+    pub fn serialize<S: Serializer>(
+        duration: &Option<Duration>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match duration {
+            Some(duration) => {
+                serializer.serialize_some(&(duration.as_millis().min(u64::MAX as u128) as u64))
+            }
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Duration>, D::Error> {
+        Ok(Option::<u64>::deserialize(deserializer)?.map(Duration::from_millis))
+    }
+}
+
+/// Serialize a list of [`Method`](http::Method)s as their string forms.
+pub(crate) mod method_list {
+    use http::Method;
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(methods: &[Method], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(methods.iter().map(Method::as_str))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<Method>, D::Error> {
+        Vec::<String>::deserialize(deserializer)?
+            .into_iter()
+            .map(|method| Method::from_bytes(method.as_bytes()).map_err(D::Error::custom))
+            .collect()
+    }
+}
+
+/// Serialize an optional [`HeaderName`](http::HeaderName) as its string form.
+pub(crate) mod header_name_str {
+    use http::HeaderName;
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        header: &Option<HeaderName>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match header {
+            Some(header) => serializer.serialize_some(header.as_str()),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<HeaderName>, D::Error> {
+        Option::<String>::deserialize(deserializer)?
+            .map(|header| HeaderName::from_bytes(header.as_bytes()).map_err(D::Error::custom))
+            .transpose()
+    }
+}
+
+/// Serialize header filters as a list of `{ name, value }` tables, omitting
+/// the value of presence-only filters.
+pub(crate) mod header_list {
+    use http::HeaderName;
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    #[derive(Serialize, Deserialize)]
+    struct Header<N, V> {
+        name: N,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        value: Option<V>,
+    }
+
+    pub fn serialize<S: Serializer>(
+        headers: &[(HeaderName, Option<String>)],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(headers.iter().map(|(name, value)| Header {
+            name: name.as_str(),
+            value: value.as_deref(),
+        }))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<(HeaderName, Option<String>)>, D::Error> {
+        Vec::<Header<String, String>>::deserialize(deserializer)?
+            .into_iter()
+            .map(|header| {
+                let name =
+                    HeaderName::from_bytes(header.name.as_bytes()).map_err(D::Error::custom)?;
+                Ok((name, header.value))
+            })
+            .collect()
+    }
+}
+
+/// Default route weight for deserialized routes.
+pub(crate) const fn default_weight() -> u32 {
+    1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::RateLimitBuilder;
+    use crate::types::ThrottleBehavior;
+    use http::Method;
+    use std::time::Duration;
+
+    fn sample_builder() -> RateLimitBuilder {
+        RateLimitBuilder::new()
+            .host("clob.polymarket.com", |host| {
+                host.route(|r| r.limit(9000, Duration::from_secs(10)))
+                    .route(|r| {
+                        r.method(Method::POST)
+                            .path("/order")
+                            .limit(3500, Duration::from_secs(10))
+                            .limit(36000, Duration::from_secs(600))
+                            .on_limit(ThrottleBehavior::DelayWithTimeout(Duration::from_millis(
+                                250,
+                            )))
+                    })
+            })
+            .route(|r| {
+                r.path_pattern("/markets/*/book")
+                    .key_by_header("X-User-Id")
+                    .header("X-Internal", "true")
+                    .header_present("X-Trace-Id")
+                    .weight(2)
+                    .limit(10, Duration::from_millis(1500))
+                    .on_limit(ThrottleBehavior::Error)
+            })
+    }
+
+    #[test]
+    fn test_config_round_trip() {
+        let config = RateLimitConfig {
+            routes: sample_builder().routes,
+            ..RateLimitConfig::default()
+        };
+
+        let json = serde_json::to_string(&config).unwrap();
+        let decoded: RateLimitConfig = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(format!("{config:?}"), format!("{decoded:?}"));
+    }
+
+    #[test]
+    fn test_config_representation() {
+        let config = RateLimitConfig {
+            routes: sample_builder().routes,
+            ..RateLimitConfig::default()
+        };
+        let value = serde_json::to_value(&config).unwrap();
+
+        let order = &value["routes"][1];
+        assert_eq!(order["methods"], serde_json::json!(["POST"]));
+        assert_eq!(order["limits"][1]["window"], 600_000);
+        assert_eq!(order["on_limit"]["delay_with_timeout"], 250);
+
+        let book = &value["routes"][2];
+        assert_eq!(book["path_pattern"], "/markets/*/book");
+        assert_eq!(book["key_header"], "x-user-id");
+        assert_eq!(
+            book["headers"],
+            serde_json::json!([{"name": "x-internal", "value": "true"}, {"name": "x-trace-id"}])
+        );
+        assert_eq!(book["on_limit"], "error");
+    }
+
+    #[test]
+    fn test_config_defaults() {
+        let config: RateLimitConfig =
+            serde_json::from_str(r#"{"routes": [{"limits": [{"requests": 5, "window": 1000}]}]}"#)
+                .unwrap();
+        let middleware = RateLimitBuilder::from_config(config).build();
+
+        let table = middleware.table.load();
+        let route = &table.routes[0];
+        assert!(route.hosts.is_empty());
+        assert!(route.methods.is_empty());
+        assert!(route.path_prefix.is_empty());
+        assert_eq!(route.on_limit, ThrottleBehavior::Delay);
+        assert_eq!(route.weight, 1);
+        assert_eq!(route.limits[0].window, Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_config_ipv6_host_without_brackets() {
+        let config: RateLimitConfig = serde_json::from_str(
+            r#"{"routes": [{"hosts": ["[::1]"], "limits": [{"requests": 5, "window": 1000}]}]}"#,
+        )
+        .unwrap();
+        let middleware = RateLimitBuilder::from_config(config).build();
+
+        assert!(middleware.table.load().routes[0].hosts.contains("::1"));
+    }
+
+    #[test]
+    fn test_invalid_method_rejected() {
+        let result = serde_json::from_str::<RateLimitConfig>(
+            r#"{"routes": [{"methods": ["NOT A METHOD"], "limits": []}]}"#,
+        );
+        assert!(result.is_err());
+    }
+
+    #[test]
+    #[should_panic(expected = "requests must be greater than 0")]
+    fn test_from_config_validates_limits() {
+        let config: RateLimitConfig =
+            serde_json::from_str(r#"{"routes": [{"limits": [{"requests": 0, "window": 1000}]}]}"#)
+                .unwrap();
+        let _middleware = RateLimitBuilder::from_config(config).build();
+    }
+
+    #[test]
+    #[should_panic(expected = "route must have at least one limit")]
+    fn test_from_config_validates_routes() {
+        let config: RateLimitConfig =
+            serde_json::from_str(r#"{"routes": [{"path_prefix": "/a", "limits": []}]}"#).unwrap();
+        let _middleware = RateLimitBuilder::from_config(config).build();
+    }
+
+    #[test]
+    fn test_from_config_try_build_returns_errors() {
+        let config: RateLimitConfig = serde_json::from_str(
+            r#"{"routes": [
+                {"path_prefix": "/a", "limits": [{"requests": 10, "window": 1000}]},
+                {"path_prefix": "/b", "limits": [{"requests": 0, "window": 1000}]}
+            ]}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            RateLimitBuilder::from_config(config)
+                .try_build()
+                .unwrap_err(),
+            crate::BuildError::InvalidLimit {
+                route: 1,
+                limit: 0,
+                reason: crate::InvalidLimit::ZeroRequests,
+            }
+        );
+    }
+}
@@ -1,14 +1,157 @@
 //! Error types for rate limiting.
 
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use thiserror::Error;
 
+use crate::warning::ConfigWarning;
+
 /// Errors that can occur during rate limiting.
 #[derive(Debug, Error)]
 pub enum RateLimitError {
     /// The request was rate limited and the configured behavior is to error.
-    #[error("rate limit exceeded, retry after {0:?}")]
-    RateLimited(Duration),
+    #[error(
+        "rate limit exceeded on {host} {route} (limit {}/{limit_count}), retry after {retry_after:?}",
+        limit_index + 1
+    )]
+    RateLimited {
+        /// Host of the rejected request.
+        host: String,
+        /// [Template](crate::Route::template), path prefix, or pattern of the
+        /// route whose limit was exceeded, or `*` if the route matches any
+        /// path.
+        route: String,
+        /// Index of the exceeded limit within the route, in configuration order.
+        limit_index: usize,
+        /// Number of limits configured on the route.
+        limit_count: usize,
+        /// How long to wait before the limit allows the request, including
+        /// [jitter](crate::RateLimitBuilder::jitter).
+        retry_after: Duration,
+        /// When the limit allows the request: `retry_after` past the moment of
+        /// rejection on the middleware's [`Clock`](crate::Clock), as an
+        /// [`Instant`].
+        retry_at: Instant,
+    },
+    /// The route's concurrency limit was reached and the configured behavior
+    /// is to error.
+    #[error("concurrency limit exceeded")]
+    ConcurrencyLimited,
+    /// The route was throttled repeatedly and its circuit breaker is open.
+    ///
+    /// See [`RouteBuilder::cooldown_after`](crate::RouteBuilder::cooldown_after).
+    #[error("circuit open on {host} {route}, retry after {retry_after:?}")]
+    CircuitOpen {
+        /// Host of the rejected request.
+        host: String,
+        /// [Template](crate::Route::template), path prefix, or pattern of the
+        /// route whose circuit is open, or `*` if the route matches any path.
+        route: String,
+        /// How long until the circuit lets requests through again.
+        retry_after: Duration,
+        /// When the circuit lets requests through again.
+        retry_at: Instant,
+    },
+    /// The request matches no route and unmatched requests are denied.
+    ///
+    /// See [`RateLimitBuilder::unmatched`](crate::RateLimitBuilder::unmatched).
+    #[error("no rate limit route matches {host} {path}")]
+    Unmatched {
+        /// Host of the rejected request.
+        host: String,
+        /// Path of the rejected request.
+        path: String,
+    },
+    /// The middleware was shut down while the request was delayed, or before
+    /// it would have been.
+    ///
+    /// See [`RateLimitMiddleware::shutdown`](crate::RateLimitMiddleware::shutdown).
+    #[error("rate limiter is shutting down")]
+    ShuttingDown,
+}
+
+impl RateLimitError {
+    /// How long to wait before retrying, if known.
+    ///
+    /// Returns `None` for [`ConcurrencyLimited`](Self::ConcurrencyLimited),
+    /// which depends on when in-flight requests complete, and for
+    /// [`Unmatched`](Self::Unmatched) and [`ShuttingDown`](Self::ShuttingDown),
+    /// which retrying does not help.
+    #[must_use]
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            Self::RateLimited { retry_after, .. } | Self::CircuitOpen { retry_after, .. } => {
+                Some(*retry_after)
+            }
+            Self::ConcurrencyLimited | Self::Unmatched { .. } | Self::ShuttingDown => None,
+        }
+    }
+
+    /// The instant at which to retry, if known.
+    ///
+    /// Unlike [`retry_after`](Self::retry_after), this does not drift while the
+    /// error is propagated. Returns `None` for
+    /// [`ConcurrencyLimited`](Self::ConcurrencyLimited),
+    /// [`Unmatched`](Self::Unmatched), and [`ShuttingDown`](Self::ShuttingDown).
+    #[must_use]
+    pub fn retry_at(&self) -> Option<Instant> {
+        match self {
+            Self::RateLimited { retry_at, .. } | Self::CircuitOpen { retry_at, .. } => {
+                Some(*retry_at)
+            }
+            Self::ConcurrencyLimited | Self::Unmatched { .. } | Self::ShuttingDown => None,
+        }
+    }
+}
+
+/// Access to the [`RateLimitError`] inside the errors of a
+/// [`reqwest_middleware`] client.
+///
+/// Implemented on [`reqwest_middleware::Error`] and on results carrying it,
+/// so retry logic does not need to downcast by hand.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use route_ratelimit::RateLimitResultExt;
+///
+/// # async fn example(client: reqwest_middleware::ClientWithMiddleware) {
+/// let result = client.get("https://api.example.com/book").send().await;
+/// if let Some(wait) = result.rate_limit_retry_after() {
+///     tokio::time::sleep(wait).await;
+///     // retry
+/// }
+/// # }
+/// ```
+pub trait RateLimitResultExt {
+    /// The rate limiting error that rejected the request, if any.
+    fn rate_limit_error(&self) -> Option<&RateLimitError>;
+
+    /// How long to wait before retrying a request rejected by a rate limit or
+    /// an open circuit.
+    ///
+    /// Returns `None` if the request was not rejected by the middleware, or
+    /// if the wait is unknown (see [`RateLimitError::retry_after`]).
+    /// Requests answered by a synthetic `429` response under
+    /// [`ThrottleBehavior::Respond429`](crate::ThrottleBehavior::Respond429)
+    /// are not errors; read their `Retry-After` header instead.
+    fn rate_limit_retry_after(&self) -> Option<Duration> {
+        self.rate_limit_error()?.retry_after()
+    }
+}
+
+impl RateLimitResultExt for reqwest_middleware::Error {
+    fn rate_limit_error(&self) -> Option<&RateLimitError> {
+        match self {
+            reqwest_middleware::Error::Middleware(err) => err.downcast_ref(),
+            reqwest_middleware::Error::Reqwest(_) => None,
+        }
+    }
+}
+
+impl<T> RateLimitResultExt for Result<T, reqwest_middleware::Error> {
+    fn rate_limit_error(&self) -> Option<&RateLimitError> {
+        self.as_ref().err()?.rate_limit_error()
+    }
 }
 
 impl From<RateLimitError> for reqwest_middleware::Error {
@@ -16,3 +159,98 @@ impl From<RateLimitError> for reqwest_middleware::Error {
         reqwest_middleware::Error::Middleware(err.into())
     }
 }
+
+/// Errors in a configuration, returned by
+/// [`RateLimitBuilder::try_build`](crate::RateLimitBuilder::try_build).
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+#[non_exhaustive]
+pub enum BuildError {
+    /// A route has no limits.
+    #[error("route must have at least one limit configured via .limit() (route {route})")]
+    NoLimits {
+        /// Index of the route.
+        route: usize,
+    },
+    /// A limit of a route is out of range.
+    #[error("{reason} (limit {limit} of route {route})")]
+    InvalidLimit {
+        /// Index of the route.
+        route: usize,
+        /// Index of the limit within the route.
+        limit: usize,
+        /// What is wrong with the limit.
+        reason: InvalidLimit,
+    },
+    /// A route's settings are out of range or contradict each other.
+    #[error("{reason} (route {route})")]
+    InvalidRoute {
+        /// Index of the route.
+        route: usize,
+        /// What is wrong with the route.
+        reason: String,
+    },
+    /// A route of a [`strict`](crate::RateLimitBuilder::strict) builder
+    /// matches every request without being declared a catch-all.
+    #[error("route {route} matches every request; add a filter or call `catch_all()`")]
+    UndeclaredCatchAll {
+        /// Index of the route.
+        route: usize,
+    },
+    /// The limit of a quota pool is out of range.
+    #[error("{reason} (pool {pool:?})")]
+    InvalidPool {
+        /// Name of the pool.
+        pool: String,
+        /// What is wrong with the pool's limit.
+        reason: InvalidLimit,
+    },
+    /// A route draws from a quota pool that is not defined.
+    #[error("route {route} draws from undefined pool {pool:?}")]
+    UnknownPool {
+        /// Index of the route.
+        route: usize,
+        /// Name of the pool.
+        pool: String,
+    },
+    /// There is no route at the given index.
+    #[error("there is no route {route}")]
+    UnknownRoute {
+        /// The index that was out of range.
+        route: usize,
+    },
+    /// Routes in the same shared group have different limits or algorithms.
+    #[error("routes in shared group {group:?} must have identical limits and algorithm")]
+    SharedGroupMismatch {
+        /// Name of the group.
+        group: String,
+    },
+    /// The configuration has warnings, and the builder
+    /// [denies warnings](crate::RateLimitBuilder::deny_warnings).
+    #[error("configuration has {} warning(s), the first: {}", .0.len(), .0[0])]
+    Warnings(Vec<ConfigWarning>),
+}
+
+/// Why a [`RateLimit`](crate::RateLimit) is invalid, returned by
+/// [`RateLimit::try_new`](crate::RateLimit::try_new).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+#[non_exhaustive]
+pub enum InvalidLimit {
+    /// The limit allows no requests.
+    #[error("requests must be greater than 0")]
+    ZeroRequests,
+    /// The window is zero.
+    #[error("window must be greater than 0")]
+    ZeroWindow,
+    /// The window is shorter than one nanosecond per request.
+    #[error("window must be at least one nanosecond per request")]
+    WindowTooShort,
+    /// The window is too long to keep state for.
+    #[error("window must not exceed u64::MAX / 2 microseconds (~292,000 years)")]
+    WindowTooLong,
+    /// The burst allows no requests.
+    #[error("burst must be greater than 0")]
+    ZeroBurst,
+    /// The burst takes too long to refill to keep state for.
+    #[error("burst refill time must not exceed u64::MAX / 2 microseconds (~292,000 years)")]
+    BurstTooLong,
+}
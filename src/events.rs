@@ -0,0 +1,47 @@
+//! Observability hooks for rate limiting decisions.
+
+use http::Method;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// What the middleware did with a throttled request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThrottleOutcome {
+    /// The request was delayed and will be retried after the wait.
+    Delayed,
+    /// The request was rejected with an error.
+    Rejected,
+}
+
+/// Details of a single throttling decision, passed to the callback registered
+/// with [`RateLimitBuilder::on_throttle`](crate::RateLimitBuilder::on_throttle).
+#[derive(Debug, Clone)]
+pub struct ThrottleEvent {
+    /// Host of the throttled request, if it has one.
+    pub host: Option<String>,
+    /// HTTP method of the throttled request.
+    pub method: Method,
+    /// Path of the throttled request.
+    pub path: String,
+    /// Index of the matched route, in configuration order.
+    pub route_index: usize,
+    /// Index of the limit within the route that was exceeded.
+    pub limit_index: usize,
+    /// Per-key bucket discriminator, if the route is keyed.
+    pub bucket_key: Option<String>,
+    /// How long the request must wait before the limit allows it.
+    pub wait: Duration,
+    /// Whether the request was delayed or rejected.
+    pub outcome: ThrottleOutcome,
+}
+
+/// A shared callback invoked for every [`ThrottleEvent`].
+#[derive(Clone)]
+pub(crate) struct ThrottleCallback(pub(crate) Arc<dyn Fn(ThrottleEvent) + Send + Sync>);
+
+impl fmt::Debug for ThrottleCallback {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ThrottleCallback")
+    }
+}
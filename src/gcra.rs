@@ -13,48 +13,221 @@ use std::time::Duration;
 #[derive(Debug)]
 pub(crate) struct GcraState {
     /// Theoretical arrival time in nanoseconds since the start instant.
+    ///
+    /// Never more than a full burst ahead of the time it was last updated at;
+    /// a TAT further ahead, e.g. saturated near `u64::MAX`, is clamped back
+    /// to an empty bucket.
     tat_nanos: AtomicU64,
+    /// Time before which no request is admitted, set by
+    /// [`block_until`](Self::block_until), or 0.
+    blocked_until_nanos: AtomicU64,
 }
 
 impl GcraState {
     /// Create a new GCRA state.
     pub fn new() -> Self {
+        Self::with_tat(0)
+    }
+
+    /// Create a GCRA state whose TAT is `tat_nanos`, so that it is drained
+    /// until then.
+    pub fn with_tat(tat_nanos: u64) -> Self {
         Self {
-            tat_nanos: AtomicU64::new(0),
+            tat_nanos: AtomicU64::new(tat_nanos),
+            blocked_until_nanos: AtomicU64::new(0),
         }
     }
 
     /// Get the current theoretical arrival time (TAT) in nanoseconds.
+    #[cfg(test)]
     pub fn tat(&self, ordering: Ordering) -> u64 {
         self.tat_nanos.load(ordering)
     }
 
+    /// The TAT requests are admitted against, including the effect of a
+    /// [block](Self::block_until): once it ends, one request is admitted at a
+    /// time.
+    pub fn effective_tat(&self, emission_interval_nanos: u64, limit_nanos: u64) -> u64 {
+        let blocked_until = self.blocked_until_nanos.load(Ordering::Acquire);
+        self.tat_nanos.load(Ordering::Acquire).max(after_block(
+            blocked_until,
+            emission_interval_nanos,
+            limit_nanos,
+        ))
+    }
+
+    /// Number of unit-weight tokens that could be acquired at `now_nanos`
+    /// without mutating the state.
+    pub fn remaining(&self, now_nanos: u64, emission_interval_nanos: u64, limit_nanos: u64) -> u64 {
+        let Ok(tat) = self.base_tat(
+            self.tat_nanos.load(Ordering::Acquire),
+            now_nanos,
+            emission_interval_nanos,
+            limit_nanos,
+        ) else {
+            return 0;
+        };
+        let limit_at = now_nanos.saturating_add(limit_nanos);
+        limit_at.saturating_sub(tat.max(now_nanos)) / emission_interval_nanos.max(1)
+    }
+
+    /// Move the TAT forward to `at_nanos`.
+    ///
+    /// Has no effect if the TAT is already at or beyond `at_nanos`, so this can
+    /// only make the limit stricter.
+    pub fn bump_tat_to(&self, at_nanos: u64) {
+        self.tat_nanos.fetch_max(at_nanos, Ordering::AcqRel);
+    }
+
+    /// Admit no request before `at_nanos`, and then one at a time.
+    ///
+    /// Has no effect if requests are already blocked until later, so this can
+    /// only make the limit stricter.
+    pub fn block_until(&self, at_nanos: u64) {
+        self.blocked_until_nanos
+            .fetch_max(at_nanos, Ordering::AcqRel);
+    }
+
+    /// The TAT to admit requests against at `now_nanos`, given the stored
+    /// `tat`, or the wait until requests are no longer blocked.
+    ///
+    /// A stored TAT beyond a full burst past `now_nanos` is treated as an
+    /// empty bucket.
+    fn base_tat(
+        &self,
+        tat: u64,
+        now_nanos: u64,
+        emission_interval_nanos: u64,
+        limit_nanos: u64,
+    ) -> Result<u64, Duration> {
+        let blocked_until = self.blocked_until_nanos.load(Ordering::Acquire);
+        if now_nanos < blocked_until {
+            return Err(Duration::from_nanos(blocked_until - now_nanos));
+        }
+        Ok(tat
+            .min(now_nanos.saturating_add(limit_nanos))
+            .max(after_block(
+                blocked_until,
+                emission_interval_nanos,
+                limit_nanos,
+            )))
+    }
+
+    /// Give back the TAT taken by a request of `weight` that should not have
+    /// counted.
+    ///
+    /// The TAT never moves below `now_nanos`, so a refund cannot create more
+    /// capacity than a full burst.
+    pub fn refund(
+        &self,
+        now_nanos: u64,
+        emission_interval_nanos: u64,
+        limit_nanos: u64,
+        weight: u32,
+    ) {
+        let refund_nanos = emission_interval_nanos
+            .saturating_mul(u64::from(weight))
+            .min(limit_nanos);
+        let _ = self
+            .tat_nanos
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |tat| {
+                (tat > now_nanos).then(|| tat.saturating_sub(refund_nanos).max(now_nanos))
+            });
+    }
+
+    /// Take tokens worth `charge_nanos` of emission intervals without checking
+    /// whether they are available.
+    ///
+    /// The TAT never moves beyond a full burst past `now_nanos`, so a large
+    /// charge drains the bucket rather than putting it into debt.
+    pub fn charge(&self, now_nanos: u64, charge_nanos: u64, limit_nanos: u64) {
+        let limit_at = now_nanos.saturating_add(limit_nanos);
+        let _ = self
+            .tat_nanos
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |tat| {
+                let charged = tat.max(now_nanos).saturating_add(charge_nanos);
+                Some(charged.min(limit_at).max(tat))
+            });
+    }
+
+    /// Check whether a token could be acquired at `now_nanos`, without acquiring it.
+    #[cfg(test)]
+    pub fn peek(
+        &self,
+        now_nanos: u64,
+        emission_interval_nanos: u64,
+        limit_nanos: u64,
+    ) -> Result<(), Duration> {
+        self.peek_weighted(now_nanos, emission_interval_nanos, limit_nanos, 1)
+    }
+
+    /// Check whether `weight` tokens could be acquired at `now_nanos`, without
+    /// acquiring them.
+    ///
+    /// Returns the same result [`try_acquire_weighted`](Self::try_acquire_weighted)
+    /// would, but never updates the TAT.
+    pub fn peek_weighted(
+        &self,
+        now_nanos: u64,
+        emission_interval_nanos: u64,
+        limit_nanos: u64,
+        weight: u32,
+    ) -> Result<(), Duration> {
+        let tat = self.tat_nanos.load(Ordering::Acquire);
+        let tat = self.base_tat(tat, now_nanos, emission_interval_nanos, limit_nanos)?;
+        next_tat(tat, now_nanos, emission_interval_nanos, limit_nanos, weight).map(|_| ())
+    }
+
     /// Try to acquire a token. Returns Ok(()) if allowed, or Err(wait_duration) if rate limited.
+    #[cfg(test)]
     pub fn try_acquire(
         &self,
         now_nanos: u64,
         emission_interval_nanos: u64,
         limit_nanos: u64,
+    ) -> Result<(), Duration> {
+        self.try_acquire_weighted(now_nanos, emission_interval_nanos, limit_nanos, 1)
+    }
+
+    /// Try to acquire `weight` tokens at once.
+    ///
+    /// The TAT advances by `emission_interval_nanos * weight`. A weight whose cost
+    /// exceeds the whole burst capacity (`limit_nanos`) is clamped to that capacity,
+    /// so such a request is admitted once the bucket is completely full and then
+    /// drains it, rather than never being admitted at all.
+    ///
+    /// A stored TAT more than `limit_nanos` ahead of `now_nanos`, which no
+    /// admitted request leaves behind, is first clamped back to an empty
+    /// bucket, so that the bucket recovers from it instead of rejecting every
+    /// request.
+    pub fn try_acquire_weighted(
+        &self,
+        now_nanos: u64,
+        emission_interval_nanos: u64,
+        limit_nanos: u64,
+        weight: u32,
     ) -> Result<(), Duration> {
         loop {
             let tat = self.tat_nanos.load(Ordering::Acquire);
-
-            // Calculate new TAT using saturating arithmetic to prevent overflow
-            let new_tat = if tat <= now_nanos {
-                // No pending requests, start fresh
-                now_nanos.saturating_add(emission_interval_nanos)
-            } else {
-                // Add to the queue
-                tat.saturating_add(emission_interval_nanos)
-            };
-
-            // Check if new TAT exceeds the limit (burst capacity exhausted)
             let limit_at = now_nanos.saturating_add(limit_nanos);
-            if new_tat > limit_at {
-                // Rate limited - calculate how long to wait
-                let wait_nanos = new_tat.saturating_sub(limit_at);
-                return Err(Duration::from_nanos(wait_nanos));
+            if tat > limit_at {
+                // Store the clamp, whether or not this request is admitted
+                let _ = self.tat_nanos.compare_exchange_weak(
+                    tat,
+                    limit_at,
+                    Ordering::AcqRel,
+                    Ordering::Acquire,
+                );
+                continue;
             }
+            let base = self.base_tat(tat, now_nanos, emission_interval_nanos, limit_nanos)?;
+            let new_tat = next_tat(
+                base,
+                now_nanos,
+                emission_interval_nanos,
+                limit_nanos,
+                weight,
+            )?;
 
             // Try to update TAT atomically
             match self.tat_nanos.compare_exchange_weak(
@@ -70,6 +243,55 @@ impl GcraState {
     }
 }
 
+/// The earliest TAT after requests were blocked until `blocked_until`, so
+/// that one request is admitted when the block ends.
+#[inline]
+fn after_block(blocked_until: u64, emission_interval_nanos: u64, limit_nanos: u64) -> u64 {
+    if blocked_until == 0 {
+        return 0;
+    }
+    blocked_until
+        .saturating_add(limit_nanos)
+        .saturating_sub(emission_interval_nanos)
+}
+
+/// Compute the TAT after admitting a request of `weight` at `now_nanos`, or
+/// the wait until it could be admitted.
+///
+/// The TAT advances by `emission_interval_nanos * weight`, clamped to the
+/// whole burst capacity (`limit_nanos`).
+#[inline]
+fn next_tat(
+    tat: u64,
+    now_nanos: u64,
+    emission_interval_nanos: u64,
+    limit_nanos: u64,
+    weight: u32,
+) -> Result<u64, Duration> {
+    let increment_nanos = emission_interval_nanos
+        .saturating_mul(u64::from(weight))
+        .min(limit_nanos);
+
+    // Calculate new TAT using saturating arithmetic to prevent overflow
+    let new_tat = if tat <= now_nanos {
+        // No pending requests, start fresh
+        now_nanos.saturating_add(increment_nanos)
+    } else {
+        // Add to the queue
+        tat.saturating_add(increment_nanos)
+    };
+
+    // Check if new TAT exceeds the limit (burst capacity exhausted)
+    let limit_at = now_nanos.saturating_add(limit_nanos);
+    if new_tat > limit_at {
+        // Rate limited - calculate how long to wait
+        let wait_nanos = new_tat.saturating_sub(limit_at);
+        return Err(Duration::from_nanos(wait_nanos));
+    }
+
+    Ok(new_tat)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -112,4 +334,235 @@ mod tests {
         let now = Duration::from_millis(100).as_nanos() as u64;
         assert!(state.try_acquire(now, emission_nanos, limit_nanos).is_ok());
     }
+
+    #[test]
+    fn test_gcra_resets_after_huge_time_jump() {
+        let state = GcraState::new();
+        let emission_nanos = Duration::from_millis(100).as_nanos() as u64;
+        let limit_nanos = Duration::from_secs(1).as_nanos() as u64;
+
+        let now = 1_000_000_000;
+        for _ in 0..10 {
+            state.try_acquire(now, emission_nanos, limit_nanos).unwrap();
+        }
+        assert!(state.try_acquire(now, emission_nanos, limit_nanos).is_err());
+
+        // Centuries later the full burst is available again, and no more
+        let now = u64::MAX / 2;
+        for _ in 0..10 {
+            state.try_acquire(now, emission_nanos, limit_nanos).unwrap();
+        }
+        assert_eq!(state.tat(Ordering::Acquire), now + limit_nanos);
+        assert!(state.try_acquire(now, emission_nanos, limit_nanos).is_err());
+    }
+
+    #[test]
+    fn test_gcra_recovers_from_saturated_tat() {
+        let emission_nanos = Duration::from_millis(100).as_nanos() as u64;
+        let limit_nanos = Duration::from_secs(1).as_nanos() as u64;
+        let state = GcraState::with_tat(u64::MAX);
+
+        // The bucket is empty, not drained for centuries
+        let now = 1_000_000_000;
+        assert_eq!(
+            state.peek(now, emission_nanos, limit_nanos),
+            Err(Duration::from_millis(100))
+        );
+        assert_eq!(
+            state.try_acquire(now, emission_nanos, limit_nanos),
+            Err(Duration::from_millis(100))
+        );
+        assert_eq!(state.tat(Ordering::Acquire), now + limit_nanos);
+        assert_eq!(state.remaining(now, emission_nanos, limit_nanos), 0);
+
+        // And refills at the usual rate
+        let now = now + emission_nanos;
+        assert!(state.try_acquire(now, emission_nanos, limit_nanos).is_ok());
+        assert!(state.try_acquire(now, emission_nanos, limit_nanos).is_err());
+    }
+
+    #[test]
+    fn test_block_admits_one_request_when_it_ends() {
+        let emission_nanos = Duration::from_millis(100).as_nanos() as u64;
+        let limit_nanos = Duration::from_secs(1).as_nanos() as u64;
+        let state = GcraState::new();
+
+        // Blocked for far longer than a full burst
+        let until = 60 * limit_nanos;
+        state.block_until(until);
+        assert_eq!(
+            state.try_acquire(0, emission_nanos, limit_nanos),
+            Err(Duration::from_nanos(until))
+        );
+        assert_eq!(state.remaining(until - 1, emission_nanos, limit_nanos), 0);
+
+        assert!(
+            state
+                .try_acquire(until, emission_nanos, limit_nanos)
+                .is_ok()
+        );
+        assert!(
+            state
+                .try_acquire(until, emission_nanos, limit_nanos)
+                .is_err()
+        );
+        assert_eq!(
+            state.effective_tat(emission_nanos, limit_nanos),
+            until + limit_nanos
+        );
+    }
+
+    #[test]
+    fn test_remaining_tracks_consumption() {
+        let state = GcraState::new();
+        let emission_nanos = Duration::from_millis(100).as_nanos() as u64;
+        let limit_nanos = Duration::from_secs(1).as_nanos() as u64;
+
+        assert_eq!(state.remaining(0, emission_nanos, limit_nanos), 10);
+
+        for _ in 0..4 {
+            state.try_acquire(0, emission_nanos, limit_nanos).unwrap();
+        }
+        assert_eq!(state.remaining(0, emission_nanos, limit_nanos), 6);
+
+        // Half an interval later nothing new is available; a full one frees a slot
+        assert_eq!(state.remaining(50_000_000, emission_nanos, limit_nanos), 6);
+        assert_eq!(state.remaining(100_000_000, emission_nanos, limit_nanos), 7);
+    }
+
+    #[test]
+    fn test_peek_does_not_consume() {
+        let state = GcraState::new();
+        let emission_nanos = Duration::from_millis(100).as_nanos() as u64;
+        let limit_nanos = Duration::from_secs(1).as_nanos() as u64;
+
+        for _ in 0..9 {
+            state.try_acquire(0, emission_nanos, limit_nanos).unwrap();
+        }
+
+        // One slot left: peeking any number of times leaves it available
+        for _ in 0..3 {
+            assert!(state.peek(0, emission_nanos, limit_nanos).is_ok());
+        }
+        assert_eq!(
+            state.peek_weighted(0, emission_nanos, limit_nanos, 2),
+            Err(Duration::from_millis(100))
+        );
+
+        state.try_acquire(0, emission_nanos, limit_nanos).unwrap();
+        assert_eq!(
+            state.peek(0, emission_nanos, limit_nanos),
+            state.try_acquire(0, emission_nanos, limit_nanos)
+        );
+    }
+
+    #[test]
+    fn test_refund_never_moves_tat_below_now() {
+        let state = GcraState::new();
+        let emission = 1_000_000_000; // 1 request per second
+        let limit = 2_000_000_000; // burst of 2
+
+        assert!(state.try_acquire(0, emission, limit).is_ok());
+        assert!(state.try_acquire(0, emission, limit).is_ok());
+        assert!(state.try_acquire(0, emission, limit).is_err());
+
+        state.refund(0, emission, limit, 1);
+        assert_eq!(state.tat(Ordering::Acquire), emission);
+        assert!(state.try_acquire(0, emission, limit).is_ok());
+
+        // A large refund stops at now
+        state.refund(500_000_000, emission, limit, 10);
+        assert_eq!(state.tat(Ordering::Acquire), 500_000_000);
+    }
+
+    #[test]
+    fn test_charge_stops_at_full_burst() {
+        let state = GcraState::new();
+        let emission = 1_000_000; // 1000 tokens per second
+        let limit = 1_000_000_000;
+
+        state.charge(0, 400 * emission, limit);
+        assert_eq!(state.tat(Ordering::Acquire), 400 * emission);
+        assert_eq!(state.remaining(0, emission, limit), 600);
+
+        // Charging more than is left empties the bucket, without debt
+        state.charge(0, 5_000 * emission, limit);
+        assert_eq!(state.tat(Ordering::Acquire), limit);
+        assert!(state.try_acquire(emission, emission, limit).is_ok());
+    }
+
+    #[test]
+    fn test_bump_tat_only_moves_forward() {
+        let state = GcraState::new();
+
+        state.bump_tat_to(1_000);
+        assert_eq!(state.tat(Ordering::Acquire), 1_000);
+
+        state.bump_tat_to(500);
+        assert_eq!(state.tat(Ordering::Acquire), 1_000);
+    }
+
+    #[test]
+    fn test_gcra_weighted_consumes_multiple_slots() {
+        let state = GcraState::new();
+        let emission_nanos = Duration::from_millis(100).as_nanos() as u64; // 10 req/s
+        let limit_nanos = Duration::from_secs(1).as_nanos() as u64;
+        let now = 0u64;
+
+        // A weight-3 request consumes three of the ten slots
+        assert!(
+            state
+                .try_acquire_weighted(now, emission_nanos, limit_nanos, 3)
+                .is_ok()
+        );
+        for _ in 0..7 {
+            assert!(state.try_acquire(now, emission_nanos, limit_nanos).is_ok());
+        }
+        assert!(state.try_acquire(now, emission_nanos, limit_nanos).is_err());
+    }
+
+    #[test]
+    fn test_gcra_weighted_exceeding_remaining_burst() {
+        let state = GcraState::new();
+        let emission_nanos = Duration::from_millis(100).as_nanos() as u64;
+        let limit_nanos = Duration::from_secs(1).as_nanos() as u64;
+        let now = 0u64;
+
+        for _ in 0..8 {
+            assert!(state.try_acquire(now, emission_nanos, limit_nanos).is_ok());
+        }
+
+        // Only 2 slots remain, so a weight-3 request must wait one interval
+        let wait = state
+            .try_acquire_weighted(now, emission_nanos, limit_nanos, 3)
+            .unwrap_err();
+        assert_eq!(wait, Duration::from_millis(100));
+    }
+
+    #[test]
+    fn test_gcra_weight_larger_than_burst_is_clamped() {
+        let state = GcraState::new();
+        let emission_nanos = Duration::from_millis(100).as_nanos() as u64;
+        let limit_nanos = Duration::from_secs(1).as_nanos() as u64;
+
+        // A weight-50 request against a burst of 10 drains the full bucket
+        assert!(
+            state
+                .try_acquire_weighted(0, emission_nanos, limit_nanos, 50)
+                .is_ok()
+        );
+        assert!(state.try_acquire(0, emission_nanos, limit_nanos).is_err());
+
+        // It waits for a full bucket rather than being rejected forever
+        let wait = state
+            .try_acquire_weighted(0, emission_nanos, limit_nanos, 50)
+            .unwrap_err();
+        assert_eq!(wait, Duration::from_secs(1));
+        let now = limit_nanos;
+        assert!(
+            state
+                .try_acquire_weighted(now, emission_nanos, limit_nanos, 50)
+                .is_ok()
+        );
+    }
 }
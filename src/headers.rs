@@ -0,0 +1,149 @@
+//! Parsing of server-provided rate limit response headers.
+
+use http::HeaderMap;
+use http::header::RETRY_AFTER;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Header advertising how many requests the server still allows.
+const X_RATELIMIT_REMAINING: &str = "x-ratelimit-remaining";
+
+/// Header advertising when the server's quota resets.
+const X_RATELIMIT_RESET: &str = "x-ratelimit-reset";
+
+/// `X-RateLimit-Reset` values from this many seconds on are Unix timestamps
+/// (September 2001) rather than delays.
+const UNIX_RESET_THRESHOLD: f64 = 1_000_000_000.0;
+
+/// Parse a `Retry-After` header into the delay it requests.
+///
+/// Both forms defined by RFC 9110 are supported: a number of delay seconds
+/// and an HTTP-date. Dates in the past yield a zero delay. Returns `None` if
+/// the header is missing or malformed.
+pub(crate) fn parse_retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+
+    let date = httpdate::parse_http_date(value).ok()?;
+    Some(
+        date.duration_since(SystemTime::now())
+            .unwrap_or(Duration::ZERO),
+    )
+}
+
+/// Parse `X-RateLimit-Remaining` and `X-RateLimit-Reset` into the delay until
+/// the server's quota resets, if it is used up.
+///
+/// The reset is either a number of delay seconds, possibly fractional, or a
+/// Unix timestamp in seconds, as sent by e.g. GitHub. Resets in the past
+/// yield a zero delay. Returns `None` if requests remain, or if either header
+/// is missing or malformed.
+pub(crate) fn parse_ratelimit_reset(headers: &HeaderMap) -> Option<Duration> {
+    let header = |name| headers.get(name)?.to_str().ok().map(str::trim);
+
+    let remaining = header(X_RATELIMIT_REMAINING)?.parse::<u64>().ok()?;
+    if remaining > 0 {
+        return None;
+    }
+
+    let reset = header(X_RATELIMIT_RESET)?.parse::<f64>().ok()?;
+    if reset >= UNIX_RESET_THRESHOLD {
+        let reset_at = UNIX_EPOCH + Duration::try_from_secs_f64(reset).ok()?;
+        return Some(
+            reset_at
+                .duration_since(SystemTime::now())
+                .unwrap_or(Duration::ZERO),
+        );
+    }
+    Duration::try_from_secs_f64(reset).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::HeaderValue;
+
+    fn headers_with(value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(RETRY_AFTER, HeaderValue::from_str(value).unwrap());
+        headers
+    }
+
+    #[test]
+    fn test_retry_after_delta_seconds() {
+        let headers = headers_with("120");
+        assert_eq!(parse_retry_after(&headers), Some(Duration::from_secs(120)));
+    }
+
+    #[test]
+    fn test_retry_after_http_date() {
+        let future = SystemTime::now() + Duration::from_secs(60);
+        let headers = headers_with(&httpdate::fmt_http_date(future));
+
+        // HTTP-dates have one-second resolution
+        let delay = parse_retry_after(&headers).unwrap();
+        assert!(delay <= Duration::from_secs(60), "{delay:?}");
+        assert!(delay >= Duration::from_secs(58), "{delay:?}");
+    }
+
+    #[test]
+    fn test_retry_after_past_date_is_zero() {
+        let headers = headers_with("Wed, 21 Oct 2015 07:28:00 GMT");
+        assert_eq!(parse_retry_after(&headers), Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn test_retry_after_missing_or_invalid() {
+        assert_eq!(parse_retry_after(&HeaderMap::new()), None);
+        assert_eq!(parse_retry_after(&headers_with("soon")), None);
+        assert_eq!(parse_retry_after(&headers_with("-5")), None);
+    }
+
+    fn ratelimit_headers(remaining: &str, reset: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            X_RATELIMIT_REMAINING,
+            HeaderValue::from_str(remaining).unwrap(),
+        );
+        headers.insert(X_RATELIMIT_RESET, HeaderValue::from_str(reset).unwrap());
+        headers
+    }
+
+    #[test]
+    fn test_ratelimit_reset_delay_seconds() {
+        let headers = ratelimit_headers("0", "1.5");
+        assert_eq!(
+            parse_ratelimit_reset(&headers),
+            Some(Duration::from_millis(1500))
+        );
+    }
+
+    #[test]
+    fn test_ratelimit_reset_unix_timestamp() {
+        let reset = SystemTime::now() + Duration::from_secs(60);
+        let seconds = reset.duration_since(UNIX_EPOCH).unwrap().as_secs();
+        let headers = ratelimit_headers("0", &seconds.to_string());
+
+        let delay = parse_ratelimit_reset(&headers).unwrap();
+        assert!(delay <= Duration::from_secs(60), "{delay:?}");
+        assert!(delay >= Duration::from_secs(58), "{delay:?}");
+
+        let headers = ratelimit_headers("0", "1000000000");
+        assert_eq!(parse_ratelimit_reset(&headers), Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn test_ratelimit_reset_ignored_unless_exhausted() {
+        assert_eq!(parse_ratelimit_reset(&ratelimit_headers("1", "5")), None);
+        assert_eq!(parse_ratelimit_reset(&ratelimit_headers("none", "5")), None);
+        assert_eq!(parse_ratelimit_reset(&ratelimit_headers("0", "soon")), None);
+        assert_eq!(parse_ratelimit_reset(&ratelimit_headers("0", "-5")), None);
+        assert_eq!(parse_ratelimit_reset(&ratelimit_headers("0", "NaN")), None);
+
+        let mut headers = HeaderMap::new();
+        headers.insert(X_RATELIMIT_REMAINING, HeaderValue::from_static("0"));
+        assert_eq!(parse_ratelimit_reset(&headers), None);
+    }
+}
@@ -0,0 +1,44 @@
+//! Sources of randomness for delay jitter.
+//!
+//! Delayed requests sleep for a random fraction of their wait on top of it,
+//! so that requests throttled together do not all retry at once. Abstracting
+//! the randomness behind the [`JitterSource`] trait allows tests to make the
+//! delays exact.
+
+use rand::Rng;
+use std::fmt::Debug;
+
+/// A source of jitter samples used by the middleware.
+pub trait JitterSource: Debug + Send + Sync {
+    /// Returns a sample between `0.0` and `1.0`, inclusive.
+    ///
+    /// The jitter added to a delay is the sample times the maximum jitter set
+    /// with [`RateLimitBuilder::jitter`](crate::RateLimitBuilder::jitter).
+    /// Samples outside that range are clamped.
+    fn sample(&self) -> f64;
+}
+
+/// The default [`JitterSource`], drawing uniformly distributed samples from
+/// the thread-local random number generator.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RandomJitter;
+
+impl JitterSource for RandomJitter {
+    #[inline]
+    fn sample(&self) -> f64 {
+        rand::rng().random_range(0.0..=1.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_random_jitter_in_range() {
+        for _ in 0..1000 {
+            let sample = RandomJitter.sample();
+            assert!((0.0..=1.0).contains(&sample));
+        }
+    }
+}
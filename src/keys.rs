@@ -0,0 +1,65 @@
+//! Ready-made key functions for [`RouteBuilder::key_fn`].
+//!
+//! [`RouteBuilder::key_fn`]: crate::RouteBuilder::key_fn
+
+use reqwest::Request;
+
+/// Key requests by the authority of their URL, `host:port`, giving every
+/// endpoint instance its own buckets.
+///
+/// The port is the URL's explicit port or its scheme's default, so
+/// `https://api.example.com` and `https://api.example.com:443` share a
+/// bucket. IPv6 hosts are keyed with their brackets, like `[::1]:8080`.
+///
+/// # Example
+///
+/// ```rust
+/// use route_ratelimit::{RateLimitMiddleware, keys};
+/// use std::time::Duration;
+///
+/// // Each backend instance behind the same host gets its own limit
+/// let middleware = RateLimitMiddleware::builder()
+///     .route(|r| {
+///         r.host("10.0.0.1")
+///             .key_fn(keys::by_authority())
+///             .limit(100, Duration::from_secs(1))
+///     })
+///     .build();
+/// ```
+pub fn by_authority() -> impl Fn(&Request) -> Option<String> + Send + Sync + 'static {
+    |req| {
+        let url = req.url();
+        let host = url.host_str()?;
+        match url.port_or_known_default() {
+            Some(port) => Some(format!("{host}:{port}")),
+            None => Some(host.to_owned()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::{Method, Url};
+
+    fn key(url: &str) -> Option<String> {
+        by_authority()(&Request::new(Method::GET, Url::parse(url).unwrap()))
+    }
+
+    #[test]
+    fn test_by_authority_includes_port() {
+        assert_eq!(
+            key("https://api.example.com/book").as_deref(),
+            Some("api.example.com:443")
+        );
+        assert_eq!(
+            key("https://api.example.com:443/book"),
+            key("https://api.example.com/book")
+        );
+        assert_eq!(
+            key("http://127.0.0.1:8080/").as_deref(),
+            Some("127.0.0.1:8080")
+        );
+        assert_eq!(key("http://[::1]:8080/").as_deref(), Some("[::1]:8080"));
+    }
+}
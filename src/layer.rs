@@ -0,0 +1,141 @@
+//! [`tower`](https://docs.rs/tower) adapter for clients that do not use reqwest.
+//!
+//! Enabled by the `tower` feature.
+
+use std::error::Error as StdError;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tower_layer::Layer;
+use tower_service::Service;
+
+use crate::middleware::RateLimitMiddleware;
+use crate::request::RequestParts;
+use crate::types::{BypassRateLimit, Priority, RequestWeight};
+
+/// Boxed error returned by [`RateLimitService`].
+pub type BoxError = Box<dyn StdError + Send + Sync>;
+
+/// A [`Layer`] that rate limits requests with a [`RateLimitMiddleware`].
+///
+/// Wraps any [`Service`] taking an [`http::Request`], such as a `hyper`
+/// client, in a [`RateLimitService`]. Routes are matched and limits applied
+/// exactly as for reqwest.
+///
+/// # Example
+///
+/// ```rust
+/// use route_ratelimit::{RateLimitLayer, RateLimitMiddleware};
+/// use std::time::Duration;
+/// use tower::ServiceBuilder;
+///
+/// let middleware = RateLimitMiddleware::builder()
+///     .host("api.example.com", |host| {
+///         host.route(|r| r.limit(100, Duration::from_secs(10)))
+///     })
+///     .build();
+///
+/// let service = ServiceBuilder::new()
+///     .layer(RateLimitLayer::new(middleware))
+///     .service_fn(|_req: http::Request<String>| async {
+///         Ok::<_, std::convert::Infallible>(http::Response::new(String::new()))
+///     });
+/// ```
+#[derive(Debug, Clone)]
+pub struct RateLimitLayer {
+    middleware: RateLimitMiddleware,
+}
+
+impl RateLimitLayer {
+    /// Create a layer enforcing the routes of `middleware`.
+    ///
+    /// Services created by the layer share rate limit state with `middleware`
+    /// and all of its clones, so a limit can be enforced across reqwest and
+    /// tower clients alike.
+    #[must_use]
+    pub fn new(middleware: RateLimitMiddleware) -> Self {
+        Self { middleware }
+    }
+}
+
+impl<S> Layer<S> for RateLimitLayer {
+    type Service = RateLimitService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RateLimitService {
+            inner,
+            middleware: self.middleware.clone(),
+        }
+    }
+}
+
+/// A [`Service`] that rate limits requests before passing them to an inner
+/// service.
+///
+/// Created by [`RateLimitLayer`]. Requests are delayed or rejected according to
+/// each matching route's [`ThrottleBehavior`](crate::ThrottleBehavior), and a
+/// [`RequestWeight`] in the request extensions overrides the routes' weights.
+/// Requests with a [`BypassRateLimit`] extension are passed on unchecked.
+///
+/// Rejected requests fail with a [`RateLimitError`](crate::RateLimitError),
+/// boxed as a [`BoxError`]. Since a synthetic response cannot be built for an
+/// arbitrary body type, this includes routes using
+/// [`ThrottleBehavior::Respond429`](crate::ThrottleBehavior::Respond429).
+/// Errors from the inner service are boxed as they are.
+#[derive(Debug, Clone)]
+pub struct RateLimitService<S> {
+    inner: S,
+    middleware: RateLimitMiddleware,
+}
+
+impl<S, ReqBody, ResBody> Service<http::Request<ReqBody>> for RateLimitService<S>
+where
+    S: Service<http::Request<ReqBody>, Response = http::Response<ResBody>> + Clone + Send + 'static,
+    S::Future: Send,
+    S::Error: Into<BoxError>,
+    ReqBody: Send + 'static,
+{
+    type Response = http::Response<ResBody>;
+    type Error = BoxError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, req: http::Request<ReqBody>) -> Self::Future {
+        // Use the service that was polled ready, leaving a fresh clone behind
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let middleware = self.middleware.clone();
+
+        Box::pin(async move {
+            if !middleware.is_enabled() || req.extensions().get::<BypassRateLimit>().is_some() {
+                return inner.call(req).await.map_err(Into::into);
+            }
+
+            // Only the parts are held across the wait, so the body need not be `Sync`
+            let parts = RequestParts::from(&req);
+            let weight_override = RequestWeight::of(req.extensions());
+            let priority = req
+                .extensions()
+                .get::<Priority>()
+                .copied()
+                .unwrap_or_default();
+            let admission = middleware
+                .admit(&parts, weight_override, priority)
+                .await
+                .map_err(|rejection| Box::new(rejection.into_error()) as BoxError)?;
+
+            let response = inner.call(req).await;
+            middleware.complete(
+                admission,
+                response
+                    .as_ref()
+                    .ok()
+                    .map(|response| (response.status(), response.headers())),
+            );
+            response.map_err(Into::into)
+        })
+    }
+}
@@ -11,6 +11,18 @@
 //! - **Lock-free performance**: Uses GCRA algorithm with atomic operations
 //! - **Shared state**: Rate limits are tracked across all client clones
 //!
+//! # Optional Features
+//!
+//! - `tracing`: diagnostic logging, e.g. warnings about route ordering and
+//!   `debug` events for every rate limit decision
+//! - `serde`: (de)serializable configuration types
+//! - `metrics`: request counters and delay histograms via the [`metrics`](https://docs.rs/metrics)
+//!   crate (`route_ratelimit_requests_total` and `route_ratelimit_delay_seconds`)
+//! - `tower`: `RateLimitLayer` and `RateLimitService` to rate limit any
+//!   [`tower`](https://docs.rs/tower) service taking an [`http::Request`]
+//! - `governor-compat`: conversion from [`governor`](https://docs.rs/governor)
+//!   quotas into [`RateLimit`]
+//!
 //! # Route Matching Behavior
 //!
 //! Routes are checked in the order they are defined, and **all matching routes'
@@ -75,51 +87,91 @@
 //! # }
 //! ```
 
+mod adaptive;
+mod backend;
+#[cfg(feature = "bench")]
+#[doc(hidden)]
+pub mod bench;
 mod builder;
+mod circuit;
+mod clock;
+#[cfg(feature = "serde")]
+mod config;
 mod error;
+mod events;
 mod gcra;
+mod headers;
+mod jitter;
+pub mod keys;
+#[cfg(feature = "tower")]
+mod layer;
+mod limiter;
+mod match_cache;
 mod middleware;
+mod pattern;
+mod queue;
+mod request;
+mod route_table;
+mod shutdown;
+mod single_flight;
+mod sliding_window;
+mod snapshot;
+#[cfg(feature = "metrics")]
+mod telemetry;
+pub mod testing;
+mod token_bucket;
 mod types;
+mod warning;
 
 // Public re-exports
+pub use backend::{InMemoryBackend, StateBackend};
 pub use builder::{HostBuilder, HostRouteBuilder, RateLimitBuilder, RouteBuilder};
-pub use error::RateLimitError;
+pub use clock::{Clock, MonotonicClock};
+#[cfg(feature = "serde")]
+pub use config::RateLimitConfig;
+pub use error::{BuildError, InvalidLimit, RateLimitError, RateLimitResultExt};
+pub use events::{ThrottleEvent, ThrottleOutcome};
+pub use jitter::{JitterSource, RandomJitter};
+#[cfg(feature = "tower")]
+pub use layer::{BoxError, RateLimitLayer, RateLimitService};
 pub use middleware::RateLimitMiddleware;
-pub use types::{RateLimit, Route, ThrottleBehavior};
+pub use pattern::PathPattern;
+pub use route_table::Routes;
+pub use snapshot::StateSnapshot;
+pub use types::{
+    Adaptive, Algorithm, BucketStat, BypassRateLimit, Cooldown, KeyFn, LimitUnit, MatchMode,
+    Priority, RateLimit, RequestWeight, Route, RouteMatch, StatusPredicate, ThrottleBehavior,
+    UnmatchedBehavior,
+};
+pub use warning::ConfigWarning;
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::request::RequestParts;
     use http::Method;
     use std::time::Duration;
 
     #[test]
     fn test_route_matching_all() {
-        let route = Route {
-            host: None,
-            method: None,
-            path_prefix: String::new(),
-            limits: vec![],
-            on_limit: ThrottleBehavior::Delay,
-        };
+        let route = Route::builder()
+            .limit(1, Duration::from_secs(1))
+            .into_route();
 
         let req = reqwest::Client::new()
             .get("https://example.com/test")
             .build()
             .unwrap();
 
-        assert!(route.matches(&req));
+        assert!(route.matches(&RequestParts::from(&req)));
     }
 
     #[test]
     fn test_route_matching_host() {
-        let route = Route {
-            host: Some("api.example.com".to_string()),
-            method: None,
-            path_prefix: String::new(),
-            limits: vec![],
-            on_limit: ThrottleBehavior::Delay,
-        };
+        let route = Route::builder()
+            .host("api.example.com")
+            .limit(1, Duration::from_secs(1))
+            .into_route();
 
         let req_match = reqwest::Client::new()
             .get("https://api.example.com/test")
@@ -130,20 +182,119 @@ mod tests {
             .build()
             .unwrap();
 
-        assert!(route.matches(&req_match));
-        assert!(!route.matches(&req_no_match));
+        assert!(route.matches(&RequestParts::from(&req_match)));
+        assert!(!route.matches(&RequestParts::from(&req_no_match)));
     }
 
     #[test]
-    fn test_route_matching_method() {
-        let route = Route {
-            host: None,
-            method: Some(Method::POST),
-            path_prefix: String::new(),
-            limits: vec![],
-            on_limit: ThrottleBehavior::Delay,
+    fn test_route_matching_multiple_hosts() {
+        let route = Route::builder()
+            .hosts(["api.example.com", "api-eu.example.com"])
+            .limit(1, Duration::from_secs(1))
+            .into_route();
+
+        let client = reqwest::Client::new();
+        let req_us = client.get("https://api.example.com/test").build().unwrap();
+        let req_eu = client
+            .get("https://api-eu.example.com/test")
+            .build()
+            .unwrap();
+        let req_other = client
+            .get("https://other.example.com/test")
+            .build()
+            .unwrap();
+
+        assert!(route.matches(&RequestParts::from(&req_us)));
+        assert!(route.matches(&RequestParts::from(&req_eu)));
+        assert!(!route.matches(&RequestParts::from(&req_other)));
+    }
+
+    #[test]
+    fn test_route_matching_ipv6_host() {
+        let req = reqwest::Client::new()
+            .get("http://[::1]:8080/test")
+            .build()
+            .unwrap();
+        let other = reqwest::Client::new()
+            .get("http://[::2]/test")
+            .build()
+            .unwrap();
+
+        // With or without brackets, and regardless of the port
+        for host in ["::1", "[::1]"] {
+            let route = Route::builder()
+                .host(host)
+                .limit(1, Duration::from_secs(1))
+                .into_route();
+            assert!(route.matches(&RequestParts::from(&req)));
+            assert!(!route.matches(&RequestParts::from(&other)));
+        }
+
+        let route = Route::builder()
+            .hosts(["[::1]"])
+            .limit(1, Duration::from_secs(1))
+            .into_route();
+        assert!(route.matches(&RequestParts::from(&req)));
+
+        // Origin-form requests carry the host in the Host header
+        let req = http::Request::builder()
+            .uri("/test")
+            .header(http::header::HOST, "[::1]:8080")
+            .body(())
+            .unwrap();
+        assert!(route.matches(&RequestParts::from(&req)));
+    }
+
+    #[test]
+    fn test_route_matching_host_suffix() {
+        let route = Route::builder()
+            .host("example.org")
+            .host_suffix(".api.example.com")
+            .limit(1, Duration::from_secs(1))
+            .into_route();
+
+        let client = reqwest::Client::new();
+        let matches = |url: &str| {
+            let req = client.get(url).build().unwrap();
+            route.matches(&RequestParts::from(&req))
         };
 
+        assert!(matches("https://tenant1.api.example.com/test"));
+        assert!(matches("https://a.b.api.example.com/test"));
+        assert!(matches("https://example.org/test"));
+        assert!(!matches("https://api.example.com/test"));
+        assert!(!matches("https://notapi.example.com/test"));
+        assert!(!matches("https://evilapi.example.com/test"));
+        assert!(!matches("https://tenant1.api.example.com.evil.org/test"));
+    }
+
+    #[test]
+    fn test_host_suffix_requires_label_boundary() {
+        assert!(types::host_has_suffix(
+            "tenant1.api.example.com",
+            "api.example.com"
+        ));
+        assert!(!types::host_has_suffix(
+            "notapi.example.com",
+            "api.example.com"
+        ));
+        assert!(!types::host_has_suffix(
+            "api.example.com",
+            ".api.example.com"
+        ));
+        assert!(!types::host_has_suffix(
+            ".api.example.com",
+            ".api.example.com"
+        ));
+    }
+
+    #[test]
+    fn test_route_matching_method() {
+        let route = Route::builder()
+            .method(Method::POST)
+            .limit(1, Duration::from_secs(1))
+            .into_route();
+
         let req_match = reqwest::Client::new()
             .post("https://example.com/test")
             .build()
@@ -153,19 +304,37 @@ mod tests {
             .build()
             .unwrap();
 
-        assert!(route.matches(&req_match));
-        assert!(!route.matches(&req_no_match));
+        assert!(route.matches(&RequestParts::from(&req_match)));
+        assert!(!route.matches(&RequestParts::from(&req_no_match)));
+    }
+
+    #[test]
+    fn test_route_matching_scheme() {
+        let route = Route::builder()
+            .host("api.example.com")
+            .scheme("https")
+            .limit(1, Duration::from_secs(1))
+            .into_route();
+
+        let req_https = reqwest::Client::new()
+            .get("https://api.example.com/test")
+            .build()
+            .unwrap();
+        let req_http = reqwest::Client::new()
+            .get("http://api.example.com/test")
+            .build()
+            .unwrap();
+
+        assert!(route.matches(&RequestParts::from(&req_https)));
+        assert!(!route.matches(&RequestParts::from(&req_http)));
     }
 
     #[test]
     fn test_route_matching_path_prefix() {
-        let route = Route {
-            host: None,
-            method: None,
-            path_prefix: "/api/v1".to_string(),
-            limits: vec![],
-            on_limit: ThrottleBehavior::Delay,
-        };
+        let route = Route::builder()
+            .path("/api/v1")
+            .limit(1, Duration::from_secs(1))
+            .into_route();
 
         let req_match = reqwest::Client::new()
             .get("https://example.com/api/v1/users")
@@ -176,19 +345,16 @@ mod tests {
             .build()
             .unwrap();
 
-        assert!(route.matches(&req_match));
-        assert!(!route.matches(&req_no_match));
+        assert!(route.matches(&RequestParts::from(&req_match)));
+        assert!(!route.matches(&RequestParts::from(&req_no_match)));
     }
 
     #[test]
     fn test_route_matching_path_segment_boundary() {
-        let route = Route {
-            host: None,
-            method: None,
-            path_prefix: "/order".to_string(),
-            limits: vec![],
-            on_limit: ThrottleBehavior::Delay,
-        };
+        let route = Route::builder()
+            .path("/order")
+            .limit(1, Duration::from_secs(1))
+            .into_route();
 
         // Should match: exact, with trailing slash, with sub-path
         let req_exact = reqwest::Client::new()
@@ -204,10 +370,16 @@ mod tests {
             .build()
             .unwrap();
 
-        assert!(route.matches(&req_exact), "/order should match /order");
-        assert!(route.matches(&req_trailing), "/order should match /order/");
         assert!(
-            route.matches(&req_subpath),
+            route.matches(&RequestParts::from(&req_exact)),
+            "/order should match /order"
+        );
+        assert!(
+            route.matches(&RequestParts::from(&req_trailing)),
+            "/order should match /order/"
+        );
+        assert!(
+            route.matches(&RequestParts::from(&req_subpath)),
             "/order should match /order/123"
         );
 
@@ -222,15 +394,235 @@ mod tests {
             .unwrap();
 
         assert!(
-            !route.matches(&req_orders),
+            !route.matches(&RequestParts::from(&req_orders)),
             "/order should NOT match /orders"
         );
         assert!(
-            !route.matches(&req_order_dash),
+            !route.matches(&RequestParts::from(&req_order_dash)),
             "/order should NOT match /order-test"
         );
     }
 
+    #[test]
+    fn test_route_matching_exact_path() {
+        let client = reqwest::Client::new();
+        let matches = |route: &Route, url: &str| {
+            let req = client.get(url).build().unwrap();
+            route.matches(&RequestParts::from(&req))
+        };
+
+        let prefix = Route::builder()
+            .path("/order")
+            .limit(1, Duration::from_secs(1))
+            .into_route();
+        let exact = Route::builder()
+            .path_exact("/order")
+            .limit(1, Duration::from_secs(1))
+            .into_route();
+
+        for url in ["https://example.com/order", "https://example.com/order/"] {
+            assert!(matches(&prefix, url), "prefix should match {url}");
+            assert!(matches(&exact, url), "exact should match {url}");
+        }
+        // Only the prefix covers sub-resources
+        assert!(matches(&prefix, "https://example.com/order/123"));
+        assert!(!matches(&exact, "https://example.com/order/123"));
+        assert!(!matches(&exact, "https://example.com/orders"));
+        assert!(!matches(&exact, "https://example.com/"));
+    }
+
+    #[test]
+    fn test_route_matching_excluded_paths() {
+        let route = Route::builder()
+            .path("/api")
+            .exclude_path("/api/health")
+            .exclude_path("/api/metrics")
+            .limit(1, Duration::from_secs(1))
+            .into_route();
+
+        let client = reqwest::Client::new();
+        let matches = |url: &str| {
+            let req = client.get(url).build().unwrap();
+            route.matches(&RequestParts::from(&req))
+        };
+
+        assert!(matches("https://example.com/api/users"));
+        assert!(matches("https://example.com/api/healthz"));
+        assert!(!matches("https://example.com/api/health"));
+        assert!(!matches("https://example.com/api/health/live"));
+        assert!(!matches("https://example.com/api/metrics"));
+    }
+
+    #[test]
+    fn test_route_matching_path_pattern() {
+        let route = Route::builder()
+            .path_pattern("/markets/*/book")
+            .limit(1, Duration::from_secs(1))
+            .into_route();
+
+        let req_match = reqwest::Client::new()
+            .get("https://example.com/markets/0x123/book")
+            .build()
+            .unwrap();
+        let req_extra = reqwest::Client::new()
+            .get("https://example.com/markets/0x123/book/extra")
+            .build()
+            .unwrap();
+
+        assert!(route.matches(&RequestParts::from(&req_match)));
+        assert!(!route.matches(&RequestParts::from(&req_extra)));
+    }
+
+    #[test]
+    fn test_route_matching_case_insensitive_path() {
+        let client = reqwest::Client::new();
+        let matches = |route: &Route, url: &str| {
+            let req = client.get(url).build().unwrap();
+            route.matches(&RequestParts::from(&req))
+        };
+
+        let sensitive = Route::builder()
+            .path("/order")
+            .limit(1, Duration::from_secs(1))
+            .into_route();
+        assert!(!matches(&sensitive, "https://example.com/Order/123"));
+
+        let route = Route::builder()
+            .path("/order")
+            .exclude_path("/order/STATUS")
+            .case_insensitive_path()
+            .limit(1, Duration::from_secs(1))
+            .into_route();
+        assert!(matches(&route, "https://example.com/Order/123"));
+        assert!(matches(&route, "https://example.com/ORDER"));
+        assert!(!matches(&route, "https://example.com/Orders"));
+        assert!(!matches(&route, "https://example.com/order/status"));
+
+        let pattern = Route::builder()
+            .path_pattern("/markets/*/book")
+            .case_insensitive_path()
+            .limit(1, Duration::from_secs(1))
+            .into_route();
+        assert!(matches(&pattern, "https://example.com/Markets/0xABC/BOOK"));
+        assert!(!matches(
+            &pattern,
+            "https://example.com/Markets/0xABC/books"
+        ));
+    }
+
+    #[test]
+    fn test_route_matching_query() {
+        let route = Route::builder()
+            .path("/search")
+            .query("type", "deep")
+            .limit(1, Duration::from_secs(1))
+            .into_route();
+
+        let client = reqwest::Client::new();
+        let req_match = client
+            .get("https://example.com/search?type=deep&page=2")
+            .build()
+            .unwrap();
+        let req_other_value = client
+            .get("https://example.com/search?type=shallow&page=2")
+            .build()
+            .unwrap();
+        let req_missing = client
+            .get("https://example.com/search?page=2")
+            .build()
+            .unwrap();
+        let req_no_query = client.get("https://example.com/search").build().unwrap();
+
+        assert!(route.matches(&RequestParts::from(&req_match)));
+        assert!(!route.matches(&RequestParts::from(&req_other_value)));
+        assert!(!route.matches(&RequestParts::from(&req_missing)));
+        assert!(!route.matches(&RequestParts::from(&req_no_query)));
+    }
+
+    #[test]
+    fn test_route_matching_multiple_query_pairs() {
+        let route = Route::builder()
+            .query("type", "deep")
+            .query("lang", "en us")
+            .limit(1, Duration::from_secs(1))
+            .into_route();
+
+        let client = reqwest::Client::new();
+        let req_both = client
+            .get("https://example.com/search?lang=en%20us&type=deep")
+            .build()
+            .unwrap();
+        let req_one = client
+            .get("https://example.com/search?type=deep")
+            .build()
+            .unwrap();
+
+        assert!(route.matches(&RequestParts::from(&req_both)));
+        assert!(!route.matches(&RequestParts::from(&req_one)));
+    }
+
+    #[test]
+    fn test_route_matching_all_filters() {
+        let route = Route::builder()
+            .host("api.example.com")
+            .scheme("https")
+            .method(Method::GET)
+            .path("/search")
+            .query("type", "deep")
+            .limit(1, Duration::from_secs(1))
+            .into_route();
+        let client = reqwest::Client::new();
+        let matches = |url: &str, method: Method| {
+            let req = client.request(method, url).build().unwrap();
+            route.matches(&RequestParts::from(&req))
+        };
+
+        assert!(matches(
+            "https://api.example.com/search?type=deep",
+            Method::GET
+        ));
+        assert!(matches(
+            "HTTPS://api.example.com:8443/search/v2?page=1&type=deep",
+            Method::GET
+        ));
+
+        // Each filter on its own rejects the request
+        assert!(!matches(
+            "http://api.example.com/search?type=deep",
+            Method::GET
+        ));
+        assert!(!matches(
+            "https://other.example.com/search?type=deep",
+            Method::GET
+        ));
+        assert!(!matches(
+            "https://api.example.com/search?type=deep",
+            Method::POST
+        ));
+        assert!(!matches(
+            "https://api.example.com/searches?type=deep",
+            Method::GET
+        ));
+        assert!(!matches(
+            "https://api.example.com/search?type=shallow",
+            Method::GET
+        ));
+    }
+
+    #[test]
+    fn test_route_matching_percent_encoded_query() {
+        let route = Route::builder()
+            .query("q", "a b")
+            .limit(1, Duration::from_secs(1))
+            .into_route();
+
+        let client = reqwest::Client::new();
+        for url in ["http://example.com/?q=a%20b", "ftp://example.com/?q=a+b"] {
+            let req = client.get(url).build().unwrap();
+            assert!(route.matches(&RequestParts::from(&req)), "{url}");
+        }
+    }
+
     #[test]
     fn test_emission_interval() {
         let limit = RateLimit::new(100, Duration::from_secs(10));
@@ -240,6 +632,188 @@ mod tests {
         assert_eq!(limit.emission_interval(), Duration::from_millis(60));
     }
 
+    #[test]
+    fn test_long_emission_intervals_admit_exactly_requests_per_window() {
+        use crate::limiter::LimiterState;
+
+        let limits = [
+            RateLimit::per_minute(7),
+            RateLimit::per_minute(25),
+            RateLimit::per_hour(5),
+            RateLimit::new(5, Duration::from_millis(3600)),
+            RateLimit::new(3, Duration::from_secs(10)),
+        ];
+        // 60s / 7 does not divide evenly, but never overshoots the window
+        assert_eq!(
+            limits[0].emission_interval(),
+            Duration::from_nanos(8_571_428_571)
+        );
+        assert_eq!(limits[3].emission_interval(), Duration::from_millis(720));
+
+        for limit in &limits {
+            for algorithm in [Algorithm::Gcra, Algorithm::TokenBucket] {
+                let state = LimiterState::new(algorithm);
+                let admit_all = |now: Duration| {
+                    let now = now.as_nanos() as u64;
+                    std::iter::from_fn(|| state.try_acquire_weighted(now, limit, 1).ok()).count()
+                };
+
+                assert_eq!(admit_all(Duration::ZERO), limit.requests as usize);
+                for window in 1..=24 {
+                    // Just before the window ends, all but its last request have refilled
+                    let end = limit.window * window;
+                    assert_eq!(
+                        admit_all(end - Duration::from_micros(1)),
+                        limit.requests as usize - 1,
+                        "{limit:?} {algorithm:?} window {window}"
+                    );
+                    assert_eq!(admit_all(end), 1, "{limit:?} {algorithm:?} window {window}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_bandwidth_charge_does_not_multiply_truncation() {
+        use crate::limiter::LimiterState;
+        use std::sync::atomic::Ordering;
+
+        // 1.5ns per byte, truncated to 1ns
+        let limit = RateLimit::bandwidth(1_000_000_000, Duration::from_millis(1500));
+        assert_eq!(limit.emission_interval(), Duration::from_nanos(1));
+        assert_eq!(limit.emission_of(300_000_000), Duration::from_millis(450));
+
+        let state = LimiterState::new(Algorithm::Gcra);
+        state.charge(0, &limit, 300_000_000);
+        let LimiterState::Gcra(gcra) = &state else {
+            unreachable!()
+        };
+        assert_eq!(gcra.tat(Ordering::Acquire), 450_000_000);
+    }
+
+    #[test]
+    #[should_panic(expected = "window must be at least one nanosecond per request")]
+    fn test_sub_nanosecond_emission_interval_panics() {
+        RateLimit::new(2_000, Duration::from_micros(1));
+    }
+
+    #[test]
+    fn test_per_unit_constructors() {
+        assert_eq!(
+            RateLimit::per_second(10),
+            RateLimit::new(10, Duration::from_secs(1))
+        );
+        assert_eq!(
+            RateLimit::per_minute(60).emission_interval(),
+            Duration::from_secs(1)
+        );
+        assert_eq!(
+            RateLimit::per_hour(7200).emission_interval(),
+            Duration::from_millis(500)
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "requests must be greater than 0")]
+    fn test_per_minute_zero_panics() {
+        RateLimit::per_minute(0);
+    }
+
+    #[cfg(feature = "governor-compat")]
+    #[test]
+    fn test_from_governor_quota() {
+        use std::num::NonZeroU32;
+
+        let quota = governor::Quota::per_second(NonZeroU32::new(10).unwrap())
+            .allow_burst(NonZeroU32::new(25).unwrap());
+        let limit = RateLimit::from(quota);
+
+        assert_eq!(limit.emission_interval(), Duration::from_millis(100));
+        assert_eq!(limit.burst(), 25);
+        assert_eq!(limit.burst_window(), Duration::from_millis(2500));
+    }
+
+    #[test]
+    fn test_burst_window() {
+        let limit = RateLimit::new(10, Duration::from_secs(1));
+        assert_eq!(limit.burst(), 10);
+        assert_eq!(limit.burst_window(), Duration::from_secs(1));
+
+        // The burst changes capacity but not the sustained rate
+        let limit = RateLimit::with_burst(10, Duration::from_secs(1), 50);
+        assert_eq!(limit.emission_interval(), Duration::from_millis(100));
+        assert_eq!(limit.burst(), 50);
+        assert_eq!(limit.burst_window(), Duration::from_secs(5));
+
+        let limit = RateLimit::with_burst(10, Duration::from_secs(1), 1);
+        assert_eq!(limit.burst_window(), Duration::from_millis(100));
+    }
+
+    #[test]
+    fn test_token_bucket_decouples_capacity_and_refill() {
+        use crate::limiter::LimiterState;
+
+        let second = Duration::from_secs(1).as_nanos() as u64;
+        for (capacity, refill) in [(100, 5), (10, 5), (10, 50)] {
+            let limit = RateLimit::token_bucket(capacity, refill);
+            let state = LimiterState::new(Algorithm::TokenBucket);
+            let admit_all = |now: u64| {
+                std::iter::from_fn(|| state.try_acquire_weighted(now, &limit, 1).ok()).count()
+            };
+
+            // The capacity alone sets the burst
+            assert_eq!(admit_all(0), capacity as usize);
+            // The refill rate alone sets the sustained rate
+            for seconds in 1..=3 {
+                assert_eq!(admit_all(seconds * second), refill.min(capacity) as usize);
+            }
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "capacity must be greater than 0")]
+    fn test_zero_token_bucket_capacity_panics() {
+        RateLimit::token_bucket(0, 5);
+    }
+
+    #[test]
+    #[should_panic(expected = "refill_per_sec must be greater than 0")]
+    fn test_zero_token_bucket_refill_panics() {
+        RateLimit::token_bucket(100, 0);
+    }
+
+    #[test]
+    fn test_sustained_rate_is_binding_limit() {
+        assert_eq!(
+            RateLimit::new(3500, Duration::from_secs(10)).rate_per_second(),
+            350.0
+        );
+        assert_eq!(
+            RateLimit::with_burst(5, Duration::from_secs(1), 100).rate_per_second(),
+            5.0
+        );
+
+        let route = Route::builder()
+            .limit(3500, Duration::from_secs(10))
+            .limit(36_000, Duration::from_secs(600))
+            .bandwidth_limit(1_000, Duration::from_secs(60))
+            .into_route();
+        assert_eq!(route.sustained_rate(), 60.0);
+
+        // Each request takes several tokens
+        let route = Route::builder()
+            .weight(4)
+            .limit(36_000, Duration::from_secs(600))
+            .into_route();
+        assert_eq!(route.sustained_rate(), 15.0);
+    }
+
+    #[test]
+    #[should_panic(expected = "burst must be greater than 0")]
+    fn test_zero_burst_panics() {
+        RateLimit::with_burst(10, Duration::from_secs(1), 0);
+    }
+
     #[test]
     #[should_panic(expected = "requests must be greater than 0")]
     fn test_zero_requests_panics() {
@@ -253,9 +827,42 @@ mod tests {
     }
 
     #[test]
-    #[should_panic(expected = "window must not exceed u64::MAX nanoseconds")]
+    fn test_try_new_returns_errors() {
+        assert_eq!(
+            RateLimit::try_new(100, Duration::from_secs(10)),
+            Ok(RateLimit::new(100, Duration::from_secs(10)))
+        );
+        assert_eq!(
+            RateLimit::try_new(0, Duration::from_secs(10)),
+            Err(InvalidLimit::ZeroRequests)
+        );
+        assert_eq!(
+            RateLimit::try_new(100, Duration::ZERO),
+            Err(InvalidLimit::ZeroWindow)
+        );
+        assert_eq!(
+            RateLimit::try_new(2_000, Duration::from_micros(1)),
+            Err(InvalidLimit::WindowTooShort)
+        );
+        assert_eq!(
+            RateLimit::try_new(1, Duration::MAX),
+            Err(InvalidLimit::WindowTooLong)
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "window must not exceed u64::MAX / 2 microseconds")]
     fn test_overflow_window_panics() {
-        // u64::MAX nanoseconds is ~585 years, so 600 years should overflow
-        RateLimit::new(100, Duration::from_secs(600 * 365 * 24 * 60 * 60));
+        // u64::MAX / 2 microseconds is ~292,000 years
+        RateLimit::new(100, Duration::from_secs(300_000 * 365 * 24 * 60 * 60));
+    }
+
+    #[test]
+    fn test_tick_length() {
+        let year = Duration::from_secs(365 * 24 * 60 * 60);
+        assert_eq!(RateLimit::new(1000, 30 * year / 365).tick_nanos(), 1);
+        assert_eq!(RateLimit::new(1, 200 * year).tick_nanos(), 1);
+        assert_eq!(RateLimit::new(1, 1000 * year).tick_nanos(), 1_000);
+        assert_eq!(RateLimit::with_burst(1, 100 * year, 10).tick_nanos(), 1_000);
     }
 }
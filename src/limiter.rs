@@ -0,0 +1,277 @@
+//! Dispatch over the per-key state of each rate limiting algorithm.
+
+use std::time::Duration;
+
+use crate::gcra::GcraState;
+use crate::sliding_window::SlidingWindowLogState;
+use crate::snapshot::SavedState;
+use crate::token_bucket::TokenBucketState;
+use crate::types::{Algorithm, RateLimit};
+
+/// Rate limit state for a single route, limit, and bucket key.
+#[derive(Debug)]
+pub(crate) enum LimiterState {
+    Gcra(GcraState),
+    TokenBucket(TokenBucketState),
+    SlidingWindowLog(SlidingWindowLogState),
+}
+
+impl LimiterState {
+    /// Create fresh state for `algorithm`, with the full burst available.
+    pub fn new(algorithm: Algorithm) -> Self {
+        match algorithm {
+            Algorithm::Gcra => Self::Gcra(GcraState::new()),
+            Algorithm::TokenBucket => Self::TokenBucket(TokenBucketState::new()),
+            Algorithm::SlidingWindowLog => Self::SlidingWindowLog(SlidingWindowLogState::new()),
+        }
+    }
+
+    /// Create state for `algorithm` at `now_nanos` that recovers its full
+    /// burst only after `ramp_up`, as if requests had drained it down to
+    /// what refills in that time.
+    ///
+    /// A ramp-up longer than the time a full burst takes to refill starts
+    /// the state empty. The sliding window log has no such state and starts
+    /// with its full burst.
+    pub fn warming_up(
+        algorithm: Algorithm,
+        now_nanos: u64,
+        limit: &RateLimit,
+        ramp_up: Duration,
+    ) -> Self {
+        let ticks = Ticks::new(now_nanos, limit);
+        let full_at = ticks
+            .now
+            .saturating_add(ticks.ticks_of(ramp_up).min(ticks.burst_window));
+        match algorithm {
+            Algorithm::Gcra => Self::Gcra(GcraState::with_tat(full_at)),
+            Algorithm::TokenBucket => Self::TokenBucket(TokenBucketState::with_full_at(full_at)),
+            Algorithm::SlidingWindowLog => Self::new(algorithm),
+        }
+    }
+
+    /// Time at which the state will have fully recovered, in nanoseconds.
+    ///
+    /// Once this is in the past, the state is indistinguishable from fresh state.
+    pub fn recovered_at(&self, limit: &RateLimit) -> u64 {
+        let ticks = Ticks::new(0, limit);
+        let recovered_at = match self {
+            Self::Gcra(state) => state.effective_tat(ticks.emission, ticks.burst_window),
+            Self::TokenBucket(state) => state.effective_full_at(ticks.emission, ticks.burst_window),
+            Self::SlidingWindowLog(state) => state.recovered_at(ticks.window),
+        };
+        ticks.to_nanos(recovered_at)
+    }
+
+    /// Number of unit-weight requests that could be admitted at `now_nanos`.
+    pub fn remaining(&self, now_nanos: u64, limit: &RateLimit) -> u64 {
+        let ticks = Ticks::new(now_nanos, limit);
+        match self {
+            Self::Gcra(state) => state.remaining(ticks.now, ticks.emission, ticks.burst_window),
+            Self::TokenBucket(state) => {
+                state.remaining(ticks.now, ticks.emission, ticks.burst_window)
+            }
+            Self::SlidingWindowLog(state) => {
+                state.remaining(ticks.now, limit.requests, ticks.window)
+            }
+        }
+    }
+
+    /// Ensure no request is admitted before `at_nanos`, and exactly one is
+    /// admitted at `at_nanos` where the algorithm allows.
+    ///
+    /// Never makes the limit more permissive.
+    pub fn defer_until(&self, at_nanos: u64, limit: &RateLimit) {
+        let ticks = Ticks::new(at_nanos, limit);
+        match self {
+            Self::Gcra(state) => state.block_until(ticks.now),
+            Self::TokenBucket(state) => state.block_until(ticks.now),
+            Self::SlidingWindowLog(state) => {
+                state.block_until(ticks.now, limit.requests, ticks.window)
+            }
+        }
+    }
+
+    /// Undo the admission of a request of the given weight that should not
+    /// have counted, without creating more capacity than a full burst.
+    pub fn refund(&self, now_nanos: u64, limit: &RateLimit, weight: u32) {
+        let ticks = Ticks::new(now_nanos, limit);
+        match self {
+            Self::Gcra(state) => {
+                state.refund(ticks.now, ticks.emission, ticks.burst_window, weight)
+            }
+            Self::TokenBucket(state) => {
+                state.refund(ticks.now, ticks.emission, ticks.burst_window, weight)
+            }
+            Self::SlidingWindowLog(state) => state.refund(limit.requests, weight),
+        }
+    }
+
+    /// Take `amount` units of `limit` after the fact, without checking
+    /// whether they are available, at most emptying the bucket.
+    ///
+    /// Has no effect on a sliding window log, which routes with bandwidth
+    /// limits are not allowed to use.
+    pub fn charge(&self, now_nanos: u64, limit: &RateLimit, amount: u64) {
+        let ticks = Ticks::new(now_nanos, limit);
+        // Dividing the whole charge keeps it exact, rather than multiplying
+        // the truncated emission interval
+        let charge = ticks.ticks_of(limit.emission_of(amount));
+        match self {
+            Self::Gcra(state) => state.charge(ticks.now, charge, ticks.burst_window),
+            Self::TokenBucket(state) => {
+                state.charge(ticks.now, ticks.emission, ticks.burst_window, charge)
+            }
+            Self::SlidingWindowLog(_) => {}
+        }
+    }
+
+    /// Capture the state at `now_nanos`, relative to it.
+    ///
+    /// Returns `None` if the state is indistinguishable from fresh state.
+    pub fn save(&self, now_nanos: u64, limit: &RateLimit) -> Option<SavedState> {
+        let ticks = Ticks::new(now_nanos, limit);
+        let after_now = |at: u64| Some(ticks.duration(at.checked_sub(ticks.now)?));
+        let state = match self {
+            Self::Gcra(state) => SavedState::Gcra {
+                tat_in: after_now(state.effective_tat(ticks.emission, ticks.burst_window))
+                    .filter(|d| !d.is_zero())?,
+            },
+            Self::TokenBucket(state) => SavedState::TokenBucket {
+                full_in: after_now(state.effective_full_at(ticks.emission, ticks.burst_window))
+                    .filter(|d| !d.is_zero())?,
+            },
+            Self::SlidingWindowLog(state) => {
+                let ages = state.ages(ticks.now, ticks.window);
+                if ages.is_empty() {
+                    return None;
+                }
+                SavedState::SlidingWindowLog {
+                    ages: ages.into_iter().map(|age| ticks.duration(age)).collect(),
+                }
+            }
+        };
+        Some(state)
+    }
+
+    /// Apply state captured by [`save`](Self::save) as if it had been
+    /// captured at `now_nanos`.
+    ///
+    /// The state is capped at what `limit` allows, and merged so that it never
+    /// makes the limit more permissive.
+    pub fn restore(&self, now_nanos: u64, limit: &RateLimit, saved: &SavedState) {
+        let ticks = Ticks::new(now_nanos, limit);
+        let until = |offset: Duration| {
+            let offset = ticks.ticks_of(offset.min(limit.burst_window()));
+            ticks.now.saturating_add(offset)
+        };
+        match (self, saved) {
+            (Self::Gcra(state), SavedState::Gcra { tat_in }) => state.bump_tat_to(until(*tat_in)),
+            (Self::TokenBucket(state), SavedState::TokenBucket { full_in }) => {
+                state.bump_full_at(until(*full_in));
+            }
+            (Self::SlidingWindowLog(state), SavedState::SlidingWindowLog { ages }) => {
+                // Requests older than the restored clock's origin are logged
+                // at the origin, keeping them in the window a little longer
+                let in_window = ages.iter().filter(|age| **age < limit.window);
+                let newest = in_window.rev().take(limit.requests as usize);
+                state.restore(newest.map(|age| ticks.now.saturating_sub(ticks.ticks_of(*age))));
+            }
+            _ => {}
+        }
+    }
+
+    /// Check whether a request of the given weight would be admitted at
+    /// `now_nanos`, without consuming any capacity.
+    ///
+    /// Returns the same result as [`try_acquire_weighted`](Self::try_acquire_weighted).
+    pub fn peek_weighted(
+        &self,
+        now_nanos: u64,
+        limit: &RateLimit,
+        weight: u32,
+    ) -> Result<(), Duration> {
+        let ticks = Ticks::new(now_nanos, limit);
+        match self {
+            Self::Gcra(state) => {
+                state.peek_weighted(ticks.now, ticks.emission, ticks.burst_window, weight)
+            }
+            Self::TokenBucket(state) => {
+                state.peek_weighted(ticks.now, ticks.emission, ticks.burst_window, weight)
+            }
+            Self::SlidingWindowLog(state) => {
+                state.peek_weighted(ticks.now, limit.requests, ticks.window, weight)
+            }
+        }
+        .map_err(|wait| wait * ticks.tick_nanos)
+    }
+
+    /// Try to admit a request of the given weight.
+    ///
+    /// Returns Ok(()) if allowed, or Err(wait_duration) if rate limited.
+    pub fn try_acquire_weighted(
+        &self,
+        now_nanos: u64,
+        limit: &RateLimit,
+        weight: u32,
+    ) -> Result<(), Duration> {
+        let ticks = Ticks::new(now_nanos, limit);
+        match self {
+            Self::Gcra(state) => {
+                state.try_acquire_weighted(ticks.now, ticks.emission, ticks.burst_window, weight)
+            }
+            Self::TokenBucket(state) => {
+                state.try_acquire_weighted(ticks.now, ticks.emission, ticks.burst_window, weight)
+            }
+            Self::SlidingWindowLog(state) => {
+                state.try_acquire_weighted(ticks.now, limit.requests, ticks.window, weight)
+            }
+        }
+        .map_err(|wait| wait * ticks.tick_nanos)
+    }
+}
+
+/// The current time and a limit's durations, in the ticks its state is kept
+/// in.
+///
+/// The algorithms work in whole ticks of any length. State is kept in
+/// nanoseconds, unless the limit's windows are too long for that (see
+/// [`RateLimit::tick_nanos`]). Waits they return are in ticks as well, as if
+/// ticks were nanoseconds, so they are scaled back by `tick_nanos`.
+struct Ticks {
+    tick_nanos: u32,
+    now: u64,
+    emission: u64,
+    window: u64,
+    burst_window: u64,
+}
+
+impl Ticks {
+    #[inline]
+    fn new(now_nanos: u64, limit: &RateLimit) -> Self {
+        let tick_nanos = limit.tick_nanos();
+        let ticks = |duration: Duration| (duration.as_nanos() / u128::from(tick_nanos)) as u64;
+        Self {
+            tick_nanos,
+            now: now_nanos / u64::from(tick_nanos),
+            emission: ticks(limit.emission_interval()),
+            window: ticks(limit.window),
+            burst_window: ticks(limit.burst_window()),
+        }
+    }
+
+    #[inline]
+    fn ticks_of(&self, duration: Duration) -> u64 {
+        (duration.as_nanos() / u128::from(self.tick_nanos)).min(u128::from(u64::MAX)) as u64
+    }
+
+    #[inline]
+    fn duration(&self, ticks: u64) -> Duration {
+        Duration::from_nanos(ticks) * self.tick_nanos
+    }
+
+    #[inline]
+    fn to_nanos(&self, ticks: u64) -> u64 {
+        ticks.saturating_mul(u64::from(self.tick_nanos))
+    }
+}
@@ -0,0 +1,203 @@
+//! Cache of the routes matching recently seen requests.
+
+use dashmap::DashMap;
+use http::Method;
+use std::hash::{BuildHasher, RandomState};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::request::RequestParts;
+
+/// Default number of distinct requests whose matching routes are cached.
+pub(crate) const DEFAULT_MATCH_CACHE_CAPACITY: usize = 1024;
+
+/// Maps the parts of a request that routes match on to the indices of the
+/// matching routes, so repeated requests skip the scan over every route.
+///
+/// Entries are keyed by a hash of the request parts and hold the parts
+/// themselves, so lookups need not allocate and hash collisions are detected.
+/// The cache is bounded: once full, each new request evicts one entry,
+/// picked like the CLOCK algorithm does, skipping (and clearing the mark of)
+/// entries hit since the last eviction that passed them. It belongs to a
+/// [`RouteTable`], so reconfiguring the routes starts over with an empty cache.
+///
+/// [`RouteTable`]: crate::route_table::RouteTable
+#[derive(Debug)]
+pub(crate) struct MatchCache {
+    entries: DashMap<u64, Entry>,
+    hasher: RandomState,
+    capacity: usize,
+}
+
+#[derive(Debug)]
+struct Entry {
+    scheme: Option<String>,
+    host: Option<String>,
+    method: Method,
+    path: String,
+    query: Option<String>,
+    routes: Arc<[usize]>,
+    /// Whether the entry was hit since an eviction last passed over it.
+    referenced: AtomicBool,
+}
+
+impl Entry {
+    fn is_for(&self, req: &RequestParts<'_>) -> bool {
+        self.path == req.path
+            && self.host.as_deref() == req.host
+            && self.method == *req.method
+            && self.query.as_deref() == req.query
+            && self.scheme.as_deref() == req.scheme
+    }
+}
+
+impl MatchCache {
+    /// Create a cache holding up to `capacity` requests; zero disables it.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: DashMap::new(),
+            hasher: RandomState::new(),
+            capacity,
+        }
+    }
+
+    /// Returns the cached routes matching `req`, or computes and caches them
+    /// with `compute`.
+    pub fn get_or_insert_with(
+        &self,
+        req: &RequestParts<'_>,
+        compute: impl FnOnce() -> Arc<[usize]>,
+    ) -> Arc<[usize]> {
+        if self.capacity == 0 {
+            return compute();
+        }
+
+        let hash = self
+            .hasher
+            .hash_one((req.scheme, req.host, req.method, req.path, req.query));
+        if let Some(entry) = self.entries.get(&hash) {
+            if entry.is_for(req) {
+                entry.referenced.store(true, Ordering::Relaxed);
+                return Arc::clone(&entry.routes);
+            }
+        }
+
+        let routes = compute();
+        if self.entries.len() >= self.capacity && !self.entries.contains_key(&hash) {
+            self.evict_one();
+        }
+        self.entries.insert(
+            hash,
+            Entry {
+                scheme: req.scheme.map(str::to_owned),
+                host: req.host.map(str::to_owned),
+                method: req.method.clone(),
+                path: req.path.to_owned(),
+                query: req.query.map(str::to_owned),
+                routes: Arc::clone(&routes),
+                referenced: AtomicBool::new(false),
+            },
+        );
+        routes
+    }
+
+    /// Remove the first entry not hit since it was last passed over, clearing
+    /// the mark of those that were, or the first entry if all were.
+    fn evict_one(&self) {
+        let mut first = None;
+        let mut victim = None;
+        for entry in self.entries.iter() {
+            first.get_or_insert(*entry.key());
+            if !entry.referenced.swap(false, Ordering::Relaxed) {
+                victim = Some(*entry.key());
+                break;
+            }
+        }
+        if let Some(hash) = victim.or(first) {
+            self.entries.remove(&hash);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::HeaderMap;
+    use std::cell::Cell;
+
+    fn parts<'a>(path: &'a str, headers: &'a HeaderMap) -> RequestParts<'a> {
+        RequestParts {
+            scheme: Some("https"),
+            host: Some("api.example.com"),
+            method: &Method::GET,
+            path,
+            query: None,
+            headers,
+            request: None,
+        }
+    }
+
+    #[test]
+    fn test_repeated_request_is_computed_once() {
+        let cache = MatchCache::new(8);
+        let headers = HeaderMap::new();
+        let computed = Cell::new(0);
+        let compute = || {
+            computed.set(computed.get() + 1);
+            Arc::from([1, 3])
+        };
+
+        let first = cache.get_or_insert_with(&parts("/order", &headers), compute);
+        let second = cache.get_or_insert_with(&parts("/order", &headers), compute);
+        assert_eq!(&*first, &[1, 3]);
+        assert_eq!(&*second, &[1, 3]);
+        assert_eq!(computed.get(), 1);
+
+        cache.get_or_insert_with(&parts("/book", &headers), compute);
+        assert_eq!(computed.get(), 2);
+    }
+
+    #[test]
+    fn test_cache_is_bounded() {
+        let cache = MatchCache::new(2);
+        let headers = HeaderMap::new();
+        for path in ["/a", "/b", "/c", "/d", "/e"] {
+            cache.get_or_insert_with(&parts(path, &headers), || Arc::from([0]));
+            assert!(cache.entries.len() <= 2);
+        }
+    }
+
+    #[test]
+    fn test_full_cache_evicts_one_entry_not_recently_hit() {
+        let cache = MatchCache::new(2);
+        let headers = HeaderMap::new();
+        let computed = Cell::new(0);
+        let compute = || {
+            computed.set(computed.get() + 1);
+            Arc::from([0])
+        };
+
+        cache.get_or_insert_with(&parts("/a", &headers), compute);
+        cache.get_or_insert_with(&parts("/b", &headers), compute);
+        cache.get_or_insert_with(&parts("/a", &headers), compute);
+        assert_eq!(computed.get(), 2);
+
+        // Overflowing evicts `/b`, which was not hit, and keeps `/a`
+        cache.get_or_insert_with(&parts("/c", &headers), compute);
+        assert_eq!(computed.get(), 3);
+        assert_eq!(cache.entries.len(), 2);
+        cache.get_or_insert_with(&parts("/a", &headers), compute);
+        cache.get_or_insert_with(&parts("/c", &headers), compute);
+        assert_eq!(computed.get(), 3);
+        cache.get_or_insert_with(&parts("/b", &headers), compute);
+        assert_eq!(computed.get(), 4);
+    }
+
+    #[test]
+    fn test_zero_capacity_disables_cache() {
+        let cache = MatchCache::new(0);
+        let headers = HeaderMap::new();
+        cache.get_or_insert_with(&parts("/order", &headers), || Arc::from([0]));
+        assert!(cache.entries.is_empty());
+    }
+}
@@ -1,20 +1,49 @@
 //! The rate limiting middleware implementation.
 
+use arc_swap::ArcSwap;
 use async_trait::async_trait;
-use dashmap::DashMap;
 use http::Extensions;
-use rand::Rng;
-use reqwest::{Request, Response};
+use http::header::{AUTHORIZATION, CONTENT_LENGTH, COOKIE, PROXY_AUTHORIZATION, RETRY_AFTER};
+use http::{Method, StatusCode};
+use reqwest::{Request, Response, ResponseBuilderExt, Url};
 use reqwest_middleware::{Middleware, Next, Result as MiddlewareResult};
+use std::borrow::Cow;
+use std::fmt;
+use std::future::poll_fn;
+use std::pin::Pin;
 use std::sync::Arc;
-use std::sync::atomic::Ordering;
-use std::time::Instant;
-use tokio::time::sleep;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::task::Poll;
+use std::time::{Duration, Instant};
+use tokio::sync::OwnedSemaphorePermit;
+use tokio::time::{MissedTickBehavior, sleep, timeout};
 
+use crate::backend::{self, StateBackend};
 use crate::builder::RateLimitBuilder;
-use crate::error::RateLimitError;
-use crate::gcra::GcraState;
-use crate::types::{Route, RouteKey, ThrottleBehavior};
+use crate::clock::Clock;
+use crate::error::{BuildError, RateLimitError};
+use crate::events::{ThrottleCallback, ThrottleEvent, ThrottleOutcome};
+use crate::headers::{parse_ratelimit_reset, parse_retry_after};
+use crate::jitter::JitterSource;
+use crate::limiter::LimiterState;
+use crate::queue::Ticket;
+use crate::request::RequestParts;
+use crate::route_table::{self, LimitCheck, RouteTable, Routes};
+use crate::shutdown::Shutdown;
+use crate::single_flight::{self, Flight, SharedResponse};
+use crate::snapshot::{self, StateSnapshot};
+#[cfg(feature = "metrics")]
+use crate::telemetry;
+use crate::types::{
+    Algorithm, BucketStat, BypassRateLimit, LimitScope, LimitUnit, MatchMode, Priority, RateLimit,
+    RequestWeight, Route, RouteKey, RouteMatch, ThrottleBehavior, UnmatchedBehavior,
+};
+
+/// Default maximum jitter, as a fraction of the wait.
+pub(crate) const DEFAULT_JITTER: f64 = 0.5;
+
+/// Default number of windows after which a fully recovered entry is stale.
+pub(crate) const DEFAULT_CLEANUP_STALE_AFTER: u32 = 2;
 
 /// The rate limiting middleware.
 ///
@@ -25,14 +54,45 @@ use crate::types::{Route, RouteKey, ThrottleBehavior};
 ///
 /// `RateLimitMiddleware` is `Send + Sync` and can be safely shared across
 /// threads and async tasks. The internal state uses lock-free atomic operations
-/// (via [`DashMap`] and atomic integers) to ensure correct behavior under
-/// concurrent access. When cloned, clones share the same rate limit state,
-/// so limits are enforced across all clones.
-#[derive(Debug, Clone)]
+/// (via [`DashMap`](dashmap::DashMap) and atomic integers) to ensure correct
+/// behavior under concurrent access. When cloned, clones share the same routes
+/// and rate limit state, so limits are enforced across all clones.
+///
+/// Its `Debug` output summarizes the configuration and state, rather than
+/// listing every route and bucket.
+#[derive(Clone)]
 pub struct RateLimitMiddleware {
-    pub(crate) routes: Arc<Vec<Route>>,
-    pub(crate) state: Arc<DashMap<RouteKey, GcraState>>,
-    pub(crate) start_instant: Instant,
+    /// The current routes and their state, replaced as a whole by
+    /// [`update_routes`](Self::update_routes).
+    pub(crate) table: Arc<ArcSwap<RouteTable>>,
+    pub(crate) clock: Arc<dyn Clock>,
+    pub(crate) respect_retry_after: bool,
+    pub(crate) respect_ratelimit_headers: bool,
+    pub(crate) on_throttle: Option<ThrottleCallback>,
+    /// Maximum jitter added to delays, as a fraction of the wait.
+    pub(crate) jitter: f64,
+    pub(crate) jitter_source: Arc<dyn JitterSource>,
+    /// Storage for the state of GCRA limits, if not kept in the route table.
+    pub(crate) state_backend: Option<Arc<dyn StateBackend>>,
+    /// Methods exempt from rate limiting.
+    pub(crate) bypass_methods: Arc<[Method]>,
+    /// Maximum time a single request may sleep on rate limits in total.
+    pub(crate) max_total_delay: Option<Duration>,
+    /// Which of the matching routes apply to a request.
+    pub(crate) match_mode: MatchMode,
+    /// What happens to requests matching no route.
+    pub(crate) unmatched: UnmatchedBehavior,
+    /// Capacity of the match cache of each route table.
+    pub(crate) match_cache_capacity: usize,
+    /// Number of buckets the state map of each route table is sized for.
+    pub(crate) state_capacity: usize,
+    /// Number of windows after fully recovering that state entries are removed.
+    pub(crate) cleanup_stale_after: u32,
+    /// Whether limits are enforced, shared by every clone.
+    pub(crate) enabled: Arc<AtomicBool>,
+    /// Releases delayed requests on [`shutdown`](Self::shutdown), shared by
+    /// every clone.
+    pub(crate) shutdown: Arc<Shutdown>,
 }
 
 impl RateLimitMiddleware {
@@ -42,25 +102,245 @@ impl RateLimitMiddleware {
         RateLimitBuilder::new()
     }
 
+    /// Create a middleware from a list of routes, with default settings.
+    ///
+    /// # Panics
+    ///
+    /// Panics if any route is invalid: every route needs at least one limit,
+    /// limits need non-zero requests and windows, and a route cannot set both a
+    /// path prefix and a path pattern.
+    #[must_use]
+    pub fn from_routes(routes: Vec<Route>) -> Self {
+        RateLimitBuilder::from_routes(routes).build()
+    }
+
     #[inline]
     pub(crate) fn now_nanos(&self) -> u64 {
-        // Use saturating conversion to prevent overflow on very long-running processes
-        // (would require running for ~585 years to overflow)
-        self.start_instant
-            .elapsed()
-            .as_nanos()
-            .min(u64::MAX as u128) as u64
+        self.clock.now_nanos()
+    }
+
+    /// Replace the configured routes at runtime.
+    ///
+    /// Takes effect for every clone of the middleware and every client using
+    /// it. Requests already past the rate limiter finish under the old routes.
+    ///
+    /// All rate limit state is discarded along with the old routes, so every
+    /// limit starts over with its full burst capacity, even for routes that are
+    /// unchanged. Likewise, in-flight requests no longer count towards the
+    /// [`max_concurrent`](crate::RouteBuilder::max_concurrent) limits of the
+    /// new routes. The new routes can draw from the
+    /// [pools](crate::RateLimitBuilder::pool) the middleware was built with,
+    /// which start over too.
+    ///
+    /// # Panics
+    ///
+    /// Panics if any route is invalid (see [`from_routes`](Self::from_routes)),
+    /// or draws from a pool that is not defined.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use route_ratelimit::{RateLimitMiddleware, RouteBuilder};
+    /// use std::time::Duration;
+    ///
+    /// let middleware = RateLimitMiddleware::builder()
+    ///     .route(|r| r.limit(10, Duration::from_secs(1)))
+    ///     .build();
+    ///
+    /// // Upgrade to a higher tier once the account is known
+    /// let upgraded = RateLimitMiddleware::builder()
+    ///     .route(|r| r.limit(100, Duration::from_secs(1)))
+    ///     .into_routes();
+    /// middleware.update_routes(upgraded);
+    /// ```
+    pub fn update_routes(&self, routes: Vec<Route>) {
+        for route in &routes {
+            route.validate();
+        }
+        let pools = self.table.load().pools.clone();
+        self.table.store(Arc::new(RouteTable::new(
+            routes,
+            pools,
+            self.match_mode,
+            self.match_cache_capacity,
+            self.state_capacity,
+        )));
+    }
+
+    /// Replace the limits of the route at `index` at runtime, keeping the
+    /// state of every other route.
+    ///
+    /// Takes effect for every clone of the middleware, like
+    /// [`update_routes`](Self::update_routes). Only the buckets of the
+    /// changed route are discarded, so its new limits start over with their
+    /// full burst capacity, whatever their number; for a route in a
+    /// [shared group](crate::RouteBuilder::shared_group), those are the
+    /// buckets of the whole group. In-flight and delayed requests keep
+    /// counting towards every route, as do circuit breakers and adaptive
+    /// rates.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error, leaving the routes unchanged, if there is no route at
+    /// `index`, if the route would be invalid with `limits`, or if it is in a
+    /// shared group with other routes, whose limits must be the same.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use route_ratelimit::{RateLimit, RateLimitMiddleware};
+    /// use std::time::Duration;
+    ///
+    /// let middleware = RateLimitMiddleware::builder()
+    ///     .route(|r| r.path("/book").limit(100, Duration::from_secs(1)))
+    ///     .route(|r| r.path("/order").limit(10, Duration::from_secs(1)))
+    ///     .build();
+    ///
+    /// // The provider lowered its order limit
+    /// middleware
+    ///     .set_route_limits(1, vec![RateLimit::new(5, Duration::from_secs(1))])
+    ///     .unwrap();
+    /// assert_eq!(middleware.routes()[1].limits()[0].requests, 5);
+    /// ```
+    pub fn set_route_limits(&self, index: usize, limits: Vec<RateLimit>) -> Result<(), BuildError> {
+        let current = self.table.load_full();
+        let mut routes = current.routes.clone();
+        let route = routes
+            .get_mut(index)
+            .ok_or(BuildError::UnknownRoute { route: index })?;
+        route.limits = limits;
+        route.check().map_err(|err| err.at(index))?;
+        route_table::shared_groups(&routes)?;
+
+        let table = RouteTable::new(
+            routes,
+            current.pools.clone(),
+            self.match_mode,
+            self.match_cache_capacity,
+            0,
+        );
+        self.table
+            .store(Arc::new(table.sharing_state(&current, index)));
+        Ok(())
+    }
+
+    /// Turn rate limiting on or off at runtime.
+    ///
+    /// While disabled, requests pass straight through without being matched,
+    /// counted, or delayed. Takes effect for every clone of the middleware and
+    /// can be called from any thread. Rate limit state is kept, so limits
+    /// resume where they left off once re-enabled.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use route_ratelimit::RateLimitMiddleware;
+    /// use std::time::Duration;
+    ///
+    /// let middleware = RateLimitMiddleware::builder()
+    ///     .route(|r| r.limit(10, Duration::from_secs(1)))
+    ///     .build();
+    ///
+    /// middleware.set_enabled(false);
+    /// assert!(!middleware.is_enabled());
+    /// ```
+    pub fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Returns `true` unless rate limiting was turned off with
+    /// [`set_enabled`](Self::set_enabled).
+    #[must_use]
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    /// Release every request delayed by this middleware or its clones, so the
+    /// runtime can shut down without waiting out their delays.
+    ///
+    /// Requests waiting on a rate or concurrency limit, or queued behind
+    /// others, fail with [`RateLimitError::ShuttingDown`], and so do later
+    /// requests that would have to wait. Requests within their limits are
+    /// still sent. The [automatic cleanup](RateLimitBuilder::auto_cleanup)
+    /// task, if any, stops.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use route_ratelimit::RateLimitMiddleware;
+    /// use std::time::Duration;
+    ///
+    /// let middleware = RateLimitMiddleware::builder()
+    ///     .route(|r| r.limit(10, Duration::from_secs(1)))
+    ///     .build();
+    ///
+    /// middleware.shutdown();
+    /// assert!(middleware.is_shut_down());
+    /// ```
+    pub fn shutdown(&self) {
+        self.shutdown.trigger();
+    }
+
+    /// Returns `true` once [`shutdown`](Self::shutdown) was called on this
+    /// middleware or one of its clones.
+    #[must_use]
+    pub fn is_shut_down(&self) -> bool {
+        self.shutdown.is_triggered()
+    }
+
+    /// Capture the current rate limit state, e.g. before shutting down.
+    ///
+    /// Pass the snapshot to [`import_state`](Self::import_state) of a new
+    /// middleware to carry the limits over, so that a restart does not let a
+    /// fresh burst through. Buckets that have fully recovered are left out.
+    /// Concurrency limits and circuit breakers are not captured.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use route_ratelimit::RateLimitMiddleware;
+    /// use std::time::Duration;
+    ///
+    /// let build = || {
+    ///     RateLimitMiddleware::builder()
+    ///         .route(|r| r.path("/order").limit(10, Duration::from_secs(1)))
+    ///         .build()
+    /// };
+    ///
+    /// let old = build();
+    /// let snapshot = old.export_state();
+    ///
+    /// let new = build();
+    /// new.import_state(&snapshot);
+    /// ```
+    #[must_use]
+    pub fn export_state(&self) -> StateSnapshot {
+        snapshot::export(&self.table.load(), self.now_nanos())
+    }
+
+    /// Apply state captured by [`export_state`](Self::export_state), possibly
+    /// from another middleware or process.
+    ///
+    /// Routes are matched by what they match (hosts, scheme, method, path, and
+    /// query, or shared group) and limits by their position within the route,
+    /// so state carries over as long as those are unchanged. Time that passed
+    /// since the snapshot was taken counts towards recovery. Importing never
+    /// makes a limit more permissive than its current state.
+    pub fn import_state(&self, snapshot: &StateSnapshot) {
+        snapshot::import(&self.table.load(), self.now_nanos(), snapshot);
     }
 
     /// Remove stale rate limit state entries that haven't been accessed recently.
     ///
-    /// An entry is considered stale when its theoretical arrival time (TAT) has
-    /// recovered past twice the limit window, meaning the burst capacity has been
-    /// fully recovered for an extended period.
+    /// An entry is considered stale when it fully recovered its burst capacity
+    /// more than twice the limit window ago, or as many windows as set with
+    /// [`RateLimitBuilder::cleanup_stale_after`].
     ///
     /// This method should be called periodically in long-running applications to
     /// prevent unbounded memory growth from accumulated state entries.
     ///
+    /// Returns the number of entries removed.
+    ///
     /// # Example
     ///
     /// ```rust,no_run
@@ -73,29 +353,774 @@ impl RateLimitMiddleware {
     ///     .build();
     ///
     /// // Call periodically to clean up stale entries
-    /// middleware.cleanup();
+    /// let removed = middleware.cleanup();
+    /// println!("removed {removed} stale rate limit entries");
+    /// # }
+    /// ```
+    pub fn cleanup(&self) -> usize {
+        self.table
+            .load()
+            .cleanup_stale(self.now_nanos(), self.cleanup_stale_after)
+    }
+
+    /// Remove all rate limit state, so that every limit starts over with its
+    /// full burst.
+    ///
+    /// Useful between tests, or between sessions that should not count
+    /// against each other. Safe to call while requests are in flight; they
+    /// keep the quota they already took and start over too. State kept in a
+    /// [`StateBackend`], in-flight slots, and circuit breakers are unaffected.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use http::Method;
+    /// use route_ratelimit::RateLimitMiddleware;
+    /// use std::time::Duration;
+    ///
+    /// # async fn example() -> Result<(), route_ratelimit::RateLimitError> {
+    /// let middleware = RateLimitMiddleware::builder()
+    ///     .route(|r| r.limit(1, Duration::from_secs(60)))
+    ///     .build();
+    ///
+    /// middleware.acquire("api.example.com", &Method::GET, "/").await?;
+    /// middleware.reset();
+    /// middleware.acquire("api.example.com", &Method::GET, "/").await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn reset(&self) {
+        self.table.load().state.clear();
+    }
+
+    /// Remove the rate limit state of the routes that apply to a request
+    /// described by `host`, `method`, and `path`, like
+    /// [`reset`](Self::reset) does for all routes.
+    ///
+    /// Routes are matched like [`quota_remaining`](Self::quota_remaining)
+    /// does, and every bucket of a matching route is cleared, whatever its
+    /// key. Routes in a [shared group](crate::RouteBuilder::shared_group)
+    /// clear the state of the whole group.
+    pub fn reset_route(&self, host: &str, method: &Method, path: &str) {
+        let table = self.table.load();
+        let (path, query) = split_query(path);
+        let matching = table.applicable(
+            table
+                .routes
+                .iter()
+                .enumerate()
+                .filter(|(_, route)| route.matches_components(Some(host), method, path, query))
+                .map(|(route_index, _)| route_index),
+        );
+        let scopes: Vec<LimitScope> = matching
+            .iter()
+            .map(|&route_index| table.routes[route_index].limit_scope(route_index))
+            .collect();
+        table.state.retain(|key, _| !scopes.contains(&key.scope));
+    }
+
+    /// Spawn a task that calls [`cleanup`](Self::cleanup) every `interval`.
+    ///
+    /// The task only holds weak references to the shared state, so it stops on
+    /// its next tick once every clone of the middleware has been dropped, or
+    /// right away on [`shutdown`](Self::shutdown).
+    pub(crate) fn spawn_auto_cleanup(&self, interval: Duration) {
+        let table = Arc::downgrade(&self.table);
+        let clock = Arc::clone(&self.clock);
+        let stale_after = self.cleanup_stale_after;
+        let shutdown = Arc::clone(&self.shutdown);
+
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            // The first tick completes immediately
+            ticker.tick().await;
+
+            while shutdown.until(ticker.tick()).await.is_some() {
+                let Some(table) = table.upgrade() else {
+                    break;
+                };
+                table.load().cleanup_stale(clock.now_nanos(), stale_after);
+            }
+        });
+    }
+
+    /// Wait until a request described by `host`, `method`, and `path` may be
+    /// made, consuming quota as if it had been sent.
+    ///
+    /// Applies the same limits and [`ThrottleBehavior`] as for HTTP requests,
+    /// so the route configuration can also throttle other work, such as
+    /// messages on a websocket. `path` may include a `?query` string. Routes
+    /// restricted to a scheme or keyed by a header only apply their default
+    /// bucket or not at all, since neither is known, and concurrency limits
+    /// are released as soon as this returns.
+    ///
+    /// # Errors
+    ///
+    /// Returns the error a request would have been rejected with, including
+    /// for routes configured with [`ThrottleBehavior::Respond429`].
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use http::Method;
+    /// use route_ratelimit::RateLimitMiddleware;
+    /// use std::time::Duration;
+    ///
+    /// # async fn example() -> Result<(), route_ratelimit::RateLimitError> {
+    /// let middleware = RateLimitMiddleware::builder()
+    ///     .host("stream.example.com", |host| {
+    ///         host.route(|r| r.path("/ws").limit(10, Duration::from_secs(1)))
+    ///     })
+    ///     .build();
+    ///
+    /// loop {
+    ///     middleware
+    ///         .acquire("stream.example.com", &Method::GET, "/ws")
+    ///         .await?;
+    ///     // send a message
+    /// #   break;
+    /// }
+    /// # Ok(())
     /// # }
     /// ```
-    pub fn cleanup(&self) {
+    pub async fn acquire(
+        &self,
+        host: &str,
+        method: &Method,
+        path: &str,
+    ) -> Result<(), RateLimitError> {
+        if !self.is_enabled() {
+            return Ok(());
+        }
+        let (path, query) = split_query(path);
+        let headers = http::HeaderMap::new();
+        let parts = RequestParts {
+            scheme: None,
+            host: Some(host),
+            method,
+            path,
+            query,
+            headers: &headers,
+            request: None,
+        };
+        match self.admit(&parts, None, Priority::default()).await {
+            Ok(admission) => {
+                self.complete(admission, None);
+                Ok(())
+            }
+            Err(Rejection::Error(err) | Rejection::Respond(err)) => Err(err),
+        }
+    }
+
+    /// Reserve quota for `n` requests described by `host`, `method`, and
+    /// `path` at once, or for none of them.
+    ///
+    /// Takes `n` times each matching route's weight from every one of its
+    /// limits if all of them have room, e.g. before sending a batch that is
+    /// only worth sending whole. If any limit is short, the quota already
+    /// taken from the others is given back and the wait for that limit is
+    /// returned, without delaying. Like weighted requests, a reservation
+    /// larger than a limit's whole burst is admitted once its bucket is full.
+    ///
+    /// Routes are matched like [`quota_remaining`](Self::quota_remaining)
+    /// does, and only rate limits are checked: concurrency limits, circuit
+    /// breakers, and [unmatched](RateLimitBuilder::unmatched) requests are
+    /// not. Limits kept in a [`StateBackend`] are reserved after all others,
+    /// and given back through [`StateBackend::refund`], which backends may
+    /// not support. Methods exempted with
+    /// [`bypass_methods`](RateLimitBuilder::bypass_methods) reserve nothing.
+    ///
+    /// # Errors
+    ///
+    /// Returns how long to wait before the first limit found short could fit
+    /// the reservation.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use http::Method;
+    /// use route_ratelimit::RateLimitMiddleware;
+    /// use std::time::Duration;
+    ///
+    /// let middleware = RateLimitMiddleware::builder()
+    ///     .route(|r| r.path("/orders").limit(10, Duration::from_secs(1)))
+    ///     .build();
+    ///
+    /// assert!(middleware.try_acquire_n("api.example.com", &Method::POST, "/orders", 8).is_ok());
+    /// // Only two of the next five would fit, so none are reserved
+    /// assert!(middleware.try_acquire_n("api.example.com", &Method::POST, "/orders", 5).is_err());
+    /// assert!(middleware.try_acquire_n("api.example.com", &Method::POST, "/orders", 2).is_ok());
+    /// ```
+    pub fn try_acquire_n(
+        &self,
+        host: &str,
+        method: &Method,
+        path: &str,
+        n: u32,
+    ) -> Result<(), Duration> {
+        let table = self.table.load();
+        self.reserve(&table, host, method, path, n, self.now_nanos())
+            .map_err(|(_, _, wait)| wait)
+    }
+
+    /// Admit a request described by `host`, `method`, and `path` as if it
+    /// were made at `now_nanos`, without waiting.
+    ///
+    /// Replays recorded traffic against the configured limits: `now_nanos` is
+    /// used in place of the middleware's [`Clock`], measured from the same
+    /// origin, so a trace of timestamps shows which requests would have been
+    /// throttled. Timestamps should not decrease from one call to the next.
+    ///
+    /// Every limit is treated as [`ThrottleBehavior::Error`]: a request that
+    /// would be delayed is rejected right away, with the wait in its error,
+    /// and takes no quota. Routes are matched and checked like
+    /// [`try_acquire_n`](Self::try_acquire_n) does for a single request.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`RateLimitError::RateLimited`] for the first limit the request
+    /// exceeds, with `retry_after` measured from `now_nanos`, and `retry_at`
+    /// the instant that is `now_nanos` plus `retry_after` past the clock's
+    /// origin.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use http::Method;
+    /// use route_ratelimit::RateLimitMiddleware;
+    /// use std::time::Duration;
+    ///
+    /// let middleware = RateLimitMiddleware::builder()
+    ///     .route(|r| r.limit(1, Duration::from_secs(1)))
+    ///     .build();
+    ///
+    /// let trace = [0, 400_000_000, 1_200_000_000];
+    /// let throttled: Vec<bool> = trace
+    ///     .into_iter()
+    ///     .map(|at| middleware.acquire_at("api.example.com", &Method::GET, "/", at).is_err())
+    ///     .collect();
+    /// assert_eq!(throttled, [false, true, false]);
+    /// ```
+    pub fn acquire_at(
+        &self,
+        host: &str,
+        method: &Method,
+        path: &str,
+        now_nanos: u64,
+    ) -> Result<(), RateLimitError> {
+        let table = self.table.load();
+        self.reserve(&table, host, method, path, 1, now_nanos)
+            .map_err(|(route_index, limit_index, wait)| {
+                let route = &table.routes[route_index];
+                RateLimitError::RateLimited {
+                    host: host.to_owned(),
+                    route: route.path_description(),
+                    limit_index,
+                    limit_count: route.limit_count(),
+                    retry_after: wait,
+                    retry_at: self.retry_at(now_nanos, wait),
+                }
+            })
+    }
+
+    /// The instant `retry_after` past `now_nanos` on the middleware's clock.
+    fn retry_at(&self, now_nanos: u64, retry_after: Duration) -> Instant {
+        let at = now_nanos.saturating_add(retry_after.as_nanos() as u64);
+        let now = Instant::now();
+        let current = self.now_nanos();
+        if at >= current {
+            now + Duration::from_nanos(at - current)
+        } else {
+            now.checked_sub(Duration::from_nanos(current - at))
+                .unwrap_or(now)
+        }
+    }
+
+    /// Take `n` requests' worth of quota at `now` from every limit that
+    /// applies to a request, or none.
+    ///
+    /// Fails with the indexes of the first route and limit found short, and
+    /// the wait until it has room.
+    fn reserve(
+        &self,
+        table: &RouteTable,
+        host: &str,
+        method: &Method,
+        path: &str,
+        n: u32,
+        now: u64,
+    ) -> Result<(), (usize, usize, Duration)> {
+        if !self.is_enabled() || n == 0 || self.bypass_methods.contains(method) {
+            return Ok(());
+        }
+        let (path, query) = split_query(path);
+        let headers = http::HeaderMap::new();
+        let parts = RequestParts {
+            scheme: None,
+            host: Some(host),
+            method,
+            path,
+            query,
+            headers: &headers,
+            request: None,
+        };
+
+        let mut scopes = Vec::new();
+        let mut reserved = Vec::new();
+        let mut remote = Vec::new();
+        let mut result = Ok(());
+        'routes: for &route_index in table.matching(&parts).iter() {
+            let route = &table.routes[route_index];
+            // A request matching several routes of a group counts once
+            let scope = route.limit_scope(route_index);
+            if scopes.contains(&scope) {
+                continue;
+            }
+            scopes.push(scope.clone());
+
+            let bucket_key = route.bucket_key(&parts);
+            for check in table.checks(route_index, &bucket_key, route.weight) {
+                // A pool drawn from by several matching routes is drawn from
+                // once
+                if check.is_pool() {
+                    if scopes.contains(&check.key.scope) {
+                        continue;
+                    }
+                    scopes.push(check.key.scope.clone());
+                }
+                let limit_index = check.limit_index;
+                let key = check.key;
+                let effective = check.effective.into_owned();
+                let cost = check.cost.saturating_mul(n);
+                if let Some(backend) = self.backend_for(route, &key, check.limit) {
+                    remote.push((route_index, limit_index, backend, key, effective, cost));
+                    continue;
+                }
+                result = try_acquire_local(table, route, &key, now, &effective, cost)
+                    .map_err(|wait| (route_index, limit_index, wait));
+                if result.is_err() {
+                    break 'routes;
+                }
+                reserved.push((key, effective, cost));
+            }
+        }
+        let mut reserved_remote = Vec::new();
+        if result.is_ok() {
+            for (route_index, limit_index, backend, key, limit, cost) in remote {
+                result = backend::try_acquire(backend, table, &key, now, &limit, cost)
+                    .map_err(|wait| (route_index, limit_index, wait));
+                if result.is_err() {
+                    break;
+                }
+                reserved_remote.push((backend, key, limit, cost));
+            }
+        }
+
+        if result.is_err() {
+            for (key, limit, cost) in reserved {
+                if let Some(state) = table.state.get(&key) {
+                    state.refund(now, &limit, cost);
+                }
+            }
+            for (backend, key, limit, cost) in reserved_remote {
+                backend::refund(backend, table, &key, now, &limit, cost);
+            }
+        }
+        result
+    }
+
+    /// Returns the remaining capacity of every limit that applies to a request.
+    ///
+    /// For each route matching `host`, `method`, and `path`, and for each of its
+    /// limits, returns the limit together with the number of requests that could
+    /// be made right now before that limit is hit. No quota is consumed.
+    /// `path` may include a `?query` string to match routes with query
+    /// constraints. Routes are matched regardless of their scheme filter, and
+    /// under [`MatchMode::MostSpecific`] or [`MatchMode::FirstMatch`] only the
+    /// one route that applies is reported.
+    ///
+    /// Returns an empty vector if no route matches. For routes keyed by a header
+    /// (see [`RouteBuilder::key_by_header`](crate::RouteBuilder::key_by_header)),
+    /// the shared default bucket is reported.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use route_ratelimit::RateLimitMiddleware;
+    /// use http::Method;
+    /// use std::time::Duration;
+    ///
+    /// let middleware = RateLimitMiddleware::builder()
+    ///     .host("api.example.com", |host| {
+    ///         host.route(|r| r.path("/book").limit(1500, Duration::from_secs(10)))
+    ///     })
+    ///     .build();
+    ///
+    /// let quota = middleware.quota_remaining("api.example.com", &Method::GET, "/book");
+    /// assert_eq!(quota[0].1, 1500);
+    /// ```
+    #[must_use]
+    pub fn quota_remaining(
+        &self,
+        host: &str,
+        method: &Method,
+        path: &str,
+    ) -> Vec<(RateLimit, u32)> {
+        let table = self.table.load();
+        let now = self.now_nanos();
+        let mut quota = Vec::new();
+        let (path, query) = split_query(path);
+
+        let matching = table.applicable(
+            table
+                .routes
+                .iter()
+                .enumerate()
+                .filter(|(_, route)| route.matches_components(Some(host), method, path, query))
+                .map(|(route_index, _)| route_index),
+        );
+        for &route_index in matching.iter() {
+            quota.extend(remaining(&table, now, route_index, None));
+        }
+
+        quota
+    }
+
+    /// Number of requests currently delayed on the routes that apply to a
+    /// request described by `host`, `method`, and `path`.
+    ///
+    /// Counts requests sleeping on a limit or waiting for their turn behind
+    /// other delayed requests, as a backpressure signal. Routes are matched
+    /// like [`quota_remaining`](Self::quota_remaining) does. See
+    /// [`RouteBuilder::max_pending`](crate::RouteBuilder::max_pending) to cap
+    /// the count.
+    #[must_use]
+    pub fn pending(&self, host: &str, method: &Method, path: &str) -> usize {
+        let table = self.table.load();
+        let (path, query) = split_query(path);
+
+        let matching = table.applicable(
+            table
+                .routes
+                .iter()
+                .enumerate()
+                .filter(|(_, route)| route.matches_components(Some(host), method, path, query))
+                .map(|(route_index, _)| route_index),
+        );
+        matching
+            .iter()
+            .map(|&route_index| table.pending[route_index].load(Ordering::Relaxed))
+            .sum()
+    }
+
+    /// Wait until a request described by `host`, `method`, and `path` could
+    /// be made without being throttled, without consuming any quota.
+    ///
+    /// Resolves once every limit of every matching route has room for a
+    /// request of the route's weight, for example to resume a producer that
+    /// was paused while a route was saturated. Routes are matched like
+    /// [`quota_remaining`](Self::quota_remaining) does, against their default
+    /// bucket. Resolves immediately if no route matches or the middleware is
+    /// [disabled](Self::set_enabled).
+    ///
+    /// The notification is advisory: other requests may use up the capacity
+    /// before the caller sends its own, and concurrency limits and circuit
+    /// breakers are not taken into account.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use http::Method;
+    /// use route_ratelimit::RateLimitMiddleware;
+    /// use std::time::Duration;
+    ///
+    /// # async fn example() {
+    /// let middleware = RateLimitMiddleware::builder()
+    ///     .host("api.example.com", |host| {
+    ///         host.route(|r| r.path("/order").limit(10, Duration::from_secs(1)))
+    ///     })
+    ///     .build();
+    ///
+    /// middleware
+    ///     .notify_on_available("api.example.com", &Method::POST, "/order")
+    ///     .await;
+    /// // resume producing orders
+    /// # }
+    /// ```
+    pub async fn notify_on_available(&self, host: &str, method: &Method, path: &str) {
+        let (path, query) = split_query(path);
+        loop {
+            if !self.is_enabled() {
+                return;
+            }
+            let table = self.table.load();
+            let now = self.now_nanos();
+            let matching = table.applicable(
+                table
+                    .routes
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, route)| route.matches_components(Some(host), method, path, query))
+                    .map(|(route_index, _)| route_index),
+            );
+            let wait = matching
+                .iter()
+                .map(|&route_index| wait_until_available(&table, now, route_index))
+                .max()
+                .unwrap_or_default();
+            if wait.is_zero() {
+                return;
+            }
+            drop(table);
+            sleep(wait).await;
+        }
+    }
+
+    /// Explain how every route relates to a request, for debugging why it was
+    /// or wasn't limited.
+    ///
+    /// Returns one entry per configured route, in route order, reporting
+    /// whether the route applies to `req` and, if it does, the remaining capacity
+    /// of each of its limits in the bucket `req` would use. No quota is
+    /// consumed. Bypassed methods and a [disabled](Self::set_enabled)
+    /// middleware are not taken into account.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use route_ratelimit::RateLimitMiddleware;
+    /// use std::time::Duration;
+    ///
+    /// let middleware = RateLimitMiddleware::builder()
+    ///     .host("api.example.com", |host| {
+    ///         host.route(|r| r.path("/book").limit(1500, Duration::from_secs(10)))
+    ///             .route(|r| r.path("/price").limit(1500, Duration::from_secs(10)))
+    ///     })
+    ///     .build();
+    ///
+    /// let req = reqwest::Client::new()
+    ///     .get("https://api.example.com/book")
+    ///     .build()
+    ///     .unwrap();
+    /// let explanation = middleware.explain(&req);
+    /// assert!(explanation[0].matched);
+    /// assert!(!explanation[1].matched);
+    /// ```
+    #[must_use]
+    pub fn explain(&self, req: &Request) -> Vec<RouteMatch> {
+        let table = self.table.load();
         let now = self.now_nanos();
-        self.state.retain(|key, gcra_state| {
-            // Bounds check to handle edge cases
-            if key.route_index >= self.routes.len() {
-                return false;
+        let parts = RequestParts::from(req);
+        let applicable = table.applicable(
+            table
+                .routes
+                .iter()
+                .enumerate()
+                .filter(|(_, route)| route.matches(&parts))
+                .map(|(route_index, _)| route_index),
+        );
+
+        table
+            .routes
+            .iter()
+            .enumerate()
+            .map(|(route_index, route)| {
+                let matched = applicable.contains(&route_index);
+                let remaining = if matched {
+                    let bucket_key = route.bucket_key(&parts);
+                    remaining(&table, now, route_index, bucket_key)
+                } else {
+                    Vec::new()
+                };
+                RouteMatch {
+                    route_index,
+                    matched,
+                    remaining,
+                }
+            })
+            .collect()
+    }
+
+    /// Check whether a request would be let through right now, without
+    /// consuming any quota.
+    ///
+    /// Considers every route matching `req` and every limit of those routes,
+    /// and returns `false` if any of them would throttle the request, or if a
+    /// matching route's [`max_concurrent`](crate::RouteBuilder::max_concurrent)
+    /// slots are all in use. Requests are counted with their route's weight,
+    /// since a per-request [`RequestWeight`] is only known to
+    /// the middleware chain.
+    ///
+    /// The answer is only a snapshot: concurrent requests may use up the
+    /// remaining capacity before `req` is actually sent.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use route_ratelimit::{RateLimitMiddleware, ThrottleBehavior};
+    /// use std::time::Duration;
+    ///
+    /// let middleware = RateLimitMiddleware::builder()
+    ///     .host("api.example.com", |host| {
+    ///         host.route(|r| r.limit(10, Duration::from_secs(1)).on_limit(ThrottleBehavior::Error))
+    ///     })
+    ///     .build();
+    ///
+    /// let req = reqwest::Client::new()
+    ///     .get("https://api.example.com/orders")
+    ///     .build()
+    ///     .unwrap();
+    /// assert!(middleware.would_allow(&req));
+    /// ```
+    #[must_use]
+    pub fn would_allow(&self, req: &Request) -> bool {
+        if !self.is_enabled() || self.bypass_methods.contains(req.method()) {
+            return true;
+        }
+        let table = self.table.load();
+        let now = self.now_nanos();
+        let parts = RequestParts::from(req);
+        let mut checked_groups = Vec::new();
+
+        let matching = table.matching(&parts);
+        if matching.is_empty() && self.unmatched == UnmatchedBehavior::Deny {
+            return false;
+        }
+        for &route_index in matching.iter() {
+            let route = &table.routes[route_index];
+
+            // A request matching several routes of a group counts once
+            if let Some(group) = &route.shared_group {
+                if checked_groups.contains(&group) {
+                    continue;
+                }
+                checked_groups.push(group);
+            }
+
+            if let Some(semaphore) = &table.semaphores[route_index] {
+                if semaphore.available_permits() == 0 {
+                    return false;
+                }
             }
-            let route = &self.routes[key.route_index];
-            if key.limit_index >= route.limits.len() {
-                return false;
+
+            let bucket_key = route.bucket_key(&parts);
+            for check in table.checks(route_index, &bucket_key, route.weight) {
+                // Missing state means the full burst is available
+                if let Some(state) = table.state.get(&check.key) {
+                    if state
+                        .peek_weighted(now, &check.effective, check.cost)
+                        .is_err()
+                    {
+                        return false;
+                    }
+                }
             }
+        }
 
-            let limit = &route.limits[key.limit_index];
-            let window_nanos = limit.window.as_nanos() as u64;
-            let tat = gcra_state.tat(Ordering::Acquire);
+        true
+    }
 
-            // Keep if TAT is within 2x window of now (recently active)
-            // An entry with TAT far in the past has fully recovered and can be removed
-            tat > now.saturating_sub(window_nanos.saturating_mul(2))
+    /// The configured routes, in the order they are matched.
+    ///
+    /// The routes are read once; later calls to
+    /// [`update_routes`](Self::update_routes) do not affect the returned
+    /// [`Routes`]. Nothing is cloned, so this is cheap to call.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use route_ratelimit::RateLimitMiddleware;
+    /// use std::time::Duration;
+    ///
+    /// let middleware = RateLimitMiddleware::builder()
+    ///     .host("api.example.com", |host| {
+    ///         host.route(|r| r.limit(100, Duration::from_secs(10)))
+    ///             .route(|r| r.path("/order").limit(10, Duration::from_secs(1)))
+    ///     })
+    ///     .build();
+    ///
+    /// for route in &middleware.routes() {
+    ///     println!("{:?} {}: {:?}", route.hosts(), route.path_prefix(), route.limits());
+    /// }
+    /// assert_eq!(middleware.routes().len(), 2);
+    /// ```
+    #[must_use]
+    pub fn routes(&self) -> Routes {
+        Routes::new(self.table.load_full())
+    }
+
+    /// A point-in-time view of every active bucket, for operational
+    /// visibility.
+    ///
+    /// Reports, for each bucket with state, the route it belongs to, its
+    /// limit, its per-key discriminator, the number of requests it could admit
+    /// right now, and how long until it has fully recovered. Buckets are
+    /// sorted by route, limit, and key. Nothing is modified, and buckets
+    /// created after the state is read may be missing. Buckets kept in a
+    /// [`StateBackend`] and those of quota
+    /// [pools](crate::RateLimitBuilder::pool) are not included.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use route_ratelimit::RateLimitMiddleware;
+    /// use std::time::Duration;
+    ///
+    /// let middleware = RateLimitMiddleware::builder()
+    ///     .route(|r| r.limit(100, Duration::from_secs(10)))
+    ///     .build();
+    ///
+    /// for stat in middleware.stats() {
+    ///     println!("{} {}: {} remaining", stat.host, stat.path, stat.remaining);
+    /// }
+    /// ```
+    #[must_use]
+    pub fn stats(&self) -> Vec<BucketStat> {
+        let table = self.table.load();
+        let now = self.now_nanos();
+        let mut stats: Vec<BucketStat> = table
+            .state
+            .iter()
+            .filter_map(|entry| {
+                let key = entry.key();
+                // Bounds check in case the key no longer refers to a limit
+                let limit = table.limit_for(key)?;
+                let route_index = table.route_index_for(key)?;
+                let route = &table.routes[route_index];
+                let limit = table.effective_limit(route_index, limit);
+                let state = entry.value();
+                Some(BucketStat {
+                    route_index,
+                    host: route.host_description(),
+                    methods: route.methods.clone(),
+                    path: route.path_description(),
+                    limit_index: key.limit_index,
+                    key: key.key.clone(),
+                    remaining: state.remaining(now, &limit).min(u64::from(limit.burst())) as u32,
+                    recovers_in: Duration::from_nanos(
+                        state.recovered_at(&limit).saturating_sub(now),
+                    ),
+                })
+            })
+            .collect();
+        stats.sort_by(|a, b| {
+            (a.route_index, a.limit_index, &a.key).cmp(&(b.route_index, b.limit_index, &b.key))
         });
+        stats
+    }
+
+    /// `wait` plus a random fraction of it, up to the configured jitter, to
+    /// prevent a thundering herd.
+    fn add_jitter(&self, wait: Duration) -> Duration {
+        let jitter_max_nanos = (wait.as_nanos() as f64 * self.jitter) as u64;
+        let jitter_nanos = if jitter_max_nanos > 0 {
+            let sample = self.jitter_source.sample().clamp(0.0, 1.0);
+            (jitter_max_nanos as f64 * sample) as u64
+        } else {
+            0
+        };
+        wait + Duration::from_nanos(jitter_nanos)
     }
 
     /// Returns the number of active rate limit state entries.
@@ -103,65 +1128,751 @@ impl RateLimitMiddleware {
     /// This can be useful for monitoring memory usage.
     #[must_use]
     pub fn state_count(&self) -> usize {
-        self.state.len()
+        self.table.load().state.len()
+    }
+
+    /// Push the given limits forward so that no request is admitted before
+    /// `delay` from now.
+    fn defer_until(&self, table: &RouteTable, acquired: &[(usize, RouteKey)], delay: Duration) {
+        let retry_at = self
+            .now_nanos()
+            .saturating_add(delay.as_nanos().min(u64::MAX as u128) as u64);
+
+        for (_, key) in acquired {
+            let Some(limit) = table.limit_for(key) else {
+                continue;
+            };
+            if let Some(state) = table.state.get(key) {
+                state.defer_until(retry_at, limit);
+            }
+        }
+    }
+
+    /// Wait until a request may be sent, or decide to reject it.
+    ///
+    /// `weight_override` replaces the weight of every matching route, as set by
+    /// the [`RequestWeight`] extension, and `priority` orders the request among
+    /// others delayed on the same limit, as set by the [`Priority`] extension.
+    /// The returned [`Admission`] must be passed to [`complete`](Self::complete)
+    /// once the response has arrived.
+    pub(crate) async fn admit(
+        &self,
+        req: &RequestParts<'_>,
+        weight_override: Option<u32>,
+        priority: Priority,
+    ) -> Result<Admission, Rejection> {
+        // Check and apply rate limits, then hold in-flight slots until the
+        // response arrives
+        let table = self.table.load_full();
+        let acquired = self
+            .check_and_apply_limits(&table, req, weight_override, priority)
+            .await?;
+        let permits = match self.acquire_permits(&table, &acquired).await {
+            Ok(permits) => permits,
+            Err(rejection) => {
+                // The request is never sent, so it must not use up quota
+                self.refund_acquired(&table, &acquired, weight_override);
+                return Err(rejection);
+            }
+        };
+        Ok(Admission {
+            table,
+            acquired,
+            weight_override,
+            permits,
+        })
     }
 
-    async fn check_and_apply_limits(&self, req: &Request) -> Result<(), RateLimitError> {
+    /// Release an admitted request, given the status and headers of its
+    /// response if one was received.
+    pub(crate) fn complete(
+        &self,
+        admission: Admission,
+        response: Option<(StatusCode, &http::HeaderMap)>,
+    ) {
+        let Admission {
+            table,
+            acquired,
+            weight_override,
+            permits,
+        } = admission;
+        drop(permits);
+
+        let Some((status, headers)) = response else {
+            return;
+        };
+        self.refund_uncounted(&table, &acquired, weight_override, status);
+        self.charge_response_bytes(&table, &acquired, headers);
+        adapt(&table, &acquired, status);
+        if self.respect_retry_after
+            && !acquired.is_empty()
+            && (status == StatusCode::TOO_MANY_REQUESTS
+                || status == StatusCode::SERVICE_UNAVAILABLE)
+        {
+            if let Some(delay) = parse_retry_after(headers) {
+                self.defer_until(&table, &acquired, delay);
+            }
+        }
+        if self.respect_ratelimit_headers && !acquired.is_empty() {
+            if let Some(delay) = parse_ratelimit_reset(headers) {
+                self.defer_until(&table, &acquired, delay);
+            }
+        }
+    }
+
+    /// Give back the quota taken on routes whose
+    /// [`count_if`](crate::RouteBuilder::count_if) predicate rejects `status`.
+    fn refund_uncounted(
+        &self,
+        table: &RouteTable,
+        acquired: &[(usize, RouteKey)],
+        weight_override: Option<u32>,
+        status: StatusCode,
+    ) {
+        let now = self.now_nanos();
+        for (route_index, key) in acquired {
+            let route = &table.routes[*route_index];
+            let Some(predicate) = &route.count_if else {
+                continue;
+            };
+            if predicate.counts(status) {
+                continue;
+            }
+            let (Some(limit), Some(state)) = (table.limit_for(key), table.state.get(key)) else {
+                continue;
+            };
+            // The response's bytes were transferred all the same
+            if limit.unit == LimitUnit::Bytes {
+                continue;
+            }
+            let cost = match &key.scope {
+                LimitScope::Pool(pool) => route.pool_cost(pool),
+                _ => weight_override.unwrap_or(route.weight),
+            };
+            state.refund(now, limit, cost);
+        }
+    }
+
+    /// Give back all the quota taken by a request that was rejected after
+    /// passing its limits.
+    fn refund_acquired(
+        &self,
+        table: &RouteTable,
+        acquired: &[(usize, RouteKey)],
+        weight_override: Option<u32>,
+    ) {
+        let now = self.now_nanos();
+        for (route_index, key) in acquired {
+            let route = &table.routes[*route_index];
+            let Some(limit) = table.limit_for(key) else {
+                continue;
+            };
+            let (effective, cost) = match &key.scope {
+                LimitScope::Pool(pool) => (Cow::Borrowed(limit), route.pool_cost(pool)),
+                _ => (
+                    table.effective_limit(*route_index, limit),
+                    limit.cost(weight_override.unwrap_or(route.weight)),
+                ),
+            };
+            match self.backend_for(route, key, limit) {
+                Some(backend) => backend::refund(backend, table, key, now, &effective, cost),
+                None => {
+                    if let Some(state) = table.state.get(key) {
+                        state.refund(now, &effective, cost);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Charge the size of a response body to the bandwidth limits it was
+    /// admitted on, beyond the byte taken at admission.
+    fn charge_response_bytes(
+        &self,
+        table: &RouteTable,
+        acquired: &[(usize, RouteKey)],
+        headers: &http::HeaderMap,
+    ) {
+        let content_length = headers
+            .get(CONTENT_LENGTH)
+            .and_then(|value| value.to_str().ok()?.trim().parse::<u64>().ok());
+        let now = self.now_nanos();
+        for (route_index, key) in acquired {
+            let Some(limit) = table.limit_for(key) else {
+                continue;
+            };
+            if limit.unit != LimitUnit::Bytes {
+                continue;
+            }
+            let route = &table.routes[*route_index];
+            let bytes = content_length.unwrap_or(route.default_response_bytes);
+            if let Some(state) = table.state.get(key) {
+                let limit = table.effective_limit(*route_index, limit);
+                state.charge(now, &limit, bytes.saturating_sub(1));
+            }
+        }
+    }
+
+    /// Apply all matching limits, returning the keys of the limits that were
+    /// acquired, each with the index of the route it was acquired for.
+    ///
+    /// With the `tracing` feature, runs in a `debug` span carrying the host and
+    /// path, and emits a `debug` event for every throttle and admission.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "rate_limit",
+            level = "debug",
+            skip_all,
+            fields(host = req.host, path = req.path)
+        )
+    )]
+    pub(crate) async fn check_and_apply_limits(
+        &self,
+        table: &RouteTable,
+        req: &RequestParts<'_>,
+        weight_override: Option<u32>,
+        priority: Priority,
+    ) -> Result<Vec<(usize, RouteKey)>, Rejection> {
+        let mut acquired = Vec::new();
+        if self.bypass_methods.contains(req.method) {
+            #[cfg(feature = "tracing")]
+            tracing::debug!(method = %req.method, "method bypasses rate limiting");
+            return Ok(acquired);
+        }
+
+        // Places in the wait queues of the buckets this request was delayed
+        // on, released when it is admitted or rejected
+        let mut tickets: Vec<Ticket> = Vec::new();
+        // Routes that throttled this request, which must not reset their
+        // circuit breakers once it is admitted
+        let mut throttled = Vec::new();
+        // Time delayed so far, sleeping on limits or queued behind other
+        // requests, kept across restarts of the check
+        let mut slept = Duration::ZERO;
+        // The request's own timeout should govern the network call, so it
+        // bounds the delay like the total delay budget
+        let budget = self.max_total_delay.into_iter().chain(req.timeout()).min();
+        let matching = table.matching(req);
+        if matching.is_empty() && self.unmatched == UnmatchedBehavior::Deny {
+            #[cfg(feature = "tracing")]
+            tracing::debug!("no route matches");
+            return Err(Rejection::Error(RateLimitError::Unmatched {
+                host: req.host.unwrap_or_default().to_owned(),
+                path: req.path.to_owned(),
+            }));
+        }
+
         'outer: loop {
-            let now = self.now_nanos();
+            let mut now = self.now_nanos();
+            acquired.clear();
 
-            for (route_index, route) in self.routes.iter().enumerate() {
-                if !route.matches(req) {
+            // Fail fast on open circuits before taking any quota
+            for &route_index in matching.iter() {
+                let Some(breaker) = &table.breakers[route_index] else {
                     continue;
+                };
+                let route = &table.routes[route_index];
+                if let Err(retry_after) = breaker.check(now) {
+                    #[cfg(feature = "tracing")]
+                    tracing::debug!(route = route_index, ?retry_after, "circuit open");
+                    #[cfg(feature = "metrics")]
+                    telemetry::record_outcome(&table.labels[route_index], telemetry::ERRORED);
+                    let retry_at = self.retry_at(now, retry_after);
+                    return Err(circuit_open(req, route, retry_after, retry_at));
                 }
+            }
 
-                for (limit_index, limit) in route.limits.iter().enumerate() {
-                    let key = RouteKey {
-                        route_index,
-                        limit_index,
-                    };
+            for &route_index in matching.iter() {
+                let route = &table.routes[route_index];
+
+                // A request matching several routes of a group counts once
+                let scope = route.limit_scope(route_index);
+                if matches!(scope, LimitScope::Group(_))
+                    && acquired
+                        .iter()
+                        .any(|(_, key): &(usize, RouteKey)| key.scope == scope)
+                {
+                    continue;
+                }
 
-                    let emission_interval_nanos = limit.emission_interval().as_nanos() as u64;
-                    let limit_nanos = limit.window.as_nanos() as u64;
+                let weight = weight_override.unwrap_or(route.weight);
+                let bucket_key = route.bucket_key(req);
+                let queue_key = (scope.clone(), bucket_key.clone());
 
-                    // Get or create GCRA state for this route+limit
-                    let state = self.state.entry(key).or_insert_with(GcraState::new);
+                // Delayed requests take turns, so wait behind any already
+                // waiting on this bucket rather than racing them
+                let delays = table
+                    .checks(route_index, &bucket_key, weight)
+                    .any(|check| route.behavior(check.limit) == ThrottleBehavior::Delay);
+                if delays {
+                    let queue = table.queues.get(&queue_key).map(|queue| Arc::clone(&queue));
+                    if let Some(queue) = queue {
+                        let ticket = match tickets.iter().position(|t| t.is_for(&queue)) {
+                            Some(index) => Some(&tickets[index]),
+                            // A full route doesn't queue more requests; the limit
+                            // check below sheds this one if it would wait
+                            None if !queue.is_empty() && !table.pending_full(route_index) => {
+                                tickets.push(queue.join(priority.0));
+                                tickets.last()
+                            }
+                            None => None,
+                        };
+                        if let Some(ticket) = ticket {
+                            let _pending = table.pend(route_index);
+                            self.wait_turn(ticket, budget, &mut slept).await?;
+                            now = self.now_nanos();
+                        }
+                    }
+                }
 
-                    match state.try_acquire(now, emission_interval_nanos, limit_nanos) {
-                        Ok(()) => {}
+                for check in table.checks(route_index, &bucket_key, weight) {
+                    // A pool drawn from by several matching routes is drawn
+                    // from once
+                    if check.is_pool() && acquired.iter().any(|(_, key)| *key == check.key) {
+                        continue;
+                    }
+                    let LimitCheck {
+                        limit_index,
+                        key,
+                        limit,
+                        effective,
+                        cost,
+                    } = check;
+                    let acquire = match self.backend_for(route, &key, limit) {
+                        Some(backend) => {
+                            backend::try_acquire(backend, table, &key, now, &effective, cost)
+                        }
+                        None => try_acquire_local(table, route, &key, now, &effective, cost),
+                    };
+                    match acquire {
+                        Ok(()) => acquired.push((route_index, key)),
                         Err(wait_duration) => {
-                            match route.on_limit {
-                                ThrottleBehavior::Delay => {
-                                    // Release the lock before sleeping
-                                    drop(state);
-                                    // Add jitter (0-50% of wait duration) to prevent thundering herd
-                                    let jitter_max_nanos = wait_duration.as_nanos() as u64 / 2;
-                                    let jitter_nanos = if jitter_max_nanos > 0 {
-                                        rand::rng().random_range(0..=jitter_max_nanos)
+                            // Spread out the retries of requests rejected together
+                            let rate_limited = || {
+                                let retry_after = self.add_jitter(wait_duration);
+                                RateLimitError::RateLimited {
+                                    host: req.host.unwrap_or_default().to_owned(),
+                                    route: route.path_description(),
+                                    limit_index,
+                                    limit_count: route.limit_count(),
+                                    retry_after,
+                                    retry_at: self.retry_at(now, retry_after),
+                                }
+                            };
+                            let tripped = match &table.breakers[route_index] {
+                                Some(breaker) => {
+                                    throttled.push(route_index);
+                                    breaker.record_throttle(now)
+                                }
+                                None => false,
+                            };
+                            let over_budget =
+                                budget.is_some_and(|budget| slept + wait_duration > budget);
+                            // Only requests that haven't waited yet are shed
+                            let shed = slept.is_zero()
+                                && tickets.is_empty()
+                                && table.pending_full(route_index);
+                            let on_limit = route.behavior(limit);
+                            let max_sleep = match on_limit {
+                                // The throttle event that opens the circuit fails fast too
+                                _ if tripped => {
+                                    let cooldown = route.cooldown.map(|c| c.duration);
+                                    let retry_after = cooldown.unwrap_or_default();
+                                    let retry_at = self.retry_at(now, retry_after);
+                                    Err(circuit_open(req, route, retry_after, retry_at))
+                                }
+                                ThrottleBehavior::Delay | ThrottleBehavior::DelayWithTimeout(_)
+                                    if over_budget || shed =>
+                                {
+                                    Err(Rejection::Error(rate_limited()))
+                                }
+                                ThrottleBehavior::Delay => Ok(None),
+                                ThrottleBehavior::DelayWithTimeout(max_wait)
+                                    if wait_duration <= max_wait =>
+                                {
+                                    Ok(Some(max_wait))
+                                }
+                                ThrottleBehavior::Error | ThrottleBehavior::DelayWithTimeout(_) => {
+                                    Err(Rejection::Error(rate_limited()))
+                                }
+                                ThrottleBehavior::Respond429 => {
+                                    Err(Rejection::Respond(rate_limited()))
+                                }
+                            };
+
+                            #[cfg(feature = "tracing")]
+                            tracing::debug!(
+                                route = route_index,
+                                limit = limit_index,
+                                wait = ?wait_duration,
+                                behavior = ?on_limit,
+                                delayed = max_sleep.is_ok(),
+                                "rate limit reached"
+                            );
+
+                            if let Some(ThrottleCallback(callback)) = &self.on_throttle {
+                                callback(ThrottleEvent {
+                                    host: req.host.map(str::to_owned),
+                                    method: req.method.clone(),
+                                    path: req.path.to_owned(),
+                                    route_index,
+                                    limit_index,
+                                    bucket_key: key.key,
+                                    wait: wait_duration,
+                                    outcome: if max_sleep.is_ok() {
+                                        ThrottleOutcome::Delayed
                                     } else {
-                                        0
-                                    };
-                                    let sleep_duration = wait_duration
-                                        + std::time::Duration::from_nanos(jitter_nanos);
-                                    sleep(sleep_duration).await;
-                                    // After sleeping, restart the entire check with fresh timestamp
+                                        ThrottleOutcome::Rejected
+                                    },
+                                });
+                            }
+                            #[cfg(feature = "metrics")]
+                            telemetry::record_outcome(
+                                &table.labels[route_index],
+                                if max_sleep.is_ok() {
+                                    telemetry::DELAYED
+                                } else {
+                                    telemetry::ERRORED
+                                },
+                            );
+                            let max_sleep = max_sleep?;
+
+                            // Only the request at the head of the queue waits for
+                            // the limit, the others wait for their turn
+                            if on_limit == ThrottleBehavior::Delay {
+                                let queue =
+                                    Arc::clone(&table.queues.entry(queue_key.clone()).or_default());
+                                let index = match tickets.iter().position(|t| t.is_for(&queue)) {
+                                    Some(index) => index,
+                                    None => {
+                                        tickets.push(queue.join(priority.0));
+                                        tickets.len() - 1
+                                    }
+                                };
+                                if !tickets[index].is_head() {
+                                    let _pending = table.pend(route_index);
+                                    self.wait_turn(&tickets[index], budget, &mut slept).await?;
                                     continue 'outer;
                                 }
-                                ThrottleBehavior::Error => {
-                                    return Err(RateLimitError::RateLimited(wait_duration));
-                                }
                             }
+
+                            let mut sleep_duration = self.add_jitter(wait_duration);
+                            if let Some(max_sleep) = max_sleep {
+                                sleep_duration = sleep_duration.min(max_sleep);
+                            }
+                            if let Some(budget) = budget {
+                                sleep_duration = sleep_duration.min(budget - slept);
+                            }
+                            slept += sleep_duration;
+                            #[cfg(feature = "metrics")]
+                            telemetry::record_delay(&table.labels[route_index], sleep_duration);
+                            let _pending = table.pend(route_index);
+                            self.until_shutdown(sleep(sleep_duration)).await?;
+                            // After sleeping, restart the entire check with fresh timestamp
+                            continue 'outer;
                         }
                     }
                 }
             }
 
+            #[cfg(feature = "tracing")]
+            let mut matched = Vec::new();
+            let mut last_route = None;
+            for &(route_index, _) in &acquired {
+                if last_route == Some(route_index) {
+                    continue;
+                }
+                last_route = Some(route_index);
+                #[cfg(feature = "tracing")]
+                matched.push(route_index);
+                #[cfg(feature = "metrics")]
+                telemetry::record_outcome(&table.labels[route_index], telemetry::ALLOWED);
+                if let Some(breaker) = &table.breakers[route_index] {
+                    if !throttled.contains(&route_index) {
+                        breaker.record_success();
+                    }
+                }
+            }
+
+            #[cfg(feature = "tracing")]
+            tracing::debug!(routes = ?matched, "request admitted");
+
             // All limits passed, we can proceed
-            break Ok(());
+            break Ok(acquired);
+        }
+    }
+
+    /// Take an in-flight slot from every concurrency-limited route a request
+    /// matched, in route order.
+    ///
+    /// The returned permits must be held until the response has been received.
+    async fn acquire_permits(
+        &self,
+        table: &RouteTable,
+        acquired: &[(usize, RouteKey)],
+    ) -> Result<Vec<OwnedSemaphorePermit>, Rejection> {
+        let mut permits = Vec::new();
+        let mut last_route = None;
+
+        for &(route_index, _) in acquired {
+            if last_route == Some(route_index) {
+                continue;
+            }
+            last_route = Some(route_index);
+
+            let Some(semaphore) = &table.semaphores[route_index] else {
+                continue;
+            };
+            let semaphore = Arc::clone(semaphore);
+
+            // The semaphores are never closed, so acquiring only fails on timeout
+            let on_limit = table.routes[route_index].on_limit;
+            let permit = match on_limit {
+                ThrottleBehavior::Delay => {
+                    self.until_shutdown(semaphore.acquire_owned()).await?.ok()
+                }
+                ThrottleBehavior::DelayWithTimeout(max_wait) => self
+                    .until_shutdown(timeout(max_wait, semaphore.acquire_owned()))
+                    .await?
+                    .ok()
+                    .and_then(Result::ok),
+                ThrottleBehavior::Error | ThrottleBehavior::Respond429 => {
+                    semaphore.try_acquire_owned().ok()
+                }
+            };
+            let Some(permit) = permit else {
+                return Err(if on_limit == ThrottleBehavior::Respond429 {
+                    Rejection::Respond(RateLimitError::ConcurrencyLimited)
+                } else {
+                    Rejection::Error(RateLimitError::ConcurrencyLimited)
+                });
+            };
+            permits.push(permit);
+        }
+
+        Ok(permits)
+    }
+
+    /// Wait for `ticket`'s turn in its queue, adding the time waited to
+    /// `slept`.
+    ///
+    /// Gives up once `slept` reaches `budget`, leaving the next limit check to
+    /// reject the request if it still has to wait.
+    async fn wait_turn(
+        &self,
+        ticket: &Ticket,
+        budget: Option<Duration>,
+        slept: &mut Duration,
+    ) -> Result<(), Rejection> {
+        let started = tokio::time::Instant::now();
+        match budget {
+            Some(budget) => {
+                let left = budget.saturating_sub(*slept);
+                // Timing out just spends the budget
+                let _ = self
+                    .until_shutdown(timeout(left, ticket.wait_turn()))
+                    .await?;
+                *slept = (*slept + started.elapsed()).min(budget);
+            }
+            None => {
+                self.until_shutdown(ticket.wait_turn()).await?;
+                *slept += started.elapsed();
+            }
+        }
+        Ok(())
+    }
+
+    /// The backend keeping the state of `limit`, the limit of `key` checked
+    /// for `route`, or `None` if it is kept in the route table.
+    fn backend_for(
+        &self,
+        route: &Route,
+        key: &RouteKey,
+        limit: &RateLimit,
+    ) -> Option<&dyn StateBackend> {
+        // Pools always use GCRA. Bandwidth limits are charged after the
+        // response, which backends do not support
+        let gcra = route.algorithm == Algorithm::Gcra || matches!(key.scope, LimitScope::Pool(_));
+        let supported = gcra && limit.unit == LimitUnit::Requests;
+        self.state_backend.as_deref().filter(|_| supported)
+    }
+
+    /// Wait for `future`, unless the middleware is shut down first.
+    async fn until_shutdown<F: Future>(&self, future: F) -> Result<F::Output, Rejection> {
+        self.shutdown
+            .until(future)
+            .await
+            .ok_or(Rejection::Error(RateLimitError::ShuttingDown))
+    }
+}
+
+/// A request admitted by the rate limiter, holding its in-flight slots.
+pub(crate) struct Admission {
+    table: Arc<RouteTable>,
+    acquired: Vec<(usize, RouteKey)>,
+    weight_override: Option<u32>,
+    permits: Vec<OwnedSemaphorePermit>,
+}
+
+impl Admission {
+    /// The shortest hedging delay among the routes the request was admitted
+    /// on, if any of them hedges.
+    fn hedge_after(&self) -> Option<Duration> {
+        self.acquired
+            .iter()
+            .filter_map(|&(route_index, _)| self.table.routes[route_index].hedge_after)
+            .min()
+    }
+}
+
+/// Why a request was not admitted.
+pub(crate) enum Rejection {
+    /// Fail the request with an error.
+    Error(RateLimitError),
+    /// Answer the request with a synthetic `429 Too Many Requests` response,
+    /// advising a retry after the error's wait if known.
+    Respond(RateLimitError),
+}
+
+impl Rejection {
+    /// The error describing why the request was rejected.
+    #[cfg(feature = "tower")]
+    pub(crate) fn into_error(self) -> RateLimitError {
+        match self {
+            Self::Error(err) | Self::Respond(err) => err,
         }
     }
 }
 
+/// Adjust the rates of the adaptive routes a request was admitted on to the
+/// status of its response.
+fn adapt(table: &RouteTable, acquired: &[(usize, RouteKey)], status: StatusCode) {
+    let mut last_route = None;
+    for &(route_index, _) in acquired {
+        if last_route == Some(route_index) {
+            continue;
+        }
+        last_route = Some(route_index);
+        let Some(rate) = &table.adaptive[route_index] else {
+            continue;
+        };
+        if status == StatusCode::TOO_MANY_REQUESTS {
+            rate.record_throttled();
+        } else if status.is_success() {
+            rate.record_success();
+        }
+    }
+}
+
+/// Reject a request because `route`'s circuit breaker is open until
+/// `retry_at`, `retry_after` from now.
+fn circuit_open(
+    req: &RequestParts<'_>,
+    route: &Route,
+    retry_after: Duration,
+    retry_at: Instant,
+) -> Rejection {
+    let err = RateLimitError::CircuitOpen {
+        host: req.host.unwrap_or_default().to_owned(),
+        route: route.path_description(),
+        retry_after,
+        retry_at,
+    };
+    if route.on_limit == ThrottleBehavior::Respond429 {
+        Rejection::Respond(err)
+    } else {
+        Rejection::Error(err)
+    }
+}
+
+/// Try to take `cost` from the local bucket `key` of `limit`, one of the
+/// limits of `route` at its effective rate, creating the bucket if missing.
+fn try_acquire_local(
+    table: &RouteTable,
+    route: &Route,
+    key: &RouteKey,
+    now: u64,
+    limit: &RateLimit,
+    cost: u32,
+) -> Result<(), Duration> {
+    table
+        .limiter(key, || match (&key.scope, route.ramp_up) {
+            (LimitScope::Pool(_), _) => LimiterState::new(Algorithm::Gcra),
+            (_, Some(ramp_up)) => LimiterState::warming_up(route.algorithm, now, limit, ramp_up),
+            (_, None) => LimiterState::new(route.algorithm),
+        })
+        .try_acquire_weighted(now, limit, cost)
+}
+
+/// Each limit of a route with the number of requests its bucket for
+/// `bucket_key` could admit at `now`.
+fn remaining(
+    table: &RouteTable,
+    now: u64,
+    route_index: usize,
+    bucket_key: Option<String>,
+) -> Vec<(RateLimit, u32)> {
+    let route = &table.routes[route_index];
+    route
+        .limits
+        .iter()
+        .enumerate()
+        .map(|(limit_index, limit)| {
+            let key = RouteKey {
+                scope: route.limit_scope(route_index),
+                limit_index,
+                key: route.limit_key(limit, &bucket_key),
+            };
+            let remaining = match table.state.get(&key) {
+                Some(state) => state.remaining(now, limit).min(u64::from(limit.burst())) as u32,
+                None => limit.burst(),
+            };
+            (limit.clone(), remaining)
+        })
+        .collect()
+}
+
+/// Time until every limit of a route has room for a request of the route's
+/// weight in the default bucket.
+fn wait_until_available(table: &RouteTable, now: u64, route_index: usize) -> Duration {
+    let route = &table.routes[route_index];
+    table
+        .checks(route_index, &None, route.weight)
+        .filter_map(|check| {
+            let state = table.state.get(&check.key)?;
+            state.peek_weighted(now, &check.effective, check.cost).err()
+        })
+        .max()
+        .unwrap_or_default()
+}
+
+/// Split a path into the path proper and its query string, if any.
+fn split_query(path: &str) -> (&str, Option<&str>) {
+    match path.split_once('?') {
+        Some((path, query)) => (path, Some(query)),
+        None => (path, None),
+    }
+}
+
+/// Build the synthetic response for [`ThrottleBehavior::Respond429`].
+///
+/// The body is empty and `Retry-After`, if set, is the wait in whole seconds,
+/// rounded up.
+fn too_many_requests(url: &Url, retry_after: Option<Duration>) -> Response {
+    let mut builder = http::Response::builder()
+        .status(StatusCode::TOO_MANY_REQUESTS)
+        .url(url.clone());
+    if let Some(wait) = retry_after {
+        let seconds = wait.as_nanos().div_ceil(1_000_000_000);
+        builder = builder.header(RETRY_AFTER, seconds.to_string());
+    }
+    let response = builder
+        .body(Vec::new())
+        .expect("status and headers are valid");
+    Response::from(response)
+}
+
 #[async_trait]
 impl Middleware for RateLimitMiddleware {
     async fn handle(
@@ -170,11 +1881,177 @@ impl Middleware for RateLimitMiddleware {
         extensions: &mut Extensions,
         next: Next<'_>,
     ) -> MiddlewareResult<Response> {
-        // Check and apply rate limits
-        self.check_and_apply_limits(&req).await?;
+        if !self.is_enabled() || extensions.get::<BypassRateLimit>().is_some() {
+            return next.run(req, extensions).await;
+        }
+        let table = self.table.load_full();
+        let Some(key) = single_flight_key(&table, &req) else {
+            return self.send(req, extensions, next).await;
+        };
+
+        let leader = match single_flight::join(&table, key) {
+            Flight::Leader(leader) => leader,
+            Flight::Follower(receiver) => {
+                if let Some(shared) = single_flight::follow(receiver).await {
+                    return Ok(shared.to_response());
+                }
+                // The first request failed, so send this one on its own
+                return self.send(req, extensions, next).await;
+            }
+        };
+        let response = self.send(req, extensions, next).await?;
+        let shared = Arc::new(SharedResponse::buffer(response).await?);
+        let response = shared.to_response();
+        leader.finish(shared);
+        Ok(response)
+    }
+}
+
+impl RateLimitMiddleware {
+    /// Rate limit `req` and pass it on to `next`, hedging it if configured.
+    async fn send(
+        &self,
+        req: Request,
+        extensions: &mut Extensions,
+        next: Next<'_>,
+    ) -> MiddlewareResult<Response> {
+        let weight_override = RequestWeight::of(extensions);
+        let priority = extensions.get::<Priority>().copied().unwrap_or_default();
+        let admission = match self
+            .admit(&RequestParts::from(&req), weight_override, priority)
+            .await
+        {
+            Ok(admission) => admission,
+            Err(Rejection::Error(err)) => return Err(err.into()),
+            Err(Rejection::Respond(err)) => {
+                return Ok(too_many_requests(req.url(), err.retry_after()));
+            }
+        };
+
+        // Proceed with the request, unless it should be hedged and its body
+        // can be cloned for the duplicate
+        let hedge = admission
+            .hedge_after()
+            .and_then(|delay| Some((delay, req.try_clone()?)));
+        let Some((delay, hedge_req)) = hedge else {
+            let response = next.run(req, extensions).await;
+            self.complete(admission, response_parts(&response));
+            return response;
+        };
+
+        let mut hedge_extensions = extensions.clone();
+        let mut primary = next.clone().run(req, extensions);
+        if let Ok(response) = timeout(delay, &mut primary).await {
+            self.complete(admission, response_parts(&response));
+            return response;
+        }
+
+        // The duplicate must pass rate limiting too, which may take longer
+        // than the original request
+        let hedge_admission = {
+            let parts = RequestParts::from(&hedge_req);
+            let mut admit = Box::pin(self.admit(&parts, weight_override, priority));
+            match race(&mut primary, &mut admit).await {
+                Race::First(response) => {
+                    self.complete(admission, response_parts(&response));
+                    return response;
+                }
+                Race::Second(hedge_admission) => hedge_admission,
+            }
+        };
+        let Ok(hedge_admission) = hedge_admission else {
+            let response = primary.await;
+            self.complete(admission, response_parts(&response));
+            return response;
+        };
+
+        let mut hedge = next.run(hedge_req, &mut hedge_extensions);
+        let (response, winner, loser) = match race(&mut primary, &mut hedge).await {
+            Race::First(response) => (response, admission, hedge_admission),
+            Race::Second(response) => (response, hedge_admission, admission),
+        };
+        // Cancel the slower request before releasing its slots
+        drop(primary);
+        drop(hedge);
+        self.complete(loser, None);
+        self.complete(winner, response_parts(&response));
+        response
+    }
+}
+
+/// The key under which `req` is coalesced with identical requests, if it
+/// matches a single-flight route and its method is safe to coalesce.
+///
+/// Requests with different credentials are never coalesced, so that no caller
+/// receives a response meant for another.
+fn single_flight_key(table: &RouteTable, req: &Request) -> Option<String> {
+    if !matches!(*req.method(), Method::GET | Method::HEAD) {
+        return None;
+    }
+    let parts = RequestParts::from(req);
+    let mut key = None;
+    for &route_index in table.matching(&parts).iter() {
+        let route = &table.routes[route_index];
+        if !route.single_flight {
+            continue;
+        }
+        let key = key.get_or_insert_with(|| {
+            let mut key = format!("{} {}", req.method(), req.url());
+            for name in [AUTHORIZATION, COOKIE, PROXY_AUTHORIZATION] {
+                for value in req.headers().get_all(&name) {
+                    // Escaped, so values cannot run into each other
+                    key.push_str(&format!("\n{name}: {}", value.as_bytes().escape_ascii()));
+                }
+            }
+            key
+        });
+        if let Some(bucket_key) = route.bucket_key(&parts) {
+            key.push(' ');
+            key.push_str(&bucket_key);
+        }
+    }
+    key
+}
+
+/// The status and headers of a response, if one was received.
+fn response_parts(response: &MiddlewareResult<Response>) -> Option<(StatusCode, &http::HeaderMap)> {
+    response
+        .as_ref()
+        .ok()
+        .map(|response| (response.status(), response.headers()))
+}
+
+/// Which of two raced futures completed first.
+enum Race<A, B> {
+    First(A),
+    Second(B),
+}
+
+/// Poll two futures until one of them completes, favoring `first` if both
+/// are ready.
+async fn race<A, B>(first: &mut A, second: &mut B) -> Race<A::Output, B::Output>
+where
+    A: Future + Unpin,
+    B: Future + Unpin,
+{
+    poll_fn(|cx| {
+        if let Poll::Ready(output) = Pin::new(&mut *first).poll(cx) {
+            return Poll::Ready(Race::First(output));
+        }
+        Pin::new(&mut *second).poll(cx).map(Race::Second)
+    })
+    .await
+}
 
-        // Proceed with the request
-        next.run(req, extensions).await
+impl fmt::Debug for RateLimitMiddleware {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let table = self.table.load();
+        f.debug_struct("RateLimitMiddleware")
+            .field("routes", &table.routes.len())
+            .field("buckets", &table.state.len())
+            .field("clock", &Duration::from_nanos(self.now_nanos()))
+            .field("enabled", &self.is_enabled())
+            .finish_non_exhaustive()
     }
 }
 
@@ -0,0 +1,342 @@
+//! Glob-style path patterns.
+
+use std::fmt;
+
+/// A single segment of a [`PathPattern`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Segment {
+    /// Matches a segment with exactly this text.
+    Literal(String),
+    /// `*`: matches exactly one segment.
+    Any,
+    /// `**`: matches zero or more segments.
+    AnyMany,
+    /// `{name}`: matches exactly one segment, capturing it as `name`.
+    Capture(String),
+}
+
+/// A compiled glob pattern matched against the whole request path.
+///
+/// Patterns are split into `/`-separated segments:
+/// - `*` matches exactly one segment
+/// - `**` matches zero or more segments
+/// - `{name}` matches exactly one segment, like `*`, and captures it
+/// - anything else must match the segment literally
+///
+/// Unlike prefix matching, a pattern must account for the entire path:
+/// `/markets/*/book` matches `/markets/0x123/book` but not
+/// `/markets/0x123/book/extra`. A single trailing slash on the request path
+/// is ignored.
+///
+/// # Example
+///
+/// ```rust
+/// use route_ratelimit::PathPattern;
+///
+/// let pattern = PathPattern::new("/markets/*/book");
+/// assert!(pattern.matches("/markets/0x123/book"));
+/// assert!(!pattern.matches("/markets/0x123/book/extra"));
+///
+/// let pattern = PathPattern::new("/markets/**");
+/// assert!(pattern.matches("/markets/0x123/book/extra"));
+///
+/// let pattern = PathPattern::new("/markets/{id}/book");
+/// assert_eq!(pattern.capture("/markets/0x123/book", "id"), Some("0x123"));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct PathPattern {
+    pattern: String,
+    segments: Vec<Segment>,
+}
+
+impl PathPattern {
+    /// Compile a pattern.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `pattern` does not start with `/`, or if two captures share
+    /// a name.
+    pub fn new(pattern: &str) -> Self {
+        assert!(pattern.starts_with('/'), "path pattern must start with '/'");
+        let segments: Vec<Segment> = split_segments(pattern)
+            .map(|segment| match segment {
+                "*" => Segment::Any,
+                "**" => Segment::AnyMany,
+                capture
+                    if capture.len() > 2 && capture.starts_with('{') && capture.ends_with('}') =>
+                {
+                    Segment::Capture(capture[1..capture.len() - 1].to_string())
+                }
+                literal => Segment::Literal(literal.to_string()),
+            })
+            .collect();
+        let captures: Vec<&str> = segments.iter().filter_map(Segment::capture_name).collect();
+        for (index, name) in captures.iter().enumerate() {
+            assert!(
+                !captures[..index].contains(name),
+                "path pattern captures {{{name}}} more than once"
+            );
+        }
+        Self {
+            pattern: pattern.to_string(),
+            segments,
+        }
+    }
+
+    /// The pattern as originally written.
+    #[must_use]
+    pub fn as_str(&self) -> &str {
+        &self.pattern
+    }
+
+    /// Check whether a request path matches this pattern.
+    #[must_use]
+    pub fn matches(&self, path: &str) -> bool {
+        self.matches_with_case(path, false)
+    }
+
+    /// The segment of `path` captured as `{name}`, if the path matches this
+    /// pattern and the pattern has such a capture.
+    #[must_use]
+    pub fn capture<'p>(&self, path: &'p str, name: &str) -> Option<&'p str> {
+        self.capture_with_case(path, name, false)
+    }
+
+    /// Returns `true` if the pattern has a `{name}` segment.
+    pub(crate) fn has_capture(&self, name: &str) -> bool {
+        self.segments
+            .iter()
+            .any(|segment| segment.capture_name() == Some(name))
+    }
+
+    /// Total length of the literal segments, each counted with its leading
+    /// `/`, so that `/markets/*/book` is as long as `/markets/book`.
+    pub(crate) fn literal_len(&self) -> usize {
+        self.segments
+            .iter()
+            .map(|segment| match segment {
+                Segment::Literal(literal) => literal.len() + 1,
+                Segment::Any | Segment::AnyMany | Segment::Capture(_) => 0,
+            })
+            .sum()
+    }
+
+    /// Check whether a request path matches this pattern, comparing literal
+    /// segments ignoring ASCII case if `ignore_case` is set.
+    pub(crate) fn matches_with_case(&self, path: &str, ignore_case: bool) -> bool {
+        let path_segments: Vec<&str> = split_segments(path).collect();
+        match_segments(&self.segments, &path_segments, ignore_case, &mut Vec::new())
+    }
+
+    /// Like [`capture`](Self::capture), comparing literal segments ignoring
+    /// ASCII case if `ignore_case` is set.
+    pub(crate) fn capture_with_case<'p>(
+        &self,
+        path: &'p str,
+        name: &str,
+        ignore_case: bool,
+    ) -> Option<&'p str> {
+        let index = self
+            .segments
+            .iter()
+            .filter_map(Segment::capture_name)
+            .position(|capture| capture == name)?;
+        let path_segments: Vec<&str> = split_segments(path).collect();
+        let mut captures = Vec::new();
+        if !match_segments(&self.segments, &path_segments, ignore_case, &mut captures) {
+            return None;
+        }
+        Some(captures[index])
+    }
+}
+
+impl Segment {
+    fn capture_name(&self) -> Option<&str> {
+        match self {
+            Segment::Capture(name) => Some(name),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for PathPattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.pattern)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for PathPattern {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.pattern)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for PathPattern {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let pattern = String::deserialize(deserializer)?;
+        if !pattern.starts_with('/') {
+            return Err(serde::de::Error::custom("path pattern must start with '/'"));
+        }
+        Ok(Self::new(&pattern))
+    }
+}
+
+/// Split a path into segments, ignoring the leading slash and a single trailing slash.
+fn split_segments(path: &str) -> impl Iterator<Item = &str> {
+    let path = path.strip_prefix('/').unwrap_or(path);
+    let path = path.strip_suffix('/').unwrap_or(path);
+    path.split('/').filter(move |_| !path.is_empty())
+}
+
+/// Match `path` against `pattern`, pushing the segments captured along the
+/// way onto `captures`, in pattern order. `captures` is left unchanged if the
+/// path does not match.
+///
+/// Like a wildcard match over characters, only the last `**` seen is ever
+/// widened on a mismatch: segments between it and an earlier `**` already
+/// matched at their first possible position, so widening the earlier one
+/// could not help. This keeps matching within O(pattern × path) segments.
+fn match_segments<'p>(
+    pattern: &[Segment],
+    path: &[&'p str],
+    ignore_case: bool,
+    captures: &mut Vec<&'p str>,
+) -> bool {
+    let start = captures.len();
+    let (mut p, mut s) = (0, 0);
+    // The pattern index after the last `**`, the path index it currently
+    // resumes at, and the number of captures made before it
+    let mut widen: Option<(usize, usize, usize)> = None;
+    while s < path.len() {
+        let advanced = match pattern.get(p) {
+            Some(Segment::AnyMany) => {
+                widen = Some((p + 1, s, captures.len()));
+                p += 1;
+                continue;
+            }
+            Some(Segment::Any) => true,
+            Some(Segment::Capture(_)) => {
+                captures.push(path[s]);
+                true
+            }
+            Some(Segment::Literal(literal)) => {
+                if ignore_case {
+                    path[s].eq_ignore_ascii_case(literal)
+                } else {
+                    path[s] == literal
+                }
+            }
+            None => false,
+        };
+        if advanced {
+            p += 1;
+            s += 1;
+            continue;
+        }
+        // Let the last `**` take one more segment and retry after it
+        let Some((after, resume, kept)) = widen else {
+            captures.truncate(start);
+            return false;
+        };
+        captures.truncate(kept);
+        widen = Some((after, resume + 1, kept));
+        p = after;
+        s = resume + 1;
+    }
+    let matched = pattern[p..]
+        .iter()
+        .all(|segment| *segment == Segment::AnyMany);
+    if !matched {
+        captures.truncate(start);
+    }
+    matched
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_single_star_matches_one_segment() {
+        let pattern = PathPattern::new("/markets/*/book");
+
+        assert!(pattern.matches("/markets/0x123/book"));
+        assert!(pattern.matches("/markets/0x123/book/"));
+        assert!(!pattern.matches("/markets/0x123/book/extra"));
+        assert!(!pattern.matches("/markets/book"));
+        assert!(!pattern.matches("/markets/a/b/book"));
+        assert!(!pattern.matches("/markets/0x123/books"));
+    }
+
+    #[test]
+    fn test_double_star_matches_many_segments() {
+        let pattern = PathPattern::new("/markets/**/book");
+
+        assert!(pattern.matches("/markets/book"));
+        assert!(pattern.matches("/markets/a/book"));
+        assert!(pattern.matches("/markets/a/b/c/book"));
+        assert!(!pattern.matches("/markets/a/b/c/book/extra"));
+
+        let pattern = PathPattern::new("/api/**");
+        assert!(pattern.matches("/api"));
+        assert!(pattern.matches("/api/v1/users"));
+        assert!(!pattern.matches("/apis"));
+    }
+
+    #[test]
+    fn test_many_double_stars_match_in_polynomial_time() {
+        // Backtracking over every way to split the path between the `**`s
+        // would take on the order of C(60, 12) attempts
+        let pattern = PathPattern::new(&format!("{}/b", "/**/a".repeat(12)));
+        let path = "/a".repeat(60);
+
+        assert!(!pattern.matches(&path));
+        assert!(pattern.matches(&format!("{path}/b")));
+        assert!(pattern.matches(&format!("/x{path}/b")));
+        assert!(!pattern.matches(&format!("{}/b", "/a".repeat(11))));
+    }
+
+    #[test]
+    fn test_captures_after_double_star() {
+        let pattern = PathPattern::new("/**/{id}/book");
+
+        assert_eq!(pattern.capture("/a/b/c/book", "id"), Some("c"));
+        assert_eq!(pattern.capture("/c/book", "id"), Some("c"));
+        assert_eq!(pattern.capture("/book", "id"), None);
+        assert_eq!(pattern.capture("/a/book/x", "id"), None);
+    }
+
+    #[test]
+    fn test_root_pattern() {
+        let pattern = PathPattern::new("/");
+        assert!(pattern.matches("/"));
+        assert!(!pattern.matches("/a"));
+    }
+
+    #[test]
+    fn test_captures_match_one_segment() {
+        let pattern = PathPattern::new("/markets/{id}/book/**");
+
+        assert!(pattern.matches("/markets/A/book"));
+        assert!(!pattern.matches("/markets/book"));
+        assert_eq!(pattern.capture("/markets/A/book", "id"), Some("A"));
+        assert_eq!(pattern.capture("/markets/B/book/depth", "id"), Some("B"));
+        assert_eq!(pattern.capture("/markets/A/price", "id"), None);
+        assert_eq!(pattern.capture("/markets/A/book", "side"), None);
+        assert_eq!(pattern.literal_len(), "/markets/book".len());
+    }
+
+    #[test]
+    #[should_panic(expected = "path pattern captures {id} more than once")]
+    fn test_duplicate_capture_panics() {
+        PathPattern::new("/markets/{id}/orders/{id}");
+    }
+
+    #[test]
+    #[should_panic(expected = "path pattern must start with '/'")]
+    fn test_relative_pattern_panics() {
+        PathPattern::new("markets/*");
+    }
+}
@@ -0,0 +1,139 @@
+//! Ordered waiting for requests delayed on the same bucket.
+
+use std::collections::BinaryHeap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use tokio::sync::Notify;
+
+/// Queue of requests waiting on one rate limit bucket.
+///
+/// Only the request at the head of the queue may retry the limit; the others
+/// wait for their turn. The head is the waiter with the highest priority, and
+/// the one that arrived first among equal priorities.
+#[derive(Debug, Default)]
+pub(crate) struct WaitQueue {
+    waiters: Mutex<BinaryHeap<Waiter>>,
+    /// Signaled whenever a waiter leaves, so the others can check whether they
+    /// are now at the head.
+    turn: Notify,
+    next_seq: AtomicU64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct Waiter {
+    priority: u8,
+    /// Reversed arrival order, so that earlier waiters compare greater.
+    arrival: std::cmp::Reverse<u64>,
+}
+
+impl WaitQueue {
+    fn lock(&self) -> MutexGuard<'_, BinaryHeap<Waiter>> {
+        // The heap is consistent after every push and removal, so a poisoned
+        // lock can safely be reused
+        self.waiters
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Returns `true` if no request is waiting.
+    pub fn is_empty(&self) -> bool {
+        self.lock().is_empty()
+    }
+
+    /// Add a waiter with the given priority.
+    ///
+    /// The waiter leaves the queue when the returned ticket is dropped.
+    pub fn join(self: &Arc<Self>, priority: u8) -> Ticket {
+        let waiter = Waiter {
+            priority,
+            arrival: std::cmp::Reverse(self.next_seq.fetch_add(1, Ordering::Relaxed)),
+        };
+        self.lock().push(waiter);
+        Ticket {
+            queue: Arc::clone(self),
+            waiter,
+        }
+    }
+}
+
+/// A place in a [`WaitQueue`].
+#[derive(Debug)]
+pub(crate) struct Ticket {
+    queue: Arc<WaitQueue>,
+    waiter: Waiter,
+}
+
+impl Ticket {
+    /// Returns `true` if this ticket belongs to `queue`.
+    pub fn is_for(&self, queue: &Arc<WaitQueue>) -> bool {
+        Arc::ptr_eq(&self.queue, queue)
+    }
+
+    /// Returns `true` if this ticket is at the head of its queue.
+    pub fn is_head(&self) -> bool {
+        self.queue.lock().peek() == Some(&self.waiter)
+    }
+
+    /// Wait until this ticket is at the head of its queue.
+    pub async fn wait_turn(&self) {
+        loop {
+            // Register for wakeups before checking, so a waiter leaving in
+            // between is not missed
+            let turn = self.queue.turn.notified();
+            if self.is_head() {
+                return;
+            }
+            turn.await;
+        }
+    }
+}
+
+impl Drop for Ticket {
+    fn drop(&mut self) {
+        self.queue.lock().retain(|waiter| *waiter != self.waiter);
+        self.queue.turn.notify_waiters();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_head_is_highest_priority_then_first_arrival() {
+        let queue = Arc::new(WaitQueue::default());
+        let low_first = queue.join(0);
+        let low_second = queue.join(0);
+        let high = queue.join(5);
+
+        let head = |queue: &WaitQueue| queue.lock().peek().copied();
+        assert_eq!(head(&queue), Some(high.waiter));
+        drop(high);
+        assert_eq!(head(&queue), Some(low_first.waiter));
+        drop(low_first);
+        assert_eq!(head(&queue), Some(low_second.waiter));
+        drop(low_second);
+        assert!(queue.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_wait_turn_resumes_when_head_leaves() {
+        let queue = Arc::new(WaitQueue::default());
+        let head = queue.join(1);
+        let next = queue.join(0);
+
+        head.wait_turn().await;
+        let waiting = tokio::spawn(async move {
+            next.wait_turn().await;
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!waiting.is_finished());
+
+        drop(head);
+        tokio::time::timeout(Duration::from_secs(1), waiting)
+            .await
+            .unwrap()
+            .unwrap();
+    }
+}
@@ -0,0 +1,146 @@
+//! A view of the parts of a request that rate limiting depends on.
+
+use http::{HeaderMap, Method};
+use std::time::Duration;
+
+/// The parts of an outgoing request used to match routes and select buckets.
+///
+/// Borrowed from either a [`reqwest::Request`] or an [`http::Request`], so the
+/// reqwest middleware and the tower service share the same matching logic.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct RequestParts<'a> {
+    /// URL scheme (e.g., `https`), if known.
+    pub scheme: Option<&'a str>,
+    /// Host, without the port.
+    pub host: Option<&'a str>,
+    pub method: &'a Method,
+    pub path: &'a str,
+    /// Raw query string, without the leading `?`.
+    pub query: Option<&'a str>,
+    pub headers: &'a HeaderMap,
+    /// The whole request, if it is a [`reqwest::Request`].
+    pub request: Option<&'a reqwest::Request>,
+}
+
+impl<'a> From<&'a reqwest::Request> for RequestParts<'a> {
+    fn from(req: &'a reqwest::Request) -> Self {
+        let url = req.url();
+        Self {
+            scheme: Some(url.scheme()),
+            host: url.host_str(),
+            method: req.method(),
+            path: url.path(),
+            query: url.query(),
+            headers: req.headers(),
+            request: Some(req),
+        }
+    }
+}
+
+impl<'a, B> From<&'a http::Request<B>> for RequestParts<'a> {
+    fn from(req: &'a http::Request<B>) -> Self {
+        let uri = req.uri();
+        // Requests sent through a proxy or a client connection pool may only
+        // carry the host in the `Host` header
+        let host = uri.host().or_else(|| {
+            let host = req.headers().get(http::header::HOST)?.to_str().ok()?;
+            Some(strip_port(host))
+        });
+        Self {
+            scheme: uri.scheme_str(),
+            host,
+            method: req.method(),
+            path: uri.path(),
+            query: uri.query(),
+            headers: req.headers(),
+            request: None,
+        }
+    }
+}
+
+impl RequestParts<'_> {
+    /// The request's own timeout, if it is a [`reqwest::Request`] with one.
+    pub fn timeout(&self) -> Option<Duration> {
+        self.request?.timeout().copied()
+    }
+}
+
+/// Remove the brackets around an IPv6 address, which URLs require but
+/// configured hosts may omit.
+pub(crate) fn unbracket(host: &str) -> &str {
+    host.strip_prefix('[')
+        .and_then(|host| host.strip_suffix(']'))
+        .unwrap_or(host)
+}
+
+/// Remove the port from a `Host` header value, keeping IPv6 brackets intact.
+fn strip_port(host: &str) -> &str {
+    match host.rfind(':') {
+        Some(colon) if !host[colon..].contains(']') => &host[..colon],
+        _ => host,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parts_from_http_request() {
+        let req = http::Request::builder()
+            .method(Method::POST)
+            .uri("https://api.example.com:8443/order?id=1")
+            .body(())
+            .unwrap();
+        let parts = RequestParts::from(&req);
+
+        assert_eq!(parts.scheme, Some("https"));
+        assert_eq!(parts.host, Some("api.example.com"));
+        assert_eq!(parts.method, Method::POST);
+        assert_eq!(parts.path, "/order");
+        assert_eq!(parts.query, Some("id=1"));
+    }
+
+    #[test]
+    fn test_parts_without_host() {
+        let req = http::Request::builder().uri("/order").body(()).unwrap();
+        assert_eq!(RequestParts::from(&req).host, None);
+
+        // The request builder rejects such URLs, but requests can still be
+        // constructed directly
+        let url = reqwest::Url::parse("data:text/plain,hello").unwrap();
+        let req = reqwest::Request::new(Method::GET, url);
+        let parts = RequestParts::from(&req);
+        assert_eq!(parts.scheme, Some("data"));
+        assert_eq!(parts.host, None);
+        assert_eq!(parts.path, "text/plain,hello");
+    }
+
+    #[test]
+    fn test_parts_from_origin_form_uses_host_header() {
+        let req = http::Request::builder()
+            .uri("/order")
+            .header(http::header::HOST, "api.example.com:8443")
+            .body(())
+            .unwrap();
+        let parts = RequestParts::from(&req);
+
+        assert_eq!(parts.scheme, None);
+        assert_eq!(parts.host, Some("api.example.com"));
+        assert_eq!(parts.path, "/order");
+
+        assert_eq!(strip_port(""), "");
+        assert_eq!(strip_port(":"), "");
+        assert_eq!(strip_port("[::1]:8080"), "[::1]");
+        assert_eq!(strip_port("[::1]"), "[::1]");
+        assert_eq!(strip_port("example.com"), "example.com");
+    }
+
+    #[test]
+    fn test_unbracket() {
+        assert_eq!(unbracket("[::1]"), "::1");
+        assert_eq!(unbracket("::1"), "::1");
+        assert_eq!(unbracket("[::1"), "[::1");
+        assert_eq!(unbracket("example.com"), "example.com");
+    }
+}
@@ -0,0 +1,562 @@
+//! The configured routes together with the state that depends on them.
+
+use dashmap::DashMap;
+use dashmap::mapref::one::Ref;
+use std::borrow::Cow;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Deref;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tokio::sync::Semaphore;
+
+use crate::adaptive::AdaptiveRate;
+use crate::circuit::CircuitBreaker;
+use crate::error::BuildError;
+use crate::limiter::LimiterState;
+use crate::match_cache::MatchCache;
+use crate::queue::WaitQueue;
+use crate::request::{RequestParts, unbracket};
+use crate::single_flight::Flights;
+use crate::types::{LimitScope, MatchMode, RateLimit, Route, RouteKey};
+
+/// Wait queues of delayed requests, by bucket.
+pub(crate) type Queues = DashMap<(LimitScope, Option<String>), Arc<WaitQueue>>;
+
+/// A route configuration and its rate limit state.
+///
+/// State is keyed by route index (or shared group) and limit index, so it is
+/// only meaningful for the routes it was recorded against. Keeping both in one value lets the
+/// middleware swap them together when the routes are reconfigured.
+#[derive(Debug)]
+pub(crate) struct RouteTable {
+    pub routes: Vec<Route>,
+    pub state: Arc<DashMap<RouteKey, LimiterState>>,
+    /// Index of the first route in each shared group, whose limits the group uses.
+    pub groups: HashMap<String, usize>,
+    /// Limit of each quota pool, by name.
+    pub pools: BTreeMap<String, RateLimit>,
+    /// In-flight request limiter for each route with `max_concurrent` set.
+    pub semaphores: Vec<Option<Arc<Semaphore>>>,
+    /// Circuit breaker for each route with a cooldown configured.
+    pub breakers: Vec<Option<Arc<CircuitBreaker>>>,
+    /// Current rate of each adaptive route.
+    pub adaptive: Vec<Option<Arc<AdaptiveRate>>>,
+    /// Number of requests currently delayed on each route.
+    pub pending: Arc<[AtomicUsize]>,
+    /// Requests delayed on each bucket, keyed like the state without the limit
+    /// index. Created on demand when a request is first delayed.
+    pub queues: Arc<Queues>,
+    /// Routes matching recently seen requests, before header filters.
+    pub matches: MatchCache,
+    /// Whether any route filters on request headers.
+    has_header_filters: bool,
+    /// Which of the matching routes apply to a request.
+    match_mode: MatchMode,
+    /// Requests in flight on single-flight routes.
+    pub flights: Flights,
+    /// Precomputed `host` metric label for each route.
+    #[cfg(feature = "metrics")]
+    pub labels: Vec<metrics::SharedString>,
+}
+
+impl RouteTable {
+    /// Create a table with fresh state for `routes` drawing from `pools`,
+    /// applying them to requests according to `match_mode`, caching the matching routes of up to
+    /// `match_cache_capacity` distinct requests and with room for
+    /// `state_capacity` buckets before the state map grows.
+    ///
+    /// # Panics
+    ///
+    /// Panics if routes in the same shared group have different limits or
+    /// algorithms, or if a route draws from a pool that isn't in `pools`.
+    pub fn new(
+        mut routes: Vec<Route>,
+        pools: BTreeMap<String, RateLimit>,
+        match_mode: MatchMode,
+        match_cache_capacity: usize,
+        state_capacity: usize,
+    ) -> Self {
+        // Deserialized routes may write IPv6 hosts with brackets
+        for route in &mut routes {
+            if route.hosts.iter().any(|host| host.starts_with('[')) {
+                route.hosts = route
+                    .hosts
+                    .iter()
+                    .map(|host| unbracket(host).to_owned())
+                    .collect();
+            }
+        }
+
+        let groups = shared_groups(&routes).unwrap_or_else(|err| panic!("{err}"));
+        if let Err(err) = check_pools(&routes, &pools) {
+            panic!("{err}");
+        }
+
+        Self {
+            groups,
+            pools,
+            semaphores: routes
+                .iter()
+                .map(|route| {
+                    route
+                        .max_concurrent
+                        .map(|max| Arc::new(Semaphore::new(max)))
+                })
+                .collect(),
+            breakers: routes
+                .iter()
+                .map(|route| route.cooldown.map(|c| Arc::new(CircuitBreaker::new(c))))
+                .collect(),
+            adaptive: routes
+                .iter()
+                .map(|route| route.adaptive.map(|a| Arc::new(AdaptiveRate::new(a))))
+                .collect(),
+            pending: routes.iter().map(|_| AtomicUsize::new(0)).collect(),
+            #[cfg(feature = "metrics")]
+            labels: crate::telemetry::host_labels(&routes),
+            has_header_filters: routes.iter().any(|route| !route.headers.is_empty()),
+            match_mode,
+            routes,
+            state: Arc::new(DashMap::with_capacity(state_capacity)),
+            queues: Arc::default(),
+            matches: MatchCache::new(match_cache_capacity),
+            flights: DashMap::new(),
+        }
+    }
+
+    /// Keep the state of `previous`, a table with the same routes apart from
+    /// the limits of the route at `changed`, instead of starting over:
+    /// buckets, wait queues, delayed and in-flight request counts, circuit
+    /// breakers, and adaptive rates. Only the buckets of the changed route are
+    /// dropped, before this table is used, so that none created under its new
+    /// limits are lost.
+    ///
+    /// The two tables share the state from then on, so requests still using
+    /// `previous` count towards the limits of this one.
+    pub fn sharing_state(self, previous: &Self, changed: usize) -> Self {
+        let scope = previous.routes[changed].limit_scope(changed);
+        previous.state.retain(|key, _| key.scope != scope);
+        Self {
+            state: Arc::clone(&previous.state),
+            queues: Arc::clone(&previous.queues),
+            pending: Arc::clone(&previous.pending),
+            semaphores: previous.semaphores.clone(),
+            breakers: previous.breakers.clone(),
+            adaptive: previous.adaptive.clone(),
+            ..self
+        }
+    }
+
+    /// Get the limiter state for `key`, creating it with `create` if missing.
+    ///
+    /// Only holds a shared lock on the state's shard once the state exists, so
+    /// requests to the same bucket update its atomics concurrently instead of
+    /// queueing on the shard lock.
+    pub fn limiter(
+        &self,
+        key: &RouteKey,
+        create: impl FnOnce() -> LimiterState,
+    ) -> Ref<'_, RouteKey, LimiterState> {
+        if let Some(state) = self.state.get(key) {
+            return state;
+        }
+        self.state
+            .entry(key.clone())
+            .or_insert_with(create)
+            .downgrade()
+    }
+
+    /// Indices of the routes that apply to `req`, in route order.
+    ///
+    /// The cache is keyed by the request target only, so header filters are
+    /// applied to the cached routes afterwards, before narrowing them down to
+    /// the most specific one if the match mode says so.
+    pub fn matching(&self, req: &RequestParts<'_>) -> Arc<[usize]> {
+        let matching = self.matches.get_or_insert_with(req, || {
+            let matching = self
+                .routes
+                .iter()
+                .enumerate()
+                .filter(|(_, route)| route.matches_target(req))
+                .map(|(index, _)| index);
+            if self.has_header_filters {
+                matching.collect()
+            } else {
+                self.applicable(matching)
+            }
+        });
+        if !self.has_header_filters {
+            return matching;
+        }
+        self.applicable(
+            matching
+                .iter()
+                .copied()
+                .filter(|&index| self.routes[index].matches_headers(req.headers)),
+        )
+    }
+
+    /// Of the indices of the routes matching a request, in route order, those
+    /// that apply to it under the match mode, highest priority first.
+    pub fn applicable(&self, matching: impl Iterator<Item = usize>) -> Arc<[usize]> {
+        let priority = |index: usize| self.routes[index].priority;
+        match self.match_mode {
+            MatchMode::All => {
+                let mut applicable: Vec<usize> = matching.collect();
+                // Stable, so routes of equal priority keep their order
+                applicable.sort_by_key(|&index| Reverse(priority(index)));
+                applicable.into()
+            }
+            // `min_by_key` keeps the first of equally ranked routes
+            MatchMode::MostSpecific => matching
+                .min_by_key(|&index| Reverse((self.routes[index].specificity(), priority(index))))
+                .into_iter()
+                .collect(),
+            MatchMode::FirstMatch => matching
+                .min_by_key(|&index| Reverse(priority(index)))
+                .into_iter()
+                .collect(),
+        }
+    }
+
+    /// `limit`, one of the limits of the route at `route_index`, at the
+    /// route's current adaptive rate.
+    pub fn effective_limit<'a>(
+        &self,
+        route_index: usize,
+        limit: &'a RateLimit,
+    ) -> Cow<'a, RateLimit> {
+        match &self.adaptive[route_index] {
+            Some(rate) => Cow::Owned(rate.scale(limit)),
+            None => Cow::Borrowed(limit),
+        }
+    }
+
+    /// Whether the route at `route_index` already has as many delayed
+    /// requests as its `max_pending` allows.
+    pub fn pending_full(&self, route_index: usize) -> bool {
+        self.routes[route_index]
+            .max_pending
+            .is_some_and(|max| self.pending[route_index].load(Ordering::Relaxed) >= max)
+    }
+
+    /// Count a request as delayed on the route at `route_index` until the
+    /// returned guard is dropped.
+    pub fn pend(&self, route_index: usize) -> PendingGuard<'_> {
+        let count = &self.pending[route_index];
+        count.fetch_add(1, Ordering::Relaxed);
+        PendingGuard(count)
+    }
+
+    /// Look up the configured limit for a state key.
+    ///
+    /// Returns `None` if the key no longer refers to a configured limit.
+    #[inline]
+    pub fn limit_for(&self, key: &RouteKey) -> Option<&RateLimit> {
+        if let LimitScope::Pool(pool) = &key.scope {
+            return self.pools.get(pool).filter(|_| key.limit_index == 0);
+        }
+        let route_index = self.route_index_for(key)?;
+        self.routes.get(route_index)?.limits.get(key.limit_index)
+    }
+
+    /// Index of the route whose limits a state key refers to: its own route,
+    /// or the first route of its shared group.
+    ///
+    /// Returns `None` for a group that is no longer configured, and for pools,
+    /// which belong to no route.
+    #[inline]
+    pub fn route_index_for(&self, key: &RouteKey) -> Option<usize> {
+        match &key.scope {
+            LimitScope::Route(index) => Some(*index),
+            LimitScope::Group(group) => self.groups.get(group).copied(),
+            LimitScope::Pool(_) => None,
+        }
+    }
+
+    /// Every limit a request of `weight` on the route at `route_index` is
+    /// checked against, in order: the route's own limits, in the buckets for
+    /// `bucket_key`, then the pools it draws from.
+    pub fn checks<'a>(
+        &'a self,
+        route_index: usize,
+        bucket_key: &'a Option<String>,
+        weight: u32,
+    ) -> impl Iterator<Item = LimitCheck<'a>> + 'a {
+        let route = &self.routes[route_index];
+        let own = route
+            .limits
+            .iter()
+            .enumerate()
+            .map(move |(limit_index, limit)| LimitCheck {
+                limit_index,
+                key: RouteKey {
+                    scope: route.limit_scope(route_index),
+                    limit_index,
+                    key: route.limit_key(limit, bucket_key),
+                },
+                limit,
+                effective: self.effective_limit(route_index, limit),
+                cost: limit.cost(weight),
+            });
+        // Adaptive limiting only scales the route's own limits
+        let pools = route
+            .pools
+            .iter()
+            .filter_map(|(pool, cost)| Some((RouteKey::pool(pool), self.pools.get(pool)?, *cost)))
+            .zip(route.limits.len()..)
+            .map(|((key, limit, cost), limit_index)| LimitCheck {
+                limit_index,
+                key,
+                limit,
+                effective: Cow::Borrowed(limit),
+                cost,
+            });
+        own.chain(pools)
+    }
+
+    /// Remove entries that have been fully recovered for more than
+    /// `stale_after` times their window, and wait queues nobody is waiting in.
+    ///
+    /// Returns the number of state entries removed.
+    pub fn cleanup_stale(&self, now: u64, stale_after: u32) -> usize {
+        self.queues.retain(|_, queue| !queue.is_empty());
+
+        let mut removed = 0;
+        self.state.retain(|key, limiter_state| {
+            // Bounds check to handle edge cases
+            let Some(limit) = self.limit_for(key) else {
+                removed += 1;
+                return false;
+            };
+
+            let window_nanos = limit.window.as_nanos().min(u64::MAX as u128) as u64;
+            let recovered_at = limiter_state.recovered_at(limit);
+
+            // Keep if recovery is within `stale_after` windows of now (recently
+            // active). An entry that recovered far in the past can be removed
+            let stale_nanos = window_nanos.saturating_mul(u64::from(stale_after));
+            let keep = recovered_at > now.saturating_sub(stale_nanos);
+            if !keep {
+                removed += 1;
+            }
+            keep
+        });
+        removed
+    }
+}
+
+/// Index of the first route in each shared group of `routes`.
+///
+/// # Errors
+///
+/// Returns an error if routes in the same shared group have different limits
+/// or algorithms.
+pub(crate) fn shared_groups(routes: &[Route]) -> Result<HashMap<String, usize>, BuildError> {
+    let mut groups = HashMap::new();
+    for (index, route) in routes.iter().enumerate() {
+        let Some(group) = &route.shared_group else {
+            continue;
+        };
+        let first = &routes[*groups.entry(group.clone()).or_insert(index)];
+        if first.limits != route.limits || first.algorithm != route.algorithm {
+            return Err(BuildError::SharedGroupMismatch {
+                group: group.clone(),
+            });
+        }
+    }
+    Ok(groups)
+}
+
+/// A limit a request is checked against.
+pub(crate) struct LimitCheck<'a> {
+    /// Index of the limit among those of the route, its pools counting after
+    /// its own limits.
+    pub limit_index: usize,
+    /// Key of the bucket the request is counted in.
+    pub key: RouteKey,
+    /// The limit as configured.
+    pub limit: &'a RateLimit,
+    /// The limit as currently enforced.
+    pub effective: Cow<'a, RateLimit>,
+    /// How many requests the request counts as.
+    pub cost: u32,
+}
+
+impl LimitCheck<'_> {
+    /// Whether this is the limit of a quota pool.
+    pub fn is_pool(&self) -> bool {
+        matches!(self.key.scope, LimitScope::Pool(_))
+    }
+}
+
+/// Check that every pool `routes` draw from is in `pools`, and that the
+/// limits of `pools` are valid.
+///
+/// # Errors
+///
+/// Returns an error for the first invalid pool, or route drawing from an
+/// unknown pool.
+pub(crate) fn check_pools(
+    routes: &[Route],
+    pools: &BTreeMap<String, RateLimit>,
+) -> Result<(), BuildError> {
+    for (pool, limit) in pools {
+        limit.check().map_err(|reason| BuildError::InvalidPool {
+            pool: pool.clone(),
+            reason,
+        })?;
+    }
+    for (route, config) in routes.iter().enumerate() {
+        if let Some((pool, _)) = config
+            .pools
+            .iter()
+            .find(|(pool, _)| !pools.contains_key(pool))
+        {
+            return Err(BuildError::UnknownPool {
+                route,
+                pool: pool.clone(),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// A request counted as delayed on a route, until dropped.
+///
+/// Dropping rather than decrementing explicitly keeps the count right for
+/// requests that are cancelled or released by shutdown while they wait.
+#[derive(Debug)]
+pub(crate) struct PendingGuard<'a>(&'a AtomicUsize);
+
+impl Drop for PendingGuard<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// The routes configured on a middleware.
+///
+/// Returned by [`RateLimitMiddleware::routes`](crate::RateLimitMiddleware::routes).
+/// Holds on to the configuration it was read from, so it stays unchanged if
+/// the routes are replaced with
+/// [`update_routes`](crate::RateLimitMiddleware::update_routes) in the
+/// meantime. Dereferences to a slice of [`Route`]s, and compares equal to
+/// another `Routes` with equal routes in the same order.
+#[derive(Debug, Clone)]
+pub struct Routes(Arc<RouteTable>);
+
+impl Routes {
+    pub(crate) fn new(table: Arc<RouteTable>) -> Self {
+        Self(table)
+    }
+}
+
+impl Deref for Routes {
+    type Target = [Route];
+
+    fn deref(&self) -> &[Route] {
+        &self.0.routes
+    }
+}
+
+impl PartialEq for Routes {
+    fn eq(&self, other: &Self) -> bool {
+        **self == **other
+    }
+}
+
+impl Eq for Routes {}
+
+impl<'a> IntoIterator for &'a Routes {
+    type Item = &'a Route;
+    type IntoIter = std::slice::Iter<'a, Route>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.routes.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::Algorithm;
+    use http::Method;
+    use std::time::Duration;
+
+    #[test]
+    fn test_limiter_is_shared_while_held() {
+        let routes = crate::RateLimitMiddleware::builder()
+            .route(|r| r.limit(10, Duration::from_secs(1)))
+            .into_routes();
+        let table = RouteTable::new(routes, BTreeMap::new(), MatchMode::All, 0, 0);
+        let key = RouteKey {
+            scope: LimitScope::Route(0),
+            limit_index: 0,
+            key: None,
+        };
+
+        // Both on creation and on later lookups, other requests are not locked out
+        let created = table.limiter(&key, || LimiterState::new(Algorithm::Gcra));
+        assert!(table.state.try_get(&key).is_present());
+        drop(created);
+        let _found = table.limiter(&key, || unreachable!("the state exists"));
+        assert!(table.state.try_get(&key).is_present());
+        assert_eq!(table.state.len(), 1);
+    }
+
+    #[test]
+    fn test_most_specific_ranks_path_then_method_then_host() {
+        let routes = crate::RateLimitMiddleware::builder()
+            .route(|r| r.limit(10, Duration::from_secs(1)))
+            .route(|r| r.host("api.example.com").limit(10, Duration::from_secs(1)))
+            .route(|r| r.method(Method::GET).limit(10, Duration::from_secs(1)))
+            .route(|r| r.path("/markets").limit(10, Duration::from_secs(1)))
+            .route(|r| {
+                r.path_pattern("/markets/*/book")
+                    .limit(10, Duration::from_secs(1))
+            })
+            .route(|r| r.path("/markets").limit(10, Duration::from_secs(1)))
+            .into_routes();
+        let table = RouteTable::new(routes, BTreeMap::new(), MatchMode::MostSpecific, 0, 0);
+        let client = reqwest::Client::new();
+        let applicable = |method: Method, url: &str| {
+            let req = client.request(method, url).build().unwrap();
+            table.matching(&RequestParts::from(&req)).to_vec()
+        };
+
+        // The literal segments of the pattern are longer than the prefix
+        assert_eq!(
+            applicable(Method::GET, "https://api.example.com/markets/0x1/book"),
+            [4]
+        );
+        // Of equally specific routes, the first applies
+        assert_eq!(
+            applicable(Method::GET, "https://api.example.com/markets"),
+            [3]
+        );
+        assert_eq!(applicable(Method::GET, "https://api.example.com/"), [2]);
+        assert_eq!(applicable(Method::POST, "https://api.example.com/"), [1]);
+        assert_eq!(applicable(Method::POST, "https://example.com/"), [0]);
+    }
+
+    #[test]
+    fn test_first_match_follows_declaration_order() {
+        let routes = crate::RateLimitMiddleware::builder()
+            .route(|r| r.path("/markets").limit(10, Duration::from_secs(1)))
+            .route(|r| r.limit(10, Duration::from_secs(1)))
+            .route(|r| r.path("/markets/book").limit(10, Duration::from_secs(1)))
+            .into_routes();
+        let table = RouteTable::new(routes, BTreeMap::new(), MatchMode::FirstMatch, 0, 0);
+        let client = reqwest::Client::new();
+        let applicable = |url: &str| {
+            let req = client.get(url).build().unwrap();
+            table.matching(&RequestParts::from(&req)).to_vec()
+        };
+
+        // The more specific route comes too late to ever apply
+        assert_eq!(applicable("https://example.com/markets/book"), [0]);
+        assert_eq!(applicable("https://example.com/book"), [1]);
+    }
+}
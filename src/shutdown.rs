@@ -0,0 +1,82 @@
+//! Release of delayed requests when the middleware shuts down.
+
+use std::future::{Future, poll_fn};
+use std::pin::pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::task::Poll;
+use tokio::sync::Notify;
+
+/// Shutdown signal shared by every clone of a middleware.
+#[derive(Debug, Default)]
+pub(crate) struct Shutdown {
+    triggered: AtomicBool,
+    notify: Notify,
+}
+
+impl Shutdown {
+    /// Shut down, waking every request waiting in [`until`](Self::until).
+    pub fn trigger(&self) {
+        self.triggered.store(true, Ordering::Release);
+        self.notify.notify_waiters();
+    }
+
+    /// Returns `true` once [`trigger`](Self::trigger) was called.
+    pub fn is_triggered(&self) -> bool {
+        self.triggered.load(Ordering::Acquire)
+    }
+
+    /// Run `future` to completion unless shutdown is triggered first.
+    ///
+    /// Returns `None` without polling `future` if shutdown was already
+    /// triggered.
+    pub async fn until<F: Future>(&self, future: F) -> Option<F::Output> {
+        // Register for the wakeup before checking, so a shutdown in between is
+        // not missed
+        let mut notified = pin!(self.notify.notified());
+        notified.as_mut().enable();
+        if self.is_triggered() {
+            return None;
+        }
+        let mut future = pin!(future);
+        poll_fn(|cx| {
+            if notified.as_mut().poll(cx).is_ready() {
+                return Poll::Ready(None);
+            }
+            future.as_mut().poll(cx).map(Some)
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_trigger_releases_waiters() {
+        let shutdown = Arc::new(Shutdown::default());
+        assert_eq!(shutdown.until(async { 1 }).await, Some(1));
+
+        let waiting = tokio::spawn({
+            let shutdown = Arc::clone(&shutdown);
+            async move {
+                shutdown
+                    .until(tokio::time::sleep(Duration::from_secs(60)))
+                    .await
+            }
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        shutdown.trigger();
+        let released = tokio::time::timeout(Duration::from_secs(1), waiting)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(released, None);
+
+        // Later waits end right away
+        assert!(shutdown.is_triggered());
+        assert_eq!(shutdown.until(async { 1 }).await, None);
+    }
+}
@@ -0,0 +1,119 @@
+//! Coalescing of identical requests that are in flight at the same time.
+
+use bytes::Bytes;
+use dashmap::DashMap;
+use dashmap::mapref::entry::Entry;
+use http::{HeaderMap, StatusCode, Version};
+use reqwest::{Response, ResponseBuilderExt, Url};
+use std::sync::Arc;
+use tokio::sync::watch;
+
+use crate::route_table::RouteTable;
+
+/// In-flight requests by key, each with a channel that receives its
+/// response once it arrives.
+pub(crate) type Flights = DashMap<String, watch::Sender<Option<Arc<SharedResponse>>>>;
+
+/// A fully buffered response that can be handed to several callers.
+#[derive(Debug)]
+pub(crate) struct SharedResponse {
+    status: StatusCode,
+    version: Version,
+    headers: HeaderMap,
+    url: Url,
+    body: Bytes,
+}
+
+impl SharedResponse {
+    /// Read the whole body of `response`.
+    pub async fn buffer(response: Response) -> reqwest::Result<Self> {
+        let status = response.status();
+        let version = response.version();
+        let headers = response.headers().clone();
+        let url = response.url().clone();
+        let body = response.bytes().await?;
+        Ok(Self {
+            status,
+            version,
+            headers,
+            url,
+            body,
+        })
+    }
+
+    /// Build a response with the buffered status, headers, and body.
+    pub fn to_response(&self) -> Response {
+        let mut builder = http::Response::builder()
+            .status(self.status)
+            .version(self.version)
+            .url(self.url.clone());
+        if let Some(headers) = builder.headers_mut() {
+            headers.clone_from(&self.headers);
+        }
+        let response = builder
+            .body(self.body.clone())
+            .expect("status and headers are valid");
+        Response::from(response)
+    }
+}
+
+/// A request's role among identical requests in flight.
+pub(crate) enum Flight {
+    /// The first request, which is sent and shares its response.
+    Leader(Leader),
+    /// A duplicate, which waits for the leader's response.
+    Follower(watch::Receiver<Option<Arc<SharedResponse>>>),
+}
+
+/// Join the requests in flight under `key`, leading them if there are none.
+pub(crate) fn join(table: &Arc<RouteTable>, key: String) -> Flight {
+    match table.flights.entry(key) {
+        Entry::Occupied(entry) => Flight::Follower(entry.get().subscribe()),
+        Entry::Vacant(entry) => {
+            let (sender, _) = watch::channel(None);
+            let key = entry.key().clone();
+            entry.insert(sender.clone());
+            Flight::Leader(Leader {
+                table: Arc::clone(table),
+                key,
+                sender,
+            })
+        }
+    }
+}
+
+/// Wait for the leader's response.
+///
+/// Returns `None` if the leader failed or was cancelled without a response.
+pub(crate) async fn follow(
+    mut receiver: watch::Receiver<Option<Arc<SharedResponse>>>,
+) -> Option<Arc<SharedResponse>> {
+    let response = receiver.wait_for(Option::is_some).await.ok()?;
+    response.clone()
+}
+
+/// The sending request of a flight.
+///
+/// Dropping it ends the flight, so that later requests are sent again.
+/// Followers still waiting then get the response passed to
+/// [`finish`](Self::finish), if any.
+pub(crate) struct Leader {
+    table: Arc<RouteTable>,
+    key: String,
+    sender: watch::Sender<Option<Arc<SharedResponse>>>,
+}
+
+impl Leader {
+    /// Share `response` with every follower.
+    pub fn finish(self, response: Arc<SharedResponse>) {
+        self.sender.send_replace(Some(response));
+    }
+}
+
+impl Drop for Leader {
+    fn drop(&mut self) {
+        self.table
+            .flights
+            .remove_if(&self.key, |_, sender| sender.same_channel(&self.sender));
+    }
+}
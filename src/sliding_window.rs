@@ -0,0 +1,298 @@
+//! Sliding window log implementation.
+//!
+//! The log remembers when each admitted request was made and admits a new one
+//! only if fewer than the limit were made within the last window. Unlike GCRA
+//! and the token bucket, this guarantees that no window-sized span of time,
+//! wherever it starts, ever contains more requests than the limit.
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Sliding window log state.
+///
+/// Timestamps cannot be updated atomically as a group, so the log is kept
+/// behind a [`Mutex`]. The lock is only held for the bookkeeping of a single
+/// check, never across an `await`. Memory grows with the limit's request
+/// count, as one timestamp is kept per admitted request.
+#[derive(Debug)]
+pub(crate) struct SlidingWindowLogState {
+    /// Timestamps of admitted requests within the last window, oldest first,
+    /// in nanoseconds since the start instant. A weighted request appears once
+    /// per unit of weight.
+    log: Mutex<VecDeque<u64>>,
+}
+
+impl SlidingWindowLogState {
+    /// Create an empty log.
+    pub fn new() -> Self {
+        Self {
+            log: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, VecDeque<u64>> {
+        // The log is consistent after every push and pop, so a poisoned lock
+        // can safely be reused
+        self.log
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Time at which every logged request will have left the window, in
+    /// nanoseconds. Zero for an empty log.
+    pub fn recovered_at(&self, window_nanos: u64) -> u64 {
+        self.lock()
+            .back()
+            .map_or(0, |newest| newest.saturating_add(window_nanos))
+    }
+
+    /// Number of requests that could be admitted at `now_nanos`, without
+    /// mutating the state.
+    pub fn remaining(&self, now_nanos: u64, requests: u32, window_nanos: u64) -> u64 {
+        let log = self.lock();
+        let in_window = log
+            .iter()
+            .filter(|&&at| at.saturating_add(window_nanos) > now_nanos)
+            .count();
+        u64::from(requests).saturating_sub(in_window as u64)
+    }
+
+    /// Check whether a request counting as `weight` requests could be admitted
+    /// at `now_nanos`, without logging it.
+    pub fn peek_weighted(
+        &self,
+        now_nanos: u64,
+        requests: u32,
+        window_nanos: u64,
+        weight: u32,
+    ) -> Result<(), Duration> {
+        let log = self.lock();
+        let expired = log
+            .iter()
+            .take_while(|&&at| at.saturating_add(window_nanos) <= now_nanos)
+            .count();
+        wait_for_room(&log, expired, now_nanos, requests, window_nanos, weight)
+    }
+
+    /// Fill the log so that no request is admitted before `at_nanos`.
+    ///
+    /// Has no effect on requests already blocked until later, so this can only
+    /// make the limit stricter.
+    pub fn block_until(&self, at_nanos: u64, requests: u32, window_nanos: u64) {
+        let floor = at_nanos.saturating_sub(window_nanos);
+        let mut log = self.lock();
+        for at in log.iter_mut() {
+            *at = (*at).max(floor);
+        }
+        while log.len() < requests as usize {
+            log.push_front(floor);
+        }
+    }
+
+    /// Remove the most recent entries for a request counting as `weight`
+    /// requests that should not have counted.
+    pub fn refund(&self, requests: u32, weight: u32) {
+        let mut log = self.lock();
+        let len = log.len().saturating_sub(weight.min(requests) as usize);
+        log.truncate(len);
+    }
+
+    /// Age at `now_nanos` of every logged request still within the window,
+    /// oldest first.
+    pub fn ages(&self, now_nanos: u64, window_nanos: u64) -> Vec<u64> {
+        self.lock()
+            .iter()
+            .filter(|&&at| at.saturating_add(window_nanos) > now_nanos)
+            .map(|&at| now_nanos.saturating_sub(at))
+            .collect()
+    }
+
+    /// Add requests made at the given times to the log.
+    pub fn restore(&self, timestamps: impl IntoIterator<Item = u64>) {
+        let mut log = self.lock();
+        log.extend(timestamps);
+        log.make_contiguous().sort_unstable();
+    }
+
+    /// Try to admit a single request. Returns Ok(()) if allowed, or
+    /// Err(wait_duration) if rate limited.
+    #[cfg(test)]
+    pub fn try_acquire(
+        &self,
+        now_nanos: u64,
+        requests: u32,
+        window_nanos: u64,
+    ) -> Result<(), Duration> {
+        self.try_acquire_weighted(now_nanos, requests, window_nanos, 1)
+    }
+
+    /// Try to admit a request counting as `weight` requests.
+    ///
+    /// A weight larger than the limit is clamped to the limit, so such a
+    /// request is admitted once the window is completely empty.
+    pub fn try_acquire_weighted(
+        &self,
+        now_nanos: u64,
+        requests: u32,
+        window_nanos: u64,
+        weight: u32,
+    ) -> Result<(), Duration> {
+        let mut log = self.lock();
+
+        // Forget requests that have left the window
+        while log
+            .front()
+            .is_some_and(|&at| at.saturating_add(window_nanos) <= now_nanos)
+        {
+            log.pop_front();
+        }
+
+        wait_for_room(&log, 0, now_nanos, requests, window_nanos, weight)?;
+        log.extend(std::iter::repeat_n(
+            now_nanos,
+            weight.min(requests) as usize,
+        ));
+        Ok(())
+    }
+}
+
+/// Check that a request of `weight` fits in the window, given a log whose
+/// first `expired` entries have already left it.
+///
+/// Returns the wait until enough of the oldest requests leave the window.
+fn wait_for_room(
+    log: &VecDeque<u64>,
+    expired: usize,
+    now_nanos: u64,
+    requests: u32,
+    window_nanos: u64,
+    weight: u32,
+) -> Result<(), Duration> {
+    let weight = weight.min(requests) as usize;
+    let excess = (log.len() - expired + weight).saturating_sub(requests as usize);
+    if excess > 0 {
+        let expires_at = log[expired + excess - 1].saturating_add(window_nanos);
+        return Err(Duration::from_nanos(expires_at - now_nanos));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WINDOW: u64 = 1_000_000_000; // 1s
+
+    #[test]
+    fn test_sliding_window_allows_burst() {
+        let state = SlidingWindowLogState::new();
+
+        for _ in 0..10 {
+            assert!(state.try_acquire(0, 10, WINDOW).is_ok());
+        }
+        assert_eq!(
+            state.try_acquire(0, 10, WINDOW),
+            Err(Duration::from_secs(1))
+        );
+    }
+
+    #[test]
+    fn test_sliding_window_waits_for_oldest_entry() {
+        let state = SlidingWindowLogState::new();
+        state.try_acquire(0, 2, WINDOW).unwrap();
+        state.try_acquire(400_000_000, 2, WINDOW).unwrap();
+
+        // The request at t=0 leaves the window at t=1s
+        assert_eq!(
+            state.try_acquire(700_000_000, 2, WINDOW),
+            Err(Duration::from_millis(300))
+        );
+        assert_eq!(state.remaining(999_999_999, 2, WINDOW), 0);
+        assert_eq!(state.remaining(WINDOW, 2, WINDOW), 1);
+        assert!(state.try_acquire(WINDOW, 2, WINDOW).is_ok());
+        assert!(state.try_acquire(WINDOW, 2, WINDOW).is_err());
+    }
+
+    #[test]
+    fn test_sliding_window_never_exceeds_limit_in_any_window() {
+        let state = SlidingWindowLogState::new();
+        let requests = 5;
+        let mut admitted = Vec::new();
+
+        // Hammer the limiter every 10ms for several windows, with a burst
+        // right before and right after each window boundary
+        let mut now = 0;
+        while now < 5 * WINDOW {
+            for _ in 0..3 {
+                if state.try_acquire(now, requests, WINDOW).is_ok() {
+                    admitted.push(now);
+                }
+            }
+            now += 10_000_000;
+        }
+
+        assert!(admitted.len() >= 5 * requests as usize);
+        for (i, &start) in admitted.iter().enumerate() {
+            let in_window = admitted[i..]
+                .iter()
+                .take_while(|&&at| at < start + WINDOW)
+                .count();
+            assert!(
+                in_window <= requests as usize,
+                "{in_window} requests admitted in the window starting at {start}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_sliding_window_weighted() {
+        let state = SlidingWindowLogState::new();
+
+        assert!(state.try_acquire_weighted(0, 10, WINDOW, 8).is_ok());
+        assert!(state.try_acquire_weighted(0, 10, WINDOW, 3).is_err());
+        assert!(state.try_acquire_weighted(0, 10, WINDOW, 2).is_ok());
+
+        // A weight larger than the limit waits for an empty window
+        assert_eq!(
+            state.try_acquire_weighted(0, 10, WINDOW, 50),
+            Err(Duration::from_secs(1))
+        );
+        assert!(state.try_acquire_weighted(WINDOW, 10, WINDOW, 50).is_ok());
+        assert_eq!(state.remaining(WINDOW, 10, WINDOW), 0);
+    }
+
+    #[test]
+    fn test_sliding_window_peek() {
+        let state = SlidingWindowLogState::new();
+        state.try_acquire(0, 3, WINDOW).unwrap();
+        state.try_acquire(0, 3, WINDOW).unwrap();
+
+        assert!(state.peek_weighted(0, 3, WINDOW, 1).is_ok());
+        assert!(state.peek_weighted(0, 3, WINDOW, 1).is_ok());
+        assert_eq!(
+            state.peek_weighted(0, 3, WINDOW, 2),
+            Err(Duration::from_secs(1))
+        );
+
+        // Expired entries are skipped without being removed
+        state.try_acquire(400_000_000, 3, WINDOW).unwrap();
+        assert_eq!(
+            state.peek_weighted(WINDOW, 3, WINDOW, 3),
+            Err(Duration::from_millis(400))
+        );
+        assert!(state.try_acquire(WINDOW, 3, WINDOW).is_ok());
+    }
+
+    #[test]
+    fn test_block_until() {
+        let state = SlidingWindowLogState::new();
+        state.try_acquire(0, 3, WINDOW).unwrap();
+
+        state.block_until(2 * WINDOW, 3, WINDOW);
+        assert_eq!(state.remaining(2 * WINDOW - 1, 3, WINDOW), 0);
+        assert!(state.try_acquire(2 * WINDOW - 1, 3, WINDOW).is_err());
+        assert!(state.try_acquire(2 * WINDOW, 3, WINDOW).is_ok());
+        assert_eq!(state.recovered_at(WINDOW), 3 * WINDOW);
+    }
+}
@@ -0,0 +1,262 @@
+//! Export and import of rate limit state, for warm restarts.
+
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime};
+
+use crate::limiter::LimiterState;
+use crate::route_table::RouteTable;
+use crate::types::{Algorithm, LimitScope, Route, RouteKey};
+
+/// A copy of a middleware's rate limit state at one point in time.
+///
+/// Created by [`RateLimitMiddleware::export_state`] and applied with
+/// [`RateLimitMiddleware::import_state`], typically to carry state across a
+/// restart so that upstream servers do not see a fresh burst. With the `serde`
+/// feature, snapshots are (de)serializable.
+///
+/// State is recorded relative to the moment it was captured and keyed by the
+/// identity of each route (its hosts, scheme, method, path, and query, or its
+/// shared group) rather than its position, so it survives restarts and
+/// reordering of the routes. State of routes that no longer exist is ignored.
+///
+/// [`RateLimitMiddleware::export_state`]: crate::RateLimitMiddleware::export_state
+/// [`RateLimitMiddleware::import_state`]: crate::RateLimitMiddleware::import_state
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct StateSnapshot {
+    /// Wall-clock time at which the snapshot was taken, used to account for
+    /// the time that passed until it is imported.
+    captured_at: SystemTime,
+    buckets: Vec<BucketSnapshot>,
+}
+
+impl StateSnapshot {
+    /// Number of buckets recorded in the snapshot.
+    ///
+    /// Buckets that had fully recovered when the snapshot was taken are not
+    /// recorded.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.buckets.len()
+    }
+
+    /// Returns `true` if no bucket is recorded in the snapshot.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.buckets.is_empty()
+    }
+}
+
+/// The state of a single route, limit, and bucket key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+struct BucketSnapshot {
+    route: String,
+    limit_index: usize,
+    key: Option<String>,
+    state: SavedState,
+}
+
+/// Algorithm state relative to the moment it was captured.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub(crate) enum SavedState {
+    /// Time until the GCRA theoretical arrival time.
+    Gcra { tat_in: Duration },
+    /// Time until the token bucket is full again.
+    TokenBucket { full_in: Duration },
+    /// Age of every request within the sliding window, oldest first.
+    SlidingWindowLog { ages: Vec<Duration> },
+}
+
+impl SavedState {
+    /// Returns `true` if this state was captured from `algorithm`.
+    fn is_for(&self, algorithm: Algorithm) -> bool {
+        matches!(
+            (self, algorithm),
+            (Self::Gcra { .. }, Algorithm::Gcra)
+                | (Self::TokenBucket { .. }, Algorithm::TokenBucket)
+                | (Self::SlidingWindowLog { .. }, Algorithm::SlidingWindowLog)
+        )
+    }
+
+    /// The state after `elapsed` more time has passed, or `None` if it has
+    /// fully recovered by then.
+    fn aged(&self, elapsed: Duration) -> Option<Self> {
+        let state = match self {
+            Self::Gcra { tat_in } => Self::Gcra {
+                tat_in: tat_in.checked_sub(elapsed).filter(|d| !d.is_zero())?,
+            },
+            Self::TokenBucket { full_in } => Self::TokenBucket {
+                full_in: full_in.checked_sub(elapsed).filter(|d| !d.is_zero())?,
+            },
+            Self::SlidingWindowLog { ages } => Self::SlidingWindowLog {
+                ages: ages.iter().map(|age| age.saturating_add(elapsed)).collect(),
+            },
+        };
+        Some(state)
+    }
+}
+
+/// Stable identity of the routes sharing a state scope.
+pub(crate) fn scope_identity(table: &RouteTable, scope: &LimitScope) -> Option<String> {
+    match scope {
+        LimitScope::Route(index) => table.routes.get(*index).map(Route::identity),
+        LimitScope::Group(group) => Some(format!("group {group}")),
+        LimitScope::Pool(pool) => Some(format!("pool {pool}")),
+    }
+}
+
+/// Capture the state of every bucket in `table` that has not fully recovered
+/// at `now_nanos`.
+pub(crate) fn export(table: &RouteTable, now_nanos: u64) -> StateSnapshot {
+    let buckets = table
+        .state
+        .iter()
+        .filter_map(|entry| {
+            let key = entry.key();
+            let limit = table.limit_for(key)?;
+            Some(BucketSnapshot {
+                route: scope_identity(table, &key.scope)?,
+                limit_index: key.limit_index,
+                key: key.key.clone(),
+                state: entry.value().save(now_nanos, limit)?,
+            })
+        })
+        .collect();
+    StateSnapshot {
+        captured_at: SystemTime::now(),
+        buckets,
+    }
+}
+
+/// Apply `snapshot` to the matching buckets of `table` at `now_nanos`.
+pub(crate) fn import(table: &RouteTable, now_nanos: u64, snapshot: &StateSnapshot) {
+    // A snapshot from the future (or a clock step backwards) is treated as
+    // just taken, which errs on the side of stricter limits
+    let elapsed = SystemTime::now()
+        .duration_since(snapshot.captured_at)
+        .unwrap_or_default();
+
+    let mut scopes = HashMap::new();
+    for (index, route) in table.routes.iter().enumerate() {
+        let scope = route.limit_scope(index);
+        if let Some(identity) = scope_identity(table, &scope) {
+            scopes.entry(identity).or_insert((scope, route.algorithm));
+        }
+    }
+    // Pools always use GCRA
+    for pool in table.pools.keys() {
+        let scope = LimitScope::Pool(pool.clone());
+        if let Some(identity) = scope_identity(table, &scope) {
+            scopes.insert(identity, (scope, Algorithm::Gcra));
+        }
+    }
+
+    for bucket in &snapshot.buckets {
+        let Some((scope, algorithm)) = scopes.get(&bucket.route) else {
+            continue;
+        };
+        let key = RouteKey {
+            scope: scope.clone(),
+            limit_index: bucket.limit_index,
+            key: bucket.key.clone(),
+        };
+        let Some(limit) = table.limit_for(&key) else {
+            continue;
+        };
+        let Some(saved) = bucket.state.aged(elapsed) else {
+            continue;
+        };
+        if !saved.is_for(*algorithm) {
+            continue;
+        }
+
+        table
+            .state
+            .entry(key)
+            .or_insert_with(|| LimiterState::new(*algorithm))
+            .restore(now_nanos, limit, &saved);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{MatchMode, RateLimit};
+    use std::collections::BTreeMap;
+
+    const SECOND: u64 = 1_000_000_000;
+
+    fn table(algorithm: Algorithm) -> RouteTable {
+        let mut route = crate::RateLimitMiddleware::builder()
+            .route(|r| r.path("/order").limit(3, Duration::from_secs(3)))
+            .into_routes()
+            .remove(0);
+        route.algorithm = algorithm;
+        RouteTable::new(vec![route], BTreeMap::new(), MatchMode::All, 0, 0)
+    }
+
+    fn exhaust(table: &RouteTable, now: u64) {
+        let key = RouteKey {
+            scope: LimitScope::Route(0),
+            limit_index: 0,
+            key: None,
+        };
+        let limit = RateLimit::new(3, Duration::from_secs(3));
+        let state = table
+            .state
+            .entry(key)
+            .or_insert_with(|| LimiterState::new(table.routes[0].algorithm));
+        while state.try_acquire_weighted(now, &limit, 1).is_ok() {}
+    }
+
+    #[test]
+    fn test_round_trip_every_algorithm() {
+        let limit = RateLimit::new(3, Duration::from_secs(3));
+        for algorithm in [
+            Algorithm::Gcra,
+            Algorithm::TokenBucket,
+            Algorithm::SlidingWindowLog,
+        ] {
+            let old = table(algorithm);
+            exhaust(&old, 5 * SECOND);
+            let snapshot = export(&old, 5 * SECOND);
+            assert_eq!(snapshot.len(), 1, "{algorithm:?}");
+
+            // The new table's clock starts later than the old one's
+            let new = table(algorithm);
+            import(&new, 100 * SECOND, &snapshot);
+            let state = new.state.iter().next().unwrap();
+            assert_eq!(state.remaining(100 * SECOND, &limit), 0, "{algorithm:?}");
+            assert_eq!(
+                state.remaining(103 * SECOND, &limit),
+                3,
+                "{algorithm:?} should recover within the window"
+            );
+        }
+    }
+
+    #[test]
+    fn test_import_skips_mismatched_algorithm() {
+        let old = table(Algorithm::Gcra);
+        exhaust(&old, 0);
+        let snapshot = export(&old, 0);
+
+        let new = table(Algorithm::TokenBucket);
+        import(&new, 0, &snapshot);
+        assert!(new.state.is_empty());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_snapshot_serde_round_trip() {
+        let old = table(Algorithm::SlidingWindowLog);
+        exhaust(&old, SECOND);
+        let snapshot = export(&old, 2 * SECOND);
+
+        let json = serde_json::to_string(&snapshot).unwrap();
+        let parsed: StateSnapshot = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, snapshot);
+    }
+}
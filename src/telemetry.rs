@@ -0,0 +1,51 @@
+//! Metrics integration via the [`metrics`] crate.
+//!
+//! Enabled by the `metrics` feature. Emits:
+//! - `route_ratelimit_requests_total` (counter), labeled by `host` and `outcome`
+//!   (`allowed`, `delayed`, or `errored`)
+//! - `route_ratelimit_delay_seconds` (histogram), labeled by `host`, observed
+//!   whenever a delayed request sleeps
+
+use metrics::SharedString;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::types::Route;
+
+/// Counter of rate limiting decisions.
+pub(crate) const REQUESTS_TOTAL: &str = "route_ratelimit_requests_total";
+
+/// Histogram of delays imposed on requests.
+pub(crate) const DELAY_SECONDS: &str = "route_ratelimit_delay_seconds";
+
+/// Outcome label for a request that passed a route's limits.
+pub(crate) const ALLOWED: &str = "allowed";
+
+/// Outcome label for a request that was delayed by a route's limits.
+pub(crate) const DELAYED: &str = "delayed";
+
+/// Outcome label for a request that was rejected by a route's limits.
+pub(crate) const ERRORED: &str = "errored";
+
+/// Precompute the `host` label for each route.
+///
+/// Routes matching several hosts are labeled with a comma-separated list, and
+/// routes matching any host with `*`.
+pub(crate) fn host_labels(routes: &[Route]) -> Vec<SharedString> {
+    routes
+        .iter()
+        .map(|route| SharedString::from_shared(Arc::from(route.host_description())))
+        .collect()
+}
+
+/// Count a rate limiting decision for a route.
+#[inline]
+pub(crate) fn record_outcome(host: &SharedString, outcome: &'static str) {
+    metrics::counter!(REQUESTS_TOTAL, "host" => host.clone(), "outcome" => outcome).increment(1);
+}
+
+/// Observe a delay imposed on a request.
+#[inline]
+pub(crate) fn record_delay(host: &SharedString, delay: Duration) {
+    metrics::histogram!(DELAY_SECONDS, "host" => host.clone()).record(delay.as_secs_f64());
+}
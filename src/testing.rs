@@ -0,0 +1,265 @@
+//! Utilities for testing code that uses the rate limiting middleware.
+
+use http::Method;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use crate::builder::RateLimitBuilder;
+use crate::clock::Clock;
+use crate::jitter::JitterSource;
+use crate::middleware::RateLimitMiddleware;
+
+/// A builder backed by a new [`TestClock`], with delays free of jitter.
+///
+/// Returns the builder together with its clock, so that tests can move time
+/// forward themselves. Time only passes when the clock is advanced, so
+/// consumption counts are exact.
+///
+/// # Example
+///
+/// ```rust
+/// use http::Method;
+/// use route_ratelimit::testing;
+/// use std::time::Duration;
+///
+/// # async fn example() -> Result<(), route_ratelimit::RateLimitError> {
+/// let (builder, clock) = testing::builder();
+/// let middleware = builder
+///     .host("api.example.com", |host| {
+///         host.route(|r| r.path("/order").limit(10, Duration::from_secs(1)))
+///     })
+///     .build();
+///
+/// for _ in 0..3 {
+///     middleware.acquire("api.example.com", &Method::POST, "/order").await?;
+/// }
+/// assert_eq!(middleware.consumed("api.example.com", &Method::POST, "/order"), 3);
+///
+/// clock.advance(Duration::from_secs(1));
+/// assert_eq!(middleware.consumed("api.example.com", &Method::POST, "/order"), 0);
+/// # Ok(())
+/// # }
+/// ```
+#[must_use]
+pub fn builder() -> (RateLimitBuilder, Arc<TestClock>) {
+    let clock = Arc::new(TestClock::new());
+    let builder = RateLimitMiddleware::builder()
+        .with_clock(clock.clone())
+        .with_jitter_source(Arc::new(FixedJitter::new(0.0)));
+    (builder, clock)
+}
+
+/// A middleware without routes, which never limits a request.
+///
+/// Useful where code under test requires a middleware but rate limiting is
+/// not what is being tested.
+#[must_use]
+pub fn noop() -> RateLimitMiddleware {
+    RateLimitMiddleware::builder().build()
+}
+
+impl RateLimitMiddleware {
+    /// Number of requests spent against the limits that apply to a request,
+    /// for asserting on consumption in tests.
+    ///
+    /// For each limit of each route matching `host`, `method`, and `path`,
+    /// counts how much of its burst is used up right now, in unit-weight
+    /// requests, and returns the largest count, or 0 if no route matches.
+    /// Limits recover over time, so counts are only exact while time stands
+    /// still, such as with the clock of [`builder`]. Matching and buckets
+    /// follow [`quota_remaining`](Self::quota_remaining).
+    #[must_use]
+    pub fn consumed(&self, host: &str, method: &Method, path: &str) -> u32 {
+        self.quota_remaining(host, method, path)
+            .into_iter()
+            .map(|(limit, remaining)| limit.burst().saturating_sub(remaining))
+            .max()
+            .unwrap_or_default()
+    }
+}
+
+/// A manually controlled [`Clock`] for deterministic tests.
+///
+/// Time starts at zero and only moves when [`advance`](TestClock::advance) or
+/// [`set`](TestClock::set) is called.
+///
+/// # Example
+///
+/// ```rust
+/// use route_ratelimit::RateLimitMiddleware;
+/// use route_ratelimit::testing::TestClock;
+/// use std::sync::Arc;
+/// use std::time::Duration;
+///
+/// let clock = Arc::new(TestClock::new());
+/// let middleware = RateLimitMiddleware::builder()
+///     .with_clock(clock.clone())
+///     .route(|r| r.limit(10, Duration::from_secs(1)))
+///     .build();
+///
+/// clock.advance(Duration::from_millis(100));
+/// ```
+#[derive(Debug, Default)]
+pub struct TestClock {
+    nanos: AtomicU64,
+}
+
+impl TestClock {
+    /// Create a new clock at time zero.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Move the clock forward by `duration`.
+    pub fn advance(&self, duration: Duration) {
+        let nanos = duration.as_nanos().min(u64::MAX as u128) as u64;
+        let _ = self
+            .nanos
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |current| {
+                Some(current.saturating_add(nanos))
+            });
+    }
+
+    /// Set the clock to `elapsed` since its origin.
+    ///
+    /// Callers are responsible for keeping time monotonic.
+    pub fn set(&self, elapsed: Duration) {
+        let nanos = elapsed.as_nanos().min(u64::MAX as u128) as u64;
+        self.nanos.store(nanos, Ordering::Release);
+    }
+}
+
+impl Clock for TestClock {
+    #[inline]
+    fn now_nanos(&self) -> u64 {
+        self.nanos.load(Ordering::Acquire)
+    }
+}
+
+/// A [`JitterSource`] always returning the same sample, for exact delays in
+/// tests.
+///
+/// # Example
+///
+/// ```rust
+/// use route_ratelimit::RateLimitMiddleware;
+/// use route_ratelimit::testing::FixedJitter;
+/// use std::sync::Arc;
+/// use std::time::Duration;
+///
+/// // Delays are always one and a half times the wait
+/// let middleware = RateLimitMiddleware::builder()
+///     .jitter(0.5)
+///     .with_jitter_source(Arc::new(FixedJitter::new(1.0)))
+///     .route(|r| r.limit(10, Duration::from_secs(1)))
+///     .build();
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct FixedJitter {
+    sample: f64,
+}
+
+impl FixedJitter {
+    /// Create a source returning `sample`, between `0.0` and `1.0`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `sample` is not within `0.0..=1.0`.
+    #[must_use]
+    pub fn new(sample: f64) -> Self {
+        assert!(
+            (0.0..=1.0).contains(&sample),
+            "jitter sample must be between 0.0 and 1.0"
+        );
+        Self { sample }
+    }
+}
+
+impl JitterSource for FixedJitter {
+    #[inline]
+    fn sample(&self) -> f64 {
+        self.sample
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clock_advances() {
+        let clock = TestClock::new();
+        assert_eq!(clock.now_nanos(), 0);
+
+        clock.advance(Duration::from_millis(5));
+        assert_eq!(clock.now_nanos(), 5_000_000);
+
+        clock.set(Duration::from_secs(1));
+        assert_eq!(clock.now_nanos(), 1_000_000_000);
+    }
+
+    #[tokio::test]
+    async fn test_consumed_counts_acquired_requests() {
+        let (builder, clock) = builder();
+        let middleware = builder
+            .host("api.example.com", |host| {
+                host.route(|r| r.path("/book").limit(10, Duration::from_secs(1)))
+                    .route(|r| r.path("/order").weight(5).limit(50, Duration::from_secs(1)))
+            })
+            .build();
+
+        for _ in 0..3 {
+            middleware
+                .acquire("api.example.com", &Method::GET, "/book")
+                .await
+                .unwrap();
+        }
+        middleware
+            .acquire("api.example.com", &Method::POST, "/order")
+            .await
+            .unwrap();
+
+        assert_eq!(
+            middleware.consumed("api.example.com", &Method::GET, "/book"),
+            3
+        );
+        // The weighted order counts five times
+        assert_eq!(
+            middleware.consumed("api.example.com", &Method::POST, "/order"),
+            5
+        );
+        assert_eq!(middleware.consumed("api.example.com", &Method::GET, "/"), 0);
+
+        // A tenth of a second recovers one request of the `/book` limit
+        clock.advance(Duration::from_millis(100));
+        assert_eq!(
+            middleware.consumed("api.example.com", &Method::GET, "/book"),
+            2
+        );
+    }
+
+    #[tokio::test]
+    async fn test_noop_never_limits() {
+        let middleware = noop();
+        for _ in 0..1000 {
+            middleware
+                .acquire("api.example.com", &Method::GET, "/")
+                .await
+                .unwrap();
+        }
+        assert_eq!(middleware.consumed("api.example.com", &Method::GET, "/"), 0);
+    }
+
+    #[test]
+    fn test_fixed_jitter() {
+        assert_eq!(FixedJitter::new(0.25).sample(), 0.25);
+    }
+
+    #[test]
+    #[should_panic(expected = "jitter sample must be between 0.0 and 1.0")]
+    fn test_invalid_fixed_jitter_panics() {
+        let _jitter = FixedJitter::new(1.5);
+    }
+}
@@ -0,0 +1,464 @@
+//! Token bucket implementation.
+//!
+//! A classic token bucket holds up to a fixed number of tokens and is refilled
+//! by one token at every tick of a fixed schedule. Each request takes tokens out
+//! of the bucket and is rejected if not enough are left.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Token bucket state.
+///
+/// Rather than a token count and a last-refill time, which could not be
+/// updated together atomically, the bucket is stored as the time at which it
+/// will be full again. The token count at any instant follows from that time,
+/// the refill interval, and the capacity, so a single atomic is enough for
+/// lock-free concurrent access.
+///
+/// Refills happen on a fixed grid of emission intervals, as if a timer added a
+/// token at every tick. A drained bucket therefore gets its next token at the
+/// next tick, which may come sooner than a full interval after the request
+/// that drained it.
+#[derive(Debug)]
+pub(crate) struct TokenBucketState {
+    /// Time at which the bucket will be full again, in nanoseconds since the
+    /// start instant. A multiple of the emission interval unless moved by
+    /// [`bump_full_at`](Self::bump_full_at). Never more than the bucket's
+    /// capacity ahead of the time it was last updated at; a refill time
+    /// further ahead, e.g. saturated near `u64::MAX`, is clamped back to an
+    /// empty bucket.
+    full_at_nanos: AtomicU64,
+    /// Time before which no tokens are taken, set by
+    /// [`block_until`](Self::block_until), or 0.
+    blocked_until_nanos: AtomicU64,
+}
+
+impl TokenBucketState {
+    /// Create a new, full token bucket.
+    pub fn new() -> Self {
+        Self::with_full_at(0)
+    }
+
+    /// Create a token bucket that will be full at `full_at_nanos`.
+    pub fn with_full_at(full_at_nanos: u64) -> Self {
+        Self {
+            full_at_nanos: AtomicU64::new(full_at_nanos),
+            blocked_until_nanos: AtomicU64::new(0),
+        }
+    }
+
+    /// Get the time at which the bucket will be full again, in nanoseconds.
+    #[cfg(test)]
+    pub fn full_at(&self, ordering: Ordering) -> u64 {
+        self.full_at_nanos.load(ordering)
+    }
+
+    /// The refill time tokens are taken against, including the effect of a
+    /// [block](Self::block_until): once it ends, the bucket holds one token.
+    pub fn effective_full_at(&self, emission_interval_nanos: u64, limit_nanos: u64) -> u64 {
+        let blocked_until = self.blocked_until_nanos.load(Ordering::Acquire);
+        self.full_at_nanos.load(Ordering::Acquire).max(after_block(
+            blocked_until,
+            emission_interval_nanos,
+            limit_nanos,
+        ))
+    }
+
+    /// Number of tokens in the bucket at `now_nanos`, without mutating the state.
+    pub fn remaining(&self, now_nanos: u64, emission_interval_nanos: u64, limit_nanos: u64) -> u64 {
+        let full_at = self.full_at_nanos.load(Ordering::Acquire);
+        let Ok(full_at) =
+            self.base_full_at(full_at, now_nanos, emission_interval_nanos, limit_nanos)
+        else {
+            return 0;
+        };
+        let emission_interval_nanos = emission_interval_nanos.max(1);
+        let tick = last_tick(now_nanos, emission_interval_nanos);
+        tick.saturating_add(capacity(emission_interval_nanos, limit_nanos))
+            .saturating_sub(full_at.max(tick))
+            / emission_interval_nanos
+    }
+
+    /// Take no tokens before `at_nanos`, and then as if the bucket held one.
+    ///
+    /// Has no effect if the bucket is already blocked until later, so this
+    /// can only make the limit stricter.
+    pub fn block_until(&self, at_nanos: u64) {
+        self.blocked_until_nanos
+            .fetch_max(at_nanos, Ordering::AcqRel);
+    }
+
+    /// The refill time to take tokens against at `now_nanos`, given the
+    /// stored `full_at`, or the wait until the bucket is no longer blocked.
+    ///
+    /// A stored refill time beyond the bucket's capacity past the current
+    /// tick is treated as an empty bucket.
+    fn base_full_at(
+        &self,
+        full_at: u64,
+        now_nanos: u64,
+        emission_interval_nanos: u64,
+        limit_nanos: u64,
+    ) -> Result<u64, Duration> {
+        let blocked_until = self.blocked_until_nanos.load(Ordering::Acquire);
+        if now_nanos < blocked_until {
+            return Err(Duration::from_nanos(blocked_until - now_nanos));
+        }
+        Ok(full_at
+            .min(empty_at(now_nanos, emission_interval_nanos, limit_nanos))
+            .max(after_block(
+                blocked_until,
+                emission_interval_nanos,
+                limit_nanos,
+            )))
+    }
+
+    /// Move the refill time forward to `at_nanos`.
+    ///
+    /// Has no effect if the bucket already refills at or beyond `at_nanos`, so
+    /// this can only make the limit stricter.
+    pub fn bump_full_at(&self, at_nanos: u64) {
+        self.full_at_nanos.fetch_max(at_nanos, Ordering::AcqRel);
+    }
+
+    /// Put back the tokens taken by a request of `weight` that should not have
+    /// counted.
+    ///
+    /// The refill time never moves below `now_nanos`, so the bucket cannot
+    /// hold more than its capacity.
+    pub fn refund(
+        &self,
+        now_nanos: u64,
+        emission_interval_nanos: u64,
+        limit_nanos: u64,
+        weight: u32,
+    ) {
+        let refund_nanos = emission_interval_nanos
+            .saturating_mul(u64::from(weight))
+            .min(limit_nanos);
+        let _ = self
+            .full_at_nanos
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |full_at| {
+                (full_at > now_nanos).then(|| full_at.saturating_sub(refund_nanos).max(now_nanos))
+            });
+    }
+
+    /// Take tokens worth `charge_nanos` of refill time without checking
+    /// whether they are available.
+    ///
+    /// The bucket is at most emptied, so a large charge never puts it into
+    /// debt.
+    pub fn charge(
+        &self,
+        now_nanos: u64,
+        emission_interval_nanos: u64,
+        limit_nanos: u64,
+        charge_nanos: u64,
+    ) {
+        let emission_interval_nanos = emission_interval_nanos.max(1);
+        let capacity_nanos = limit_nanos - limit_nanos % emission_interval_nanos;
+        let tick = last_tick(now_nanos, emission_interval_nanos);
+        let empty_at = tick.saturating_add(capacity_nanos);
+        let _ = self
+            .full_at_nanos
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |full_at| {
+                let charged = full_at.max(tick).saturating_add(charge_nanos);
+                Some(charged.min(empty_at).max(full_at))
+            });
+    }
+
+    /// Check whether `weight` tokens could be taken at `now_nanos`, without
+    /// taking them.
+    pub fn peek_weighted(
+        &self,
+        now_nanos: u64,
+        emission_interval_nanos: u64,
+        limit_nanos: u64,
+        weight: u32,
+    ) -> Result<(), Duration> {
+        let full_at = self.full_at_nanos.load(Ordering::Acquire);
+        let full_at =
+            self.base_full_at(full_at, now_nanos, emission_interval_nanos, limit_nanos)?;
+        next_full_at(
+            full_at,
+            now_nanos,
+            emission_interval_nanos,
+            limit_nanos,
+            weight,
+        )
+        .map(|_| ())
+    }
+
+    /// Try to take a token. Returns Ok(()) if allowed, or Err(wait_duration) if rate limited.
+    #[cfg(test)]
+    pub fn try_acquire(
+        &self,
+        now_nanos: u64,
+        emission_interval_nanos: u64,
+        limit_nanos: u64,
+    ) -> Result<(), Duration> {
+        self.try_acquire_weighted(now_nanos, emission_interval_nanos, limit_nanos, 1)
+    }
+
+    /// Try to take `weight` tokens at once.
+    ///
+    /// A weight larger than the bucket's capacity is clamped to the capacity,
+    /// so such a request is admitted once the bucket is completely full and
+    /// then empties it.
+    ///
+    /// A stored refill time more than the bucket's capacity ahead, which no
+    /// taken token leaves behind, is first clamped back to an empty bucket, so
+    /// that the bucket recovers from it instead of rejecting every request.
+    pub fn try_acquire_weighted(
+        &self,
+        now_nanos: u64,
+        emission_interval_nanos: u64,
+        limit_nanos: u64,
+        weight: u32,
+    ) -> Result<(), Duration> {
+        loop {
+            let full_at = self.full_at_nanos.load(Ordering::Acquire);
+            let empty_at = empty_at(now_nanos, emission_interval_nanos, limit_nanos);
+            if full_at > empty_at {
+                // Store the clamp, whether or not this request is admitted
+                let _ = self.full_at_nanos.compare_exchange_weak(
+                    full_at,
+                    empty_at,
+                    Ordering::AcqRel,
+                    Ordering::Acquire,
+                );
+                continue;
+            }
+            let base =
+                self.base_full_at(full_at, now_nanos, emission_interval_nanos, limit_nanos)?;
+            let new_full_at = next_full_at(
+                base,
+                now_nanos,
+                emission_interval_nanos,
+                limit_nanos,
+                weight,
+            )?;
+
+            match self.full_at_nanos.compare_exchange_weak(
+                full_at,
+                new_full_at,
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => return Ok(()),
+                Err(_) => continue, // Retry on contention
+            }
+        }
+    }
+}
+
+/// Compute the refill time after taking `weight` tokens at `now_nanos`, or the
+/// wait until enough tokens are in the bucket.
+#[inline]
+fn next_full_at(
+    full_at: u64,
+    now_nanos: u64,
+    emission_interval_nanos: u64,
+    limit_nanos: u64,
+    weight: u32,
+) -> Result<u64, Duration> {
+    let emission_interval_nanos = emission_interval_nanos.max(1);
+    let capacity_nanos = limit_nanos - limit_nanos % emission_interval_nanos;
+    let cost_nanos = emission_interval_nanos
+        .saturating_mul(u64::from(weight))
+        .min(capacity_nanos);
+    let tick = last_tick(now_nanos, emission_interval_nanos);
+
+    // Taking tokens pushes the refill time back by their cost
+    let new_full_at = full_at.max(tick).saturating_add(cost_nanos);
+
+    // Not enough tokens: wait for the tick that brings enough of them
+    let refill_needed_at = new_full_at.saturating_sub(capacity_nanos);
+    if refill_needed_at > now_nanos {
+        return Err(Duration::from_nanos(refill_needed_at - now_nanos));
+    }
+
+    Ok(new_full_at)
+}
+
+/// Largest multiple of the emission interval within `limit_nanos`: the time
+/// an empty bucket takes to fill up.
+#[inline]
+fn capacity(emission_interval_nanos: u64, limit_nanos: u64) -> u64 {
+    limit_nanos - limit_nanos % emission_interval_nanos.max(1)
+}
+
+/// The refill time of a bucket that is empty at `now_nanos`.
+#[inline]
+fn empty_at(now_nanos: u64, emission_interval_nanos: u64, limit_nanos: u64) -> u64 {
+    last_tick(now_nanos, emission_interval_nanos.max(1))
+        .saturating_add(capacity(emission_interval_nanos, limit_nanos))
+}
+
+/// The earliest refill time after the bucket was blocked until
+/// `blocked_until`, so that it holds one token when the block ends.
+#[inline]
+fn after_block(blocked_until: u64, emission_interval_nanos: u64, limit_nanos: u64) -> u64 {
+    if blocked_until == 0 {
+        return 0;
+    }
+    blocked_until
+        .saturating_add(capacity(emission_interval_nanos, limit_nanos))
+        .saturating_sub(emission_interval_nanos.max(1))
+}
+
+/// The most recent refill tick at or before `now_nanos`.
+#[inline]
+fn last_tick(now_nanos: u64, emission_interval_nanos: u64) -> u64 {
+    now_nanos - now_nanos % emission_interval_nanos
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EMISSION: u64 = 100_000_000; // 100ms, 10 req/s
+    const LIMIT: u64 = 1_000_000_000; // 1s
+
+    #[test]
+    fn test_token_bucket_allows_full_burst() {
+        let state = TokenBucketState::new();
+
+        for _ in 0..10 {
+            assert!(state.try_acquire(0, EMISSION, LIMIT).is_ok());
+        }
+        assert_eq!(
+            state.try_acquire(0, EMISSION, LIMIT),
+            Err(Duration::from_millis(100))
+        );
+    }
+
+    #[test]
+    fn test_token_bucket_refills_one_token_per_interval() {
+        let state = TokenBucketState::new();
+        for _ in 0..10 {
+            state.try_acquire(0, EMISSION, LIMIT).unwrap();
+        }
+
+        // Each tick adds exactly one token
+        for tick in 1..=3 {
+            let now = tick * EMISSION;
+            assert!(state.try_acquire(now, EMISSION, LIMIT).is_ok());
+            assert!(state.try_acquire(now, EMISSION, LIMIT).is_err());
+        }
+
+        // Several ticks add several tokens
+        let now = 6 * EMISSION;
+        assert_eq!(state.remaining(now, EMISSION, LIMIT), 3);
+    }
+
+    #[test]
+    fn test_token_bucket_refills_on_fixed_ticks() {
+        let state = TokenBucketState::new();
+
+        // Drain the bucket halfway through an interval
+        let now = 50_000_000;
+        for _ in 0..10 {
+            state.try_acquire(now, EMISSION, LIMIT).unwrap();
+        }
+
+        // The next token arrives at the next tick, not a full interval later
+        assert_eq!(
+            state.try_acquire(now, EMISSION, LIMIT),
+            Err(Duration::from_millis(50))
+        );
+        assert!(state.try_acquire(EMISSION, EMISSION, LIMIT).is_ok());
+    }
+
+    #[test]
+    fn test_token_bucket_never_exceeds_capacity() {
+        let state = TokenBucketState::new();
+
+        // A long idle period still only leaves a full bucket
+        let now = 100 * LIMIT;
+        assert_eq!(state.remaining(now, EMISSION, LIMIT), 10);
+        for _ in 0..10 {
+            assert!(state.try_acquire(now, EMISSION, LIMIT).is_ok());
+        }
+        assert!(state.try_acquire(now, EMISSION, LIMIT).is_err());
+    }
+
+    #[test]
+    fn test_token_bucket_peek() {
+        let state = TokenBucketState::new();
+        for _ in 0..9 {
+            state.try_acquire(0, EMISSION, LIMIT).unwrap();
+        }
+
+        assert!(state.peek_weighted(0, EMISSION, LIMIT, 1).is_ok());
+        assert!(state.peek_weighted(0, EMISSION, LIMIT, 1).is_ok());
+        assert_eq!(state.remaining(0, EMISSION, LIMIT), 1);
+
+        state.try_acquire(0, EMISSION, LIMIT).unwrap();
+        assert_eq!(
+            state.peek_weighted(0, EMISSION, LIMIT, 1),
+            Err(Duration::from_millis(100))
+        );
+    }
+
+    #[test]
+    fn test_token_bucket_recovers_from_saturated_refill_time() {
+        let state = TokenBucketState::with_full_at(u64::MAX);
+
+        // The bucket is empty, not drained for centuries
+        let now = LIMIT;
+        assert_eq!(
+            state.peek_weighted(now, EMISSION, LIMIT, 1),
+            Err(Duration::from_millis(100))
+        );
+        assert!(state.try_acquire(now, EMISSION, LIMIT).is_err());
+        assert_eq!(state.full_at(Ordering::Acquire), now + LIMIT);
+        assert_eq!(state.remaining(now, EMISSION, LIMIT), 0);
+
+        // And refills at the usual rate
+        assert!(state.try_acquire(now + EMISSION, EMISSION, LIMIT).is_ok());
+        assert!(state.try_acquire(now + EMISSION, EMISSION, LIMIT).is_err());
+    }
+
+    #[test]
+    fn test_token_bucket_block_leaves_one_token() {
+        let state = TokenBucketState::new();
+
+        let until = 60 * LIMIT;
+        state.block_until(until);
+        assert_eq!(
+            state.try_acquire(0, EMISSION, LIMIT),
+            Err(Duration::from_nanos(until))
+        );
+        assert!(state.try_acquire(until, EMISSION, LIMIT).is_ok());
+        assert!(state.try_acquire(until, EMISSION, LIMIT).is_err());
+    }
+
+    #[test]
+    fn test_token_bucket_weighted() {
+        let state = TokenBucketState::new();
+
+        assert!(state.try_acquire_weighted(0, EMISSION, LIMIT, 8).is_ok());
+        assert_eq!(
+            state.try_acquire_weighted(0, EMISSION, LIMIT, 3),
+            Err(Duration::from_millis(100))
+        );
+
+        // A weight larger than the capacity empties a full bucket
+        let now = 2 * LIMIT;
+        assert!(state.try_acquire_weighted(now, EMISSION, LIMIT, 50).is_ok());
+        assert_eq!(state.remaining(now, EMISSION, LIMIT), 0);
+    }
+
+    #[test]
+    fn test_token_bucket_charge_at_most_empties() {
+        let state = TokenBucketState::new();
+
+        state.charge(0, EMISSION, LIMIT, 4 * EMISSION);
+        assert_eq!(state.remaining(0, EMISSION, LIMIT), 6);
+
+        state.charge(0, EMISSION, LIMIT, 50 * EMISSION);
+        assert_eq!(state.remaining(0, EMISSION, LIMIT), 0);
+        assert!(state.try_acquire(EMISSION, EMISSION, LIMIT).is_ok());
+    }
+}
@@ -1,28 +1,182 @@
 //! Core types for rate limit configuration.
 
-use http::Method;
+use http::{HeaderMap, HeaderName, Method, StatusCode};
 use reqwest::Request;
+use std::collections::BTreeSet;
+use std::fmt;
+use std::sync::Arc;
 use std::time::Duration;
+use thiserror::Error;
+
+use crate::builder::RouteBuilder;
+use crate::error::{BuildError, InvalidLimit};
+use crate::pattern::PathPattern;
+use crate::request::{RequestParts, unbracket};
 
 /// Behavior when a rate limit is exceeded.
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
 pub enum ThrottleBehavior {
     /// Delay the request until the rate limit window allows it.
+    ///
+    /// Requests delayed on the same bucket wait in a queue and are admitted
+    /// one at a time, in [`Priority`] order and then in the order they started
+    /// waiting.
     #[default]
     Delay,
     /// Return an error immediately.
     Error,
+    /// Delay the request, but return an error immediately if the required
+    /// wait exceeds the given maximum.
+    ///
+    /// Each jittered sleep is capped at the maximum as well. The maximum
+    /// applies to every wait on its own, not to their sum: a request that
+    /// wakes to find the limit taken again, or that waits on several limits,
+    /// sleeps again and can be delayed longer in total. Bound the total with
+    /// [`RateLimitBuilder::max_total_delay`](crate::RateLimitBuilder::max_total_delay).
+    DelayWithTimeout(
+        #[cfg_attr(feature = "serde", serde(with = "crate::config::duration_millis"))] Duration,
+    ),
+    /// Answer the request with a synthetic `429 Too Many Requests` response
+    /// instead of sending it, so that callers' existing HTTP error handling
+    /// applies.
+    ///
+    /// The response has an empty body and a `Retry-After` header with the
+    /// required wait in seconds, rounded up. Responses for an exhausted
+    /// concurrency limit carry no `Retry-After`.
+    Respond429,
+}
+
+/// The algorithm used to enforce a route's limits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum Algorithm {
+    /// Generic Cell Rate Algorithm: each request is admitted on a smooth
+    /// schedule of one every `window / requests`, with up to `requests`
+    /// admitted at once after an idle period.
+    #[default]
+    Gcra,
+    /// Classic token bucket: the bucket holds up to `requests` tokens, gains
+    /// one token on every tick of a fixed `window / requests` schedule, and
+    /// each request takes one token (or its weight in tokens).
+    TokenBucket,
+    /// Sliding window log: remembers the time of every admitted request and
+    /// guarantees that no `window`-long span, wherever it starts, contains
+    /// more than `requests` requests.
+    ///
+    /// Stricter than the other algorithms at window edges, at the cost of a
+    /// lock per check and memory proportional to `requests` for every bucket.
+    SlidingWindowLog,
+}
+
+/// What happens to requests that match no route.
+///
+/// Set with [`RateLimitBuilder::unmatched`](crate::RateLimitBuilder::unmatched).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum UnmatchedBehavior {
+    /// Send them without any limit.
+    #[default]
+    Allow,
+    /// Reject them with [`RateLimitError::Unmatched`](crate::RateLimitError::Unmatched).
+    Deny,
+}
+
+/// Which of the routes matching a request apply to it.
+///
+/// Set with [`RateLimitBuilder::match_mode`](crate::RateLimitBuilder::match_mode).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MatchMode {
+    /// Every matching route applies, so a request counts against the limits
+    /// of a catch-all route as well as those of a more specific one.
+    ///
+    /// Their limits are checked in order of [priority](Route::priority),
+    /// highest first, then in the order routes are configured.
+    #[default]
+    All,
+    /// Only the most specific matching route applies.
+    ///
+    /// Routes are ranked by, in order:
+    /// 1. the length of their path prefix in bytes, or for a path pattern,
+    ///    the length of its literal segments including their leading `/`
+    /// 2. whether their path is [exact](crate::RouteBuilder::path_exact)
+    /// 3. whether they are restricted to some methods
+    /// 4. whether they are restricted to some hosts or host suffixes
+    ///
+    /// Other filters do not count towards specificity. Of equally specific
+    /// routes, the one with the highest [priority](Route::priority) applies,
+    /// then the first one configured.
+    MostSpecific,
+    /// Only the first matching route, in the order routes are configured,
+    /// applies, like in many HTTP routers. Specific routes must therefore be
+    /// configured before general ones, or given a higher
+    /// [priority](Route::priority), which takes precedence over the order.
+    FirstMatch,
+}
+
+/// What a [`RateLimit`] counts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum LimitUnit {
+    /// Requests, each counting by its route's weight.
+    #[default]
+    Requests,
+    /// Bytes of response bodies.
+    ///
+    /// A request is admitted while any bytes are left, taking one, and the
+    /// rest of its response body's size is charged once the response
+    /// arrives. The size is read from the `Content-Length` header, falling
+    /// back to the route's
+    /// [`default_response_bytes`](Route::default_response_bytes).
+    Bytes,
 }
 
 /// A single rate limit configuration.
-#[derive(Debug, Clone)]
+///
+/// Build limits with [`RateLimit::new`] or its other constructors. New fields
+/// may be added in minor releases, so limits cannot be constructed with a
+/// struct literal.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
 pub struct RateLimit {
     /// Maximum number of requests allowed in the window.
     pub requests: u32,
     /// Time window for the rate limit.
+    #[cfg_attr(feature = "serde", serde(with = "crate::config::duration_millis"))]
     pub window: Duration,
+    /// Number of requests that can be made at once after a quiet period.
+    ///
+    /// `None` allows a burst of `requests`. The sustained rate is
+    /// `requests / window` either way.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub burst: Option<u32>,
+    /// Behavior when this limit is exceeded. `None` uses the route's
+    /// [`on_limit`](Route::on_limit).
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub on_limit: Option<ThrottleBehavior>,
+    /// Whether each key of a keyed route gets its own bucket for this limit.
+    ///
+    /// Once a route has a per-key limit, its other limits are shared by all
+    /// keys. A route without any per-key limits keys every limit.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub per_key: bool,
+    /// What `requests` and `burst` count. Requests by default.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub unit: LimitUnit,
 }
 
+/// Longest window, in ticks of either length, that leaves room for the clock
+/// in a `u64` of ticks.
+const MAX_WINDOW_TICKS: u128 = (u64::MAX / 2) as u128;
+
 impl RateLimit {
     /// Create a new rate limit.
     ///
@@ -30,94 +184,1385 @@ impl RateLimit {
     ///
     /// Panics if:
     /// - `requests` is 0
-    /// - `window` is zero
-    /// - `window` exceeds `u64::MAX` nanoseconds (~585 years)
+    /// - `window` is zero, or shorter than one nanosecond per request
+    /// - `window` exceeds `u64::MAX / 2` microseconds (~292,000 years)
+    ///
+    /// See [`try_new`](Self::try_new) for a version returning an error.
     pub fn new(requests: u32, window: Duration) -> Self {
-        assert!(requests > 0, "requests must be greater than 0");
-        assert!(!window.is_zero(), "window must be greater than 0");
-        assert!(
-            window.as_nanos() <= u64::MAX as u128,
-            "window must not exceed u64::MAX nanoseconds (~585 years)"
-        );
-        Self { requests, window }
+        Self::try_new(requests, window).unwrap_or_else(|err| panic!("{err}"))
+    }
+
+    /// Create a new rate limit, or return why `requests` and `window` do not
+    /// make a valid one.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error under the conditions [`RateLimit::new`] panics under.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use route_ratelimit::{InvalidLimit, RateLimit};
+    /// use std::time::Duration;
+    ///
+    /// assert!(RateLimit::try_new(100, Duration::from_secs(10)).is_ok());
+    /// assert_eq!(
+    ///     RateLimit::try_new(0, Duration::from_secs(10)),
+    ///     Err(InvalidLimit::ZeroRequests)
+    /// );
+    /// ```
+    pub fn try_new(requests: u32, window: Duration) -> Result<Self, InvalidLimit> {
+        let limit = Self::unchecked(requests, window, None);
+        limit.check()?;
+        Ok(limit)
+    }
+
+    /// A limit that is only checked once its route is, so that invalid
+    /// limits set through the route builders surface from
+    /// [`RateLimitBuilder::try_build`](crate::RateLimitBuilder::try_build).
+    pub(crate) fn unchecked(requests: u32, window: Duration, burst: Option<u32>) -> Self {
+        Self {
+            requests,
+            window,
+            burst,
+            on_limit: None,
+            per_key: false,
+            unit: LimitUnit::Requests,
+        }
+    }
+
+    /// Create a limit of `requests` per second.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `requests` is 0.
+    pub fn per_second(requests: u32) -> Self {
+        Self::new(requests, Duration::from_secs(1))
+    }
+
+    /// Create a limit of `requests` per minute.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `requests` is 0.
+    pub fn per_minute(requests: u32) -> Self {
+        Self::new(requests, Duration::from_secs(60))
+    }
+
+    /// Create a limit of `requests` per hour.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `requests` is 0.
+    pub fn per_hour(requests: u32) -> Self {
+        Self::new(requests, Duration::from_secs(60 * 60))
+    }
+
+    /// Create a rate limit whose burst capacity differs from its request count.
+    ///
+    /// Requests are admitted at a sustained rate of `requests` per `window`,
+    /// but up to `burst` requests can be made at once after a quiet period.
+    /// For example, `RateLimit::with_burst(10, Duration::from_secs(1), 50)`
+    /// allows 50 immediate requests, then 10 per second.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `burst` is 0, if the burst takes longer than `u64::MAX / 2`
+    /// microseconds to refill, or under the conditions of [`RateLimit::new`].
+    pub fn with_burst(requests: u32, window: Duration, burst: u32) -> Self {
+        let limit = Self::unchecked(requests, window, Some(burst));
+        limit.validate();
+        limit
+    }
+
+    /// Create a limit holding up to `capacity` requests at once and refilling
+    /// at `refill_per_sec` requests per second.
+    ///
+    /// The capacity only bounds bursts and the refill rate only bounds the
+    /// sustained rate, so each can be tuned without changing the other. With
+    /// [`Algorithm::TokenBucket`] this is a classic token bucket.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `capacity` or `refill_per_sec` is 0.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use route_ratelimit::RateLimit;
+    /// use std::time::Duration;
+    ///
+    /// // Bursts of up to 100 requests, refilling at 5 per second
+    /// let limit = RateLimit::token_bucket(100, 5);
+    /// assert_eq!(limit.burst(), 100);
+    /// assert_eq!(limit.emission_interval(), Duration::from_millis(200));
+    /// ```
+    pub fn token_bucket(capacity: u32, refill_per_sec: u32) -> Self {
+        assert!(capacity > 0, "capacity must be greater than 0");
+        assert!(refill_per_sec > 0, "refill_per_sec must be greater than 0");
+        Self::with_burst(refill_per_sec, Duration::from_secs(1), capacity)
+    }
+
+    /// Create a limit of `bytes` of response bodies per `window`.
+    ///
+    /// See [`LimitUnit::Bytes`].
+    ///
+    /// # Panics
+    ///
+    /// Panics under the conditions of [`RateLimit::new`], with `bytes` in
+    /// place of `requests`.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use route_ratelimit::{LimitUnit, RateLimit};
+    /// use std::time::Duration;
+    ///
+    /// // 10 MB per minute
+    /// let limit = RateLimit::bandwidth(10_000_000, Duration::from_secs(60));
+    /// assert_eq!(limit.unit, LimitUnit::Bytes);
+    /// ```
+    pub fn bandwidth(bytes: u32, window: Duration) -> Self {
+        let mut limit = Self::new(bytes, window);
+        limit.unit = LimitUnit::Bytes;
+        limit
+    }
+
+    /// Check the invariants documented on [`RateLimit::new`].
+    pub(crate) fn check(&self) -> Result<(), InvalidLimit> {
+        if self.requests == 0 {
+            return Err(InvalidLimit::ZeroRequests);
+        }
+        if self.window.is_zero() {
+            return Err(InvalidLimit::ZeroWindow);
+        }
+        if self.window.as_nanos() < u128::from(self.requests) {
+            return Err(InvalidLimit::WindowTooShort);
+        }
+        if self.window.as_micros() > MAX_WINDOW_TICKS {
+            return Err(InvalidLimit::WindowTooLong);
+        }
+        if let Some(burst) = self.burst {
+            if burst == 0 {
+                return Err(InvalidLimit::ZeroBurst);
+            }
+            let refill_fits = self
+                .emission_interval()
+                .checked_mul(burst)
+                .is_some_and(|capacity| capacity.as_micros() <= MAX_WINDOW_TICKS);
+            if !refill_fits {
+                return Err(InvalidLimit::BurstTooLong);
+            }
+        }
+        Ok(())
+    }
+
+    /// # Panics
+    ///
+    /// Panics unless the invariants documented on [`RateLimit::new`] hold.
+    pub(crate) fn validate(&self) {
+        if let Err(err) = self.check() {
+            panic!("{err}");
+        }
+    }
+
+    /// Length in nanoseconds of the ticks this limit's state is kept in.
+    ///
+    /// Nanoseconds for precision, unless the window or burst window does not
+    /// fit in `u64::MAX / 2` of them (~292 years, leaving the other half for
+    /// the clock), in which case microseconds.
+    #[inline]
+    pub(crate) fn tick_nanos(&self) -> u32 {
+        if self.window.max(self.burst_window()).as_nanos() <= MAX_WINDOW_TICKS {
+            1
+        } else {
+            1_000
+        }
     }
 
-    /// Calculate the emission interval (time between requests).
+    /// The time between requests at the sustained rate: `window / requests`.
+    ///
+    /// Truncated to whole nanoseconds, so `requests` intervals never add up
+    /// to more than the window and a full window's requests are always
+    /// admitted. The truncation speeds up the sustained rate by less than one
+    /// part in the interval's length in nanoseconds, e.g. less than one
+    /// request in a million for intervals of a millisecond or more.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use route_ratelimit::RateLimit;
+    /// use std::time::Duration;
+    ///
+    /// let limit = RateLimit::new(100, Duration::from_secs(10));
+    /// assert_eq!(limit.emission_interval(), Duration::from_millis(100));
+    /// ```
     #[inline]
-    pub(crate) fn emission_interval(&self) -> Duration {
+    #[must_use]
+    pub fn emission_interval(&self) -> Duration {
         self.window / self.requests
     }
+
+    /// The number of requests that can be made at once after a quiet period.
+    ///
+    /// This is [`burst`](Self::burst) if set, and `requests` otherwise.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use route_ratelimit::RateLimit;
+    /// use std::time::Duration;
+    ///
+    /// let limit = RateLimit::new(100, Duration::from_secs(10));
+    /// assert_eq!(limit.burst(), 100);
+    ///
+    /// let limit = RateLimit::with_burst(100, Duration::from_secs(10), 20);
+    /// assert_eq!(limit.burst(), 20);
+    /// ```
+    #[inline]
+    #[must_use]
+    pub fn burst(&self) -> u32 {
+        self.burst.unwrap_or(self.requests)
+    }
+
+    /// The sustained rate, in [units](Self::unit) per second:
+    /// `requests / window`.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use route_ratelimit::RateLimit;
+    /// use std::time::Duration;
+    ///
+    /// let limit = RateLimit::new(36_000, Duration::from_secs(600));
+    /// assert_eq!(limit.rate_per_second(), 60.0);
+    /// ```
+    #[must_use]
+    pub fn rate_per_second(&self) -> f64 {
+        f64::from(self.requests) / self.window.as_secs_f64()
+    }
+
+    /// Tokens a request of `weight` takes when admitted: its weight for a
+    /// request limit, and a single byte for a bandwidth limit.
+    #[inline]
+    pub(crate) fn cost(&self, weight: u32) -> u32 {
+        match self.unit {
+            LimitUnit::Requests => weight,
+            LimitUnit::Bytes => 1,
+        }
+    }
+
+    /// Time taken to emit `tokens` at the sustained rate:
+    /// `window * tokens / requests`.
+    ///
+    /// Unlike multiplying the [`emission_interval`](Self::emission_interval),
+    /// this truncates once, so it stays exact for large counts, such as the
+    /// bytes of a response charged to a bandwidth limit.
+    pub(crate) fn emission_of(&self, tokens: u64) -> Duration {
+        let nanos = self.window.as_nanos() * u128::from(tokens) / u128::from(self.requests);
+        u64::try_from(nanos).map_or(Duration::MAX, Duration::from_nanos)
+    }
+
+    /// Time for an exhausted burst to fully refill.
+    ///
+    /// This is the window unless a separate burst is configured.
+    #[inline]
+    pub(crate) fn burst_window(&self) -> Duration {
+        match self.burst {
+            Some(burst) => self.emission_interval() * burst,
+            None => self.window,
+        }
+    }
+}
+
+/// Convert a [`governor`](https://docs.rs/governor) quota.
+///
+/// Enabled by the `governor-compat` feature.
+///
+/// A quota admits a burst of `burst_size` requests and replenishes one every
+/// `replenish_interval`. This maps to `burst_size` requests per
+/// `replenish_interval * burst_size`, whose emission interval is exactly the
+/// quota's replenish interval, so both enforce the same rate and burst.
+///
+/// # Example
+///
+/// ```rust
+/// use governor::Quota;
+/// use route_ratelimit::RateLimit;
+/// use std::num::NonZeroU32;
+/// use std::time::Duration;
+///
+/// let quota = Quota::per_second(NonZeroU32::new(10).unwrap());
+/// assert_eq!(
+///     RateLimit::from(quota),
+///     RateLimit::new(10, Duration::from_secs(1))
+/// );
+/// ```
+///
+/// # Panics
+///
+/// Panics if refilling the whole burst takes longer than `u64::MAX / 2`
+/// microseconds.
+#[cfg(feature = "governor-compat")]
+impl From<governor::Quota> for RateLimit {
+    fn from(quota: governor::Quota) -> Self {
+        let burst = quota.burst_size().get();
+        let window = quota
+            .replenish_interval()
+            .checked_mul(burst)
+            .expect("window must not exceed u64::MAX / 2 microseconds (~292,000 years)");
+        Self::new(burst, window)
+    }
+}
+
+/// Bounds of a route's adaptive rate, as multiples of its configured limits.
+///
+/// See [`RouteBuilder::adaptive`](crate::RouteBuilder::adaptive).
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Adaptive {
+    /// Lowest rate the route backs off to.
+    pub min_rate: f64,
+    /// Rate the route starts at and recovers to.
+    pub max_rate: f64,
+}
+
+/// What [`Adaptive::validate`] requires of the rates.
+const ADAPTIVE_RATES: &str = "adaptive rates must satisfy 0 < min_rate <= max_rate <= 1";
+
+impl Adaptive {
+    /// Whether `0 < min_rate <= max_rate <= 1`.
+    pub(crate) fn is_valid(&self) -> bool {
+        self.min_rate > 0.0 && self.min_rate <= self.max_rate && self.max_rate <= 1.0
+    }
+
+    /// # Panics
+    ///
+    /// Panics unless `0 < min_rate <= max_rate <= 1`.
+    pub(crate) fn validate(&self) {
+        assert!(self.is_valid(), "{ADAPTIVE_RATES}");
+    }
 }
 
+// Validated rates are never NaN, so equality is reflexive
+impl Eq for Adaptive {}
+
+/// Circuit breaker settings for a route.
+///
+/// See [`RouteBuilder::cooldown_after`](crate::RouteBuilder::cooldown_after).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Cooldown {
+    /// Number of consecutive throttle events that open the circuit.
+    pub failures: u32,
+    /// How long the circuit stays open.
+    #[cfg_attr(feature = "serde", serde(with = "crate::config::duration_millis"))]
+    pub duration: Duration,
+}
+
+/// Decides from a response's status whether its request counts against a
+/// route's limits.
+///
+/// See [`RouteBuilder::count_if`](crate::RouteBuilder::count_if).
+#[derive(Clone)]
+pub struct StatusPredicate(Arc<dyn Fn(StatusCode) -> bool + Send + Sync>);
+
+impl StatusPredicate {
+    /// Wrap a predicate returning `true` for statuses that count.
+    pub fn new<F>(predicate: F) -> Self
+    where
+        F: Fn(StatusCode) -> bool + Send + Sync + 'static,
+    {
+        Self(Arc::new(predicate))
+    }
+
+    /// Returns `true` if a response with `status` counts against the limits.
+    #[must_use]
+    pub fn counts(&self, status: StatusCode) -> bool {
+        (self.0)(status)
+    }
+}
+
+impl fmt::Debug for StatusPredicate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("StatusPredicate")
+    }
+}
+
+/// Clones of the same predicate are equal; separately created predicates
+/// never are, even if they behave the same.
+impl PartialEq for StatusPredicate {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for StatusPredicate {}
+
+/// Computes the bucket key of a request for a route.
+///
+/// See [`RouteBuilder::key_fn`](crate::RouteBuilder::key_fn).
+#[derive(Clone)]
+pub struct KeyFn(Arc<KeyFnInner>);
+
+type KeyFnInner = dyn Fn(&Request) -> Option<String> + Send + Sync;
+
+impl KeyFn {
+    /// Wrap a function returning the bucket key of a request, or `None` for
+    /// the shared default bucket.
+    pub fn new<F>(key_fn: F) -> Self
+    where
+        F: Fn(&Request) -> Option<String> + Send + Sync + 'static,
+    {
+        Self(Arc::new(key_fn))
+    }
+
+    /// Returns the bucket key of `req`.
+    #[must_use]
+    pub fn key(&self, req: &Request) -> Option<String> {
+        (self.0)(req)
+    }
+}
+
+impl fmt::Debug for KeyFn {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("KeyFn")
+    }
+}
+
+/// Clones of the same function are equal; separately created functions
+/// never are, even if they compute the same keys.
+impl PartialEq for KeyFn {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for KeyFn {}
+
 /// A route definition that matches requests and applies rate limits.
-#[derive(Debug, Clone)]
+///
+/// Routes are usually configured through [`RateLimitBuilder`]. To build one on
+/// its own, e.g. for [`RateLimitMiddleware::from_routes`], use
+/// [`Route::builder`]. New fields may be added in minor releases, so routes
+/// cannot be constructed with a struct literal.
+///
+/// Routes compare equal if they are configured the same, e.g. to tell
+/// whether a new configuration changes anything before applying it with
+/// [`update_routes`]. Routes with a [`key_fn`](Self::key_fn) or
+/// [`count_if`](Self::count_if) are only equal if they share the same
+/// function, cloned from one route to the other.
+///
+/// [`RateLimitBuilder`]: crate::RateLimitBuilder
+/// [`RateLimitMiddleware::from_routes`]: crate::RateLimitMiddleware::from_routes
+/// [`update_routes`]: crate::RateLimitMiddleware::update_routes
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
 pub struct Route {
-    /// Optional host to match (e.g., "api.example.com").
-    pub host: Option<String>,
-    /// Optional HTTP method to match.
-    pub method: Option<Method>,
+    /// Hosts to match (e.g., "api.example.com"); a request matches if its host is
+    /// any of them or ends with any of [`host_suffixes`](Self::host_suffixes).
+    /// Matches all hosts if both are empty.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub hosts: BTreeSet<String>,
+    /// Domain suffixes to match (e.g., ".api.example.com"). A suffix matches
+    /// the hosts of its subdomains at a label boundary, whether or not it
+    /// starts with a dot, but not the domain itself.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub host_suffixes: BTreeSet<String>,
+    /// URL scheme to match (e.g., "https"), in lowercase. `None` matches any scheme.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub scheme: Option<String>,
+    /// HTTP methods to match. Empty matches any method.
+    #[cfg_attr(feature = "serde", serde(default, with = "crate::config::method_list"))]
+    pub methods: Vec<Method>,
     /// Path prefix to match (e.g., "/order"). Empty matches all paths.
+    #[cfg_attr(feature = "serde", serde(default))]
     pub path_prefix: String,
+    /// Glob pattern the whole path must match. Takes the place of `path_prefix`.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub path_pattern: Option<PathPattern>,
+    /// Path prefixes excluded from the route, compared like `path_prefix`. A
+    /// request whose path matches any of them does not match the route.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub excluded_paths: Vec<String>,
+    /// Whether the path prefix, path pattern, and excluded paths are compared
+    /// ignoring ASCII case.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub case_insensitive_path: bool,
+    /// Whether the path must equal `path_prefix`, up to a trailing slash,
+    /// rather than start with it, so that sub-resources don't match.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub exact_path: bool,
+    /// Label describing the route's paths in errors and statistics, such as
+    /// `/order/{id}`, in place of its prefix or pattern. Not used for
+    /// matching.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub template: Option<String>,
+    /// Query parameters that must all be present with the given values.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub query: Vec<(String, String)>,
+    /// Request headers that must all be present, with the given value if any.
+    #[cfg_attr(feature = "serde", serde(default, with = "crate::config::header_list"))]
+    pub headers: Vec<(HeaderName, Option<String>)>,
     /// Rate limits to apply (all must pass).
     pub limits: Vec<RateLimit>,
     /// Behavior when rate limit is exceeded.
+    #[cfg_attr(feature = "serde", serde(default))]
     pub on_limit: ThrottleBehavior,
+    /// Request header whose value selects a separate bucket per distinct value.
+    ///
+    /// Requests without the header share a single default bucket.
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, with = "crate::config::header_name_str")
+    )]
+    pub key_header: Option<HeaderName>,
+    /// Function computing the bucket key of each request, in place of
+    /// `key_header`. Only applies to requests sent through the reqwest
+    /// middleware. Not serialized.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub key_fn: Option<KeyFn>,
+    /// Name of a capture of `path_pattern` whose value selects a separate
+    /// bucket per distinct value, in place of `key_header`.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub key_capture: Option<String>,
+    /// Number of tokens each matching request consumes from every limit.
+    ///
+    /// Can be overridden per request with [`RequestWeight`].
+    #[cfg_attr(feature = "serde", serde(default = "crate::config::default_weight"))]
+    pub weight: u32,
+    /// Name of a group whose routes all draw from the same limit state.
+    ///
+    /// Routes in a group must be configured with identical limits and algorithm.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub shared_group: Option<String>,
+    /// Algorithm used to enforce every limit of this route.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub algorithm: Algorithm,
+    /// Maximum number of matching requests in flight at once. `None` is unlimited.
+    ///
+    /// A request holds its slot until the response has been received.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub max_concurrent: Option<usize>,
+    /// Maximum number of matching requests delayed at once. Requests that
+    /// would be delayed beyond it fail instead. `None` is unlimited.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub max_pending: Option<usize>,
+    /// Fail requests fast for a while after repeated throttling. `None`
+    /// disables the circuit breaker.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub cooldown: Option<Cooldown>,
+    /// Slow the route down when the server answers `429 Too Many Requests`,
+    /// and speed it back up on success. `None` keeps the configured rate.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub adaptive: Option<Adaptive>,
+    /// Whether the route was explicitly declared to match every request.
+    ///
+    /// Required for routes without host, scheme, method, path, query, or
+    /// header filters when the builder is [`strict`](crate::RateLimitBuilder::strict).
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub catch_all: bool,
+    /// Send a duplicate of a matching request if it has not completed within
+    /// this duration. `None` disables hedging.
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, with = "crate::config::option_duration_millis")
+    )]
+    pub hedge_after: Option<Duration>,
+    /// Only count requests whose response status satisfies this predicate;
+    /// others are refunded. `None` counts every request. Not serialized.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub count_if: Option<StatusPredicate>,
+    /// Size charged to bandwidth limits for a response without a
+    /// `Content-Length` header.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub default_response_bytes: u64,
+    /// Coalesce identical `GET` and `HEAD` requests in flight at the same time
+    /// into one upstream request whose response they all share.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub single_flight: bool,
+    /// Start newly created buckets partly drained, so that they fill up
+    /// gradually over this duration instead of admitting a full burst at once.
+    /// `None` starts buckets full.
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, with = "crate::config::option_duration_millis")
+    )]
+    pub ramp_up: Option<Duration>,
+    /// Rank of the route among matching routes, higher first, in place of the
+    /// order routes are configured in. See [`MatchMode`]. Defaults to 0.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub priority: i32,
+    /// Quota pools the route draws from in addition to its own limits, each
+    /// with the cost of a request. See
+    /// [`RateLimitBuilder::pool`](crate::RateLimitBuilder::pool).
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub pools: Vec<(String, u32)>,
+}
+
+/// Why a route is invalid, before it is known which route of a builder it is.
+#[derive(Debug, Error)]
+pub(crate) enum RouteError {
+    #[error("route must have at least one limit configured via .limit()")]
+    NoLimits,
+    #[error("{reason}")]
+    InvalidLimit { index: usize, reason: InvalidLimit },
+    #[error("{0}")]
+    Invalid(String),
+}
+
+impl RouteError {
+    /// The error for the route at `route` of a builder.
+    pub(crate) fn at(self, route: usize) -> BuildError {
+        match self {
+            Self::NoLimits => BuildError::NoLimits { route },
+            Self::InvalidLimit { index, reason } => BuildError::InvalidLimit {
+                route,
+                limit: index,
+                reason,
+            },
+            Self::Invalid(reason) => BuildError::InvalidRoute { route, reason },
+        }
+    }
 }
 
 impl Route {
+    /// Create a builder for a single route.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use route_ratelimit::{RateLimitMiddleware, Route};
+    /// use std::time::Duration;
+    ///
+    /// let route = Route::builder()
+    ///     .host("api.example.com")
+    ///     .path("/order")
+    ///     .limit(10, Duration::from_secs(1))
+    ///     .into_route();
+    /// let middleware = RateLimitMiddleware::from_routes(vec![route]);
+    /// ```
+    #[must_use]
+    pub fn builder() -> RouteBuilder {
+        RouteBuilder::default()
+    }
+
+    /// Hosts the route matches exactly.
+    #[must_use]
+    pub fn hosts(&self) -> &BTreeSet<String> {
+        &self.hosts
+    }
+
+    /// HTTP methods the route matches. Empty matches any method.
+    #[must_use]
+    pub fn methods(&self) -> &[Method] {
+        &self.methods
+    }
+
+    /// Path prefix the route matches. Empty matches all paths.
+    #[must_use]
+    pub fn path_prefix(&self) -> &str {
+        &self.path_prefix
+    }
+
+    /// Glob pattern the whole path must match, if any.
+    #[must_use]
+    pub fn path_pattern(&self) -> Option<&PathPattern> {
+        self.path_pattern.as_ref()
+    }
+
+    /// Rate limits applied to matching requests.
+    #[must_use]
+    pub fn limits(&self) -> &[RateLimit] {
+        &self.limits
+    }
+
+    /// Behavior when a limit is exceeded.
+    #[must_use]
+    pub fn on_limit(&self) -> ThrottleBehavior {
+        self.on_limit
+    }
+
+    /// The sustained rate, in requests per second, that the route's limits
+    /// permit together: the lowest [`rate_per_second`](RateLimit::rate_per_second)
+    /// of its request limits, divided by the route's weight.
+    ///
+    /// Bursts, adaptive backoff, bandwidth limits, and other routes that
+    /// apply to the same requests are not taken into account. Returns
+    /// infinity if the route only has bandwidth limits.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use route_ratelimit::Route;
+    /// use std::time::Duration;
+    ///
+    /// let route = Route::builder()
+    ///     .limit(3500, Duration::from_secs(10))
+    ///     .limit(36_000, Duration::from_secs(600))
+    ///     .into_route();
+    /// assert_eq!(route.sustained_rate(), 60.0);
+    /// ```
+    #[must_use]
+    pub fn sustained_rate(&self) -> f64 {
+        self.limits
+            .iter()
+            .filter(|limit| limit.unit == LimitUnit::Requests)
+            .map(RateLimit::rate_per_second)
+            .fold(f64::INFINITY, f64::min)
+            / f64::from(self.weight)
+    }
+
+    /// Check the invariants enforced by the route builders.
+    ///
+    /// Fails if the route has no limits, any limit is invalid, both a path
+    /// prefix and a path pattern are set, or the weight or concurrency limit
+    /// is zero, among others.
+    pub(crate) fn check(&self) -> Result<(), RouteError> {
+        /// Fail with the formatted reason unless `cond` holds.
+        macro_rules! ensure {
+            ($cond:expr, $($reason:tt)+) => {
+                if !$cond {
+                    return Err(RouteError::Invalid(format!($($reason)+)));
+                }
+            };
+        }
+
+        if self.limits.is_empty() {
+            return Err(RouteError::NoLimits);
+        }
+        for (index, limit) in self.limits.iter().enumerate() {
+            limit
+                .check()
+                .map_err(|reason| RouteError::InvalidLimit { index, reason })?;
+        }
+        ensure!(
+            self.key_header.is_none() || self.key_fn.is_none(),
+            "key_by_header and key_fn are mutually exclusive"
+        );
+        if let Some(capture) = &self.key_capture {
+            ensure!(
+                self.key_header.is_none() && self.key_fn.is_none(),
+                "path_pattern_key cannot be combined with key_by_header or key_fn"
+            );
+            ensure!(
+                self.path_pattern
+                    .as_ref()
+                    .is_some_and(|pattern| pattern.has_capture(capture)),
+                "key capture {{{capture}}} is not in the route's path pattern"
+            );
+        }
+        ensure!(
+            self.key_header.is_some()
+                || self.key_fn.is_some()
+                || self.key_capture.is_some()
+                || self.limits.iter().all(|limit| !limit.per_key),
+            "per-key limits require a key, set with key_by_header, key_fn, or path_pattern_key"
+        );
+        ensure!(
+            self.path_prefix.is_empty() || self.path_pattern.is_none(),
+            "path and path_pattern are mutually exclusive"
+        );
+        ensure!(
+            !self.exact_path || !self.path_prefix.is_empty(),
+            "exact_path requires a path prefix"
+        );
+        ensure!(
+            self.host_suffixes
+                .iter()
+                .all(|suffix| !suffix.trim_start_matches('.').is_empty()),
+            "host suffix must not be empty"
+        );
+        ensure!(
+            self.excluded_paths.iter().all(|path| !path.is_empty()),
+            "excluded path must not be empty"
+        );
+        ensure!(
+            self.algorithm != Algorithm::SlidingWindowLog
+                || self.limits.iter().all(|limit| limit.burst.is_none()),
+            "the sliding window log algorithm does not support a separate burst"
+        );
+        ensure!(
+            self.algorithm != Algorithm::SlidingWindowLog
+                || self
+                    .limits
+                    .iter()
+                    .all(|limit| limit.unit == LimitUnit::Requests),
+            "the sliding window log algorithm does not support bandwidth limits"
+        );
+        ensure!(self.weight > 0, "weight must be greater than 0");
+        for (index, (pool, cost)) in self.pools.iter().enumerate() {
+            ensure!(*cost > 0, "pool cost must be greater than 0");
+            ensure!(
+                self.pools[..index].iter().all(|(other, _)| other != pool),
+                "route draws from pool {pool:?} more than once"
+            );
+        }
+        ensure!(
+            self.max_concurrent != Some(0),
+            "max_concurrent must be greater than 0"
+        );
+        ensure!(
+            self.max_pending != Some(0),
+            "max_pending must be greater than 0"
+        );
+        if let Some(cooldown) = self.cooldown {
+            ensure!(
+                cooldown.failures > 0,
+                "cooldown failures must be greater than 0"
+            );
+            ensure!(
+                !cooldown.duration.is_zero(),
+                "cooldown duration must be greater than 0"
+            );
+        }
+        ensure!(
+            self.hedge_after != Some(Duration::ZERO),
+            "hedge_after must be greater than 0"
+        );
+        if let Some(ramp_up) = self.ramp_up {
+            ensure!(!ramp_up.is_zero(), "ramp_up must be greater than 0");
+            ensure!(
+                self.algorithm != Algorithm::SlidingWindowLog,
+                "the sliding window log algorithm does not support ramp_up"
+            );
+        }
+        if let Some(adaptive) = self.adaptive {
+            ensure!(adaptive.is_valid(), "{ADAPTIVE_RATES}");
+            ensure!(
+                self.limits
+                    .iter()
+                    .all(|limit| limit.window.as_micros() as f64 / adaptive.min_rate
+                        <= MAX_WINDOW_TICKS as f64),
+                "adaptive min_rate stretches a window beyond u64::MAX / 2 microseconds"
+            );
+        }
+        Ok(())
+    }
+
+    /// # Panics
+    ///
+    /// Panics unless the route passes [`check`](Self::check).
+    pub(crate) fn validate(&self) {
+        if let Err(err) = self.check() {
+            panic!("{err}");
+        }
+    }
+
     /// Returns `true` if this route has no filters (matches all requests).
     ///
-    /// A catch-all route has no host, no method, and no path prefix constraints.
-    #[cfg(feature = "tracing")]
+    /// A catch-all route has no host, scheme, method, path, query, or header
+    /// constraints.
     #[inline]
     pub(crate) fn is_catch_all(&self) -> bool {
-        self.host.is_none() && self.method.is_none() && self.path_prefix.is_empty()
+        self.hosts.is_empty()
+            && self.host_suffixes.is_empty()
+            && self.scheme.is_none()
+            && self.methods.is_empty()
+            && self.path_prefix.is_empty()
+            && self.path_pattern.is_none()
+            && self.excluded_paths.is_empty()
+            && self.query.is_empty()
+            && self.headers.is_empty()
+    }
+
+    /// The behavior when `limit`, one of this route's limits, is exceeded.
+    #[inline]
+    pub(crate) fn behavior(&self, limit: &RateLimit) -> ThrottleBehavior {
+        limit.on_limit.unwrap_or(self.on_limit)
+    }
+
+    /// Number of limits a request on this route is checked against: its own,
+    /// then one for each pool it draws from.
+    #[inline]
+    pub(crate) fn limit_count(&self) -> usize {
+        self.limits.len() + self.pools.len()
+    }
+
+    /// Cost of a request on this route to `pool`, or 0 if it doesn't draw
+    /// from it.
+    pub(crate) fn pool_cost(&self, pool: &str) -> u32 {
+        self.pools
+            .iter()
+            .find(|(name, _)| name == pool)
+            .map_or(0, |&(_, cost)| cost)
+    }
+
+    /// The scope of this route's limit state, given its index.
+    #[inline]
+    pub(crate) fn limit_scope(&self, route_index: usize) -> LimitScope {
+        match &self.shared_group {
+            Some(group) => LimitScope::Group(group.clone()),
+            None => LimitScope::Route(route_index),
+        }
+    }
+
+    /// How specific this route is, for [`MatchMode::MostSpecific`]: greater
+    /// is more specific.
+    pub(crate) fn specificity(&self) -> (usize, bool, bool, bool) {
+        let path = match &self.path_pattern {
+            Some(pattern) => pattern.literal_len(),
+            None => self.path_prefix.len(),
+        };
+        (
+            path,
+            self.exact_path,
+            !self.methods.is_empty(),
+            !self.hosts.is_empty() || !self.host_suffixes.is_empty(),
+        )
+    }
+
+    /// Describe the paths this route matches: its template, its pattern, its
+    /// prefix, or `*`.
+    pub(crate) fn path_description(&self) -> String {
+        if let Some(template) = &self.template {
+            return template.clone();
+        }
+        match &self.path_pattern {
+            Some(pattern) => pattern.to_string(),
+            None if self.path_prefix.is_empty() => "*".to_string(),
+            None => self.path_prefix.clone(),
+        }
+    }
+
+    /// Comma-separated hosts and host suffixes this route matches, with
+    /// suffixes written as `*.example.com`, or `*` if it matches any host.
+    pub(crate) fn host_description(&self) -> String {
+        if self.hosts.is_empty() && self.host_suffixes.is_empty() {
+            return "*".to_string();
+        }
+        let suffixes = self.host_suffixes.iter().map(|suffix| {
+            let suffix = suffix.strip_prefix('.').unwrap_or(suffix);
+            format!("*.{suffix}")
+        });
+        self.hosts
+            .iter()
+            .cloned()
+            .chain(suffixes)
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+
+    /// Stable description of what this route matches, independent of its
+    /// position among the routes.
+    pub(crate) fn identity(&self) -> String {
+        let method = if self.methods.is_empty() {
+            "*".to_string()
+        } else {
+            let methods: Vec<_> = self.methods.iter().map(Method::as_str).collect();
+            methods.join(",")
+        };
+        let scheme = self.scheme.as_deref().unwrap_or("*");
+        let mut identity = format!(
+            "{method} {scheme}://{}{}",
+            self.host_description(),
+            self.path_description()
+        );
+        for (index, (name, value)) in self.query.iter().enumerate() {
+            let separator = if index == 0 { '?' } else { '&' };
+            identity.push_str(&format!("{separator}{name}={value}"));
+        }
+        for (name, value) in &self.headers {
+            match value {
+                Some(value) => identity.push_str(&format!(" [{name}={value}]")),
+                None => identity.push_str(&format!(" [{name}]")),
+            }
+        }
+        for excluded in &self.excluded_paths {
+            identity.push_str(&format!(" !{excluded}"));
+        }
+        if self.exact_path {
+            identity.push_str(" (exact)");
+        }
+        if self.case_insensitive_path {
+            identity.push_str(" (case-insensitive)");
+        }
+        identity
+    }
+
+    /// Extract the per-key bucket discriminator from a request, if any.
+    #[inline]
+    pub(crate) fn bucket_key(&self, req: &RequestParts<'_>) -> Option<String> {
+        if let Some(key_fn) = &self.key_fn {
+            return key_fn.key(req.request?);
+        }
+        if let Some(capture) = &self.key_capture {
+            let pattern = self.path_pattern.as_ref()?;
+            let key = pattern.capture_with_case(req.path, capture, self.case_insensitive_path)?;
+            return Some(key.to_owned());
+        }
+        let header = self.key_header.as_ref()?;
+        let value = req.headers.get(header)?;
+        Some(String::from_utf8_lossy(value.as_bytes()).into_owned())
+    }
+
+    /// The bucket a request with `bucket_key` uses for `limit`, one of this
+    /// route's limits.
+    ///
+    /// Only per-key limits are keyed if the route has any; otherwise every
+    /// limit is.
+    #[inline]
+    pub(crate) fn limit_key(
+        &self,
+        limit: &RateLimit,
+        bucket_key: &Option<String>,
+    ) -> Option<String> {
+        if limit.per_key || !self.limits.iter().any(|limit| limit.per_key) {
+            bucket_key.clone()
+        } else {
+            None
+        }
     }
 
     /// Check if this route matches a request.
+    ///
+    /// A request without a known scheme never matches a scheme filter, and one
+    /// without a host (such as a `data:` URL) never matches a host filter.
     #[inline]
-    pub(crate) fn matches(&self, req: &Request) -> bool {
-        // Check host
-        if let Some(ref host) = self.host {
-            if let Some(req_host) = req.url().host_str() {
-                if req_host != host {
-                    return false;
-                }
-            } else {
-                return false;
+    pub(crate) fn matches(&self, req: &RequestParts<'_>) -> bool {
+        self.matches_target(req) && self.matches_headers(req.headers)
+    }
+
+    /// Check if this route matches a request, ignoring header filters.
+    #[inline]
+    pub(crate) fn matches_target(&self, req: &RequestParts<'_>) -> bool {
+        self.matches_scheme(req.scheme)
+            && self.matches_components(req.host, req.method, req.path, req.query)
+    }
+
+    /// Check the header filters. Any value of a repeated header may satisfy
+    /// a filter.
+    #[inline]
+    pub(crate) fn matches_headers(&self, headers: &HeaderMap) -> bool {
+        self.headers.iter().all(|(name, expected)| {
+            let mut values = headers.get_all(name).iter();
+            match expected {
+                Some(expected) => values.any(|value| value.as_bytes() == expected.as_bytes()),
+                None => values.next().is_some(),
             }
+        })
+    }
+
+    #[inline]
+    fn matches_scheme(&self, req_scheme: Option<&str>) -> bool {
+        match &self.scheme {
+            Some(scheme) => {
+                req_scheme.is_some_and(|req_scheme| req_scheme.eq_ignore_ascii_case(scheme))
+            }
+            None => true,
+        }
+    }
+
+    /// Check the host against the exact hosts first, then the suffixes.
+    ///
+    /// IPv6 addresses are compared without brackets, as configured hosts are
+    /// stored.
+    #[inline]
+    fn matches_host(&self, req_host: Option<&str>) -> bool {
+        if self.hosts.is_empty() && self.host_suffixes.is_empty() {
+            return true;
+        }
+        req_host.map(unbracket).is_some_and(|host| {
+            self.hosts.contains(host)
+                || self
+                    .host_suffixes
+                    .iter()
+                    .any(|suffix| host_has_suffix(host, suffix))
+        })
+    }
+
+    /// Check if this route matches a request described by its components.
+    ///
+    /// The scheme is not part of the components, so scheme filters are ignored.
+    pub(crate) fn matches_components(
+        &self,
+        req_host: Option<&str>,
+        req_method: &Method,
+        path: &str,
+        query: Option<&str>,
+    ) -> bool {
+        // Check host
+        if !self.matches_host(req_host) {
+            return false;
         }
 
         // Check method
-        if let Some(ref method) = self.method {
-            if req.method() != method {
+        if !self.methods.is_empty() && !self.methods.contains(req_method) {
+            return false;
+        }
+
+        // Check query parameters
+        if !self.query.is_empty() {
+            let query = query.unwrap_or_default();
+            let all_present = self.query.iter().all(|(key, value)| {
+                form_urlencoded::parse(query.as_bytes()).any(|(k, v)| k == *key && v == *value)
+            });
+            if !all_present {
                 return false;
             }
         }
 
+        // Check excluded paths
+        if self
+            .excluded_paths
+            .iter()
+            .any(|excluded| self.has_path_prefix(path, excluded))
+        {
+            return false;
+        }
+
+        // Check path pattern
+        if let Some(ref pattern) = self.path_pattern {
+            return pattern.matches_with_case(path, self.case_insensitive_path);
+        }
+
         // Check path prefix
-        // Path prefix matching uses path segment boundaries:
-        // - "/order" matches "/order", "/order/", "/order/123"
-        // - "/order" does NOT match "/orders" or "/order-test"
-        if !self.path_prefix.is_empty() {
-            let path = req.url().path();
-            if !path.starts_with(&self.path_prefix) {
-                return false;
-            }
-            // Ensure we're matching at a path segment boundary
-            let remaining = &path[self.path_prefix.len()..];
-            if !remaining.is_empty() && !remaining.starts_with('/') {
-                return false;
-            }
+        if self.exact_path {
+            return self.is_exact_path(path);
         }
+        self.path_prefix.is_empty() || self.has_path_prefix(path, &self.path_prefix)
+    }
+
+    /// Whether `path` equals the route's path prefix, ignoring a trailing
+    /// slash on either, and case if the route is case-insensitive.
+    #[inline]
+    fn is_exact_path(&self, path: &str) -> bool {
+        let path = path.strip_suffix('/').unwrap_or(path);
+        let exact = self
+            .path_prefix
+            .strip_suffix('/')
+            .unwrap_or(&self.path_prefix);
+        if self.case_insensitive_path {
+            path.eq_ignore_ascii_case(exact)
+        } else {
+            path == exact
+        }
+    }
+
+    /// Compare `path` with `prefix` like [`has_path_prefix`], ignoring case if
+    /// the route is case-insensitive.
+    #[inline]
+    fn has_path_prefix(&self, path: &str, prefix: &str) -> bool {
+        if self.case_insensitive_path {
+            has_path_prefix_ignore_case(path, prefix)
+        } else {
+            has_path_prefix(path, prefix)
+        }
+    }
+}
+
+/// Per-request override of a route's [`weight`](Route::weight).
+///
+/// Insert this as a request extension to make a single request consume more
+/// (or fewer) tokens than the route's configured weight, e.g. a batch request
+/// that counts as one unit per item.
+///
+/// A weight larger than a limit's burst capacity is clamped to that capacity:
+/// the request waits (or errors, per [`ThrottleBehavior`]) until the limit is
+/// fully recovered and then drains it entirely. A weight of 0 counts as 1,
+/// like the smallest weight a route can be configured with, so that no request
+/// escapes its limits.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use route_ratelimit::{RateLimitMiddleware, RequestWeight};
+/// use reqwest_middleware::ClientBuilder;
+/// use std::time::Duration;
+///
+/// # async fn example() {
+/// let client = ClientBuilder::new(reqwest::Client::new())
+///     .with(RateLimitMiddleware::builder()
+///         .route(|r| r.path("/orders").limit(100, Duration::from_secs(10)))
+///         .build())
+///     .build();
+///
+/// // This batch of 50 orders counts as 50 requests
+/// client
+///     .post("https://api.example.com/orders")
+///     .with_extension(RequestWeight(50))
+///     .send()
+///     .await
+///     .unwrap();
+/// # }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RequestWeight(pub u32);
+
+impl RequestWeight {
+    /// The weight overriding the route's for a request with `extensions`, if
+    /// any, at least 1.
+    pub(crate) fn of(extensions: &http::Extensions) -> Option<u32> {
+        extensions.get::<Self>().map(|weight| weight.0.max(1))
+    }
+}
+
+/// Priority of a request waiting on a rate limit.
+///
+/// Insert this as a request extension to let a request skip ahead of other
+/// requests delayed on the same limit. When several requests are waiting for
+/// a route with [`ThrottleBehavior::Delay`], the one with the highest priority
+/// is admitted first, and requests of equal priority are admitted in the
+/// order they started waiting. Requests without this extension have priority 0.
+///
+/// Priority only orders waiting requests. It does not make a request exempt
+/// from the limit, and has no effect on routes that error instead of delaying.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use route_ratelimit::{Priority, RateLimitMiddleware};
+/// use reqwest_middleware::ClientBuilder;
+/// use std::time::Duration;
+///
+/// # async fn example() {
+/// let client = ClientBuilder::new(reqwest::Client::new())
+///     .with(RateLimitMiddleware::builder()
+///         .route(|r| r.limit(10, Duration::from_secs(1)))
+///         .build())
+///     .build();
+///
+/// // Cancel orders ahead of any queued bulk traffic
+/// client
+///     .delete("https://api.example.com/orders/42")
+///     .with_extension(Priority(10))
+///     .send()
+///     .await
+///     .unwrap();
+/// # }
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Priority(pub u8);
 
-        true
+/// Marker exempting a request from rate limiting.
+///
+/// Insert this as a request extension for calls that must never be held
+/// back, such as health checks or administrative overrides. The request is
+/// passed straight on, even if it matches a route, and does not count
+/// against any limit.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use route_ratelimit::{BypassRateLimit, RateLimitMiddleware};
+/// use reqwest_middleware::ClientBuilder;
+/// use std::time::Duration;
+///
+/// # async fn example() {
+/// let client = ClientBuilder::new(reqwest::Client::new())
+///     .with(RateLimitMiddleware::builder()
+///         .route(|r| r.limit(10, Duration::from_secs(1)))
+///         .build())
+///     .build();
+///
+/// client
+///     .get("https://api.example.com/health")
+///     .with_extension(BypassRateLimit)
+///     .send()
+///     .await
+///     .unwrap();
+/// # }
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct BypassRateLimit;
+
+/// How a route relates to a request, as reported by
+/// [`RateLimitMiddleware::explain`](crate::RateLimitMiddleware::explain).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RouteMatch {
+    /// Position of the route among the configured routes.
+    pub route_index: usize,
+    /// Whether the route applies to the request: it matches, and under
+    /// [`MatchMode::MostSpecific`] or [`MatchMode::FirstMatch`] it is the one
+    /// matching route selected.
+    pub matched: bool,
+    /// For a matching route, each of its limits with the number of requests
+    /// that could be made right now before it is hit. Empty otherwise.
+    pub remaining: Vec<(RateLimit, u32)>,
+}
+
+/// A point-in-time view of one active bucket, as reported by
+/// [`RateLimitMiddleware::stats`](crate::RateLimitMiddleware::stats).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BucketStat {
+    /// Position of the bucket's route among the configured routes. For a
+    /// shared group, the first route of the group.
+    pub route_index: usize,
+    /// Hosts the route matches, comma-separated, or `*` for any host.
+    pub host: String,
+    /// Methods the route matches; empty for any method.
+    pub methods: Vec<Method>,
+    /// [Template](Route::template), path prefix, or pattern of the route, or
+    /// `*` for any path.
+    pub path: String,
+    /// Position of the bucket's limit within its route.
+    pub limit_index: usize,
+    /// Per-key bucket discriminator, `None` for the shared default bucket.
+    pub key: Option<String>,
+    /// Number of requests that could be made right now before the limit is
+    /// hit.
+    pub remaining: u32,
+    /// Time until the bucket has fully recovered its burst capacity.
+    pub recovers_in: Duration,
+}
+
+/// Check whether `path` starts with `prefix` at a path segment boundary:
+/// - "/order" matches "/order", "/order/", "/order/123"
+/// - "/order" does NOT match "/orders" or "/order-test"
+pub(crate) fn has_path_prefix(path: &str, prefix: &str) -> bool {
+    path.strip_prefix(prefix)
+        .is_some_and(|remaining| remaining.is_empty() || remaining.starts_with('/'))
+}
+
+/// Like [`has_path_prefix`], ignoring ASCII case.
+pub(crate) fn has_path_prefix_ignore_case(path: &str, prefix: &str) -> bool {
+    match path.get(..prefix.len()) {
+        Some(start) if start.eq_ignore_ascii_case(prefix) => {
+            let remaining = &path[prefix.len()..];
+            remaining.is_empty() || remaining.starts_with('/')
+        }
+        _ => false,
     }
 }
 
+/// Check whether `host` is a subdomain of `suffix`, with or without a leading
+/// dot, so that `.example.com` and `example.com` match `api.example.com` but
+/// not `example.com` or `myexample.com`.
+pub(crate) fn host_has_suffix(host: &str, suffix: &str) -> bool {
+    let suffix = suffix.strip_prefix('.').unwrap_or(suffix);
+    host.strip_suffix(suffix)
+        .and_then(|rest| rest.strip_suffix('.'))
+        .is_some_and(|label| !label.is_empty())
+}
+
+/// What a piece of rate limit state belongs to.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) enum LimitScope {
+    /// The route at this index.
+    Route(usize),
+    /// Every route in the named shared group.
+    Group(String),
+    /// Every route drawing from the named quota pool.
+    Pool(String),
+}
+
 /// Unique key for a route's rate limit state.
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub(crate) struct RouteKey {
-    pub route_index: usize,
+    pub scope: LimitScope,
     pub limit_index: usize,
+    /// Per-key bucket discriminator, `None` for the shared default bucket.
+    pub key: Option<String>,
+}
+
+impl RouteKey {
+    /// The key of the quota pool named `pool`, which has a single bucket.
+    pub(crate) fn pool(pool: &str) -> Self {
+        Self {
+            scope: LimitScope::Pool(pool.to_owned()),
+            limit_index: 0,
+            key: None,
+        }
+    }
 }
@@ -0,0 +1,271 @@
+//! Static analysis of route configurations for likely mistakes.
+
+use thiserror::Error;
+
+use crate::types::{MatchMode, RateLimit, Route, has_path_prefix, has_path_prefix_ignore_case};
+
+/// A likely mistake in a route configuration, reported by
+/// [`RateLimitBuilder::validate`](crate::RateLimitBuilder::validate).
+///
+/// None of these stop the middleware from working; they point at routes or
+/// limits that have no effect.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+#[non_exhaustive]
+pub enum ConfigWarning {
+    /// No routes are configured, so no request is rate limited.
+    #[error("no routes are configured, so no request is rate limited")]
+    NoRoutes,
+    /// Two routes match exactly the same requests.
+    #[error("route {duplicate} matches the same requests as route {first}")]
+    DuplicateRoute {
+        /// Index of the first of the two routes.
+        first: usize,
+        /// Index of the later route.
+        duplicate: usize,
+    },
+    /// A limit never throttles a request, because a stricter limit of a more
+    /// general route applies to every request it matches.
+    #[error(
+        "limit {limit} of route {route} is never reached, since limit {general_limit} \
+         of route {general_route} is stricter and applies to all of its requests"
+    )]
+    ShadowedLimit {
+        /// Index of the route with the shadowed limit.
+        route: usize,
+        /// Index of the shadowed limit within its route.
+        limit: usize,
+        /// Index of the more general route.
+        general_route: usize,
+        /// Index of the stricter limit within the general route.
+        general_limit: usize,
+    },
+}
+
+/// Analyze `routes`, applied to requests according to `mode`, for duplicates
+/// and shadowed limits.
+pub(crate) fn check(routes: &[Route], mode: MatchMode) -> Vec<ConfigWarning> {
+    if routes.is_empty() {
+        return vec![ConfigWarning::NoRoutes];
+    }
+
+    let mut warnings = Vec::new();
+    let identities: Vec<String> = routes.iter().map(Route::identity).collect();
+    for (duplicate, identity) in identities.iter().enumerate() {
+        if let Some(first) = identities[..duplicate].iter().position(|i| i == identity) {
+            warnings.push(ConfigWarning::DuplicateRoute { first, duplicate });
+        }
+    }
+
+    // Only one route applies to each request, so none shadows another
+    if mode != MatchMode::All {
+        return warnings;
+    }
+    for (route_index, route) in routes.iter().enumerate() {
+        for (limit_index, limit) in route.limits.iter().enumerate() {
+            let shadowing = routes
+                .iter()
+                .enumerate()
+                // Duplicates are already reported
+                .filter(|&(general_index, general)| {
+                    identities[general_index] != identities[route_index] && covers(general, route)
+                })
+                .find_map(|(general_index, general)| {
+                    general
+                        .limits
+                        .iter()
+                        .position(|general_limit| {
+                            same_buckets(general, general_limit, route, limit)
+                                && at_most(
+                                    general_limit,
+                                    general_limit.cost(general.weight),
+                                    limit,
+                                    limit.cost(route.weight),
+                                )
+                        })
+                        .map(|general_limit| (general_index, general_limit))
+                });
+            if let Some((general_route, general_limit)) = shadowing {
+                warnings.push(ConfigWarning::ShadowedLimit {
+                    route: route_index,
+                    limit: limit_index,
+                    general_route,
+                    general_limit,
+                });
+            }
+        }
+    }
+    warnings
+}
+
+/// Whether `general` matches every request that `specific` matches.
+///
+/// Conservative: may return `false` for routes that do cover each other, but
+/// never `true` for routes that don't.
+fn covers(general: &Route, specific: &Route) -> bool {
+    let hosts = if general.hosts.is_empty() && general.host_suffixes.is_empty() {
+        true
+    } else {
+        specific.host_suffixes.is_empty()
+            && !specific.hosts.is_empty()
+            && specific.hosts.is_subset(&general.hosts)
+    };
+    let scheme = general.scheme.is_none() || general.scheme == specific.scheme;
+    let methods = general.methods.is_empty()
+        || (!specific.methods.is_empty()
+            && specific
+                .methods
+                .iter()
+                .all(|method| general.methods.contains(method)));
+    // An exact path only covers the same exact path
+    let exact = !general.exact_path
+        || (specific.exact_path && specific.path_prefix.len() == general.path_prefix.len());
+    let path = general.path_pattern.is_none()
+        && general.excluded_paths.is_empty()
+        && (general.path_prefix.is_empty()
+            || (specific.path_pattern.is_none()
+                && exact
+                && if general.case_insensitive_path {
+                    has_path_prefix_ignore_case(&specific.path_prefix, &general.path_prefix)
+                } else {
+                    !specific.case_insensitive_path
+                        && has_path_prefix(&specific.path_prefix, &general.path_prefix)
+                }));
+    let query = general
+        .query
+        .iter()
+        .all(|pair| specific.query.contains(pair));
+    let headers = general
+        .headers
+        .iter()
+        .all(|header| specific.headers.contains(header));
+
+    hosts && scheme && methods && path && query && headers
+}
+
+/// Whether the two limits count the same requests in the same buckets, so
+/// one can make the other redundant.
+fn same_buckets(
+    general: &Route,
+    general_limit: &RateLimit,
+    route: &Route,
+    limit: &RateLimit,
+) -> bool {
+    // Key functions and path captures cannot be compared, nor can requests
+    // with bytes
+    let uncomparable_key = |route: &Route| route.key_fn.is_some() || route.key_capture.is_some();
+    if uncomparable_key(general) || uncomparable_key(route) || general_limit.unit != limit.unit {
+        return false;
+    }
+    let keyed = |route: &Route, limit: &RateLimit| {
+        route.key_header.is_some()
+            && (limit.per_key || !route.limits.iter().any(|limit| limit.per_key))
+    };
+    match (keyed(general, general_limit), keyed(route, limit)) {
+        (false, false) => true,
+        (true, true) => general.key_header == route.key_header,
+        _ => false,
+    }
+}
+
+/// Whether `general` never admits more requests than `limit` over any
+/// interval: both its burst and its sustained rate are at most as large,
+/// counting requests by their route's weight.
+fn at_most(general: &RateLimit, general_weight: u32, limit: &RateLimit, weight: u32) -> bool {
+    let capacity = |limit: &RateLimit| u128::from(limit.burst.unwrap_or(limit.requests));
+    let burst =
+        capacity(general) * u128::from(weight) <= capacity(limit) * u128::from(general_weight);
+    let rate = u128::from(general.requests) * u128::from(weight) * limit.window.as_nanos()
+        <= u128::from(limit.requests) * u128::from(general_weight) * general.window.as_nanos();
+    burst && rate
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::RateLimitMiddleware;
+    use http::Method;
+    use std::time::Duration;
+
+    #[test]
+    fn test_no_routes() {
+        assert_eq!(check(&[], MatchMode::All), [ConfigWarning::NoRoutes]);
+    }
+
+    #[test]
+    fn test_duplicate_routes() {
+        let routes = RateLimitMiddleware::builder()
+            .host("api.example.com", |host| {
+                host.route(|r| r.path("/book").limit(100, Duration::from_secs(10)))
+                    .route(|r| r.path("/price").limit(100, Duration::from_secs(10)))
+                    .route(|r| r.path("/book").limit(50, Duration::from_secs(1)))
+            })
+            .into_routes();
+
+        assert_eq!(
+            check(&routes, MatchMode::All),
+            [ConfigWarning::DuplicateRoute {
+                first: 0,
+                duplicate: 2
+            }]
+        );
+    }
+
+    #[test]
+    fn test_looser_specific_limit_is_shadowed() {
+        let routes = RateLimitMiddleware::builder()
+            .host("api.example.com", |host| {
+                host.route(|r| r.limit(1000, Duration::from_secs(10)))
+                    // Tighter than the general limit
+                    .route(|r| r.path("/book").limit(100, Duration::from_secs(10)))
+                    // Looser in both burst and rate
+                    .route(|r| {
+                        r.method(Method::POST)
+                            .path("/order")
+                            .limit(2000, Duration::from_secs(10))
+                            .limit(10_000, Duration::from_secs(600))
+                    })
+            })
+            .into_routes();
+
+        assert_eq!(
+            check(&routes, MatchMode::All),
+            [ConfigWarning::ShadowedLimit {
+                route: 2,
+                limit: 0,
+                general_route: 0,
+                general_limit: 0
+            }]
+        );
+        // Only one route applies to orders
+        assert!(check(&routes, MatchMode::MostSpecific).is_empty());
+        assert!(check(&routes, MatchMode::FirstMatch).is_empty());
+    }
+
+    #[test]
+    fn test_limits_of_unrelated_routes_are_not_shadowed() {
+        let routes = RateLimitMiddleware::builder()
+            // Different hosts, and a larger burst at a lower rate
+            .route(|r| r.host("a.example.com").limit(10, Duration::from_secs(1)))
+            .route(|r| r.host("b.example.com").limit(50, Duration::from_secs(1)))
+            .route(|r| r.limit(100, Duration::from_secs(10)))
+            .route(|r| r.path("/bulk").limit(1000, Duration::from_secs(1000)))
+            .into_routes();
+
+        assert!(check(&routes, MatchMode::All).is_empty());
+    }
+
+    #[test]
+    fn test_weight_is_taken_into_account() {
+        let routes = RateLimitMiddleware::builder()
+            .route(|r| r.limit(100, Duration::from_secs(10)))
+            // Each request counts five times, so this allows 40 requests
+            .route(|r| {
+                r.path("/heavy")
+                    .weight(5)
+                    .limit(200, Duration::from_secs(10))
+            })
+            .into_routes();
+
+        assert!(check(&routes, MatchMode::All).is_empty());
+    }
+}
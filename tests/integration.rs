@@ -5,11 +5,17 @@
 
 use http::Method;
 use reqwest_middleware::ClientBuilder;
-use route_ratelimit::{RateLimitMiddleware, ThrottleBehavior};
+use route_ratelimit::testing::{self, FixedJitter, TestClock};
+use route_ratelimit::{
+    Algorithm, BuildError, BypassRateLimit, Clock, JitterSource, MatchMode, Priority, RateLimit,
+    RateLimitError, RateLimitMiddleware, RateLimitResultExt, RequestWeight, Route,
+    ThrottleBehavior, ThrottleEvent, ThrottleOutcome, UnmatchedBehavior, keys,
+};
 use std::sync::Arc;
+use std::sync::Mutex;
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::time::{Duration, Instant};
-use wiremock::matchers::{method, path};
+use wiremock::matchers::{header, method, path, query_param};
 use wiremock::{Mock, MockServer, ResponseTemplate};
 
 /// Helper to create a mock server with a simple OK response.
@@ -115,6 +121,236 @@ async fn test_error_includes_retry_duration() {
     );
 }
 
+#[tokio::test]
+async fn test_error_identifies_exceeded_limit() {
+    let server = setup_mock_server().await;
+
+    let middleware = RateLimitMiddleware::builder()
+        .host("127.0.0.1", |host| {
+            host.route(|r| {
+                r.method(Method::POST)
+                    .path("/order")
+                    .limit(10, Duration::from_secs(10))
+                    .limit(1, Duration::from_secs(10))
+                    .on_limit(ThrottleBehavior::Error)
+            })
+        })
+        .build();
+
+    let client = ClientBuilder::new(reqwest::Client::new())
+        .with(middleware)
+        .build();
+
+    let url = format!("{}/order", server.uri());
+    client.post(&url).send().await.unwrap();
+
+    let err = client.post(&url).send().await.unwrap_err();
+    let err_str = err.to_string();
+    assert!(
+        err_str.contains("rate limit exceeded on 127.0.0.1 /order (limit 2/2), retry after"),
+        "Error should identify the exceeded limit: {err_str}"
+    );
+
+    let reqwest_middleware::Error::Middleware(err) = err else {
+        panic!("expected a middleware error");
+    };
+    let err = err.downcast_ref::<RateLimitError>().unwrap();
+    let retry_after = err.retry_after().unwrap();
+    assert!(retry_after > Duration::from_secs(9), "{retry_after:?}");
+}
+
+#[tokio::test]
+async fn test_error_reports_retry_instant() {
+    let server = setup_mock_server().await;
+
+    let clock = Arc::new(TestClock::new());
+    let middleware = RateLimitMiddleware::builder()
+        .jitter(0.0)
+        .with_clock(clock.clone())
+        .route(|r| {
+            r.limit(1, Duration::from_secs(10))
+                .on_limit(ThrottleBehavior::Error)
+        })
+        .build();
+
+    let client = ClientBuilder::new(reqwest::Client::new())
+        .with(middleware)
+        .build();
+
+    // The retry instant follows the middleware's clock, not the wall clock
+    client.get(server.uri()).send().await.unwrap();
+    clock.advance(Duration::from_secs(4));
+    let before = Instant::now();
+    let err = client.get(server.uri()).send().await.unwrap_err();
+    let after = Instant::now();
+
+    let reqwest_middleware::Error::Middleware(err) = err else {
+        panic!("expected a middleware error");
+    };
+    let err = err.downcast_ref::<RateLimitError>().unwrap();
+    let retry_after = err.retry_after().unwrap();
+    let retry_at = err.retry_at().unwrap();
+    assert_eq!(retry_after, Duration::from_secs(6));
+    assert!(retry_at > after, "retry_at should be in the future");
+    assert!(retry_at >= before + retry_after);
+    assert!(retry_at <= after + retry_after);
+}
+
+#[tokio::test]
+async fn test_result_ext_extracts_retry_after() {
+    let server = setup_mock_server().await;
+
+    let middleware = RateLimitMiddleware::builder()
+        .jitter(0.0)
+        .route(|r| {
+            r.limit(1, Duration::from_secs(10))
+                .on_limit(ThrottleBehavior::Error)
+        })
+        .build();
+
+    let client = ClientBuilder::new(reqwest::Client::new())
+        .with(middleware)
+        .build();
+
+    let result = client.get(server.uri()).send().await;
+    assert!(result.rate_limit_error().is_none());
+    assert_eq!(result.rate_limit_retry_after(), None);
+
+    let result = client.get(server.uri()).send().await;
+    let retry_after = result.rate_limit_retry_after().unwrap();
+    assert!(retry_after > Duration::from_secs(9), "{retry_after:?}");
+    assert!(retry_after <= Duration::from_secs(10), "{retry_after:?}");
+    let err = result.unwrap_err();
+    assert_eq!(err.rate_limit_retry_after(), Some(retry_after));
+    assert!(matches!(
+        err.rate_limit_error(),
+        Some(RateLimitError::RateLimited { .. })
+    ));
+}
+
+#[tokio::test]
+async fn test_unmatched_requests_denied() {
+    let server = setup_mock_server().await;
+
+    let middleware = RateLimitMiddleware::builder()
+        .unmatched(UnmatchedBehavior::Deny)
+        .route(|r| r.path("/order").limit(10, Duration::from_secs(1)))
+        .build();
+
+    let client = ClientBuilder::new(reqwest::Client::new())
+        .with(middleware)
+        .build();
+
+    let resp = client
+        .post(format!("{}/order", server.uri()))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 200);
+
+    let err = client
+        .get(format!("{}/data", server.uri()))
+        .send()
+        .await
+        .unwrap_err();
+    let reqwest_middleware::Error::Middleware(err) = err else {
+        panic!("expected a middleware error");
+    };
+    let err = err.downcast_ref::<RateLimitError>().unwrap();
+    assert!(
+        matches!(err, RateLimitError::Unmatched { path, .. } if path == "/data"),
+        "{err:?}"
+    );
+    assert_eq!(err.retry_after(), None);
+}
+
+/// Returns `0.0`, `0.5`, `1.0`, `0.0`, ... in turn.
+#[derive(Debug, Default)]
+struct CyclingJitter(AtomicUsize);
+
+impl JitterSource for CyclingJitter {
+    fn sample(&self) -> f64 {
+        (self.0.fetch_add(1, Ordering::Relaxed) % 3) as f64 / 2.0
+    }
+}
+
+#[tokio::test]
+async fn test_error_retry_after_is_jittered() {
+    let clock = Arc::new(TestClock::new());
+    let middleware = RateLimitMiddleware::builder()
+        .with_clock(clock)
+        .jitter(0.2)
+        .with_jitter_source(Arc::new(CyclingJitter::default()))
+        .route(|r| {
+            r.limit(1, Duration::from_secs(10))
+                .on_limit(ThrottleBehavior::Error)
+        })
+        .build();
+    let acquire = || middleware.acquire("api.example.com", &Method::GET, "/");
+    acquire().await.unwrap();
+
+    // Rejected at the same instant, yet advised to retry at different times
+    let retry_after = [
+        acquire().await.unwrap_err().retry_after(),
+        acquire().await.unwrap_err().retry_after(),
+        acquire().await.unwrap_err().retry_after(),
+    ];
+    assert_eq!(
+        retry_after,
+        [
+            Some(Duration::from_secs(10)),
+            Some(Duration::from_secs(11)),
+            Some(Duration::from_secs(12)),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_respond_429_on_rate_limit_exceeded() {
+    let server = setup_mock_server().await;
+    let request_count = Arc::new(AtomicUsize::new(0));
+
+    Mock::given(method("GET"))
+        .and(path("/counted"))
+        .respond_with({
+            let count = request_count.clone();
+            move |_: &wiremock::Request| {
+                count.fetch_add(1, Ordering::SeqCst);
+                ResponseTemplate::new(200)
+            }
+        })
+        .mount(&server)
+        .await;
+
+    let middleware = RateLimitMiddleware::builder()
+        .jitter(0.0)
+        .route(|r| {
+            r.limit(1, Duration::from_millis(1500))
+                .on_limit(ThrottleBehavior::Respond429)
+        })
+        .build();
+
+    let client = ClientBuilder::new(reqwest::Client::new())
+        .with(middleware)
+        .build();
+
+    let url = format!("{}/counted", server.uri());
+    let resp = client.get(&url).send().await.unwrap();
+    assert_eq!(resp.status(), 200);
+
+    // The second request is answered locally without reaching the server
+    let resp = client.get(&url).send().await.unwrap();
+    assert_eq!(resp.status(), 429);
+    assert_eq!(resp.url().as_str(), url);
+    assert_eq!(
+        resp.headers().get("retry-after").unwrap(),
+        "2",
+        "Retry-After should be the wait rounded up to whole seconds"
+    );
+    assert!(resp.bytes().await.unwrap().is_empty());
+    assert_eq!(request_count.load(Ordering::SeqCst), 1);
+}
+
 // =============================================================================
 // Delay Behavior Tests
 // =============================================================================
@@ -158,6 +394,70 @@ async fn test_delay_on_rate_limit_exceeded() {
     );
 }
 
+#[tokio::test]
+async fn test_delay_without_jitter_matches_wait() {
+    let server = setup_mock_server().await;
+
+    let middleware = RateLimitMiddleware::builder()
+        .jitter(0.0)
+        .route(|r| r.limit(1, Duration::from_millis(300)))
+        .build();
+
+    let client = ClientBuilder::new(reqwest::Client::new())
+        .with(middleware)
+        .build();
+
+    let url = format!("{}/test", server.uri());
+    client.get(&url).send().await.unwrap();
+
+    // Without jitter the second request waits almost exactly one emission interval
+    let start = Instant::now();
+    client.get(&url).send().await.unwrap();
+    let elapsed = start.elapsed();
+
+    assert!(
+        elapsed >= Duration::from_millis(280),
+        "Should have waited for rate limit: {elapsed:?}"
+    );
+    assert!(
+        elapsed < Duration::from_millis(360),
+        "Should not have added jitter: {elapsed:?}"
+    );
+}
+
+/// A clock following Tokio's time, which a paused runtime advances on sleeps.
+#[derive(Debug)]
+struct TokioClock(tokio::time::Instant);
+
+impl Clock for TokioClock {
+    fn now_nanos(&self) -> u64 {
+        self.0.elapsed().as_nanos() as u64
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_fixed_jitter_source_makes_delay_exact() {
+    let middleware = RateLimitMiddleware::builder()
+        .with_clock(Arc::new(TokioClock(tokio::time::Instant::now())))
+        .jitter(0.5)
+        .with_jitter_source(Arc::new(FixedJitter::new(0.5)))
+        .route(|r| r.limit(1, Duration::from_millis(400)))
+        .build();
+
+    middleware
+        .acquire("api.example.com", &Method::GET, "/")
+        .await
+        .unwrap();
+
+    // A 400ms wait plus a quarter of it in jitter
+    let start = tokio::time::Instant::now();
+    middleware
+        .acquire("api.example.com", &Method::GET, "/")
+        .await
+        .unwrap();
+    assert_eq!(start.elapsed(), Duration::from_millis(500));
+}
+
 #[tokio::test]
 async fn test_delay_does_not_lose_requests() {
     let server = setup_mock_server().await;
@@ -201,68 +501,2951 @@ async fn test_delay_does_not_lose_requests() {
     );
 }
 
-// =============================================================================
-// Route Matching Tests
-// =============================================================================
+#[tokio::test]
+async fn test_delay_with_timeout_errors_when_wait_too_long() {
+    let server = setup_mock_server().await;
+
+    // Third rapid request would need to wait ~100ms
+    let middleware = RateLimitMiddleware::builder()
+        .route(|r| {
+            r.limit(2, Duration::from_millis(200))
+                .on_limit(ThrottleBehavior::DelayWithTimeout(Duration::from_millis(
+                    50,
+                )))
+        })
+        .build();
+
+    let client = ClientBuilder::new(reqwest::Client::new())
+        .with(middleware)
+        .build();
+
+    let url = format!("{}/test", server.uri());
+
+    client.get(&url).send().await.unwrap();
+    client.get(&url).send().await.unwrap();
+
+    let start = Instant::now();
+    let err = client.get(&url).send().await.unwrap_err();
+    assert!(
+        err.to_string().contains("rate limit exceeded"),
+        "Error should mention rate limit: {err}"
+    );
+    assert!(
+        start.elapsed() < Duration::from_millis(50),
+        "Should error immediately instead of delaying: {:?}",
+        start.elapsed()
+    );
+}
+
+#[tokio::test]
+async fn test_delay_with_timeout_delays_short_waits() {
+    let server = setup_mock_server().await;
+
+    // Third rapid request needs to wait ~50ms, within the 200ms timeout
+    let middleware = RateLimitMiddleware::builder()
+        .route(|r| {
+            r.limit(2, Duration::from_millis(100))
+                .on_limit(ThrottleBehavior::DelayWithTimeout(Duration::from_millis(
+                    200,
+                )))
+        })
+        .build();
+
+    let client = ClientBuilder::new(reqwest::Client::new())
+        .with(middleware)
+        .build();
+
+    let url = format!("{}/test", server.uri());
+
+    let start = Instant::now();
+    for i in 0..3 {
+        let resp = client.get(&url).send().await;
+        assert!(resp.is_ok(), "Request {i} should succeed after delay");
+    }
+    assert!(
+        start.elapsed() >= Duration::from_millis(40),
+        "Should have waited for rate limit: {:?}",
+        start.elapsed()
+    );
+}
+
+#[tokio::test]
+async fn test_max_total_delay_errors_once_budget_is_spent() {
+    let server = setup_mock_server().await;
+
+    // One request per 100ms, and two per second
+    let middleware = RateLimitMiddleware::builder()
+        .jitter(0.0)
+        .max_total_delay(Duration::from_millis(150))
+        .route(|r| {
+            r.limit(1, Duration::from_millis(100))
+                .limit(2, Duration::from_secs(1))
+        })
+        .build();
+
+    let client = ClientBuilder::new(reqwest::Client::new())
+        .with(middleware)
+        .build();
+
+    let url = format!("{}/test", server.uri());
+    client.get(&url).send().await.unwrap();
+
+    // Waits ~100ms on the first limit, within the budget
+    let start = Instant::now();
+    client.get(&url).send().await.unwrap();
+    assert!(start.elapsed() >= Duration::from_millis(80));
+
+    // Waits ~100ms on the first limit, then would need ~800ms on the second
+    let start = Instant::now();
+    let err = client.get(&url).send().await.unwrap_err();
+    assert!(
+        err.to_string().contains("rate limit exceeded"),
+        "Error should mention rate limit: {err}"
+    );
+    assert!(
+        start.elapsed() < Duration::from_millis(400),
+        "Should error instead of waiting out the second limit: {:?}",
+        start.elapsed()
+    );
+}
+
+#[tokio::test]
+async fn test_max_total_delay_counts_time_queued() {
+    let middleware = RateLimitMiddleware::builder()
+        .jitter(0.0)
+        .max_total_delay(Duration::from_millis(150))
+        .route(|r| r.limit(1, Duration::from_millis(100)))
+        .build();
+
+    // Later requests queue behind the ones sleeping on the limit
+    let start = Instant::now();
+    let handles: Vec<_> = (0..6)
+        .map(|_| {
+            let middleware = middleware.clone();
+            tokio::spawn(async move {
+                let result = middleware
+                    .acquire("api.example.com", &Method::GET, "/test")
+                    .await;
+                (result, start.elapsed())
+            })
+        })
+        .collect();
+
+    let mut admitted = 0;
+    for handle in handles {
+        let (result, elapsed) = handle.await.unwrap();
+        assert!(
+            elapsed < Duration::from_millis(300),
+            "No request should be delayed far past the budget: {elapsed:?}"
+        );
+        match result {
+            Ok(()) => admitted += 1,
+            Err(err) => assert!(matches!(err, RateLimitError::RateLimited { .. })),
+        }
+    }
+    assert!(
+        (1..=3).contains(&admitted),
+        "Only requests within the budget are admitted: {admitted}"
+    );
+}
+
+#[tokio::test]
+async fn test_request_timeout_bounds_delay() {
+    let server = setup_mock_server().await;
+
+    let middleware = RateLimitMiddleware::builder()
+        .jitter(0.0)
+        .route(|r| r.limit(1, Duration::from_secs(10)))
+        .build();
+
+    let client = ClientBuilder::new(reqwest::Client::new())
+        .with(middleware)
+        .build();
+
+    let url = format!("{}/test", server.uri());
+    client.get(&url).send().await.unwrap();
+
+    // The next slot is 10s away, far past the request's timeout
+    let start = Instant::now();
+    let err = client
+        .get(&url)
+        .timeout(Duration::from_millis(200))
+        .send()
+        .await
+        .unwrap_err();
+    assert!(
+        matches!(
+            err.rate_limit_error(),
+            Some(RateLimitError::RateLimited { .. })
+        ),
+        "Should fail with a rate limit error: {err}"
+    );
+    assert!(
+        start.elapsed() < Duration::from_millis(100),
+        "Should error instead of sleeping: {:?}",
+        start.elapsed()
+    );
+}
+
+#[tokio::test]
+async fn test_request_timeout_bounds_time_queued() {
+    let server = setup_mock_server().await;
+
+    let middleware = RateLimitMiddleware::builder()
+        .jitter(0.0)
+        .route(|r| r.limit(1, Duration::from_millis(500)))
+        .build();
+
+    let client = ClientBuilder::new(reqwest::Client::new())
+        .with(middleware)
+        .build();
+
+    let url = format!("{}/test", server.uri());
+    client.get(&url).send().await.unwrap();
+
+    // One request without a timeout sleeps ~500ms on the limit
+    let delayed = tokio::spawn({
+        let client = client.clone();
+        let url = url.clone();
+        async move { client.get(&url).send().await }
+    });
+    tokio::time::sleep(Duration::from_millis(20)).await;
+
+    // Requests with a short timeout queue behind it
+    let start = Instant::now();
+    let queued: Vec<_> = (0..3)
+        .map(|_| {
+            let client = client.clone();
+            let url = url.clone();
+            tokio::spawn(async move {
+                client
+                    .get(&url)
+                    .timeout(Duration::from_millis(100))
+                    .send()
+                    .await
+            })
+        })
+        .collect();
+    for handle in queued {
+        let err = handle.await.unwrap().unwrap_err();
+        assert!(
+            matches!(
+                err.rate_limit_error(),
+                Some(RateLimitError::RateLimited { .. })
+            ),
+            "Should fail with a rate limit error: {err}"
+        );
+    }
+    assert!(
+        start.elapsed() < Duration::from_millis(300),
+        "Should error once the timeout is spent instead of waiting in line: {:?}",
+        start.elapsed()
+    );
+    delayed.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn test_shutdown_releases_delayed_requests() {
+    let middleware = RateLimitMiddleware::builder()
+        .jitter(0.0)
+        .route(|r| r.limit(1, Duration::from_secs(60)))
+        .build();
+    let acquire = |middleware: RateLimitMiddleware| async move {
+        middleware
+            .acquire("api.example.com", &Method::GET, "/test")
+            .await
+    };
+    acquire(middleware.clone()).await.unwrap();
+
+    // One request sleeps on the limit, the other is queued behind it
+    let delayed = [
+        tokio::spawn(acquire(middleware.clone())),
+        tokio::spawn(acquire(middleware.clone())),
+    ];
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert!(delayed.iter().all(|task| !task.is_finished()));
+
+    let start = Instant::now();
+    middleware.shutdown();
+    for task in delayed {
+        let result = tokio::time::timeout(Duration::from_secs(1), task)
+            .await
+            .expect("Should be released promptly")
+            .unwrap();
+        assert!(matches!(result, Err(RateLimitError::ShuttingDown)));
+    }
+    assert!(start.elapsed() < Duration::from_millis(500));
+
+    // Later requests that would wait fail right away
+    assert!(matches!(
+        acquire(middleware.clone()).await,
+        Err(RateLimitError::ShuttingDown)
+    ));
+}
+
+#[tokio::test]
+async fn test_max_pending_sheds_excess_delayed_requests() {
+    let middleware = RateLimitMiddleware::builder()
+        .jitter(0.0)
+        .route(|r| r.limit(1, Duration::from_secs(60)).max_pending(3))
+        .build();
+    let acquire = |middleware: RateLimitMiddleware| async move {
+        middleware
+            .acquire("api.example.com", &Method::GET, "/test")
+            .await
+    };
+    acquire(middleware.clone()).await.unwrap();
+    assert_eq!(
+        middleware.pending("api.example.com", &Method::GET, "/test"),
+        0
+    );
+
+    let delayed: Vec<_> = (0..3)
+        .map(|_| tokio::spawn(acquire(middleware.clone())))
+        .collect();
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert!(delayed.iter().all(|task| !task.is_finished()));
+    assert_eq!(
+        middleware.pending("api.example.com", &Method::GET, "/test"),
+        3
+    );
+
+    // The route is full, so another request fails instead of waiting
+    let shed = tokio::time::timeout(Duration::from_secs(1), acquire(middleware.clone()))
+        .await
+        .expect("Should be shed right away");
+    assert!(matches!(shed, Err(RateLimitError::RateLimited { .. })));
+    assert_eq!(
+        middleware.pending("api.example.com", &Method::GET, "/test"),
+        3
+    );
+
+    // Released requests no longer count
+    middleware.shutdown();
+    for task in delayed {
+        assert!(task.await.unwrap().is_err());
+    }
+    assert_eq!(
+        middleware.pending("api.example.com", &Method::GET, "/test"),
+        0
+    );
+}
+
+#[tokio::test]
+async fn test_priority_orders_delayed_requests() {
+    let server = MockServer::start().await;
+    let arrivals: Arc<Mutex<Vec<String>>> = Arc::default();
+
+    Mock::given(method("GET"))
+        .respond_with({
+            let arrivals = arrivals.clone();
+            move |req: &wiremock::Request| {
+                let id = req.headers.get("x-id").unwrap().to_str().unwrap();
+                arrivals.lock().unwrap().push(id.to_owned());
+                ResponseTemplate::new(200)
+            }
+        })
+        .mount(&server)
+        .await;
+
+    let middleware = RateLimitMiddleware::builder()
+        .jitter(0.0)
+        .route(|r| r.limit(1, Duration::from_millis(100)))
+        .build();
+
+    let client = ClientBuilder::new(reqwest::Client::new())
+        .with(middleware)
+        .build();
+
+    let url = format!("{}/test", server.uri());
+    client
+        .get(&url)
+        .header("x-id", "first")
+        .send()
+        .await
+        .unwrap();
+
+    // Queue up low priority requests, then a high priority one behind them
+    let mut tasks = Vec::new();
+    for (id, priority) in [("low1", 0), ("low2", 0), ("low3", 0), ("high", 10)] {
+        let request = client
+            .get(&url)
+            .header("x-id", id)
+            .with_extension(Priority(priority));
+        tasks.push(tokio::spawn(request.send()));
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    for task in tasks {
+        task.await.unwrap().unwrap();
+    }
+
+    assert_eq!(
+        *arrivals.lock().unwrap(),
+        ["first", "high", "low1", "low2", "low3"]
+    );
+}
+
+// =============================================================================
+// Route Matching Tests
+// =============================================================================
+
+#[tokio::test]
+async fn test_different_routes_have_separate_limits() {
+    let server = setup_mock_server().await;
+
+    let middleware = RateLimitMiddleware::builder()
+        .route(|r| {
+            r.path("/test")
+                .limit(2, Duration::from_secs(10))
+                .on_limit(ThrottleBehavior::Error)
+        })
+        .route(|r| {
+            r.path("/data")
+                .limit(2, Duration::from_secs(10))
+                .on_limit(ThrottleBehavior::Error)
+        })
+        .build();
+
+    let client = ClientBuilder::new(reqwest::Client::new())
+        .with(middleware)
+        .build();
+
+    // Exhaust /test limit
+    client
+        .get(format!("{}/test", server.uri()))
+        .send()
+        .await
+        .unwrap();
+    client
+        .get(format!("{}/test", server.uri()))
+        .send()
+        .await
+        .unwrap();
+
+    // /data should still work (separate limit)
+    let resp = client.get(format!("{}/data", server.uri())).send().await;
+    assert!(resp.is_ok(), "/data should have its own limit");
+
+    // /test should fail now
+    let resp = client.get(format!("{}/test", server.uri())).send().await;
+    assert!(resp.is_err(), "/test should be rate limited");
+}
+
+#[tokio::test]
+async fn test_method_specific_limits() {
+    let server = setup_mock_server().await;
+
+    let middleware = RateLimitMiddleware::builder()
+        .route(|r| {
+            r.method(Method::POST)
+                .path("/order")
+                .limit(1, Duration::from_secs(10))
+                .on_limit(ThrottleBehavior::Error)
+        })
+        .route(|r| {
+            r.method(Method::DELETE)
+                .path("/order")
+                .limit(1, Duration::from_secs(10))
+                .on_limit(ThrottleBehavior::Error)
+        })
+        .build();
+
+    let client = ClientBuilder::new(reqwest::Client::new())
+        .with(middleware)
+        .build();
+
+    let order_url = format!("{}/order", server.uri());
+
+    // POST and DELETE have separate limits
+    client.post(&order_url).send().await.unwrap();
+    client.delete(&order_url).send().await.unwrap();
+
+    // Second POST should fail
+    let resp = client.post(&order_url).send().await;
+    assert!(resp.is_err(), "Second POST should be rate limited");
+
+    // Second DELETE should also fail
+    let resp = client.delete(&order_url).send().await;
+    assert!(resp.is_err(), "Second DELETE should be rate limited");
+}
+
+#[tokio::test]
+async fn test_include_head_shares_get_limit() {
+    let server = setup_mock_server().await;
+
+    let middleware = RateLimitMiddleware::builder()
+        .route(|r| {
+            r.method(Method::GET)
+                .include_head()
+                .limit(2, Duration::from_secs(10))
+                .on_limit(ThrottleBehavior::Error)
+        })
+        .build();
+
+    let client = ClientBuilder::new(reqwest::Client::new())
+        .with(middleware)
+        .build();
+
+    let url = format!("{}/test", server.uri());
+    client.get(&url).send().await.unwrap();
+    client.head(&url).send().await.unwrap();
+
+    // GET and HEAD drew from the same limit
+    assert!(client.get(&url).send().await.is_err());
+    assert!(client.head(&url).send().await.is_err());
+
+    // Other methods are not limited
+    client.post(&url).send().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_header_filters_select_route() {
+    let server = setup_mock_server().await;
+
+    let middleware = RateLimitMiddleware::builder()
+        .route(|r| {
+            r.header("X-Internal", "true")
+                .limit(1, Duration::from_secs(10))
+                .on_limit(ThrottleBehavior::Error)
+        })
+        .route(|r| {
+            r.header_present("X-Trace-Id")
+                .limit(1, Duration::from_secs(10))
+                .on_limit(ThrottleBehavior::Error)
+        })
+        .build();
+
+    let client = ClientBuilder::new(reqwest::Client::new())
+        .with(middleware)
+        .build();
+
+    let url = format!("{}/test", server.uri());
+    client
+        .get(&url)
+        .header("X-Internal", "true")
+        .send()
+        .await
+        .unwrap();
+    assert!(
+        client
+            .get(&url)
+            .header("X-Internal", "true")
+            .send()
+            .await
+            .is_err()
+    );
+
+    // Requests without the header, or with another value, are not limited
+    client.get(&url).send().await.unwrap();
+    client
+        .get(&url)
+        .header("X-Internal", "false")
+        .send()
+        .await
+        .unwrap();
+
+    // Any value satisfies a presence filter
+    client
+        .get(&url)
+        .header("X-Trace-Id", "a")
+        .send()
+        .await
+        .unwrap();
+    assert!(
+        client
+            .get(&url)
+            .header("X-Trace-Id", "b")
+            .send()
+            .await
+            .is_err()
+    );
+}
+
+#[tokio::test]
+async fn test_bypass_methods_skip_limits() {
+    let server = setup_mock_server().await;
+
+    let middleware = RateLimitMiddleware::builder()
+        .bypass_methods(&[Method::OPTIONS])
+        .route(|r| {
+            r.limit(1, Duration::from_secs(10))
+                .on_limit(ThrottleBehavior::Error)
+        })
+        .build();
+
+    let client = ClientBuilder::new(reqwest::Client::new())
+        .with(middleware)
+        .build();
+
+    // Preflight requests never throttle, nor take quota from real calls
+    for _ in 0..5 {
+        let resp = client.request(Method::OPTIONS, server.uri()).send().await;
+        assert!(resp.is_ok(), "OPTIONS should bypass the limit");
+    }
+
+    client.get(server.uri()).send().await.unwrap();
+    let resp = client.get(server.uri()).send().await;
+    assert!(resp.is_err(), "GET should be rate limited");
+}
+
+#[tokio::test]
+async fn test_unmatched_routes_not_limited() {
+    let server = setup_mock_server().await;
+
+    // Only limit /test path
+    let middleware = RateLimitMiddleware::builder()
+        .route(|r| {
+            r.path("/test")
+                .limit(1, Duration::from_secs(10))
+                .on_limit(ThrottleBehavior::Error)
+        })
+        .build();
+
+    let client = ClientBuilder::new(reqwest::Client::new())
+        .with(middleware)
+        .build();
+
+    // Root path should not be limited
+    for _ in 0..10 {
+        let resp = client.get(format!("{}/", server.uri())).send().await;
+        assert!(resp.is_ok(), "Unmatched route should not be limited");
+    }
+}
+
+#[tokio::test]
+async fn test_key_by_header_separates_buckets() {
+    let server = setup_mock_server().await;
+
+    let middleware = RateLimitMiddleware::builder()
+        .route(|r| {
+            r.key_by_header("X-User-Id")
+                .limit(2, Duration::from_secs(10))
+                .on_limit(ThrottleBehavior::Error)
+        })
+        .build();
+
+    let client = ClientBuilder::new(reqwest::Client::new())
+        .with(middleware.clone())
+        .build();
+
+    let url = format!("{}/test", server.uri());
+    let send_as = |user: Option<&'static str>| {
+        let mut req = client.get(&url);
+        if let Some(user) = user {
+            req = req.header("X-User-Id", user);
+        }
+        req.send()
+    };
+
+    // Exhaust alice's bucket
+    send_as(Some("alice")).await.unwrap();
+    send_as(Some("alice")).await.unwrap();
+    assert!(
+        send_as(Some("alice")).await.is_err(),
+        "alice should be limited"
+    );
+
+    // bob is unaffected
+    send_as(Some("bob")).await.unwrap();
+    send_as(Some("bob")).await.unwrap();
+    assert!(send_as(Some("bob")).await.is_err(), "bob should be limited");
+
+    // Requests without the header share the default bucket
+    send_as(None).await.unwrap();
+    send_as(None).await.unwrap();
+    assert!(
+        send_as(None).await.is_err(),
+        "default bucket should be limited"
+    );
+
+    assert_eq!(middleware.state_count(), 3);
+}
+
+#[tokio::test]
+async fn test_key_fn_separates_buckets() {
+    let server = setup_mock_server().await;
+
+    let middleware = RateLimitMiddleware::builder()
+        .route(|r| {
+            r.path("/tenants")
+                .key_fn(|req| req.url().path_segments()?.nth(1).map(str::to_owned))
+                .limit(1, Duration::from_secs(10))
+                .on_limit(ThrottleBehavior::Error)
+        })
+        .build();
+
+    let client = ClientBuilder::new(reqwest::Client::new())
+        .with(middleware)
+        .build();
+
+    let url = |path: &str| format!("{}{path}", server.uri());
+    client.get(url("/tenants/a/orders")).send().await.unwrap();
+    assert!(client.get(url("/tenants/a/book")).send().await.is_err());
+
+    // Another segment gets its own bucket
+    client.get(url("/tenants/b/orders")).send().await.unwrap();
+    assert!(client.get(url("/tenants/b/orders")).send().await.is_err());
+
+    // Requests without a key share the default bucket
+    client.get(url("/tenants")).send().await.unwrap();
+    assert!(client.get(url("/tenants")).send().await.is_err());
+}
+
+#[tokio::test]
+async fn test_by_authority_separates_ports() {
+    let first = setup_mock_server().await;
+    let second = setup_mock_server().await;
+
+    let middleware = RateLimitMiddleware::builder()
+        .route(|r| {
+            r.key_fn(keys::by_authority())
+                .limit(1, Duration::from_secs(10))
+                .on_limit(ThrottleBehavior::Error)
+        })
+        .build();
+
+    let client = ClientBuilder::new(reqwest::Client::new())
+        .with(middleware.clone())
+        .build();
+
+    // Same host, different ports
+    assert_ne!(first.address().port(), second.address().port());
+    for server in [&first, &second] {
+        let url = format!("{}/test", server.uri());
+        client.get(&url).send().await.unwrap();
+        assert!(client.get(&url).send().await.is_err());
+    }
+    assert_eq!(middleware.state_count(), 2);
+}
+
+#[tokio::test]
+async fn test_path_pattern_key_separates_buckets() {
+    let (builder, clock) = testing::builder();
+    let middleware = builder
+        .route(|r| {
+            r.path_pattern_key("/markets/{id}/book", "id")
+                .limit(2, Duration::from_secs(1))
+                .on_limit(ThrottleBehavior::Error)
+        })
+        .build();
+    let acquire = |path| middleware.acquire("api.example.com", &Method::GET, path);
+    let retry_after = |result: Result<(), RateLimitError>| result.unwrap_err().retry_after();
+
+    // Each market gets its own burst, refilling at the same rate
+    for path in ["/markets/A/book", "/markets/B/book"] {
+        acquire(path).await.unwrap();
+        acquire(path).await.unwrap();
+        assert_eq!(
+            retry_after(acquire(path).await),
+            Some(Duration::from_millis(500))
+        );
+    }
+
+    clock.advance(Duration::from_millis(500));
+    for path in ["/markets/A/book", "/markets/B/book"] {
+        acquire(path).await.unwrap();
+        assert!(acquire(path).await.is_err());
+    }
+    assert!(acquire("/markets/A/price").await.is_ok());
+}
+
+#[tokio::test]
+async fn test_per_key_limit_caps_each_key_within_shared_limit() {
+    let server = setup_mock_server().await;
+
+    let middleware = RateLimitMiddleware::builder()
+        .route(|r| {
+            r.key_by_header("X-User-Id")
+                .limit(1000, Duration::from_secs(3600))
+                .per_key_limit(100, Duration::from_secs(3600))
+                .on_limit(ThrottleBehavior::Error)
+        })
+        .build();
+
+    let client = ClientBuilder::new(reqwest::Client::new())
+        .with(middleware.clone())
+        .build();
+
+    let url = format!("{}/test", server.uri());
+    let send = |user: &'static str| client.get(&url).header("X-User-Id", user).send();
+    for _ in 0..100 {
+        send("alice").await.unwrap();
+    }
+    assert!(send("alice").await.is_err(), "alice hit her own cap");
+    send("bob").await.unwrap();
+
+    // Both users drew from the shared limit
+    let quota =
+        middleware.quota_remaining(&server.address().ip().to_string(), &Method::GET, "/test");
+    assert!(quota[0].1 <= 899);
+}
+
+#[tokio::test]
+async fn test_cleanup_removes_stale_keyed_buckets() {
+    let server = setup_mock_server().await;
+    let clock = Arc::new(TestClock::new());
+
+    let middleware = RateLimitMiddleware::builder()
+        .with_clock(clock.clone())
+        .route(|r| {
+            r.key_by_header("X-User-Id")
+                .limit(2, Duration::from_secs(1))
+        })
+        .build();
+
+    let client = ClientBuilder::new(reqwest::Client::new())
+        .with(middleware.clone())
+        .build();
+
+    let url = format!("{}/test", server.uri());
+    for user in ["a", "b", "c"] {
+        client
+            .get(&url)
+            .header("X-User-Id", user)
+            .send()
+            .await
+            .unwrap();
+    }
+    assert_eq!(middleware.state_count(), 3);
+
+    middleware.cleanup();
+    assert_eq!(middleware.state_count(), 3, "Active buckets must be kept");
+
+    clock.advance(Duration::from_secs(3));
+    middleware.cleanup();
+    assert_eq!(middleware.state_count(), 0, "Stale buckets must be removed");
+}
+
+#[tokio::test]
+async fn test_cleanup_returns_removed_count() {
+    let server = setup_mock_server().await;
+    let clock = Arc::new(TestClock::new());
+
+    let middleware = RateLimitMiddleware::builder()
+        .with_clock(clock.clone())
+        .route(|r| {
+            r.key_by_header("X-User-Id")
+                .limit(2, Duration::from_secs(1))
+        })
+        .build();
+
+    let client = ClientBuilder::new(reqwest::Client::new())
+        .with(middleware.clone())
+        .build();
+
+    let url = format!("{}/test", server.uri());
+    let send = |user: &'static str| client.get(&url).header("X-User-Id", user).send();
+    for user in ["a", "b", "c"] {
+        send(user).await.unwrap();
+    }
+    assert_eq!(middleware.cleanup(), 0);
+
+    // Past twice the window, only the bucket used since then survives
+    clock.advance(Duration::from_secs(3));
+    send("d").await.unwrap();
+    assert_eq!(middleware.cleanup(), 3);
+    assert_eq!(middleware.state_count(), 1);
+}
+
+#[tokio::test]
+async fn test_cleanup_stale_after_keeps_buckets_longer() {
+    let server = setup_mock_server().await;
+    let clock = Arc::new(TestClock::new());
+
+    let middleware = RateLimitMiddleware::builder()
+        .with_clock(clock.clone())
+        .cleanup_stale_after(5)
+        .route(|r| r.limit(2, Duration::from_secs(1)))
+        .build();
+
+    let client = ClientBuilder::new(reqwest::Client::new())
+        .with(middleware.clone())
+        .build();
+
+    client
+        .get(format!("{}/test", server.uri()))
+        .send()
+        .await
+        .unwrap();
+
+    // Would be removed past twice the window by default
+    clock.advance(Duration::from_secs(3));
+    assert_eq!(middleware.cleanup(), 0);
+
+    clock.advance(Duration::from_secs(3));
+    assert_eq!(middleware.cleanup(), 1);
+}
+
+#[tokio::test]
+async fn test_auto_cleanup_removes_stale_buckets() {
+    let server = setup_mock_server().await;
+
+    let middleware = RateLimitMiddleware::builder()
+        .auto_cleanup(Duration::from_millis(50))
+        .route(|r| {
+            r.key_by_header("X-User-Id")
+                .limit(1, Duration::from_millis(10))
+        })
+        .build();
+
+    let client = ClientBuilder::new(reqwest::Client::new())
+        .with(middleware.clone())
+        .build();
+
+    let url = format!("{}/test", server.uri());
+    for user in 0..20 {
+        client
+            .get(&url)
+            .header("X-User-Id", user.to_string())
+            .send()
+            .await
+            .unwrap();
+    }
+    assert_eq!(middleware.state_count(), 20);
+
+    // Wait past two cleanup intervals
+    tokio::time::sleep(Duration::from_millis(150)).await;
+    assert_eq!(
+        middleware.state_count(),
+        0,
+        "Stale buckets should be removed automatically"
+    );
+}
+
+#[tokio::test]
+async fn test_multiple_hosts_share_one_limit() {
+    let server = setup_mock_server().await;
+    let port = server.address().port();
+
+    // Both hostnames reach the same mock server
+    let middleware = RateLimitMiddleware::builder()
+        .route(|r| {
+            r.hosts(["127.0.0.1", "localhost"])
+                .limit(2, Duration::from_secs(10))
+                .on_limit(ThrottleBehavior::Error)
+        })
+        .build();
+
+    let client = ClientBuilder::new(reqwest::Client::new())
+        .with(middleware.clone())
+        .build();
+
+    client
+        .get(format!("http://127.0.0.1:{port}/test"))
+        .send()
+        .await
+        .unwrap();
+    client
+        .get(format!("http://localhost:{port}/test"))
+        .send()
+        .await
+        .unwrap();
+
+    // The shared limit is exhausted for both hostnames
+    assert!(
+        client
+            .get(format!("http://127.0.0.1:{port}/test"))
+            .send()
+            .await
+            .is_err()
+    );
+    assert!(
+        client
+            .get(format!("http://localhost:{port}/test"))
+            .send()
+            .await
+            .is_err()
+    );
+    assert_eq!(middleware.state_count(), 1);
+}
+
+#[tokio::test]
+async fn test_most_specific_route_alone_applies() {
+    let server = setup_mock_server().await;
+
+    let middleware = RateLimitMiddleware::builder()
+        .match_mode(MatchMode::MostSpecific)
+        .route(|r| {
+            r.limit(1, Duration::from_secs(3600))
+                .on_limit(ThrottleBehavior::Error)
+        })
+        .route(|r| {
+            r.path("/test")
+                .limit(3, Duration::from_secs(3600))
+                .on_limit(ThrottleBehavior::Error)
+        })
+        .build();
+
+    let client = ClientBuilder::new(reqwest::Client::new())
+        .with(middleware.clone())
+        .build();
+
+    // `/test` only counts against its own limit, despite the stricter
+    // catch-all
+    for _ in 0..3 {
+        client
+            .get(format!("{}/test", server.uri()))
+            .send()
+            .await
+            .unwrap();
+    }
+    assert!(
+        client
+            .get(format!("{}/test", server.uri()))
+            .send()
+            .await
+            .is_err()
+    );
+    let quota = middleware.quota_remaining("127.0.0.1", &Method::GET, "/test");
+    assert_eq!(quota.len(), 1);
+    assert_eq!(quota[0].1, 0);
+
+    // The catch-all still has its full quota for other paths
+    client
+        .get(format!("{}/data", server.uri()))
+        .send()
+        .await
+        .unwrap();
+    assert!(
+        client
+            .get(format!("{}/data", server.uri()))
+            .send()
+            .await
+            .is_err()
+    );
+}
+
+#[tokio::test]
+async fn test_first_matching_route_alone_applies() {
+    let server = setup_mock_server().await;
+    let specific = || {
+        Route::builder()
+            .path("/test")
+            .limit(1, Duration::from_secs(3600))
+            .on_limit(ThrottleBehavior::Error)
+            .into_route()
+    };
+    let catch_all = || {
+        Route::builder()
+            .limit(3, Duration::from_secs(3600))
+            .on_limit(ThrottleBehavior::Error)
+            .into_route()
+    };
+    let allowed = |routes: Vec<Route>| {
+        let client = ClientBuilder::new(reqwest::Client::new())
+            .with(
+                RateLimitMiddleware::builder()
+                    .match_mode(MatchMode::FirstMatch)
+                    .add_route(routes[0].clone())
+                    .add_route(routes[1].clone())
+                    .build(),
+            )
+            .build();
+        let uri = server.uri();
+        async move {
+            let mut allowed = 0;
+            for _ in 0..4 {
+                if client.get(format!("{uri}/test")).send().await.is_ok() {
+                    allowed += 1;
+                }
+            }
+            allowed
+        }
+    };
+
+    // Whichever route is declared first wins, regardless of specificity
+    assert_eq!(allowed(vec![specific(), catch_all()]).await, 1);
+    assert_eq!(allowed(vec![catch_all(), specific()]).await, 3);
+}
+
+#[tokio::test]
+async fn test_priority_overrides_declaration_order() {
+    for mode in [MatchMode::FirstMatch, MatchMode::MostSpecific] {
+        let (builder, _clock) = testing::builder();
+        let middleware = builder
+            .match_mode(mode)
+            .route(|r| {
+                r.path("/book")
+                    .limit(1, Duration::from_secs(3600))
+                    .on_limit(ThrottleBehavior::Error)
+            })
+            .route(|r| {
+                r.path("/book")
+                    .priority(10)
+                    .limit(3, Duration::from_secs(3600))
+                    .on_limit(ThrottleBehavior::Error)
+            })
+            .build();
+
+        // The later route applies, since it has the higher priority
+        for _ in 0..3 {
+            middleware
+                .acquire("api.example.com", &Method::GET, "/book")
+                .await
+                .unwrap();
+        }
+        assert!(
+            middleware
+                .acquire("api.example.com", &Method::GET, "/book")
+                .await
+                .is_err()
+        );
+        assert_eq!(
+            middleware
+                .explain(&reqwest::Request::new(
+                    Method::GET,
+                    "https://api.example.com/book".parse().unwrap()
+                ))
+                .iter()
+                .map(|m| m.matched)
+                .collect::<Vec<_>>(),
+            [false, true],
+            "{mode:?}"
+        );
+    }
+}
+
+// =============================================================================
+// Multiple Limits Tests
+// =============================================================================
+
+#[tokio::test]
+async fn test_multiple_limits_all_must_pass() {
+    let server = setup_mock_server().await;
+
+    // Create route with two limits:
+    // - Burst: 3 requests per 100ms
+    // - Sustained: 5 requests per 1 second
+    let middleware = RateLimitMiddleware::builder()
+        .route(|r| {
+            r.limit(3, Duration::from_millis(100)) // Burst limit
+                .limit(5, Duration::from_secs(1)) // Sustained limit
+                .on_limit(ThrottleBehavior::Error)
+        })
+        .build();
+
+    let client = ClientBuilder::new(reqwest::Client::new())
+        .with(middleware)
+        .build();
+
+    let url = format!("{}/test", server.uri());
+
+    // First 3 should succeed (within burst)
+    for i in 0..3 {
+        let resp = client.get(&url).send().await;
+        assert!(resp.is_ok(), "Request {i} should succeed within burst");
+    }
+
+    // 4th should fail (burst exhausted)
+    let resp = client.get(&url).send().await;
+    assert!(resp.is_err(), "4th request should fail - burst exhausted");
+}
+
+#[tokio::test]
+async fn test_limit_behavior_overrides_route_behavior() {
+    let server = setup_mock_server().await;
+
+    // The burst limit delays, the sustained limit errors
+    let middleware = RateLimitMiddleware::builder()
+        .route(|r| {
+            r.limit(2, Duration::from_millis(200))
+                .limit_with(3, Duration::from_secs(10), ThrottleBehavior::Error)
+                .on_limit(ThrottleBehavior::Delay)
+        })
+        .build();
+
+    let client = ClientBuilder::new(reqwest::Client::new())
+        .with(middleware)
+        .build();
+
+    let url = format!("{}/test", server.uri());
+    let start = Instant::now();
+    for i in 0..3 {
+        let resp = client.get(&url).send().await;
+        assert!(resp.is_ok(), "Request {i} should succeed after delay");
+    }
+    assert!(
+        start.elapsed() >= Duration::from_millis(80),
+        "Should have waited for the burst limit: {:?}",
+        start.elapsed()
+    );
+
+    let start = Instant::now();
+    let err = client.get(&url).send().await.unwrap_err();
+    assert!(
+        err.to_string().contains("(limit 2/2)"),
+        "Error should come from the sustained limit: {err}"
+    );
+    assert!(
+        start.elapsed() < Duration::from_secs(1),
+        "Should not wait out the sustained limit: {:?}",
+        start.elapsed()
+    );
+}
+
+#[tokio::test]
+async fn test_shared_group_limits_routes_jointly() {
+    let server = setup_mock_server().await;
+
+    let middleware = RateLimitMiddleware::builder()
+        .route(|r| {
+            r.path("/test")
+                .shared_group("backend")
+                .limit(3, Duration::from_secs(10))
+                .on_limit(ThrottleBehavior::Error)
+        })
+        .route(|r| {
+            r.path("/data")
+                .shared_group("backend")
+                .limit(3, Duration::from_secs(10))
+                .on_limit(ThrottleBehavior::Error)
+        })
+        .build();
+
+    let client = ClientBuilder::new(reqwest::Client::new())
+        .with(middleware.clone())
+        .build();
+
+    let test_url = format!("{}/test", server.uri());
+    let data_url = format!("{}/data", server.uri());
+
+    // Both paths draw from the same 3-request budget
+    client.get(&test_url).send().await.unwrap();
+    client.get(&data_url).send().await.unwrap();
+    client.get(&test_url).send().await.unwrap();
+    assert!(client.get(&data_url).send().await.is_err());
+    assert!(client.get(&test_url).send().await.is_err());
+
+    assert_eq!(middleware.state_count(), 1);
+}
+
+// =============================================================================
+// Weighted Request Tests
+// =============================================================================
+
+#[tokio::test]
+async fn test_request_weight_consumes_multiple_slots() {
+    let server = setup_mock_server().await;
+
+    let middleware = RateLimitMiddleware::builder()
+        .route(|r| {
+            r.limit(10, Duration::from_secs(1))
+                .on_limit(ThrottleBehavior::Error)
+        })
+        .build();
+
+    let client = ClientBuilder::new(reqwest::Client::new())
+        .with(middleware)
+        .build();
+
+    let url = format!("{}/test", server.uri());
+
+    // A weight-3 request consumes three of the ten slots
+    client
+        .get(&url)
+        .with_extension(RequestWeight(3))
+        .send()
+        .await
+        .unwrap();
+
+    // Only 7 unit-weight requests remain
+    for i in 0..7 {
+        let resp = client.get(&url).send().await;
+        assert!(resp.is_ok(), "Request {i} should fit in remaining burst");
+    }
+    assert!(
+        client.get(&url).send().await.is_err(),
+        "Burst should be exhausted after weight 3 + 7 requests"
+    );
+}
+
+#[tokio::test]
+async fn test_zero_request_weight_counts_as_one() {
+    let server = setup_mock_server().await;
+
+    let middleware = RateLimitMiddleware::builder()
+        .route(|r| {
+            r.limit(1, Duration::from_secs(10))
+                .on_limit(ThrottleBehavior::Error)
+        })
+        .build();
+
+    let client = ClientBuilder::new(reqwest::Client::new())
+        .with(middleware)
+        .build();
+
+    let url = format!("{}/test", server.uri());
+
+    client
+        .get(&url)
+        .with_extension(RequestWeight(0))
+        .send()
+        .await
+        .unwrap();
+    assert!(
+        client
+            .get(&url)
+            .with_extension(RequestWeight(0))
+            .send()
+            .await
+            .is_err(),
+        "A zero weight must still use up the limit"
+    );
+}
+
+#[tokio::test]
+async fn test_bypass_marker_skips_rate_limiting() {
+    let server = setup_mock_server().await;
+
+    let middleware = RateLimitMiddleware::builder()
+        .route(|r| {
+            r.limit(1, Duration::from_secs(10))
+                .on_limit(ThrottleBehavior::Error)
+        })
+        .build();
+
+    let client = ClientBuilder::new(reqwest::Client::new())
+        .with(middleware.clone())
+        .build();
+
+    let url = format!("{}/test", server.uri());
+    client.get(&url).send().await.unwrap();
+    assert!(client.get(&url).send().await.is_err());
+
+    // Marked requests pass the exhausted limit without counting against it
+    for _ in 0..3 {
+        client
+            .get(&url)
+            .with_extension(BypassRateLimit)
+            .send()
+            .await
+            .unwrap();
+    }
+    assert_eq!(
+        middleware.consumed("api.example.com", &Method::GET, "/test"),
+        1
+    );
+}
+
+#[tokio::test]
+async fn test_try_acquire_n_reserves_all_or_nothing() {
+    let (builder, _clock) = testing::builder();
+    let middleware = builder
+        .host("api.example.com", |h| {
+            h.route(|r| {
+                r.path("/orders")
+                    .limit(100, Duration::from_secs(10))
+                    .on_limit(ThrottleBehavior::Error)
+            })
+            .route(|r| {
+                r.limit(10, Duration::from_secs(10))
+                    .on_limit(ThrottleBehavior::Error)
+            })
+        })
+        .build();
+    let quota = |path| middleware.quota_remaining("api.example.com", &Method::POST, path);
+
+    // Leave 3 requests in the host-wide bucket
+    middleware
+        .try_acquire_n("api.example.com", &Method::POST, "/book", 7)
+        .unwrap();
+    assert_eq!(quota("/book")[0].1, 3);
+
+    // The /orders limit has room for 5, but the host-wide one does not, so
+    // the /orders reservation is rolled back
+    let wait = middleware
+        .try_acquire_n("api.example.com", &Method::POST, "/orders", 5)
+        .unwrap_err();
+    assert_eq!(wait, Duration::from_secs(2));
+    assert_eq!(
+        quota("/orders").iter().map(|q| q.1).collect::<Vec<_>>(),
+        [100, 3]
+    );
+
+    middleware
+        .try_acquire_n("api.example.com", &Method::POST, "/orders", 3)
+        .unwrap();
+    assert_eq!(
+        quota("/orders").iter().map(|q| q.1).collect::<Vec<_>>(),
+        [97, 0]
+    );
+}
+
+#[tokio::test]
+async fn test_try_acquire_n_skips_bypassed_methods() {
+    let (builder, _clock) = testing::builder();
+    let middleware = builder
+        .bypass_methods(&[Method::OPTIONS])
+        .route(|r| {
+            r.limit(10, Duration::from_secs(10))
+                .on_limit(ThrottleBehavior::Error)
+        })
+        .build();
+
+    for _ in 0..3 {
+        middleware
+            .try_acquire_n("api.example.com", &Method::OPTIONS, "/orders", 10)
+            .unwrap();
+    }
+    assert_eq!(
+        middleware.quota_remaining("api.example.com", &Method::GET, "/orders")[0].1,
+        10
+    );
+}
+
+#[test]
+fn test_acquire_at_replays_trace() {
+    // Delayed routes are not slept on during a replay
+    let middleware = RateLimitMiddleware::builder()
+        .route(|r| {
+            r.path("/orders")
+                .limit(2, Duration::from_secs(1))
+                .on_limit(ThrottleBehavior::Delay)
+        })
+        .build();
+
+    let trace_ms = [0, 100, 200, 500, 600, 1600];
+    let throttled: Vec<Option<Duration>> = trace_ms
+        .iter()
+        .map(|&at| {
+            let now_nanos = Duration::from_millis(at).as_nanos() as u64;
+            middleware
+                .acquire_at("api.example.com", &Method::POST, "/orders", now_nanos)
+                .err()
+                .and_then(|err| err.retry_after())
+        })
+        .collect();
+    assert_eq!(
+        throttled,
+        [
+            None,
+            None,
+            Some(Duration::from_millis(300)),
+            None,
+            Some(Duration::from_millis(400)),
+            None,
+        ]
+    );
+}
+
+#[test]
+fn test_acquire_at_retry_at_follows_replayed_time() {
+    let clock = Arc::new(TestClock::new());
+    clock.set(Duration::from_secs(10));
+    let middleware = RateLimitMiddleware::builder()
+        .jitter(0.0)
+        .with_clock(clock)
+        .route(|r| r.limit(1, Duration::from_secs(1)))
+        .build();
+
+    // Replaying at the origin, 10s before the clock's present
+    middleware
+        .acquire_at("api.example.com", &Method::GET, "/", 0)
+        .unwrap();
+    let before = Instant::now();
+    let err = middleware
+        .acquire_at("api.example.com", &Method::GET, "/", 0)
+        .unwrap_err();
+    let after = Instant::now();
+
+    // The retry is `retry_after` past the origin, about 9s before now
+    let retry_after = err.retry_after().unwrap();
+    let retry_at = err.retry_at().unwrap();
+    assert!(retry_after >= Duration::from_millis(900));
+    assert!(retry_at <= after - Duration::from_secs(10) + retry_after);
+    assert!(retry_at >= before - Duration::from_secs(10) + retry_after);
+}
+
+#[tokio::test]
+async fn test_routes_share_quota_pool() {
+    let middleware = RateLimitMiddleware::builder()
+        .jitter(0.0)
+        .with_clock(Arc::new(TestClock::new()))
+        .pool("provider", 10, Duration::from_secs(10))
+        .route(|r| {
+            r.path("/search")
+                .draws_from("provider", 5)
+                .limit(100, Duration::from_secs(1))
+                .on_limit(ThrottleBehavior::Error)
+        })
+        .route(|r| {
+            r.path("/lookup")
+                .draws_from("provider", 1)
+                .limit(100, Duration::from_secs(1))
+                .on_limit(ThrottleBehavior::Error)
+        })
+        .build();
+
+    // Two searches use up the pool, though each route has room of its own
+    for _ in 0..2 {
+        middleware
+            .acquire("api.example.com", &Method::GET, "/search")
+            .await
+            .unwrap();
+    }
+    let err = middleware
+        .acquire("api.example.com", &Method::GET, "/lookup")
+        .await
+        .unwrap_err();
+    assert!(
+        matches!(
+            err,
+            RateLimitError::RateLimited {
+                limit_index: 1,
+                limit_count: 2,
+                ..
+            }
+        ),
+        "{err}"
+    );
+    assert_eq!(err.retry_after(), Some(Duration::from_secs(1)));
+
+    // A rejected request takes nothing from the pool
+    assert!(
+        middleware
+            .acquire("api.example.com", &Method::GET, "/search")
+            .await
+            .is_err()
+    );
+}
+
+#[tokio::test]
+async fn test_route_weight_applies_to_every_request() {
+    let server = setup_mock_server().await;
+
+    let middleware = RateLimitMiddleware::builder()
+        .route(|r| {
+            r.weight(5)
+                .limit(10, Duration::from_secs(10))
+                .on_limit(ThrottleBehavior::Error)
+        })
+        .build();
+
+    let client = ClientBuilder::new(reqwest::Client::new())
+        .with(middleware)
+        .build();
+
+    let url = format!("{}/test", server.uri());
+
+    client.get(&url).send().await.unwrap();
+    client.get(&url).send().await.unwrap();
+    assert!(
+        client.get(&url).send().await.is_err(),
+        "Two weight-5 requests should exhaust a burst of 10"
+    );
+}
+
+#[tokio::test]
+async fn test_bandwidth_limit_charges_response_sizes() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/download"))
+        .respond_with(ResponseTemplate::new(200).set_body_bytes(vec![0; 400]))
+        .mount(&server)
+        .await;
+
+    let middleware = RateLimitMiddleware::builder()
+        .route(|r| {
+            r.bandwidth_limit(1000, Duration::from_secs(10))
+                .on_limit(ThrottleBehavior::Error)
+        })
+        .build();
+
+    let client = ClientBuilder::new(reqwest::Client::new())
+        .with(middleware)
+        .build();
+
+    let url = format!("{}/download", server.uri());
+
+    // 400 and 800 bytes are within the budget, and the third request is
+    // admitted on the 200 left over before its response exhausts it
+    for i in 0..3 {
+        let resp = client.get(&url).send().await.unwrap();
+        assert_eq!(resp.bytes().await.unwrap().len(), 400, "Request {i}");
+    }
+    let err = client.get(&url).send().await.unwrap_err();
+    assert!(
+        err.to_string().contains("rate limit exceeded"),
+        "Byte budget should be exhausted: {err}"
+    );
+}
+
+// =============================================================================
+// Concurrent Request Tests
+// =============================================================================
+
+#[tokio::test]
+async fn test_concurrent_requests_respect_limit() {
+    let server = setup_mock_server().await;
+
+    let middleware = RateLimitMiddleware::builder()
+        .route(|r| {
+            r.limit(5, Duration::from_millis(500))
+                .on_limit(ThrottleBehavior::Error)
+        })
+        .build();
+
+    let client = Arc::new(
+        ClientBuilder::new(reqwest::Client::new())
+            .with(middleware)
+            .build(),
+    );
+
+    let url = format!("{}/test", server.uri());
+
+    // Launch 10 concurrent requests
+    let mut handles = vec![];
+    for _ in 0..10 {
+        let client = client.clone();
+        let url = url.clone();
+        handles.push(tokio::spawn(async move { client.get(&url).send().await }));
+    }
+
+    // Wait for all to complete
+    let mut success_count = 0;
+    let mut error_count = 0;
+    for handle in handles {
+        match handle.await.unwrap() {
+            Ok(_) => success_count += 1,
+            Err(_) => error_count += 1,
+        }
+    }
+
+    // Should have exactly 5 successes and 5 failures
+    assert_eq!(success_count, 5, "Should have 5 successful requests");
+    assert_eq!(error_count, 5, "Should have 5 rate-limited requests");
+}
+
+#[tokio::test]
+async fn test_shared_state_across_clones() {
+    let server = setup_mock_server().await;
+
+    let middleware = RateLimitMiddleware::builder()
+        .route(|r| {
+            r.limit(3, Duration::from_secs(10))
+                .on_limit(ThrottleBehavior::Error)
+        })
+        .build();
+
+    // Create two clients sharing the same middleware
+    let client1 = ClientBuilder::new(reqwest::Client::new())
+        .with(middleware.clone())
+        .build();
+    let client2 = ClientBuilder::new(reqwest::Client::new())
+        .with(middleware)
+        .build();
+
+    let url = format!("{}/test", server.uri());
+
+    // Use client1 twice
+    client1.get(&url).send().await.unwrap();
+    client1.get(&url).send().await.unwrap();
+
+    // Use client2 once - should still work
+    client2.get(&url).send().await.unwrap();
+
+    // Now both clients should be rate limited (shared state)
+    assert!(
+        client1.get(&url).send().await.is_err(),
+        "client1 should be rate limited"
+    );
+    assert!(
+        client2.get(&url).send().await.is_err(),
+        "client2 should be rate limited"
+    );
+}
+
+/// Middleware placed after the rate limiter that records the peak number of
+/// requests in flight to the server.
+#[derive(Clone, Default)]
+struct InFlightTracker {
+    current: Arc<AtomicUsize>,
+    peak: Arc<AtomicUsize>,
+}
+
+#[async_trait::async_trait]
+impl reqwest_middleware::Middleware for InFlightTracker {
+    async fn handle(
+        &self,
+        req: reqwest::Request,
+        extensions: &mut http::Extensions,
+        next: reqwest_middleware::Next<'_>,
+    ) -> reqwest_middleware::Result<reqwest::Response> {
+        let current = self.current.fetch_add(1, Ordering::SeqCst) + 1;
+        self.peak.fetch_max(current, Ordering::SeqCst);
+        let response = next.run(req, extensions).await;
+        self.current.fetch_sub(1, Ordering::SeqCst);
+        response
+    }
+}
+
+#[tokio::test]
+async fn test_max_concurrent_caps_in_flight_requests() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/slow"))
+        .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_millis(100)))
+        .mount(&server)
+        .await;
+
+    let middleware = RateLimitMiddleware::builder()
+        .route(|r| {
+            r.path("/slow")
+                .max_concurrent(3)
+                .limit(100, Duration::from_secs(1))
+        })
+        .build();
+    let tracker = InFlightTracker::default();
+
+    let client = Arc::new(
+        ClientBuilder::new(reqwest::Client::new())
+            .with(middleware)
+            .with(tracker.clone())
+            .build(),
+    );
+
+    let url = format!("{}/slow", server.uri());
+
+    // Launch 10 concurrent slow requests
+    let mut handles = vec![];
+    for _ in 0..10 {
+        let client = client.clone();
+        let url = url.clone();
+        handles.push(tokio::spawn(async move { client.get(&url).send().await }));
+    }
+    for handle in handles {
+        handle.await.unwrap().unwrap();
+    }
+
+    let peak = tracker.peak.load(Ordering::SeqCst);
+    assert!(
+        peak <= 3,
+        "at most 3 requests should be in flight, saw {peak}"
+    );
+    assert!(peak > 1, "requests should run concurrently, saw {peak}");
+    assert_eq!(tracker.current.load(Ordering::SeqCst), 0);
+}
+
+#[tokio::test]
+async fn test_max_concurrent_error_behavior() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/slow"))
+        .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_millis(200)))
+        .mount(&server)
+        .await;
+
+    let middleware = RateLimitMiddleware::builder()
+        .route(|r| {
+            r.path("/slow")
+                .max_concurrent(1)
+                .limit(100, Duration::from_secs(1))
+                .on_limit(ThrottleBehavior::Error)
+        })
+        .build();
+
+    let client = Arc::new(
+        ClientBuilder::new(reqwest::Client::new())
+            .with(middleware)
+            .build(),
+    );
+
+    let url = format!("{}/slow", server.uri());
+
+    let first = {
+        let client = client.clone();
+        let url = url.clone();
+        tokio::spawn(async move { client.get(&url).send().await })
+    };
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    // The only slot is held by the first request
+    let err = client.get(&url).send().await.unwrap_err();
+    assert!(
+        err.to_string().contains("concurrency limit exceeded"),
+        "unexpected error: {err}"
+    );
+
+    // The slot is released once the first response arrives
+    first.await.unwrap().unwrap();
+    client.get(&url).send().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_concurrency_rejection_refunds_quota() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/slow"))
+        .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_millis(200)))
+        .mount(&server)
+        .await;
+
+    let middleware = RateLimitMiddleware::builder()
+        .with_clock(Arc::new(TestClock::new()))
+        .route(|r| {
+            r.path("/slow")
+                .max_concurrent(1)
+                .limit(10, Duration::from_secs(60))
+                .on_limit(ThrottleBehavior::Error)
+        })
+        .build();
+    let quota = || {
+        middleware.quota_remaining(&server.address().ip().to_string(), &Method::GET, "/slow")[0].1
+    };
+
+    let client = ClientBuilder::new(reqwest::Client::new())
+        .with(middleware.clone())
+        .build();
+    let url = format!("{}/slow", server.uri());
+
+    let first = {
+        let client = client.clone();
+        let url = url.clone();
+        tokio::spawn(async move { client.get(&url).send().await })
+    };
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(quota(), 9);
+
+    // Requests turned away for want of a slot take no quota
+    for _ in 0..3 {
+        let err = client.get(&url).send().await.unwrap_err();
+        assert!(matches!(
+            err.rate_limit_error(),
+            Some(RateLimitError::ConcurrencyLimited)
+        ));
+    }
+    assert_eq!(quota(), 9);
+
+    first.await.unwrap().unwrap();
+}
+
+// =============================================================================
+// Recovery Tests
+// =============================================================================
+
+#[tokio::test]
+async fn test_rate_limit_recovers_after_window() {
+    let server = setup_mock_server().await;
+
+    let middleware = RateLimitMiddleware::builder()
+        .route(|r| {
+            r.limit(2, Duration::from_millis(100))
+                .on_limit(ThrottleBehavior::Error)
+        })
+        .build();
+
+    let client = ClientBuilder::new(reqwest::Client::new())
+        .with(middleware)
+        .build();
+
+    let url = format!("{}/test", server.uri());
+
+    // Exhaust the limit
+    client.get(&url).send().await.unwrap();
+    client.get(&url).send().await.unwrap();
+    assert!(
+        client.get(&url).send().await.is_err(),
+        "Should be rate limited"
+    );
+
+    // Wait for recovery (one emission interval = 50ms)
+    tokio::time::sleep(Duration::from_millis(60)).await;
+
+    // Should be able to make one more request
+    let resp = client.get(&url).send().await;
+    assert!(resp.is_ok(), "Should recover after waiting");
+}
+
+#[tokio::test]
+async fn test_rate_limit_recovers_with_test_clock() {
+    let server = setup_mock_server().await;
+    let clock = Arc::new(TestClock::new());
+
+    let middleware = RateLimitMiddleware::builder()
+        .with_clock(clock.clone())
+        .route(|r| {
+            r.limit(2, Duration::from_secs(10))
+                .on_limit(ThrottleBehavior::Error)
+        })
+        .build();
+
+    let client = ClientBuilder::new(reqwest::Client::new())
+        .with(middleware)
+        .build();
+
+    let url = format!("{}/test", server.uri());
+
+    // Exhaust the limit
+    client.get(&url).send().await.unwrap();
+    client.get(&url).send().await.unwrap();
+    assert!(client.get(&url).send().await.is_err());
+
+    // Just short of one emission interval (5s) is not enough
+    clock.advance(Duration::from_millis(4999));
+    assert!(
+        client.get(&url).send().await.is_err(),
+        "Should still be rate limited before a full emission interval"
+    );
+
+    // Exactly one emission interval frees exactly one slot
+    clock.advance(Duration::from_millis(1));
+    assert!(client.get(&url).send().await.is_ok());
+    assert!(client.get(&url).send().await.is_err());
+}
+
+#[tokio::test]
+async fn test_reset_restores_full_burst() {
+    let (builder, _clock) = testing::builder();
+    let middleware = builder
+        .host("api.example.com", |h| {
+            h.route(|r| {
+                r.path("/book")
+                    .limit(3, Duration::from_secs(60))
+                    .on_limit(ThrottleBehavior::Error)
+            })
+            .route(|r| {
+                r.path("/order")
+                    .limit(3, Duration::from_secs(60))
+                    .on_limit(ThrottleBehavior::Error)
+            })
+        })
+        .build();
+    let acquire = |path| middleware.acquire("api.example.com", &Method::GET, path);
+
+    for path in ["/book", "/order"] {
+        for _ in 0..3 {
+            acquire(path).await.unwrap();
+        }
+        assert!(acquire(path).await.is_err());
+    }
+
+    // Resetting one route leaves the other exhausted
+    middleware.reset_route("api.example.com", &Method::GET, "/book");
+    assert_eq!(
+        middleware.consumed("api.example.com", &Method::GET, "/book"),
+        0
+    );
+    assert!(acquire("/order").await.is_err());
+
+    middleware.reset();
+    assert_eq!(middleware.state_count(), 0);
+    for path in ["/book", "/order"] {
+        for _ in 0..3 {
+            acquire(path).await.unwrap();
+        }
+        assert!(acquire(path).await.is_err());
+    }
+}
+
+#[tokio::test]
+async fn test_monthly_and_millennial_windows_recover() {
+    const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+    let clock = Arc::new(TestClock::new());
+
+    let middleware = RateLimitMiddleware::builder()
+        .with_clock(clock.clone())
+        .jitter(0.0)
+        .route(|r| {
+            r.path("/monthly")
+                .limit(3, 30 * DAY)
+                .on_limit(ThrottleBehavior::Error)
+        })
+        // Too long to keep in nanoseconds
+        .route(|r| {
+            r.path("/millennial")
+                .limit(2, 365_000 * DAY)
+                .on_limit(ThrottleBehavior::Error)
+        })
+        .build();
+
+    for (path, emission_interval) in [("/monthly", 10 * DAY), ("/millennial", 182_500 * DAY)] {
+        let acquire = || middleware.acquire("api.example.com", &Method::GET, path);
+        while acquire().await.is_ok() {}
+        let err = acquire().await.unwrap_err();
+        assert_eq!(err.retry_after(), Some(emission_interval));
+
+        clock.advance(emission_interval - Duration::from_secs(1));
+        assert!(acquire().await.is_err());
+        clock.advance(Duration::from_secs(1));
+        acquire().await.unwrap();
+        assert!(acquire().await.is_err());
+    }
+}
+
+#[tokio::test]
+async fn test_ramp_up_fills_new_buckets_gradually() {
+    for algorithm in [Algorithm::Gcra, Algorithm::TokenBucket] {
+        let (builder, clock) = testing::builder();
+        let middleware = builder
+            .route(|r| {
+                r.algorithm(algorithm)
+                    .limit(10, Duration::from_secs(10))
+                    .ramp_up(Duration::from_secs(5))
+                    .on_limit(ThrottleBehavior::Error)
+            })
+            .build();
+        let acquire = || middleware.acquire("api.example.com", &Method::GET, "/book");
+
+        // Half of the burst refills after the ramp-up, so only half is
+        // available at first
+        for _ in 0..5 {
+            acquire().await.unwrap();
+        }
+        assert!(acquire().await.is_err(), "{algorithm:?}");
+
+        // Past the ramp-up, an unused bucket is full
+        middleware.reset();
+        acquire().await.unwrap();
+        clock.advance(Duration::from_secs(5));
+        for _ in 0..9 {
+            acquire().await.unwrap();
+        }
+        assert!(acquire().await.is_err(), "{algorithm:?}");
+    }
+}
+
+#[tokio::test]
+async fn test_token_bucket_burst_then_refill() {
+    let server = setup_mock_server().await;
+    let clock = Arc::new(TestClock::new());
+
+    let middleware = RateLimitMiddleware::builder()
+        .with_clock(clock.clone())
+        .route(|r| {
+            r.algorithm(Algorithm::TokenBucket)
+                .limit(5, Duration::from_secs(5))
+                .on_limit(ThrottleBehavior::Error)
+        })
+        .build();
+
+    let client = ClientBuilder::new(reqwest::Client::new())
+        .with(middleware)
+        .build();
+
+    let url = format!("{}/test", server.uri());
+
+    // A full bucket allows the whole burst
+    for _ in 0..5 {
+        client.get(&url).send().await.unwrap();
+    }
+    assert!(client.get(&url).send().await.is_err());
+
+    // Each emission interval (1s) refills exactly one token
+    for _ in 0..3 {
+        clock.advance(Duration::from_secs(1));
+        assert!(client.get(&url).send().await.is_ok());
+        assert!(client.get(&url).send().await.is_err());
+    }
+}
+
+#[tokio::test]
+async fn test_burst_limit_allows_burst_then_sustained_rate() {
+    let server = setup_mock_server().await;
+    let clock = Arc::new(TestClock::new());
+
+    let middleware = RateLimitMiddleware::builder()
+        .with_clock(clock.clone())
+        .route(|r| {
+            r.burst_limit(10, Duration::from_secs(1), 50)
+                .on_limit(ThrottleBehavior::Error)
+        })
+        .build();
+
+    let client = ClientBuilder::new(reqwest::Client::new())
+        .with(middleware)
+        .build();
+
+    let url = format!("{}/test", server.uri());
+
+    // The whole burst of 50 goes through immediately
+    for _ in 0..50 {
+        client.get(&url).send().await.unwrap();
+    }
+    assert!(client.get(&url).send().await.is_err());
+
+    // Afterwards, requests refill at the sustained 10/sec
+    for _ in 0..3 {
+        clock.advance(Duration::from_millis(100));
+        assert!(client.get(&url).send().await.is_ok());
+        assert!(client.get(&url).send().await.is_err());
+    }
+
+    // A full second refills 10 requests, not the whole burst
+    clock.advance(Duration::from_secs(1));
+    for _ in 0..10 {
+        client.get(&url).send().await.unwrap();
+    }
+    assert!(client.get(&url).send().await.is_err());
+}
+
+#[tokio::test]
+async fn test_cooldown_opens_circuit_after_repeated_throttling() {
+    let server = setup_mock_server().await;
+
+    let middleware = RateLimitMiddleware::builder()
+        .jitter(0.0)
+        .route(|r| {
+            r.limit(1, Duration::from_millis(200))
+                .cooldown_after(2, Duration::from_millis(400))
+        })
+        .build();
+
+    let client = ClientBuilder::new(reqwest::Client::new())
+        .with(middleware)
+        .build();
+
+    let url = format!("{}/test", server.uri());
+    client.get(&url).send().await.unwrap();
+
+    // First throttle event: delayed as usual
+    let start = Instant::now();
+    client.get(&url).send().await.unwrap();
+    assert!(start.elapsed() >= Duration::from_millis(150));
+
+    // Second consecutive throttle event opens the circuit instead of delaying
+    let start = Instant::now();
+    let err = client.get(&url).send().await.unwrap_err();
+    assert!(start.elapsed() < Duration::from_millis(100));
+    assert!(err.to_string().contains("circuit open"), "{err}");
+
+    // While open, requests fail immediately even once the limit would allow them
+    tokio::time::sleep(Duration::from_millis(250)).await;
+    let start = Instant::now();
+    let reqwest_middleware::Error::Middleware(err) = client.get(&url).send().await.unwrap_err()
+    else {
+        panic!("expected a middleware error");
+    };
+    assert!(start.elapsed() < Duration::from_millis(100));
+    let err = err.downcast_ref::<RateLimitError>().unwrap();
+    assert!(matches!(err, RateLimitError::CircuitOpen { .. }), "{err}");
+    assert!(err.retry_after().unwrap() <= Duration::from_millis(150));
+
+    // After the cooldown, an unthrottled request goes through
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    client.get(&url).send().await.unwrap();
+}
+
+// =============================================================================
+// Reconfiguration Tests
+// =============================================================================
+
+#[tokio::test]
+async fn test_update_routes_replaces_limits() {
+    let server = setup_mock_server().await;
+
+    let middleware = RateLimitMiddleware::builder()
+        .route(|r| {
+            r.limit(2, Duration::from_secs(10))
+                .on_limit(ThrottleBehavior::Error)
+        })
+        .build();
+
+    // The client holds a clone; updates through the original must reach it
+    let client = ClientBuilder::new(reqwest::Client::new())
+        .with(middleware.clone())
+        .build();
+
+    let url = format!("{}/test", server.uri());
+
+    // Exhaust the limit
+    client.get(&url).send().await.unwrap();
+    client.get(&url).send().await.unwrap();
+    assert!(client.get(&url).send().await.is_err());
+
+    middleware.update_routes(
+        RateLimitMiddleware::builder()
+            .route(|r| {
+                r.limit(100, Duration::from_secs(10))
+                    .on_limit(ThrottleBehavior::Error)
+            })
+            .into_routes(),
+    );
+
+    // The new limit applies immediately, with a fresh burst
+    assert_eq!(middleware.state_count(), 0);
+    for _ in 0..10 {
+        client.get(&url).send().await.unwrap();
+    }
+    let quota = middleware.quota_remaining("127.0.0.1", &Method::GET, "/test");
+    assert_eq!(quota[0].0.requests, 100);
+    assert_eq!(quota[0].1, 90);
+}
+
+#[tokio::test]
+async fn test_update_routes_rematches_repeated_requests() {
+    let server = setup_mock_server().await;
+
+    let middleware = RateLimitMiddleware::builder()
+        .route(|r| r.path("/other").limit(1, Duration::from_secs(10)))
+        .build();
+    let client = ClientBuilder::new(reqwest::Client::new())
+        .with(middleware.clone())
+        .build();
+
+    // Unmatched requests are remembered as such
+    let url = format!("{}/test", server.uri());
+    for _ in 0..3 {
+        client.get(&url).send().await.unwrap();
+    }
+
+    middleware.update_routes(
+        RateLimitMiddleware::builder()
+            .route(|r| {
+                r.path("/test")
+                    .limit(1, Duration::from_secs(10))
+                    .on_limit(ThrottleBehavior::Error)
+            })
+            .into_routes(),
+    );
+
+    client.get(&url).send().await.unwrap();
+    assert!(client.get(&url).send().await.is_err());
+}
+
+#[tokio::test]
+async fn test_set_route_limits_keeps_other_routes_state() {
+    let middleware = RateLimitMiddleware::builder()
+        .with_clock(Arc::new(TestClock::new()))
+        .host("api.example.com", |host| {
+            host.route(|r| {
+                r.path("/book")
+                    .limit(10, Duration::from_secs(10))
+                    .on_limit(ThrottleBehavior::Error)
+            })
+            .route(|r| {
+                r.path("/order")
+                    .limit(10, Duration::from_secs(10))
+                    .on_limit(ThrottleBehavior::Error)
+            })
+        })
+        .build();
+
+    for path in ["/book", "/order"] {
+        for _ in 0..4 {
+            middleware
+                .acquire("api.example.com", &Method::GET, path)
+                .await
+                .unwrap();
+        }
+    }
+
+    // Tighten orders to a burst of 2 and a longer sustained limit
+    middleware
+        .set_route_limits(
+            1,
+            vec![
+                RateLimit::new(2, Duration::from_secs(10)),
+                RateLimit::new(20, Duration::from_secs(600)),
+            ],
+        )
+        .unwrap();
+
+    // Books keep their accumulated state
+    let quota = middleware.quota_remaining("api.example.com", &Method::GET, "/book");
+    assert_eq!(quota, [(RateLimit::new(10, Duration::from_secs(10)), 6)]);
+
+    // Orders start over under the new limits
+    let quota = middleware.quota_remaining("api.example.com", &Method::GET, "/order");
+    assert_eq!(quota.len(), 2);
+    assert_eq!(quota[0].1, 2);
+    for _ in 0..2 {
+        middleware
+            .acquire("api.example.com", &Method::GET, "/order")
+            .await
+            .unwrap();
+    }
+    assert!(
+        middleware
+            .acquire("api.example.com", &Method::GET, "/order")
+            .await
+            .is_err()
+    );
+
+    // Invalid changes are rejected and leave the routes as they are
+    assert_eq!(
+        middleware.set_route_limits(2, vec![RateLimit::new(1, Duration::from_secs(1))]),
+        Err(BuildError::UnknownRoute { route: 2 })
+    );
+    assert_eq!(
+        middleware.set_route_limits(0, Vec::new()),
+        Err(BuildError::NoLimits { route: 0 })
+    );
+    assert_eq!(middleware.routes()[0].limits().len(), 1);
+}
+
+#[tokio::test]
+async fn test_identically_built_routes_compare_equal() {
+    let build = |sustained: u32| {
+        RateLimitMiddleware::builder()
+            .host("api.example.com", |h| {
+                h.route(|r| {
+                    r.method(Method::POST)
+                        .path("/order")
+                        .burst_limit(sustained, Duration::from_secs(10), 50)
+                        .on_limit(ThrottleBehavior::Error)
+                })
+                .route(|r| {
+                    r.path_pattern("/markets/*/book")
+                        .limit(20, Duration::from_secs(1))
+                })
+            })
+            .build()
+    };
+
+    assert_eq!(build(100).routes(), build(100).routes());
+    assert_ne!(build(100).routes(), build(200).routes());
+
+    // Routes with key functions are only equal to their clones
+    let keyed = || {
+        Route::builder()
+            .key_fn(|req| Some(req.url().path().to_owned()))
+            .limit(10, Duration::from_secs(1))
+            .into_route()
+    };
+    let route = keyed();
+    assert_eq!(route, route.clone());
+    assert_ne!(route, keyed());
+}
+
+#[tokio::test]
+async fn test_disabled_middleware_passes_requests_through() {
+    let server = setup_mock_server().await;
+
+    let middleware = RateLimitMiddleware::builder()
+        .route(|r| {
+            r.limit(1, Duration::from_secs(10))
+                .on_limit(ThrottleBehavior::Error)
+        })
+        .build();
+    let client = ClientBuilder::new(reqwest::Client::new())
+        .with(middleware.clone())
+        .build();
+
+    let url = format!("{}/test", server.uri());
+    client.get(&url).send().await.unwrap();
+    assert!(client.get(&url).send().await.is_err());
+
+    middleware.set_enabled(false);
+    assert!(!middleware.is_enabled());
+    for _ in 0..3 {
+        client.get(&url).send().await.unwrap();
+    }
+
+    // The limit is still exhausted once re-enabled
+    middleware.set_enabled(true);
+    assert!(client.get(&url).send().await.is_err());
+}
+
+#[tokio::test]
+async fn test_exported_state_carries_over_to_new_middleware() {
+    let server = setup_mock_server().await;
+    let url = format!("{}/test", server.uri());
+
+    let old = RateLimitMiddleware::builder()
+        .route(|r| {
+            r.path("/test")
+                .limit(2, Duration::from_secs(10))
+                .on_limit(ThrottleBehavior::Error)
+        })
+        .build();
+    let client = ClientBuilder::new(reqwest::Client::new())
+        .with(old.clone())
+        .build();
+    client.get(&url).send().await.unwrap();
+    client.get(&url).send().await.unwrap();
+
+    let snapshot = old.export_state();
+    assert_eq!(snapshot.len(), 1);
+
+    // Routes are matched by what they match, not their position
+    let new = RateLimitMiddleware::builder()
+        .with_clock(Arc::new(TestClock::new()))
+        .route(|r| {
+            r.path("/data")
+                .limit(2, Duration::from_secs(10))
+                .on_limit(ThrottleBehavior::Error)
+        })
+        .route(|r| {
+            r.path("/test")
+                .limit(2, Duration::from_secs(10))
+                .on_limit(ThrottleBehavior::Error)
+        })
+        .build();
+    new.import_state(&snapshot);
+
+    let client = ClientBuilder::new(reqwest::Client::new()).with(new).build();
+    let resp = client.get(&url).send().await;
+    assert!(
+        resp.is_err(),
+        "imported state should keep the limit exhausted"
+    );
+    let resp = client.get(format!("{}/data", server.uri())).send().await;
+    assert!(resp.is_ok(), "other routes should be unaffected");
+}
+
+// =============================================================================
+// Introspection Tests
+// =============================================================================
+
+#[tokio::test]
+async fn test_debug_summarizes_routes_and_buckets() {
+    let (builder, clock) = testing::builder();
+    let middleware = builder
+        .host("api.example.com", |h| {
+            h.route(|r| r.limit(100, Duration::from_secs(10)))
+                .route(|r| {
+                    r.path("/book")
+                        .key_by_header("x-user")
+                        .limit(10, Duration::from_secs(1))
+                })
+                .route(|r| r.path("/order").limit(5, Duration::from_secs(1)))
+        })
+        .build();
+    for path in ["/book", "/order", "/price"] {
+        middleware
+            .acquire("api.example.com", &Method::GET, path)
+            .await
+            .unwrap();
+    }
+    clock.advance(Duration::from_millis(1500));
+
+    let debug = format!("{middleware:?}");
+    assert_eq!(
+        debug,
+        "RateLimitMiddleware { routes: 3, buckets: 3, clock: 1.5s, enabled: true, .. }"
+    );
+    assert!(!debug.contains("api.example.com"), "{debug}");
+}
+
+#[tokio::test]
+async fn test_quota_remaining_reflects_consumption() {
+    let server = setup_mock_server().await;
+    let host = server.address().ip().to_string();
+
+    let middleware = RateLimitMiddleware::builder()
+        .host(host.clone(), |h| {
+            h.route(|r| r.limit(10, Duration::from_secs(10)))
+                .route(|r| r.path("/test").limit(3, Duration::from_secs(10)))
+        })
+        .build();
+
+    let client = ClientBuilder::new(reqwest::Client::new())
+        .with(middleware.clone())
+        .build();
+
+    let quota = middleware.quota_remaining(&host, &Method::GET, "/test");
+    assert_eq!(quota.len(), 2);
+    assert_eq!(quota[0].1, 10);
+    assert_eq!(quota[1].1, 3);
+
+    client
+        .get(format!("{}/test", server.uri()))
+        .send()
+        .await
+        .unwrap();
+    client
+        .get(format!("{}/data", server.uri()))
+        .send()
+        .await
+        .unwrap();
+
+    // Querying does not consume quota
+    for _ in 0..2 {
+        let quota = middleware.quota_remaining(&host, &Method::GET, "/test");
+        assert_eq!(quota[0].0.requests, 10);
+        assert_eq!(quota[0].1, 8);
+        assert_eq!(quota[1].0.requests, 3);
+        assert_eq!(quota[1].1, 2);
+    }
+
+    // No matching route
+    assert!(
+        middleware
+            .quota_remaining("other.example.com", &Method::GET, "/test")
+            .is_empty()
+    );
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_notify_on_available_resolves_after_emission_interval() {
+    let middleware = RateLimitMiddleware::builder()
+        .with_clock(Arc::new(TokioClock(tokio::time::Instant::now())))
+        .host("api.example.com", |h| {
+            h.route(|r| {
+                r.path("/order")
+                    .limit(2, Duration::from_millis(200))
+                    .on_limit(ThrottleBehavior::Error)
+            })
+        })
+        .build();
+    let notify = || middleware.notify_on_available("api.example.com", &Method::POST, "/order");
+
+    // Fresh and unmatched buckets are available right away
+    let start = tokio::time::Instant::now();
+    notify().await;
+    middleware
+        .notify_on_available("other.example.com", &Method::POST, "/order")
+        .await;
+    assert_eq!(start.elapsed(), Duration::ZERO);
+
+    for _ in 0..2 {
+        middleware
+            .acquire("api.example.com", &Method::POST, "/order")
+            .await
+            .unwrap();
+    }
+    assert!(
+        middleware
+            .acquire("api.example.com", &Method::POST, "/order")
+            .await
+            .is_err()
+    );
+
+    // Waiting consumes nothing, so the next request is admitted
+    notify().await;
+    assert_eq!(start.elapsed(), Duration::from_millis(100));
+    middleware
+        .acquire("api.example.com", &Method::POST, "/order")
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn test_consumed_counts_exactly_with_test_clock() {
+    let (builder, clock) = testing::builder();
+    let middleware = builder
+        .host("api.example.com", |h| {
+            h.route(|r| r.limit(100, Duration::from_secs(10)))
+                .route(|r| r.path("/book").limit(10, Duration::from_secs(1)))
+        })
+        .build();
+    let consumed = |path| middleware.consumed("api.example.com", &Method::GET, path);
+
+    for _ in 0..4 {
+        middleware
+            .acquire("api.example.com", &Method::GET, "/book")
+            .await
+            .unwrap();
+    }
+    for _ in 0..2 {
+        middleware
+            .acquire("api.example.com", &Method::GET, "/price")
+            .await
+            .unwrap();
+    }
+
+    // `/book` reports the busier of its own and the catch-all limit
+    assert_eq!(consumed("/book"), 6);
+    assert_eq!(consumed("/price"), 6);
+
+    // Half a second recovers five requests of either limit, freeing `/book`
+    // but not the catch-all
+    clock.advance(Duration::from_millis(500));
+    assert_eq!(consumed("/book"), 1);
+    clock.advance(Duration::from_millis(100));
+    assert_eq!(consumed("/book"), 0);
+}
+
+#[tokio::test]
+async fn test_stats_report_active_buckets() {
+    let (builder, clock) = testing::builder();
+    let middleware = builder
+        .host("api.example.com", |h| {
+            h.route(|r| r.limit(100, Duration::from_secs(10)))
+                .route(|r| {
+                    r.method(Method::POST)
+                        .path("/order")
+                        .limit(10, Duration::from_secs(1))
+                })
+        })
+        .build();
+    assert!(middleware.stats().is_empty());
+
+    for _ in 0..4 {
+        middleware
+            .acquire("api.example.com", &Method::POST, "/order")
+            .await
+            .unwrap();
+    }
+    middleware
+        .acquire("api.example.com", &Method::GET, "/book")
+        .await
+        .unwrap();
+
+    let stats = middleware.stats();
+    assert_eq!(stats.len(), 2);
+    assert_eq!(stats[0].route_index, 0);
+    assert_eq!(stats[0].host, "api.example.com");
+    assert_eq!(stats[0].path, "*");
+    assert_eq!(stats[0].remaining, 95);
+    assert_eq!(stats[0].recovers_in, Duration::from_millis(500));
+
+    assert_eq!(stats[1].route_index, 1);
+    assert_eq!(stats[1].methods, [Method::POST]);
+    assert_eq!(stats[1].path, "/order");
+    assert_eq!(stats[1].limit_index, 0);
+    assert_eq!(stats[1].key, None);
+    assert_eq!(stats[1].remaining, 6);
+    assert_eq!(stats[1].recovers_in, Duration::from_millis(400));
+
+    clock.advance(Duration::from_millis(400));
+    let stats = middleware.stats();
+    assert_eq!(stats[1].remaining, 10);
+    assert_eq!(stats[1].recovers_in, Duration::ZERO);
+}
+
+#[tokio::test]
+async fn test_template_reported_instead_of_path() {
+    let (builder, _clock) = testing::builder();
+    let middleware = builder
+        .host("api.example.com", |h| {
+            h.route(|r| {
+                r.path("/order")
+                    .template("/order/{id}")
+                    .limit(1, Duration::from_secs(1))
+                    .on_limit(ThrottleBehavior::Error)
+            })
+        })
+        .build();
+
+    middleware
+        .acquire("api.example.com", &Method::GET, "/order/999")
+        .await
+        .unwrap();
+    let err = middleware
+        .acquire("api.example.com", &Method::GET, "/order/999")
+        .await
+        .unwrap_err();
+    assert!(
+        matches!(&err, RateLimitError::RateLimited { route, .. } if route == "/order/{id}"),
+        "{err:?}"
+    );
+
+    let stats = middleware.stats();
+    assert_eq!(stats.len(), 1);
+    assert_eq!(stats[0].path, "/order/{id}");
+}
+
+#[tokio::test]
+async fn test_explain_lists_every_route() {
+    let server = setup_mock_server().await;
+    let host = server.address().ip().to_string();
+
+    let middleware = RateLimitMiddleware::builder()
+        .host(host.clone(), |h| {
+            h.route(|r| r.limit(100, Duration::from_secs(3600)))
+                .route(|r| r.path("/book").limit(10, Duration::from_secs(3600)))
+                .route(|r| r.path("/price").limit(10, Duration::from_secs(3600)))
+        })
+        .build();
+
+    let client = ClientBuilder::new(reqwest::Client::new())
+        .with(middleware.clone())
+        .build();
+    let url = format!("{}/book", server.uri());
+    client.get(&url).send().await.unwrap();
+
+    let req = reqwest::Client::new().get(&url).build().unwrap();
+    let explanation = middleware.explain(&req);
+    assert_eq!(explanation.len(), 3);
+
+    // Both the catch-all and the specific route apply to /book
+    assert_eq!(explanation[0].route_index, 0);
+    assert!(explanation[0].matched);
+    assert_eq!(explanation[0].remaining[0].0.requests, 100);
+    assert_eq!(explanation[0].remaining[0].1, 99);
+    assert!(explanation[1].matched);
+    assert_eq!(explanation[1].remaining[0].1, 9);
+
+    assert_eq!(explanation[2].route_index, 2);
+    assert!(!explanation[2].matched);
+    assert!(explanation[2].remaining.is_empty());
+
+    // Explaining does not consume quota
+    assert_eq!(middleware.explain(&req), explanation);
+}
+
+#[tokio::test]
+async fn test_would_allow_nearly_exhausted_bucket() {
+    let server = setup_mock_server().await;
+
+    let middleware = RateLimitMiddleware::builder()
+        .route(|r| {
+            r.limit(100, Duration::from_secs(10))
+                .limit(3, Duration::from_secs(10))
+                .on_limit(ThrottleBehavior::Error)
+        })
+        .build();
+
+    let client = ClientBuilder::new(reqwest::Client::new())
+        .with(middleware.clone())
+        .build();
+
+    let url = format!("{}/test", server.uri());
+    let req = reqwest::Client::new().get(&url).build().unwrap();
+    assert!(middleware.would_allow(&req));
+
+    client.get(&url).send().await.unwrap();
+    client.get(&url).send().await.unwrap();
+
+    // One request left under the tighter limit: checking does not use it up
+    for _ in 0..3 {
+        assert!(middleware.would_allow(&req));
+    }
+    client.get(&url).send().await.unwrap();
+
+    // The second limit rejects even though the first has room
+    assert!(!middleware.would_allow(&req));
+    let err = client.get(&url).send().await.unwrap_err();
+    assert!(err.to_string().contains("rate limit exceeded"));
+}
+
+#[tokio::test]
+async fn test_on_throttle_fires_once_per_throttled_request() {
+    let server = setup_mock_server().await;
+    let throttled = Arc::new(AtomicUsize::new(0));
+    let events: Arc<Mutex<Vec<ThrottleEvent>>> = Arc::default();
+
+    let middleware = RateLimitMiddleware::builder()
+        .on_throttle({
+            let throttled = throttled.clone();
+            let events = events.clone();
+            move |event| {
+                throttled.fetch_add(1, Ordering::SeqCst);
+                events.lock().unwrap().push(event);
+            }
+        })
+        .route(|r| r.path("/data").limit(100, Duration::from_secs(10)))
+        .route(|r| {
+            r.path("/test")
+                .limit(2, Duration::from_secs(10))
+                .on_limit(ThrottleBehavior::Error)
+        })
+        .build();
+
+    let client = ClientBuilder::new(reqwest::Client::new())
+        .with(middleware)
+        .build();
+
+    let url = format!("{}/test", server.uri());
+    for _ in 0..5 {
+        let _ = client.get(&url).send().await;
+    }
+    client
+        .get(format!("{}/data", server.uri()))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(throttled.load(Ordering::SeqCst), 3);
+
+    let events = events.lock().unwrap();
+    let event = &events[0];
+    assert_eq!(event.path, "/test");
+    assert_eq!(event.method, Method::GET);
+    assert_eq!(event.route_index, 1);
+    assert_eq!(event.limit_index, 0);
+    assert_eq!(event.outcome, ThrottleOutcome::Rejected);
+    assert!(event.wait > Duration::ZERO);
+}
+
+#[tokio::test]
+async fn test_on_throttle_reports_delays() {
+    let server = setup_mock_server().await;
+    let events: Arc<Mutex<Vec<ThrottleEvent>>> = Arc::default();
+
+    let middleware = RateLimitMiddleware::builder()
+        .on_throttle({
+            let events = events.clone();
+            move |event| events.lock().unwrap().push(event)
+        })
+        .route(|r| r.limit(1, Duration::from_millis(20)))
+        .build();
+
+    let client = ClientBuilder::new(reqwest::Client::new())
+        .with(middleware)
+        .build();
+
+    let url = format!("{}/test", server.uri());
+    client.get(&url).send().await.unwrap();
+    client.get(&url).send().await.unwrap();
+
+    let events = events.lock().unwrap();
+    assert!(!events.is_empty());
+    assert!(
+        events
+            .iter()
+            .all(|event| event.outcome == ThrottleOutcome::Delayed)
+    );
+}
+
+#[tokio::test]
+async fn test_acquire_delays_without_request() {
+    let middleware = RateLimitMiddleware::builder()
+        .jitter(0.0)
+        .host("stream.example.com", |host| {
+            host.route(|r| r.path("/ws").limit(2, Duration::from_millis(200)))
+        })
+        .build();
+
+    // Burst of 2, then one every 100ms
+    let start = Instant::now();
+    for _ in 0..4 {
+        middleware
+            .acquire("stream.example.com", &Method::GET, "/ws")
+            .await
+            .unwrap();
+    }
+    let elapsed = start.elapsed();
+    assert!(elapsed >= Duration::from_millis(180), "{elapsed:?}");
+
+    // Other hosts are not limited
+    let start = Instant::now();
+    for _ in 0..10 {
+        middleware
+            .acquire("other.example.com", &Method::GET, "/ws")
+            .await
+            .unwrap();
+    }
+    assert!(start.elapsed() < Duration::from_millis(50));
+}
+
+#[tokio::test]
+async fn test_acquire_honors_error_behavior() {
+    let middleware = RateLimitMiddleware::builder()
+        .route(|r| {
+            r.path("/ws")
+                .limit(1, Duration::from_secs(10))
+                .on_limit(ThrottleBehavior::Error)
+        })
+        .build();
+
+    middleware
+        .acquire("stream.example.com", &Method::GET, "/ws")
+        .await
+        .unwrap();
+    let err = middleware
+        .acquire("stream.example.com", &Method::GET, "/ws?id=1")
+        .await
+        .unwrap_err();
+    assert!(matches!(err, RateLimitError::RateLimited { .. }), "{err}");
+}
+
+// =============================================================================
+// Server Feedback Tests
+// =============================================================================
+
+#[tokio::test]
+async fn test_retry_after_defers_subsequent_requests() {
+    let server = setup_mock_server().await;
+    Mock::given(method("GET"))
+        .and(path("/busy"))
+        .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "2"))
+        .mount(&server)
+        .await;
+
+    let clock = Arc::new(TestClock::new());
+    let middleware = RateLimitMiddleware::builder()
+        .with_clock(clock.clone())
+        .respect_retry_after(true)
+        .route(|r| {
+            r.limit(100, Duration::from_secs(10))
+                .on_limit(ThrottleBehavior::Error)
+        })
+        .build();
+
+    let client = ClientBuilder::new(reqwest::Client::new())
+        .with(middleware)
+        .build();
+
+    // The 429 response itself is passed through
+    let resp = client
+        .get(format!("{}/busy", server.uri()))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 429);
+
+    // Plenty of client-side burst remains, but the server asked us to back off
+    let err = client
+        .get(format!("{}/test", server.uri()))
+        .send()
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("rate limit exceeded"), "{err}");
+
+    // After the advised delay, requests flow again
+    clock.advance(Duration::from_secs(2));
+    assert!(
+        client
+            .get(format!("{}/test", server.uri()))
+            .send()
+            .await
+            .is_ok()
+    );
+}
 
 #[tokio::test]
-async fn test_different_routes_have_separate_limits() {
+async fn test_exhausted_ratelimit_headers_delay_next_request() {
     let server = setup_mock_server().await;
+    Mock::given(method("GET"))
+        .and(path("/quota"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .insert_header("X-RateLimit-Remaining", "0")
+                .insert_header("X-RateLimit-Reset", "1"),
+        )
+        .mount(&server)
+        .await;
 
     let middleware = RateLimitMiddleware::builder()
-        .route(|r| {
-            r.path("/test")
-                .limit(2, Duration::from_secs(10))
-                .on_limit(ThrottleBehavior::Error)
-        })
-        .route(|r| {
-            r.path("/data")
-                .limit(2, Duration::from_secs(10))
-                .on_limit(ThrottleBehavior::Error)
-        })
+        .jitter(0.0)
+        .respect_ratelimit_headers(true)
+        .route(|r| r.limit(100, Duration::from_secs(10)))
         .build();
 
     let client = ClientBuilder::new(reqwest::Client::new())
         .with(middleware)
         .build();
 
-    // Exhaust /test limit
+    // The server has no quota left, though the client-side limit has plenty
     client
-        .get(format!("{}/test", server.uri()))
+        .get(format!("{}/quota", server.uri()))
         .send()
         .await
         .unwrap();
+
+    let start = Instant::now();
     client
         .get(format!("{}/test", server.uri()))
         .send()
         .await
         .unwrap();
-
-    // /data should still work (separate limit)
-    let resp = client.get(format!("{}/data", server.uri())).send().await;
-    assert!(resp.is_ok(), "/data should have its own limit");
-
-    // /test should fail now
-    let resp = client.get(format!("{}/test", server.uri())).send().await;
-    assert!(resp.is_err(), "/test should be rate limited");
+    let elapsed = start.elapsed();
+    assert!(elapsed >= Duration::from_millis(900), "{elapsed:?}");
 }
 
 #[tokio::test]
-async fn test_method_specific_limits() {
+async fn test_adaptive_rate_backs_off_and_recovers() {
     let server = setup_mock_server().await;
+    Mock::given(method("GET"))
+        .and(path("/flaky"))
+        .respond_with(ResponseTemplate::new(429))
+        .up_to_n_times(2)
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/flaky"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&server)
+        .await;
 
+    // One request per 10ms at the full rate
     let middleware = RateLimitMiddleware::builder()
+        .jitter(0.0)
         .route(|r| {
-            r.method(Method::POST)
-                .path("/order")
-                .limit(1, Duration::from_secs(10))
-                .on_limit(ThrottleBehavior::Error)
-        })
-        .route(|r| {
-            r.method(Method::DELETE)
-                .path("/order")
-                .limit(1, Duration::from_secs(10))
-                .on_limit(ThrottleBehavior::Error)
+            r.burst_limit(100, Duration::from_secs(1), 1)
+                .adaptive(0.25, 1.0)
         })
         .build();
 
@@ -270,31 +3453,100 @@ async fn test_method_specific_limits() {
         .with(middleware)
         .build();
 
-    let order_url = format!("{}/order", server.uri());
+    let url = format!("{}/flaky", server.uri());
+    let mut sent = Vec::new();
+    for _ in 0..30 {
+        client.get(&url).send().await.unwrap();
+        sent.push(Instant::now());
+    }
+    let gap = |i: usize| sent[i + 1] - sent[i];
 
-    // POST and DELETE have separate limits
-    client.post(&order_url).send().await.unwrap();
-    client.delete(&order_url).send().await.unwrap();
+    // Two 429s quarter the rate, so requests are spaced about 40ms apart
+    assert!(gap(2) >= Duration::from_millis(30), "{:?}", gap(2));
+    // Twenty successes restore the full rate
+    let recovered = (sent[29] - sent[25]) / 4;
+    assert!(recovered < Duration::from_millis(20), "{recovered:?}");
+}
 
-    // Second POST should fail
-    let resp = client.post(&order_url).send().await;
-    assert!(resp.is_err(), "Second POST should be rate limited");
+#[tokio::test]
+async fn test_retry_after_ignored_when_disabled_or_unmatched() {
+    let server = setup_mock_server().await;
+    Mock::given(method("GET"))
+        .and(path("/busy"))
+        .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "60"))
+        .mount(&server)
+        .await;
 
-    // Second DELETE should also fail
-    let resp = client.delete(&order_url).send().await;
-    assert!(resp.is_err(), "Second DELETE should be rate limited");
+    // Disabled: 429s don't affect client-side state
+    let client = ClientBuilder::new(reqwest::Client::new())
+        .with(
+            RateLimitMiddleware::builder()
+                .route(|r| {
+                    r.limit(100, Duration::from_secs(10))
+                        .on_limit(ThrottleBehavior::Error)
+                })
+                .build(),
+        )
+        .build();
+    client
+        .get(format!("{}/busy", server.uri()))
+        .send()
+        .await
+        .unwrap();
+    assert!(
+        client
+            .get(format!("{}/test", server.uri()))
+            .send()
+            .await
+            .is_ok()
+    );
+
+    // Enabled, but the 429 came from a route that isn't limited
+    let client = ClientBuilder::new(reqwest::Client::new())
+        .with(
+            RateLimitMiddleware::builder()
+                .respect_retry_after(true)
+                .route(|r| {
+                    r.path("/test")
+                        .limit(100, Duration::from_secs(10))
+                        .on_limit(ThrottleBehavior::Error)
+                })
+                .build(),
+        )
+        .build();
+    client
+        .get(format!("{}/busy", server.uri()))
+        .send()
+        .await
+        .unwrap();
+    assert!(
+        client
+            .get(format!("{}/test", server.uri()))
+            .send()
+            .await
+            .is_ok()
+    );
 }
 
 #[tokio::test]
-async fn test_unmatched_routes_not_limited() {
-    let server = setup_mock_server().await;
+async fn test_count_if_refunds_uncounted_responses() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/cached"))
+        .respond_with(ResponseTemplate::new(304))
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/fresh"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&server)
+        .await;
 
-    // Only limit /test path
     let middleware = RateLimitMiddleware::builder()
         .route(|r| {
-            r.path("/test")
-                .limit(1, Duration::from_secs(10))
+            r.limit(2, Duration::from_secs(10))
                 .on_limit(ThrottleBehavior::Error)
+                .count_if(|status| status != reqwest::StatusCode::NOT_MODIFIED)
         })
         .build();
 
@@ -302,29 +3554,58 @@ async fn test_unmatched_routes_not_limited() {
         .with(middleware)
         .build();
 
-    // Root path should not be limited
-    for _ in 0..10 {
-        let resp = client.get(format!("{}/", server.uri())).send().await;
-        assert!(resp.is_ok(), "Unmatched route should not be limited");
+    // Cached responses do not deplete the limit
+    for _ in 0..5 {
+        let resp = client
+            .get(format!("{}/cached", server.uri()))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), 304);
     }
+
+    let fresh = format!("{}/fresh", server.uri());
+    client.get(&fresh).send().await.unwrap();
+    client.get(&fresh).send().await.unwrap();
+    let resp = client.get(&fresh).send().await;
+    assert!(
+        resp.is_err(),
+        "200 responses should count against the limit"
+    );
 }
 
 // =============================================================================
-// Multiple Limits Tests
+// Hedged Request Tests
 // =============================================================================
 
+/// Mount a mock answering the first request after `delay` with "slow", and
+/// later requests at once with "fast".
+async fn setup_slow_first_server(delay: Duration) -> MockServer {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_string("slow")
+                .set_delay(delay),
+        )
+        .up_to_n_times(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("fast"))
+        .mount(&server)
+        .await;
+    server
+}
+
 #[tokio::test]
-async fn test_multiple_limits_all_must_pass() {
-    let server = setup_mock_server().await;
+async fn test_hedge_returns_faster_response() {
+    let server = setup_slow_first_server(Duration::from_secs(5)).await;
 
-    // Create route with two limits:
-    // - Burst: 3 requests per 100ms
-    // - Sustained: 5 requests per 1 second
     let middleware = RateLimitMiddleware::builder()
         .route(|r| {
-            r.limit(3, Duration::from_millis(100)) // Burst limit
-                .limit(5, Duration::from_secs(1)) // Sustained limit
-                .on_limit(ThrottleBehavior::Error)
+            r.limit(10, Duration::from_secs(1))
+                .hedge_after(Duration::from_millis(100))
         })
         .build();
 
@@ -332,139 +3613,132 @@ async fn test_multiple_limits_all_must_pass() {
         .with(middleware)
         .build();
 
-    let url = format!("{}/test", server.uri());
-
-    // First 3 should succeed (within burst)
-    for i in 0..3 {
-        let resp = client.get(&url).send().await;
-        assert!(resp.is_ok(), "Request {i} should succeed within burst");
-    }
-
-    // 4th should fail (burst exhausted)
-    let resp = client.get(&url).send().await;
-    assert!(resp.is_err(), "4th request should fail - burst exhausted");
+    let start = Instant::now();
+    let resp = client.get(server.uri()).send().await.unwrap();
+    assert_eq!(resp.text().await.unwrap(), "fast");
+    assert!(start.elapsed() < Duration::from_secs(2));
+    assert_eq!(server.received_requests().await.unwrap().len(), 2);
 }
 
-// =============================================================================
-// Concurrent Request Tests
-// =============================================================================
-
 #[tokio::test]
-async fn test_concurrent_requests_respect_limit() {
-    let server = setup_mock_server().await;
+async fn test_hedge_respects_rate_limit() {
+    let server = setup_slow_first_server(Duration::from_millis(500)).await;
 
     let middleware = RateLimitMiddleware::builder()
         .route(|r| {
-            r.limit(5, Duration::from_millis(500))
+            r.limit(1, Duration::from_secs(10))
                 .on_limit(ThrottleBehavior::Error)
+                .hedge_after(Duration::from_millis(100))
         })
         .build();
 
-    let client = Arc::new(
-        ClientBuilder::new(reqwest::Client::new())
-            .with(middleware)
-            .build(),
-    );
-
-    let url = format!("{}/test", server.uri());
-
-    // Launch 10 concurrent requests
-    let mut handles = vec![];
-    for _ in 0..10 {
-        let client = client.clone();
-        let url = url.clone();
-        handles.push(tokio::spawn(async move { client.get(&url).send().await }));
-    }
-
-    // Wait for all to complete
-    let mut success_count = 0;
-    let mut error_count = 0;
-    for handle in handles {
-        match handle.await.unwrap() {
-            Ok(_) => success_count += 1,
-            Err(_) => error_count += 1,
-        }
-    }
+    let client = ClientBuilder::new(reqwest::Client::new())
+        .with(middleware)
+        .build();
 
-    // Should have exactly 5 successes and 5 failures
-    assert_eq!(success_count, 5, "Should have 5 successful requests");
-    assert_eq!(error_count, 5, "Should have 5 rate-limited requests");
+    // The duplicate is rejected, so the original response is awaited
+    let resp = client.get(server.uri()).send().await.unwrap();
+    assert_eq!(resp.text().await.unwrap(), "slow");
+    assert_eq!(server.received_requests().await.unwrap().len(), 1);
 }
 
+// =============================================================================
+// Single-Flight Tests
+// =============================================================================
+
 #[tokio::test]
-async fn test_shared_state_across_clones() {
-    let server = setup_mock_server().await;
+async fn test_single_flight_coalesces_identical_requests() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/book"))
+        .and(query_param("token_id", "1"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_string("book 1")
+                .set_delay(Duration::from_millis(200)),
+        )
+        .mount(&server)
+        .await;
 
     let middleware = RateLimitMiddleware::builder()
         .route(|r| {
-            r.limit(3, Duration::from_secs(10))
+            r.path("/book")
+                .limit(1, Duration::from_secs(10))
                 .on_limit(ThrottleBehavior::Error)
+                .single_flight()
         })
         .build();
 
-    // Create two clients sharing the same middleware
-    let client1 = ClientBuilder::new(reqwest::Client::new())
-        .with(middleware.clone())
-        .build();
-    let client2 = ClientBuilder::new(reqwest::Client::new())
+    let client = ClientBuilder::new(reqwest::Client::new())
         .with(middleware)
         .build();
 
-    let url = format!("{}/test", server.uri());
-
-    // Use client1 twice
-    client1.get(&url).send().await.unwrap();
-    client1.get(&url).send().await.unwrap();
+    let url = format!("{}/book?token_id=1", server.uri());
+    let requests: Vec<_> = (0..5)
+        .map(|_| {
+            let client = client.clone();
+            let url = url.clone();
+            tokio::spawn(async move { client.get(&url).send().await })
+        })
+        .collect();
 
-    // Use client2 once - should still work
-    client2.get(&url).send().await.unwrap();
+    // All share one response, and the duplicates took no quota
+    for request in requests {
+        let response = request.await.unwrap().unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.text().await.unwrap(), "book 1");
+    }
+    assert_eq!(server.received_requests().await.unwrap().len(), 1);
 
-    // Now both clients should be rate limited (shared state)
-    assert!(
-        client1.get(&url).send().await.is_err(),
-        "client1 should be rate limited"
-    );
-    assert!(
-        client2.get(&url).send().await.is_err(),
-        "client2 should be rate limited"
-    );
+    // Once the first request completed, the next one is sent again
+    let err = client.get(&url).send().await.unwrap_err();
+    assert!(err.to_string().contains("rate limit exceeded"), "{err}");
 }
 
-// =============================================================================
-// Recovery Tests
-// =============================================================================
-
 #[tokio::test]
-async fn test_rate_limit_recovers_after_window() {
-    let server = setup_mock_server().await;
+async fn test_single_flight_keeps_credentials_apart() {
+    let server = MockServer::start().await;
+    for user in ["alice", "bob"] {
+        Mock::given(method("GET"))
+            .and(path("/account"))
+            .and(header("authorization", format!("Bearer {user}").as_str()))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_string(format!("account of {user}"))
+                    .set_delay(Duration::from_millis(200)),
+            )
+            .mount(&server)
+            .await;
+    }
 
     let middleware = RateLimitMiddleware::builder()
         .route(|r| {
-            r.limit(2, Duration::from_millis(100))
-                .on_limit(ThrottleBehavior::Error)
+            r.path("/account")
+                .limit(100, Duration::from_secs(10))
+                .single_flight()
         })
         .build();
-
     let client = ClientBuilder::new(reqwest::Client::new())
         .with(middleware)
         .build();
 
-    let url = format!("{}/test", server.uri());
-
-    // Exhaust the limit
-    client.get(&url).send().await.unwrap();
-    client.get(&url).send().await.unwrap();
-    assert!(
-        client.get(&url).send().await.is_err(),
-        "Should be rate limited"
-    );
-
-    // Wait for recovery (one emission interval = 50ms)
-    tokio::time::sleep(Duration::from_millis(60)).await;
+    let url = format!("{}/account", server.uri());
+    let requests: Vec<_> = ["alice", "bob"]
+        .into_iter()
+        .map(|user| {
+            let client = client.clone();
+            let url = url.clone();
+            tokio::spawn(async move { client.get(&url).bearer_auth(user).send().await })
+        })
+        .collect();
 
-    // Should be able to make one more request
-    let resp = client.get(&url).send().await;
-    assert!(resp.is_ok(), "Should recover after waiting");
+    let mut bodies = Vec::new();
+    for request in requests {
+        let response = request.await.unwrap().unwrap();
+        bodies.push(response.text().await.unwrap());
+    }
+    assert_eq!(bodies, ["account of alice", "account of bob"]);
+    assert_eq!(server.received_requests().await.unwrap().len(), 2);
 }
 
 // =============================================================================
@@ -527,3 +3801,43 @@ async fn test_catch_all_route() {
     let resp = client.get(format!("{}/", server.uri())).send().await;
     assert!(resp.is_err(), "Catch-all should apply to all paths");
 }
+
+#[tokio::test]
+async fn test_hostless_urls_only_match_catch_all_routes() {
+    let middleware = RateLimitMiddleware::builder()
+        .host("api.example.com", |host| {
+            host.route(|r| {
+                r.limit(1, Duration::from_secs(10))
+                    .on_limit(ThrottleBehavior::Error)
+            })
+        })
+        .route(|r| {
+            r.limit(2, Duration::from_secs(10))
+                .on_limit(ThrottleBehavior::Error)
+        })
+        .build();
+
+    let client = ClientBuilder::new(reqwest::Client::new())
+        .with(middleware.clone())
+        .build();
+
+    // The request builder rejects these schemes, but a request constructed
+    // directly still passes through the middleware. Sending then fails, but
+    // only after passing the catch-all route.
+    let send = |url: &str| {
+        let url = reqwest::Url::parse(url).unwrap();
+        client.execute(reqwest::Request::new(Method::GET, url))
+    };
+    for url in ["data:text/plain,hello", "mailto:ops@example.com"] {
+        let err = send(url).await.unwrap_err();
+        assert!(
+            !err.to_string().contains("rate limit exceeded"),
+            "{url}: {err}"
+        );
+    }
+    let err = send("file:///etc/hosts").await.unwrap_err();
+    assert!(err.to_string().contains("rate limit exceeded"), "{err}");
+
+    // Matching without a host does not panic either
+    middleware.acquire("", &Method::GET, "").await.unwrap_err();
+}
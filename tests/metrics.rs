@@ -0,0 +1,119 @@
+//! Tests for the `metrics` feature.
+
+#![cfg(feature = "metrics")]
+
+use metrics_util::CompositeKey;
+use metrics_util::debugging::{DebugValue, DebuggingRecorder};
+use reqwest_middleware::ClientBuilder;
+use route_ratelimit::{RateLimitMiddleware, ThrottleBehavior};
+use std::time::Duration;
+use wiremock::matchers::method;
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+type Metrics = Vec<(CompositeKey, DebugValue)>;
+
+/// Run an async test body with a thread-local metrics recorder installed,
+/// returning everything it recorded.
+fn with_recorder<F: Future<Output = ()>>(test: impl FnOnce() -> F) -> Metrics {
+    let recorder = DebuggingRecorder::new();
+    let snapshotter = recorder.snapshotter();
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+
+    metrics::with_local_recorder(&recorder, || runtime.block_on(test()));
+    snapshotter
+        .snapshot()
+        .into_vec()
+        .into_iter()
+        .map(|(key, _, _, value)| (key, value))
+        .collect()
+}
+
+/// Find the counter value for a host/outcome pair.
+fn counter(metrics: &Metrics, host: &str, outcome: &str) -> u64 {
+    metrics
+        .iter()
+        .find_map(|(key, value)| {
+            let key = key.key();
+            let matches = key.name() == "route_ratelimit_requests_total"
+                && key.labels().any(|l| l.key() == "host" && l.value() == host)
+                && key
+                    .labels()
+                    .any(|l| l.key() == "outcome" && l.value() == outcome);
+            match value {
+                DebugValue::Counter(count) if matches => Some(*count),
+                _ => None,
+            }
+        })
+        .unwrap_or(0)
+}
+
+async fn setup_mock_server() -> MockServer {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&server)
+        .await;
+    server
+}
+
+#[test]
+fn test_counts_allowed_and_errored_requests() {
+    let metrics = with_recorder(|| async {
+        let server = setup_mock_server().await;
+        let host = server.address().ip().to_string();
+
+        let middleware = RateLimitMiddleware::builder()
+            .host(host, |h| {
+                h.route(|r| {
+                    r.limit(2, Duration::from_secs(10))
+                        .on_limit(ThrottleBehavior::Error)
+                })
+            })
+            .build();
+        let client = ClientBuilder::new(reqwest::Client::new())
+            .with(middleware)
+            .build();
+
+        for _ in 0..5 {
+            let _ = client.get(server.uri()).send().await;
+        }
+    });
+
+    assert_eq!(counter(&metrics, "127.0.0.1", "allowed"), 2);
+    assert_eq!(counter(&metrics, "127.0.0.1", "errored"), 3);
+    assert_eq!(counter(&metrics, "127.0.0.1", "delayed"), 0);
+}
+
+#[test]
+fn test_observes_delays() {
+    let metrics = with_recorder(|| async {
+        let server = setup_mock_server().await;
+
+        let middleware = RateLimitMiddleware::builder()
+            .route(|r| r.limit(1, Duration::from_millis(20)))
+            .build();
+        let client = ClientBuilder::new(reqwest::Client::new())
+            .with(middleware)
+            .build();
+
+        client.get(server.uri()).send().await.unwrap();
+        client.get(server.uri()).send().await.unwrap();
+    });
+
+    assert_eq!(counter(&metrics, "*", "allowed"), 2);
+    assert!(counter(&metrics, "*", "delayed") >= 1);
+
+    let delays: Vec<f64> = metrics
+        .iter()
+        .filter(|(key, _)| key.key().name() == "route_ratelimit_delay_seconds")
+        .flat_map(|(_, value)| match value {
+            DebugValue::Histogram(values) => values.iter().map(|v| v.into_inner()).collect(),
+            _ => Vec::new(),
+        })
+        .collect();
+    assert!(!delays.is_empty(), "Delay should be observed");
+    assert!(delays.iter().all(|&delay| delay > 0.0 && delay < 1.0));
+}
@@ -0,0 +1,152 @@
+//! Tests for the `tower` feature.
+
+#![cfg(feature = "tower")]
+
+use http::{Method, Request, Response, StatusCode};
+use route_ratelimit::{
+    RateLimitError, RateLimitLayer, RateLimitMiddleware, RequestWeight, ThrottleBehavior,
+};
+use std::convert::Infallible;
+use std::future::{Ready, ready};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tower::{Layer, Service, ServiceExt};
+
+/// An inner service that answers every request with `200 OK`, counting calls.
+#[derive(Clone)]
+struct Counting(Arc<AtomicUsize>);
+
+impl Service<Request<()>> for Counting {
+    type Response = Response<()>;
+    type Error = Infallible;
+    type Future = Ready<Result<Response<()>, Infallible>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Infallible>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, _req: Request<()>) -> Self::Future {
+        self.0.fetch_add(1, Ordering::SeqCst);
+        ready(Ok(Response::new(())))
+    }
+}
+
+fn get(uri: &str) -> Request<()> {
+    Request::builder().uri(uri).body(()).unwrap()
+}
+
+#[tokio::test]
+async fn test_tower_service_rejects_when_limit_trips() {
+    let calls = Arc::new(AtomicUsize::new(0));
+    let middleware = RateLimitMiddleware::builder()
+        .host("api.example.com", |host| {
+            host.route(|r| {
+                r.path("/order")
+                    .limit(2, Duration::from_secs(10))
+                    .on_limit(ThrottleBehavior::Error)
+            })
+        })
+        .build();
+    let service = RateLimitLayer::new(middleware).layer(Counting(calls.clone()));
+
+    for _ in 0..2 {
+        let response = service
+            .clone()
+            .oneshot(get("https://api.example.com/order"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    let err = service
+        .clone()
+        .oneshot(get("https://api.example.com/order/1"))
+        .await
+        .unwrap_err();
+    let err = err.downcast_ref::<RateLimitError>().unwrap();
+    assert!(err.retry_after().unwrap() > Duration::from_secs(4));
+    assert_eq!(
+        calls.load(Ordering::SeqCst),
+        2,
+        "Rejected requests must not reach the inner service"
+    );
+
+    // Other paths and hosts are unaffected
+    service
+        .clone()
+        .oneshot(get("https://api.example.com/book"))
+        .await
+        .unwrap();
+    service
+        .oneshot(get("https://other.example.com/order"))
+        .await
+        .unwrap();
+    assert_eq!(calls.load(Ordering::SeqCst), 4);
+}
+
+#[tokio::test]
+async fn test_tower_service_shares_state_with_middleware() {
+    let calls = Arc::new(AtomicUsize::new(0));
+    let middleware = RateLimitMiddleware::builder()
+        .route(|r| {
+            r.method(Method::POST)
+                .limit(3, Duration::from_secs(10))
+                .on_limit(ThrottleBehavior::Error)
+        })
+        .build();
+    let service = RateLimitLayer::new(middleware.clone()).layer(Counting(calls));
+
+    let mut req = Request::builder()
+        .method(Method::POST)
+        .uri("/order")
+        .header(http::header::HOST, "api.example.com")
+        .body(())
+        .unwrap();
+    req.extensions_mut().insert(RequestWeight(2));
+    service.clone().oneshot(req).await.unwrap();
+
+    let quota = middleware.quota_remaining("api.example.com", &Method::POST, "/order");
+    assert_eq!(quota[0].1, 1);
+
+    // The service and the middleware draw from the same limit
+    let req = Request::builder()
+        .method(Method::POST)
+        .uri("https://api.example.com/order")
+        .body(())
+        .unwrap();
+    service.clone().oneshot(req).await.unwrap();
+    let req = Request::builder()
+        .method(Method::POST)
+        .uri("https://api.example.com/order")
+        .body(())
+        .unwrap();
+    assert!(service.oneshot(req).await.is_err());
+}
+
+#[tokio::test]
+async fn test_tower_service_reports_respond_429_as_error() {
+    let calls = Arc::new(AtomicUsize::new(0));
+    let middleware = RateLimitMiddleware::builder()
+        .route(|r| {
+            r.limit(1, Duration::from_secs(10))
+                .on_limit(ThrottleBehavior::Respond429)
+        })
+        .build();
+    let service = RateLimitLayer::new(middleware).layer(Counting(calls.clone()));
+
+    service
+        .clone()
+        .oneshot(get("https://api.example.com/"))
+        .await
+        .unwrap();
+
+    // No response body can be built for an arbitrary inner service
+    let err = service
+        .oneshot(get("https://api.example.com/"))
+        .await
+        .unwrap_err();
+    assert!(err.downcast_ref::<RateLimitError>().is_some());
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+}
@@ -0,0 +1,39 @@
+//! Tests for the `tracing` feature.
+
+#![cfg(feature = "tracing")]
+
+use reqwest_middleware::ClientBuilder;
+use route_ratelimit::{RateLimitMiddleware, ThrottleBehavior};
+use std::time::Duration;
+use tracing_test::traced_test;
+use wiremock::matchers::method;
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+#[tokio::test]
+#[traced_test]
+async fn test_throttle_emits_debug_event() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&server)
+        .await;
+
+    let middleware = RateLimitMiddleware::builder()
+        .route(|r| {
+            r.limit(1, Duration::from_secs(10))
+                .on_limit(ThrottleBehavior::Error)
+        })
+        .build();
+    let client = ClientBuilder::new(reqwest::Client::new())
+        .with(middleware)
+        .build();
+
+    client.get(server.uri()).send().await.unwrap();
+    assert!(logs_contain("request admitted"));
+    assert!(!logs_contain("rate limit reached"));
+
+    assert!(client.get(server.uri()).send().await.is_err());
+    assert!(logs_contain("rate limit reached"));
+    assert!(logs_contain("behavior=Error"));
+    assert!(logs_contain("rate_limit{host=\"127.0.0.1\" path=\"/\"}"));
+}